@@ -0,0 +1,184 @@
+//! Drives `handle_search` against a synthetic index, without a live core.
+//!
+//! ```text
+//! cargo bench --bench search -- --docs 100000 --iterations 5000
+//! ```
+//!
+//! Flags: `--docs <n>` documents indexed (default 10000), `--words <n>` words
+//! per document (default 50), `--iterations <n>` searches timed (default
+//! 1000), `--limit <n>` hits asked per search (default 10).
+
+use std::time::{Duration, Instant};
+
+use crawler::search::SearchSchema;
+use nerve_protocol::constants::{MAGIC, VERSION};
+use nerve_protocol::frame::{FrameHeader, OwnedFrame};
+use nerve_protocol::types::{FrameFlags, MessageType};
+use tantivy::{Index, doc};
+use tempfile::tempdir;
+
+use nerve_search_adapter::context::Context;
+use nerve_search_adapter::handler::handle_search;
+use nerve_search_adapter::shards::Shards;
+use nerve_search_adapter::state::RequestState;
+
+const VOCABULARY: &str = "rust search adapter index query engine shard segment reader writer \
+    commit merge vector score rank filter domain crawl page link title content frame socket \
+    worker cache latency memory disk thread token stem language snippet highlight facet term \
+    phrase fuzzy prefix";
+
+struct Options {
+    docs: usize,
+    words: usize,
+    iterations: usize,
+    limit: usize,
+}
+
+fn options() -> Options {
+    let mut options = Options {
+        docs: 10_000,
+        words: 50,
+        iterations: 1_000,
+        limit: 10,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let slot = match arg.as_str() {
+            "--docs" => &mut options.docs,
+            "--words" => &mut options.words,
+            "--iterations" => &mut options.iterations,
+            "--limit" => &mut options.limit,
+            // cargo bench passes `--bench`; anything else is ignored too
+            _ => continue,
+        };
+        let value = args.next().unwrap_or_default();
+        *slot = value
+            .parse()
+            .unwrap_or_else(|_| panic!("{arg} needs a number, got {value:?}"));
+    }
+    options
+}
+
+/// Small deterministic generator, so runs are comparable.
+struct Words {
+    state: u64,
+    vocabulary: Vec<&'static str>,
+}
+
+impl Words {
+    fn new(seed: u64) -> Self {
+        Self {
+            state: seed,
+            vocabulary: VOCABULARY.split_whitespace().collect(),
+        }
+    }
+
+    fn next(&mut self) -> &'static str {
+        self.state = self
+            .state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        // skew towards the front of the vocabulary, like real term frequencies
+        let len = self.vocabulary.len();
+        let bucket = (self.state >> 33) as usize % len;
+        self.vocabulary[bucket * bucket / len]
+    }
+
+    fn text(&mut self, n: usize) -> String {
+        (0..n).map(|_| self.next()).collect::<Vec<_>>().join(" ")
+    }
+}
+
+fn build_index(dir: &std::path::Path, options: &Options) {
+    let schema = SearchSchema::build();
+    let index = Index::create_in_dir(dir, schema.schema.clone()).expect("index create");
+    let mut writer = index.writer(200_000_000).expect("writer");
+    let mut words = Words::new(1);
+    for i in 0..options.docs {
+        writer
+            .add_document(doc!(
+                schema.url_field => format!("https://bench{}.example/{i}", i % 100),
+                schema.title_field => words.text(5),
+                schema.content_field => words.text(options.words),
+                schema.domain_field => format!("bench{}.example", i % 100),
+                schema.quality_field => "0.5",
+                schema.pagerank_field => (i % 1000) as f64 / 1000.0,
+                schema.tfidf_field => 0.1f64
+            ))
+            .expect("add doc");
+    }
+    writer.commit().expect("commit");
+}
+
+fn frame(request_id: u64, payload: Vec<u8>) -> OwnedFrame {
+    let header = FrameHeader {
+        magic: MAGIC,
+        version: VERSION,
+        msg_type: MessageType::SearchQuery as u8,
+        flags: FrameFlags::empty().bits(),
+        request_id,
+        payload_length: payload.len() as u32,
+    };
+    OwnedFrame { header, payload }
+}
+
+fn main() {
+    let options = options();
+    let dir = tempdir().expect("tempdir");
+
+    let start = Instant::now();
+    build_index(dir.path(), &options);
+    println!(
+        "indexed {} documents of {} words in {:?}",
+        options.docs,
+        options.words,
+        start.elapsed()
+    );
+
+    let engine = crawler::SearchEngine::new(dir.path()).expect("search engine");
+    let context = Context::new(Shards::single(dir.path(), engine));
+    let state = RequestState::new();
+
+    let mut words = Words::new(2);
+    let queries: Vec<Vec<u8>> = (0..options.iterations)
+        .map(|_| {
+            let query = words.text(2);
+            serde_json::to_vec(&serde_json::json!({"query": query, "limit": options.limit}))
+                .expect("query json")
+        })
+        .collect();
+
+    // one pass to open readers and fill the analyzer caches
+    for payload in queries.iter().take(100) {
+        handle_search(frame(0, payload.clone()), &state, &context);
+    }
+
+    let mut latencies = Vec::with_capacity(queries.len());
+    let mut reply_bytes = 0;
+    let start = Instant::now();
+    for (id, payload) in queries.into_iter().enumerate() {
+        let began = Instant::now();
+        let reply = handle_search(frame(id as u64 + 1, payload), &state, &context);
+        latencies.push(began.elapsed());
+        reply_bytes += reply.map_or(0, |reply| reply.len());
+    }
+    let total = start.elapsed();
+
+    latencies.sort();
+    let percentile = |p: usize| latencies[(latencies.len() * p / 100).min(latencies.len() - 1)];
+    println!(
+        "{} searches in {total:?}: {:.0}/s, {} reply bytes",
+        latencies.len(),
+        latencies.len() as f64 / total.as_secs_f64(),
+        reply_bytes
+    );
+    if !latencies.is_empty() {
+        println!(
+            "latency p50 {:?}  p90 {:?}  p99 {:?}  max {:?}",
+            percentile(50),
+            percentile(90),
+            percentile(99),
+            latencies.last().copied().unwrap_or(Duration::ZERO)
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::introspect::{self, open_index, tantivy_error};
+use crate::writer::DocumentWriter;
+
+const META_FILE: &str = "meta.json";
+const SNAPSHOT_ATTEMPTS: usize = 3;
+pub const DEFAULT_MERGE_SEGMENTS: usize = 1;
+
+/// Progress of a running snapshot, reported after each file.
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotProgress {
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotReport {
+    pub target: String,
+    pub opstamp: u64,
+    pub segments: usize,
+    pub files: usize,
+    pub bytes: u64,
+    /// Files hard-linked rather than copied.
+    pub linked: usize,
+}
+
+/// Writes a consistent copy of the committed index at `index_path` into
+/// `target`, which must not exist or be empty.
+///
+/// Only files referenced by the committed `meta.json` are taken, hard-linked
+/// where the filesystem allows (segment files are immutable) and copied
+/// otherwise; `meta.json` itself is written last, so a partial snapshot is
+/// never openable. If a concurrent commit garbage-collects a segment midway,
+/// the snapshot restarts from the new commit.
+pub fn snapshot(
+    index_path: &Path,
+    target: &Path,
+    mut progress: impl FnMut(&SnapshotProgress),
+) -> io::Result<SnapshotReport> {
+    prepare_target(target)?;
+
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match snapshot_once(index_path, target, &mut progress) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound && attempt < SNAPSHOT_ATTEMPTS => {
+                warn!(error = %e, attempt, "index changed during snapshot, retrying");
+                clear_dir(target)?;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn snapshot_once(
+    index_path: &Path,
+    target: &Path,
+    progress: &mut impl FnMut(&SnapshotProgress),
+) -> io::Result<SnapshotReport> {
+    let index = open_index(index_path)?;
+    // pin a commit: meta.json must be unchanged across loading the metas
+    let (meta_bytes, metas) = loop {
+        let before = fs::read(index_path.join(META_FILE))?;
+        let metas = index.load_metas().map_err(tantivy_error)?;
+        let after = fs::read(index_path.join(META_FILE))?;
+        if before == after {
+            break (after, metas);
+        }
+    };
+
+    let files: BTreeSet<PathBuf> = metas
+        .segments
+        .iter()
+        .flat_map(|segment| segment.list_files())
+        .filter(|file| index_path.join(file).exists())
+        .collect();
+
+    let mut state = SnapshotProgress {
+        files_done: 0,
+        files_total: files.len() + 1,
+        bytes_done: 0,
+    };
+    let mut linked = 0;
+    for file in &files {
+        let from = index_path.join(file);
+        let to = target.join(file);
+        if fs::hard_link(&from, &to).is_ok() {
+            linked += 1;
+        } else {
+            fs::copy(&from, &to)?;
+        }
+        state.files_done += 1;
+        state.bytes_done += fs::metadata(&to)?.len();
+        progress(&state);
+    }
+
+    fs::write(target.join(META_FILE), &meta_bytes)?;
+    state.files_done += 1;
+    state.bytes_done += meta_bytes.len() as u64;
+    progress(&state);
+
+    let report = SnapshotReport {
+        target: target.display().to_string(),
+        opstamp: metas.opstamp,
+        segments: metas.segments.len(),
+        files: state.files_done,
+        bytes: state.bytes_done,
+        linked,
+    };
+    info!(
+        target = %report.target,
+        opstamp = report.opstamp,
+        files = report.files,
+        bytes = report.bytes,
+        "index snapshot written"
+    );
+    Ok(report)
+}
+
+fn prepare_target(target: &Path) -> io::Result<()> {
+    if target.exists() {
+        if fs::read_dir(target)?.next().is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("snapshot target {} is not empty", target.display()),
+            ));
+        }
+        return Ok(());
+    }
+    fs::create_dir_all(target)
+}
+
+fn clear_dir(dir: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        fs::remove_file(entry?.path())?;
+    }
+    Ok(())
+}
+
+/// Segment count and size on disk of an index.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct SegmentSummary {
+    pub segments: usize,
+    pub size_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MergeReport {
+    pub max_segments: usize,
+    pub before: SegmentSummary,
+    pub after: SegmentSummary,
+}
+
+/// Merges the committed segments of the index written by `writer` (rooted at
+/// `index_path`) down to at most `max_segments`.
+///
+/// Blocks until the merge is done and the merged-away files are deleted, so
+/// `after` reflects the space actually reclaimed.
+pub fn merge(
+    writer: &DocumentWriter,
+    index_path: &Path,
+    max_segments: usize,
+) -> io::Result<MergeReport> {
+    let before = segment_summary(index_path)?;
+    writer.merge_segments(max_segments)?;
+    let after = segment_summary(index_path)?;
+    info!(
+        before = before.segments,
+        after = after.segments,
+        reclaimed = before.size_bytes.saturating_sub(after.size_bytes),
+        "segments merged"
+    );
+    Ok(MergeReport {
+        max_segments,
+        before,
+        after,
+    })
+}
+
+fn segment_summary(index_path: &Path) -> io::Result<SegmentSummary> {
+    let stats = introspect::shard_stats(index_path)?;
+    Ok(SegmentSummary {
+        segments: stats.segments,
+        size_bytes: stats.size_bytes,
+    })
+}
@@ -0,0 +1,33 @@
+use std::io;
+
+/// Restricts the calling thread to the given CPU cores.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cpus: &[usize]) -> io::Result<()> {
+    // SAFETY: cpu_set_t is plain data, and every index was checked against
+    // CPU_SETSIZE by the config
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &cpu in cpus {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_cpus: &[usize]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "CPU pinning is only supported on Linux",
+    ))
+}
+
+/// Largest CPU index that can be pinned to.
+#[cfg(target_os = "linux")]
+pub const MAX_CPU: usize = libc::CPU_SETSIZE as usize - 1;
+
+#[cfg(not(target_os = "linux"))]
+pub const MAX_CPU: usize = 0;
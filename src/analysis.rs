@@ -0,0 +1,503 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+
+use serde::{Deserialize, Serialize};
+use tantivy::schema::{FieldType, Schema};
+use tantivy::tokenizer::{
+    AsciiFoldingFilter, Language as StemmerLanguage, LowerCaser, NgramTokenizer, RawTokenizer,
+    RemoveLongFilter, SimpleTokenizer, Stemmer, StopWordFilter, TextAnalyzer, TokenStream,
+    WhitespaceTokenizer,
+};
+use tracing::warn;
+
+/// Tokens longer than this are dropped, as tantivy's default analyzer does.
+const MAX_TOKEN_LEN: usize = 40;
+
+/// Languages the query analyzer knows, as ISO 639-1 codes on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    Ar,
+    Da,
+    De,
+    El,
+    En,
+    Es,
+    Fi,
+    Fr,
+    Hu,
+    It,
+    Ja,
+    Ko,
+    Nl,
+    No,
+    Pt,
+    Ro,
+    Ru,
+    Sv,
+    Ta,
+    Tr,
+    Zh,
+}
+
+impl Language {
+    /// Languages written without spaces between words.
+    pub fn is_cjk(self) -> bool {
+        matches!(self, Language::Ja | Language::Ko | Language::Zh)
+    }
+
+    /// The snowball stemmer for the language, if tantivy ships one.
+    pub fn stemmer(self) -> Option<StemmerLanguage> {
+        Some(match self {
+            Language::Ar => StemmerLanguage::Arabic,
+            Language::Da => StemmerLanguage::Danish,
+            Language::De => StemmerLanguage::German,
+            Language::El => StemmerLanguage::Greek,
+            Language::En => StemmerLanguage::English,
+            Language::Es => StemmerLanguage::Spanish,
+            Language::Fi => StemmerLanguage::Finnish,
+            Language::Fr => StemmerLanguage::French,
+            Language::Hu => StemmerLanguage::Hungarian,
+            Language::It => StemmerLanguage::Italian,
+            Language::Nl => StemmerLanguage::Dutch,
+            Language::No => StemmerLanguage::Norwegian,
+            Language::Pt => StemmerLanguage::Portuguese,
+            Language::Ro => StemmerLanguage::Romanian,
+            Language::Ru => StemmerLanguage::Russian,
+            Language::Sv => StemmerLanguage::Swedish,
+            Language::Ta => StemmerLanguage::Tamil,
+            Language::Tr => StemmerLanguage::Turkish,
+            Language::Ja | Language::Ko | Language::Zh => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenizerKind {
+    /// Splits on anything that isn't alphanumeric (tantivy's default).
+    Simple,
+    /// Splits on whitespace only, keeping punctuation inside tokens.
+    Whitespace,
+    /// Overlapping character bigrams, for text without word separators.
+    Bigram,
+}
+
+/// How query text in one language is turned into terms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct AnalyzerConfig {
+    pub tokenizer: TokenizerKind,
+    #[serde(default = "default_true")]
+    pub lowercase: bool,
+    /// Fold accented Latin characters to ASCII (`café` -> `cafe`).
+    #[serde(default)]
+    pub ascii_folding: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl AnalyzerConfig {
+    /// The analyzer used for `language` unless the config overrides it.
+    pub fn for_language(language: Language) -> Self {
+        Self {
+            tokenizer: if language.is_cjk() {
+                TokenizerKind::Bigram
+            } else {
+                TokenizerKind::Simple
+            },
+            lowercase: true,
+            ascii_folding: false,
+        }
+    }
+
+    /// Builds the analyzer, optionally ending in a stemmer.
+    pub fn build(&self, stemmer: Option<StemmerLanguage>) -> TextAnalyzer {
+        let builder = match self.tokenizer {
+            TokenizerKind::Simple => TextAnalyzer::builder(SimpleTokenizer::default()).dynamic(),
+            TokenizerKind::Whitespace => {
+                TextAnalyzer::builder(WhitespaceTokenizer::default()).dynamic()
+            }
+            TokenizerKind::Bigram => TextAnalyzer::builder(
+                NgramTokenizer::all_ngrams(2, 2).expect("2..=2 is a valid ngram range"),
+            )
+            .dynamic(),
+        };
+        let mut builder = builder.filter_dynamic(RemoveLongFilter::limit(MAX_TOKEN_LEN));
+        if self.lowercase {
+            builder = builder.filter_dynamic(LowerCaser);
+        }
+        if self.ascii_folding {
+            builder = builder.filter_dynamic(AsciiFoldingFilter);
+        }
+        if let Some(language) = stemmer {
+            builder = builder.filter_dynamic(Stemmer::new(language));
+        }
+        builder.build()
+    }
+}
+
+/// The `[analysis]` config section.
+///
+/// Queries are analyzed only when a language is known: from the request's
+/// `language` hint, else by detection when `detect` is on, else
+/// `default_language`. Without any of these the query reaches the engine
+/// untouched, as before.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AnalysisConfig {
+    pub default_language: Option<Language>,
+    pub detect: bool,
+    /// Per-language overrides of [`AnalyzerConfig::for_language`].
+    pub languages: HashMap<Language, AnalyzerConfig>,
+    pub stemming: StemmingConfig,
+    /// How individual fields were analyzed at index time.
+    pub fields: BTreeMap<String, FieldAnalyzer>,
+}
+
+/// Index-time analysis of one text field, replayed on query terms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct FieldAnalyzer {
+    pub lowercase: bool,
+    /// Drops the stopwords of the stemmer's (else the query's) language.
+    pub stopwords: bool,
+    pub stemmer: Option<Language>,
+}
+
+impl Default for FieldAnalyzer {
+    fn default() -> Self {
+        Self {
+            lowercase: true,
+            stopwords: false,
+            stemmer: None,
+        }
+    }
+}
+
+impl FieldAnalyzer {
+    /// The settings implied by tantivy's built-in tokenizers.
+    fn builtin(tokenizer: &str) -> Option<Self> {
+        let plain = |lowercase| Self {
+            lowercase,
+            stopwords: false,
+            stemmer: None,
+        };
+        match tokenizer {
+            "default" => Some(plain(true)),
+            "raw" | "whitespace" => Some(plain(false)),
+            "en_stem" => Some(Self {
+                stemmer: Some(Language::En),
+                ..plain(true)
+            }),
+            _ => None,
+        }
+    }
+
+    /// Runs one query token through the field's filters; `None` if it is
+    /// dropped as a stopword.
+    fn apply(&self, token: &str, language: Option<Language>) -> Option<String> {
+        let mut builder = TextAnalyzer::builder(RawTokenizer::default()).dynamic();
+        if self.lowercase {
+            builder = builder.filter_dynamic(LowerCaser);
+        }
+        let stopwords = self
+            .stopwords
+            .then(|| self.stemmer.or(language)?.stemmer())
+            .flatten()
+            .and_then(StopWordFilter::new);
+        if let Some(filter) = stopwords {
+            builder = builder.filter_dynamic(filter);
+        }
+        if let Some(stemmer) = self.stemmer.and_then(Language::stemmer) {
+            builder = builder.filter_dynamic(Stemmer::new(stemmer));
+        }
+        let mut analyzer = builder.build();
+        let mut stream = analyzer.token_stream(token);
+        stream.advance().then(|| stream.token().text.clone())
+    }
+}
+
+/// Checks `[analysis.fields]` against the index schema, so a config that
+/// disagrees with how the index was built fails at startup instead of
+/// silently missing documents.
+///
+/// Fields using a custom tokenizer can't be inspected and are trusted.
+pub fn validate_fields(config: &AnalysisConfig, schema: &Schema) -> io::Result<()> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    for (name, analyzer) in &config.fields {
+        let field = schema.get_field(name).map_err(|_| {
+            invalid(format!(
+                "analysis.fields.{name}: no such field in the index"
+            ))
+        })?;
+        let FieldType::Str(options) = schema.get_field_entry(field).field_type() else {
+            return Err(invalid(format!("analysis.fields.{name}: not a text field")));
+        };
+        let Some(indexing) = options.get_indexing_options() else {
+            return Err(invalid(format!(
+                "analysis.fields.{name}: field is not indexed"
+            )));
+        };
+        let tokenizer = indexing.tokenizer();
+        match FieldAnalyzer::builtin(tokenizer) {
+            Some(built) if built != *analyzer => {
+                return Err(invalid(format!(
+                    "analysis.fields.{name}: configured {analyzer:?} but the index was built \
+                     with tokenizer `{tokenizer}` ({built:?})"
+                )));
+            }
+            Some(_) => {}
+            None => {
+                warn!(field = %name, tokenizer, "custom tokenizer, analyzer settings not verified")
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Query-time stemming, mirroring how the index was built.
+///
+/// With `fields` empty every query term is stemmed. Otherwise only those
+/// fields were indexed stemmed and are queried by stem, as if listed in
+/// `[analysis.fields]` with this stemmer.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct StemmingConfig {
+    pub enabled: bool,
+    /// Stemmer language; defaults to the query's language.
+    pub language: Option<Language>,
+    pub fields: Vec<String>,
+}
+
+/// Query analyzers built from [`AnalysisConfig`].
+#[derive(Debug, Clone, Default)]
+pub struct Analyzers {
+    config: AnalysisConfig,
+}
+
+impl Analyzers {
+    pub fn new(config: AnalysisConfig) -> Self {
+        Self { config }
+    }
+
+    /// The language a query is analyzed as, if any.
+    pub fn language_for(&self, hint: Option<Language>, text: &str) -> Option<Language> {
+        hint.or_else(|| self.config.detect.then(|| detect(text)).flatten())
+            .or(self.config.default_language)
+    }
+
+    pub fn analyzer_config(&self, language: Language) -> AnalyzerConfig {
+        self.config
+            .languages
+            .get(&language)
+            .copied()
+            .unwrap_or_else(|| AnalyzerConfig::for_language(language))
+    }
+
+    /// Terms of `text` as analyzed for `language`.
+    pub fn analyze(&self, language: Language, text: &str) -> Vec<String> {
+        tokens(self.analyzer_config(language), text, None)
+    }
+
+    /// Rewrites query text into space-separated analyzed terms, so the
+    /// engine's default tokenizer sees exactly the language's terms.
+    ///
+    /// With per-field analysis configured, each term becomes
+    /// `(term field:"analyzed" ...)`: those fields match on their own
+    /// index-time form while the engine's other fields still match the term.
+    /// `stem` overrides the configured stemming for this query (`false` for
+    /// exact matching). Returns the text unchanged when nothing applies.
+    pub fn rewrite_query(&self, hint: Option<Language>, stem: Option<bool>, text: &str) -> String {
+        let language = self.language_for(hint, text);
+        let stemming = &self.config.stemming;
+        let stemmer = stem
+            .unwrap_or(stemming.enabled)
+            .then(|| stemming.language.or(language))
+            .flatten();
+        let fields = self.field_analyzers(stem != Some(false), stemmer);
+        if language.is_none() && stemmer.is_none() && fields.is_empty() {
+            return text.to_string();
+        }
+
+        let base = match language.or(stemmer) {
+            Some(language) => self.analyzer_config(language),
+            None => AnalyzerConfig::for_language(Language::En),
+        };
+        // unqualified terms are stemmed unless stemming is limited to fields
+        let whole_query_stemmer = stemmer
+            .and_then(Language::stemmer)
+            .filter(|_| stemming.fields.is_empty());
+        let terms = tokens(base, text, whole_query_stemmer);
+        if fields.is_empty() {
+            return terms.join(" ");
+        }
+
+        // filters never add or drop tokens here, so the lists line up
+        let raw = tokens(
+            AnalyzerConfig {
+                lowercase: false,
+                ..base
+            },
+            text,
+            None,
+        );
+        terms
+            .iter()
+            .zip(&raw)
+            .map(|(term, raw)| {
+                let clauses: Vec<String> = fields
+                    .iter()
+                    .filter_map(|(field, analyzer)| {
+                        let analyzed = analyzer.apply(raw, language)?;
+                        Some(format!("{field}:\"{analyzed}\""))
+                    })
+                    .collect();
+                if clauses.is_empty() {
+                    term.clone()
+                } else {
+                    format!("({term} {})", clauses.join(" "))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Per-field analysis for one query: `[analysis.fields]` plus the
+    /// stemming fields. Field stemmers reflect the index and stay on unless
+    /// the request opted out.
+    fn field_analyzers(&self, stem: bool, stemmer: Option<Language>) -> Vec<(&str, FieldAnalyzer)> {
+        let mut fields: Vec<(&str, FieldAnalyzer)> = self
+            .config
+            .fields
+            .iter()
+            .map(|(name, analyzer)| {
+                let stemmer = if stem { analyzer.stemmer } else { None };
+                (
+                    name.as_str(),
+                    FieldAnalyzer {
+                        stemmer,
+                        ..*analyzer
+                    },
+                )
+            })
+            .collect();
+        if let Some(stemmer) = stemmer {
+            for name in &self.config.stemming.fields {
+                if !self.config.fields.contains_key(name) {
+                    fields.push((
+                        name,
+                        FieldAnalyzer {
+                            stemmer: Some(stemmer),
+                            ..FieldAnalyzer::default()
+                        },
+                    ));
+                }
+            }
+        }
+        fields
+    }
+}
+
+fn tokens(config: AnalyzerConfig, text: &str, stemmer: Option<StemmerLanguage>) -> Vec<String> {
+    let mut analyzer = config.build(stemmer);
+    let mut stream = analyzer.token_stream(text);
+    let mut terms = Vec::new();
+    while stream.advance() {
+        terms.push(stream.token().text.clone());
+    }
+    terms
+}
+
+const STOPWORDS: &[(Language, &[&str])] = &[
+    (
+        Language::En,
+        &[
+            "the", "and", "of", "to", "is", "in", "for", "with", "what", "how",
+        ],
+    ),
+    (
+        Language::De,
+        &[
+            "der", "die", "das", "und", "ist", "nicht", "mit", "ein", "eine", "wie",
+        ],
+    ),
+    (
+        Language::Fr,
+        &[
+            "le", "la", "les", "et", "est", "des", "une", "pour", "dans", "comment",
+        ],
+    ),
+    (
+        Language::Es,
+        &[
+            "el", "los", "las", "y", "es", "una", "para", "con", "por", "como",
+        ],
+    ),
+    (
+        Language::It,
+        &[
+            "il", "gli", "e", "è", "della", "una", "per", "con", "che", "come",
+        ],
+    ),
+    (
+        Language::Pt,
+        &[
+            "o", "os", "e", "é", "uma", "para", "com", "não", "do", "como",
+        ],
+    ),
+    (
+        Language::Nl,
+        &[
+            "de", "het", "een", "en", "is", "niet", "met", "voor", "van", "hoe",
+        ],
+    ),
+];
+
+/// Guesses the language of short query text.
+///
+/// Non-Latin scripts decide outright; Latin text is attributed by stopword
+/// hits (ties go to the language listed first) and stays undetected when
+/// there are none.
+pub fn detect(text: &str) -> Option<Language> {
+    let mut kana = 0;
+    let mut han = 0;
+    for c in text.chars() {
+        match c {
+            '\u{3040}'..='\u{30ff}' => kana += 1,
+            '\u{4e00}'..='\u{9fff}' | '\u{3400}'..='\u{4dbf}' => han += 1,
+            '\u{ac00}'..='\u{d7af}' | '\u{1100}'..='\u{11ff}' => return Some(Language::Ko),
+            '\u{0400}'..='\u{04ff}' => return Some(Language::Ru),
+            '\u{0370}'..='\u{03ff}' => return Some(Language::El),
+            '\u{0600}'..='\u{06ff}' => return Some(Language::Ar),
+            '\u{0b80}'..='\u{0bff}' => return Some(Language::Ta),
+            _ => {}
+        }
+    }
+    if kana > 0 {
+        return Some(Language::Ja);
+    }
+    if han > 0 {
+        return Some(Language::Zh);
+    }
+
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    STOPWORDS
+        .iter()
+        .rev()
+        .map(|(language, stopwords)| {
+            let hits = words
+                .iter()
+                .filter(|w| stopwords.contains(&w.as_str()))
+                .count();
+            (*language, hits)
+        })
+        .filter(|&(_, hits)| hits > 0)
+        .max_by_key(|&(_, hits)| hits)
+        .map(|(language, _)| language)
+}
@@ -0,0 +1,407 @@
+use std::io::{self, IoSlice};
+use std::net::Shutdown;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use nerve_protocol::{MessageType, RequestId};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{Semaphore, mpsc};
+use tracing::{debug, info, warn};
+
+use crate::client::{self, MAX_WRITE_SLICES, Outbox};
+use crate::config::Config;
+use crate::connections::Routes;
+use crate::context::Context;
+use crate::control::ControlSocket;
+use crate::framing::FrameDecoder;
+use crate::handler;
+use crate::handshake::{self, Capabilities};
+use crate::health::HealthServer;
+use crate::heartbeat::{self, Heartbeat};
+use crate::peercred;
+use crate::reconnect::{self, Backoff};
+use crate::shutdown;
+use crate::transport::{Endpoint, HALF_CLOSE_LINGER, Stream};
+use crate::warm;
+use crate::watchdog;
+
+/// Async counterpart of [`client::run`](crate::client::run) (`tokio`
+/// feature).
+///
+/// Socket I/O runs on the tokio runtime and every query on its blocking
+/// pool via `spawn_blocking`, so in-flight requests cost a task rather than
+/// a dedicated thread. Replies are written by one task per connection in
+/// completion order; the connections share the in-flight limit. SIGTERM
+/// drains the connections as it does for the threaded client.
+pub async fn run(config: &Config) -> io::Result<()> {
+    config.validate()?;
+    if config.uses_tls() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "tls:// cores need the threaded client",
+        ));
+    }
+    let context = Arc::new(Context::from_config(config)?);
+    info!(
+        index = %config.index_path.display(),
+        shards = context.shards.len(),
+        peers = config.peers.len(),
+        connections = config.connections.count,
+        "search index opened"
+    );
+
+    let sockets = config.socket_paths();
+    let per_core = config.connections.count;
+    let shared = Shared {
+        config: Arc::new(config.clone()),
+        context: Arc::clone(&context),
+        routes: Arc::new(Routes::new(
+            config.connections.balance,
+            sockets.len(),
+            per_core,
+        )),
+        // queries in flight at once, across connections; past this they
+        // are turned away as overloaded
+        slots: Arc::new(Semaphore::new(config.queue_depth)),
+    };
+    let health = HealthServer::bind(&config.health, config.queue_depth)?;
+    let control = ControlSocket::bind(&config.control)?;
+    // the probes, the watchdog, the warmer and the control socket run until
+    // the connections are done
+    let stopped = Arc::new(AtomicBool::new(false));
+    let probes = health.map(|health| {
+        let (context, stopped) = (Arc::clone(&context), Arc::clone(&stopped));
+        tokio::task::spawn_blocking(move || health.serve(&context, &stopped))
+    });
+    let commands = control.map(|control| {
+        let (context, stopped) = (Arc::clone(&context), Arc::clone(&stopped));
+        tokio::task::spawn_blocking(move || control.serve(&context, &stopped))
+    });
+    let watchdog = {
+        let (context, stopped) = (Arc::clone(&context), Arc::clone(&stopped));
+        let config = config.watchdog;
+        tokio::task::spawn_blocking(move || watchdog::run(config, &context, &stopped))
+    };
+    let warmer = {
+        let (context, stopped) = (Arc::clone(&context), Arc::clone(&stopped));
+        tokio::task::spawn_blocking(move || warm::run(&context, &stopped))
+    };
+    let signals = shutdown::listen(&context.shutdown)?;
+    let connections: Vec<_> = (0..sockets.len() * per_core)
+        .map(|slot| {
+            let socket = sockets[slot / per_core].clone();
+            tokio::spawn(connection(slot, socket, shared.clone()))
+        })
+        .collect();
+    let mut served = Ok(());
+    for connection in connections {
+        let ended = connection
+            .await
+            .unwrap_or_else(|e| Err(io::Error::other(format!("connection task failed: {e}"))));
+        if served.is_ok() {
+            served = ended;
+        }
+    }
+    stopped.store(true, Ordering::Relaxed);
+    if let Some(probes) = probes {
+        let _ = probes.await;
+    }
+    let _ = watchdog.await;
+    let _ = warmer.await;
+    if let Some(commands) = commands {
+        let _ = commands.await;
+    }
+    drop(signals);
+    warm::save(&context);
+    context.profiler.save();
+    served
+}
+
+/// What the connections' tasks share.
+#[derive(Clone)]
+struct Shared {
+    config: Arc<Config>,
+    context: Arc<Context>,
+    routes: Arc<Routes<Replies>>,
+    slots: Arc<Semaphore>,
+}
+
+type Replies = mpsc::UnboundedSender<Bytes>;
+
+/// Keeps connection `slot` to the core at `socket`, reconnecting as
+/// `[reconnect]` says.
+async fn connection(slot: usize, socket: PathBuf, shared: Shared) -> io::Result<()> {
+    let config = &shared.config;
+    let endpoint = Endpoint::parse(&socket);
+    let health = shared.context.connections.slot(slot);
+    let shutdown = &shared.context.shutdown;
+    let mut backoff = Backoff::new(config.reconnect);
+    loop {
+        let stream = match endpoint.connect_async().await {
+            Ok(stream) => stream,
+            Err(e) => match backoff.next_delay() {
+                Some(delay) => {
+                    warn!(error = %e, retry_in_ms = delay.as_millis() as u64, "core not reachable");
+                    tokio::time::sleep(delay).await;
+                    if shutdown.is_started() {
+                        return Ok(());
+                    }
+                    continue;
+                }
+                None => return Err(e),
+            },
+        };
+        backoff.reset();
+        info!(connection = slot, socket = %endpoint, "connected to NERVE-CORE");
+        health.up();
+        let served = session(slot, &shared, stream).await;
+        health.down(&served);
+        if reconnect::is_refused(&served) || shutdown.is_started() {
+            return served;
+        }
+        let Some(delay) = backoff.next_delay() else {
+            return served;
+        };
+        reconnect::log_lost(&served, delay);
+        tokio::time::sleep(delay).await;
+        if shutdown.is_started() {
+            return served;
+        }
+    }
+}
+
+/// Serves one connection until it drops; see [`run`].
+async fn session(slot: usize, shared: &Shared, stream: Stream) -> io::Result<()> {
+    let Shared {
+        config,
+        context,
+        routes,
+        slots,
+    } = shared;
+    peercred::verify(&stream, &config.peer_credentials)?;
+    let write_timeout = config.write_timeout_ms.map(Duration::from_millis);
+    // the handshake is a short blocking exchange, kept off the runtime
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(write_timeout)?;
+    stream.set_buffer_sizes(config.send_buffer_bytes, config.receive_buffer_bytes)?;
+    let decoder = FrameDecoder::new(config.max_payload_bytes).with_packets(stream.keeps_packets());
+    let (handshake_config, offered) = (config.handshake, Capabilities::offered(context));
+    let secret = context.auth.clone();
+    let (stream, mut decoder, handshake) = tokio::task::spawn_blocking(move || {
+        let (mut stream, mut decoder) = (stream, decoder);
+        let handshake = handshake::exchange(
+            &mut stream,
+            handshake_config,
+            offered,
+            secret.as_ref(),
+            &mut decoder,
+        )?;
+        Ok::<_, io::Error>((stream, decoder, handshake))
+    })
+    .await
+    .map_err(io::Error::other)??;
+    let health = context.connections.slot(slot);
+    let state = client::connection_state(context, health, &handshake);
+    let _watch = context.shutdown.watch(&stream, &state)?;
+    let _orphans = health.track(Arc::clone(&state));
+    stream.set_nonblocking(true)?;
+    // a writer that gives up hangs up, so the reader stops too
+    let hang_up = stream.socket()?;
+    let write_slices = if stream.keeps_packets() { 1 } else { MAX_WRITE_SLICES };
+    let (mut socket, mut replies_out) = stream.into_tokio()?;
+
+    let (replies, mut pending) = mpsc::unbounded_channel::<Bytes>();
+    let route = routes.open(slot, replies.clone());
+    client::abort_orphans(health, |error| {
+        let _ = replies.send(error);
+    });
+    let mut outbox = Outbox::for_connection(&state);
+    let expiring = Arc::clone(&state);
+    let writer = tokio::spawn(async move {
+        let written = async {
+            while let Some(reply) = pending.recv().await {
+                // replies already waiting share the write
+                outbox.push(reply);
+                while let Ok(reply) = pending.try_recv() {
+                    outbox.push(reply);
+                }
+                while !outbox.is_empty() {
+                    if let Some(limit) = write_timeout {
+                        for id in outbox.expire(limit)? {
+                            expiring.cancel(id);
+                        }
+                    }
+                    let mut slices = [IoSlice::new(&[]); MAX_WRITE_SLICES];
+                    // a packet transport takes one frame per write
+                    let filled = outbox.slices(&mut slices[..write_slices]);
+                    let writing = replies_out.write_vectored(&slices[..filled]);
+                    let wrote = match write_timeout {
+                        Some(limit) => {
+                            let left = outbox.until_expiry(limit).unwrap_or(limit);
+                            match tokio::time::timeout(left, writing).await {
+                                Ok(written) => written?,
+                                // expiring decides what happens next
+                                Err(_) => continue,
+                            }
+                        }
+                        None => writing.await?,
+                    };
+                    match wrote {
+                        0 => return Err(io::ErrorKind::WriteZero.into()),
+                        n => outbox.advance(n),
+                    }
+                }
+            }
+            // the core reads every reply, then the end of the stream
+            replies_out.shutdown().await
+        }
+        .await;
+        if let Err(e) = &written {
+            warn!(error = %e, "reply write failed, dropping connection");
+            let _ = hang_up.shutdown(Shutdown::Both);
+        }
+        written
+    });
+
+    let mut buf = client::read_buffer(&decoder);
+    let mut heartbeat = Heartbeat::new(config.heartbeat);
+    let mut lost = None;
+    let mut frames = handshake.early;
+    loop {
+        // control frames first, as in the threaded client
+        state.progress().busy();
+        state.traffic().received(frames.len());
+        state.progress().took(&frames);
+        #[cfg(feature = "chaos")]
+        if let Some(faults) = state.faults() {
+            faults.inbound(&mut frames);
+        }
+        let received = Instant::now();
+        if !frames.is_empty() {
+            heartbeat.heard();
+        }
+        if let Some(tape) = state.tape() {
+            frames.iter().for_each(|frame| tape.inbound(frame));
+        }
+        if let Some(mac) = state.frame_mac() {
+            frames.retain_mut(|frame| {
+                client::verified(mac, frame, &mut |error| {
+                    let _ = replies.send(error);
+                })
+            });
+        }
+        let (control, queries): (Vec<_>, Vec<_>) = frames.drain(..).partition(client::is_control);
+        for frame in control.into_iter().chain(queries) {
+            match MessageType::try_from(frame.header.msg_type) {
+                Ok(MessageType::SearchQuery) => {
+                    let request_id = RequestId(frame.header.request_id);
+                    if let Err(wait) = state.admit(&frame.payload) {
+                        debug!(request_id = request_id.0, "rate limited, query rejected");
+                        if let Some(reply) = handler::rate_limited(request_id, wait) {
+                            let _ = replies.send(reply);
+                        }
+                        continue;
+                    }
+                    let Ok(permit) = Arc::clone(slots).try_acquire_owned() else {
+                        warn!(
+                            request_id = request_id.0,
+                            "request queue full, query rejected"
+                        );
+                        if let Some(reply) = handler::overloaded(request_id) {
+                            let _ = replies.send(reply);
+                        }
+                        continue;
+                    };
+                    state.queue(request_id);
+                    let replies = routes.pick(request_id, slot, &replies);
+                    let (state, context) = (Arc::clone(&state), Arc::clone(context));
+                    tokio::task::spawn_blocking(move || {
+                        let _permit = permit;
+                        client::handle_isolated(frame, received, &state, &context, |reply| {
+                            if !state.is_cancelled(request_id) {
+                                let _ = replies.send(reply);
+                            }
+                        });
+                    });
+                }
+                Ok(MessageType::Cancel) => {
+                    state.cancel(RequestId(frame.header.request_id));
+                }
+                Ok(MessageType::Ping) => {
+                    if let Some(pong) = heartbeat::pong(&frame) {
+                        let _ = replies.send(pong);
+                    }
+                }
+                Ok(MessageType::Pong) => heartbeat.pong(),
+                _ => {
+                    // ignore everything else
+                }
+            }
+        }
+
+        match heartbeat.tick() {
+            Ok(Some(ping)) => {
+                let _ = replies.send(ping);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!(error = %e, "core not answering, dropping connection");
+                lost = Some(e);
+                break;
+            }
+        }
+        let reading = socket.read(&mut buf);
+        state.progress().idle();
+        let read = match heartbeat.until_due() {
+            Some(due) => match tokio::time::timeout(due, reading).await {
+                Ok(read) => read,
+                // a ping is due
+                Err(_) => continue,
+            },
+            None => reading.await,
+        };
+        let read = match read {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) => {
+                warn!(error = %e, "socket read failed, exiting");
+                break;
+            }
+        };
+        state.traffic().read(read);
+        let start = context.profiler.is_enabled().then(Instant::now);
+        decoder.decode(&buf[..read], &mut frames, |rejected| {
+            if let Some(reply) = handler::frame_rejected(&rejected) {
+                let _ = replies.send(reply);
+            }
+        });
+        if let Some(start) = start {
+            context.profiler.record_io("decode", start.elapsed());
+        }
+    }
+
+    if let Some(e) = lost {
+        // a core that stopped answering may never drain the socket either
+        writer.abort();
+        return Err(e);
+    }
+    if let Some(notice) = client::going_away(&state) {
+        let _ = replies.send(notice);
+    }
+    // in-flight queries still hold senders; the writer ends after the last
+    drop(route);
+    drop(replies);
+    writer
+        .await
+        .unwrap_or_else(|e| Err(io::Error::other(format!("writer task failed: {e}"))))?;
+    // closing on bytes left unread would reset the connection, and the
+    // reset can throw away replies the core has yet to read
+    let _ = tokio::time::timeout(HALF_CLOSE_LINGER, async {
+        while let Ok(1..) = socket.read(&mut buf).await {}
+    })
+    .await;
+    Ok(())
+}
@@ -0,0 +1,160 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nerve_protocol::types::RequestId;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use tracing::{info, warn};
+
+use crate::introspect::fnv1a;
+use crate::slowlog::Trace;
+
+/// `[audit]` section: an append-only record of every request, one JSON
+/// object per line.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// File requests are appended to; nothing is recorded when unset.
+    pub path: Option<PathBuf>,
+    /// Record a hash of the normalized query instead of its text.
+    pub hash_queries: bool,
+    /// Mixed into every query hash, so hashes can't be matched against a
+    /// list of likely queries without it.
+    pub hash_salt: Option<String>,
+}
+
+impl AuditConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.hash_salt.is_some() && !self.hash_queries {
+            return Err("audit.hash_salt needs audit.hash_queries".into());
+        }
+        Ok(())
+    }
+}
+
+/// How a request ended, as audited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    Error,
+    Cancelled,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Error => "error",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Appends a line per finished request: when it finished (`ts_ms`, Unix
+/// milliseconds), its `request_id` and `op`, for searches the normalized
+/// `query` (or `query_hash`), `filters` and `hits`, its `latency_us` from
+/// arrival and its `outcome`.
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    file: Option<Mutex<File>>,
+    path: PathBuf,
+    hash_queries: bool,
+    hash_salt: String,
+    failed: AtomicBool,
+}
+
+impl AuditLog {
+    pub fn open(config: &AuditConfig) -> io::Result<Self> {
+        let Some(path) = &config.path else {
+            return Ok(Self::default());
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        info!(path = %path.display(), "auditing requests");
+        Ok(Self {
+            file: Some(Mutex::new(file)),
+            path: path.clone(),
+            hash_queries: config.hash_queries,
+            hash_salt: config.hash_salt.clone().unwrap_or_default(),
+            failed: AtomicBool::new(false),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.file.is_some()
+    }
+
+    pub fn record(
+        &self,
+        request_id: RequestId,
+        trace: &Trace,
+        elapsed: Duration,
+        outcome: Outcome,
+    ) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        if trace.op.is_empty() {
+            return;
+        }
+        let mut line = self.entry(request_id, trace, elapsed, outcome).to_string();
+        line.push('\n');
+        // one write per line, so concurrent workers' lines never interleave
+        let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = file.write_all(line.as_bytes())
+            && !self.failed.swap(true, Ordering::Relaxed)
+        {
+            warn!(path = %self.path.display(), error = %e, "audit log write failed");
+        }
+    }
+
+    /// The object [`record`](Self::record) appends for a request.
+    pub fn entry(
+        &self,
+        request_id: RequestId,
+        trace: &Trace,
+        elapsed: Duration,
+        outcome: Outcome,
+    ) -> Value {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut entry = Map::new();
+        entry.insert("ts_ms".into(), json!(at.as_millis() as u64));
+        entry.insert("request_id".into(), json!(request_id.0));
+        entry.insert("op".into(), json!(trace.op));
+        if let Some(query) = &trace.query {
+            let query = normalize(query);
+            if self.hash_queries {
+                let salted = format!("{}{query}", self.hash_salt);
+                let hash = format!("{:016x}", fnv1a(salted.as_bytes()));
+                entry.insert("query_hash".into(), json!(hash));
+            } else {
+                entry.insert("query".into(), json!(query));
+            }
+        }
+        if !trace.filters.is_empty() {
+            let filters = json!({"after": trace.filters.after, "before": trace.filters.before});
+            entry.insert("filters".into(), filters);
+        }
+        if let Some(hits) = trace.hits {
+            entry.insert("hits".into(), json!(hits));
+        }
+        entry.insert("latency_us".into(), json!(elapsed.as_micros() as u64));
+        entry.insert("outcome".into(), json!(outcome.as_str()));
+        Value::Object(entry)
+    }
+}
+
+/// `query` lowercased with its whitespace collapsed, so the same search
+/// typed differently is audited (and hashed) the same.
+pub fn normalize(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
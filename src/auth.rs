@@ -0,0 +1,213 @@
+use std::io;
+
+use bytes::Bytes;
+use nerve_protocol::codec::encode;
+use nerve_protocol::frame::OwnedFrame;
+use nerve_protocol::io::FrameReader;
+use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Deserialize;
+use tracing::warn;
+
+/// Random bytes in each handshake challenge.
+pub const NONCE_BYTES: usize = 32;
+/// Bytes of the MAC ending each frame payload once frames are signed.
+pub const MAC_BYTES: usize = 32;
+// keep a core's proof and the frame keys from doubling as one another
+const CORE_PROOF_LABEL: &[u8] = b"nerve-core:";
+const FRAME_KEY_LABEL: &[u8] = b"nerve-frame-mac:";
+
+/// `[auth]` section: a secret shared with the core, which has to prove it
+/// knows it during the handshake before any of its queries is served.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// The secret itself.
+    pub token: Option<String>,
+    /// Environment variable holding the secret, keeping it out of the
+    /// config file.
+    pub token_env: Option<String>,
+    /// Also sign every frame after the handshake, for transports other
+    /// than a local Unix socket. A core that doesn't take it up is refused.
+    pub frame_mac: bool,
+}
+
+impl AuthConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.token.is_some() || self.token_env.is_some()
+    }
+
+    /// The secret, read from the environment when `token_env` names it;
+    /// `None` when auth is off.
+    pub fn token(&self) -> io::Result<Option<String>> {
+        let token = match (&self.token, &self.token_env) {
+            (Some(_), Some(_)) => {
+                return Err(invalid("set auth token or token_env, not both".into()));
+            }
+            (Some(token), None) => token.clone(),
+            (None, Some(var)) => {
+                std::env::var(var).map_err(|e| invalid(format!("auth token_env {var}: {e}")))?
+            }
+            (None, None) => return Ok(None),
+        };
+        if token.is_empty() {
+            return Err(invalid("auth token is empty".into()));
+        }
+        Ok(Some(token))
+    }
+}
+
+/// The shared secret, keyed for HMAC-SHA256.
+#[derive(Debug, Clone)]
+pub struct Secret {
+    key: hmac::Key,
+    frame_mac: bool,
+}
+
+impl Secret {
+    pub fn new(token: &[u8]) -> Self {
+        Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, token),
+            frame_mac: false,
+        }
+    }
+
+    pub fn from_config(config: &AuthConfig) -> io::Result<Option<Self>> {
+        let secret = config.token()?.map(|token| Self::new(token.as_bytes()));
+        Ok(secret.map(|secret| match config.frame_mac {
+            true => secret.with_frame_mac(),
+            false => secret,
+        }))
+    }
+
+    /// Requires signed frames on every connection.
+    pub fn with_frame_mac(mut self) -> Self {
+        self.frame_mac = true;
+        self
+    }
+
+    pub fn signs_frames(&self) -> bool {
+        self.frame_mac
+    }
+
+    /// The frame signer for the connection whose handshake challenge was
+    /// `nonce`: its key is the HMAC-SHA256 under the secret of
+    /// `nerve-frame-mac:` and the nonce, so no two connections share one.
+    pub fn frame_mac(&self, nonce: &str) -> FrameMac {
+        let key = hmac::sign(&self.key, &[FRAME_KEY_LABEL, nonce.as_bytes()].concat());
+        FrameMac {
+            key: hmac::Key::new(hmac::HMAC_SHA256, key.as_ref()),
+        }
+    }
+
+    /// The core's answer to the challenge `nonce`: HMAC-SHA256 under the
+    /// secret of `nerve-core:` and the nonce, hex-encoded.
+    pub fn prove(&self, nonce: &str) -> String {
+        hex(hmac::sign(&self.key, &labelled(nonce)).as_ref())
+    }
+
+    /// Whether `proof` answers the challenge `nonce`, compared in constant
+    /// time.
+    pub fn verify(&self, nonce: &str, proof: &str) -> bool {
+        unhex(proof).is_some_and(|tag| hmac::verify(&self.key, &labelled(nonce), &tag).is_ok())
+    }
+}
+
+/// Signs and checks the frames of one connection. The MAC covers a
+/// frame's type, flags, request id and payload, and follows the payload as
+/// its last [`MAC_BYTES`] bytes.
+#[derive(Debug, Clone)]
+pub struct FrameMac {
+    key: hmac::Key,
+}
+
+impl FrameMac {
+    /// `encoded`, one whole frame, re-encoded with its MAC appended. A
+    /// frame that can't be re-encoded goes out as it was, for the core to
+    /// turn away.
+    pub fn sign(&self, encoded: Bytes) -> Bytes {
+        let decoded = FrameReader::new().read_from(&mut &encoded[..]);
+        let Ok([frame]) = decoded.as_deref() else {
+            warn!("reply is not one whole frame, sent unsigned");
+            return encoded;
+        };
+        let Ok(msg_type) = MessageType::try_from(frame.header.msg_type) else {
+            warn!(
+                msg_type = frame.header.msg_type,
+                "unknown reply type, sent unsigned"
+            );
+            return encoded;
+        };
+        let tag = hmac::sign(&self.key, &signed_bytes(frame));
+        let payload = [frame.payload.as_slice(), tag.as_ref()].concat();
+        match encode(
+            msg_type,
+            FrameFlags::from_bits_truncate(frame.header.flags),
+            RequestId(frame.header.request_id),
+            &payload,
+        ) {
+            Ok(signed) => Bytes::from(signed),
+            Err(e) => {
+                warn!(error = %e, "signed reply not encoded, sent unsigned");
+                encoded
+            }
+        }
+    }
+
+    /// Checks the MAC ending `frame`'s payload and strips it; false, with
+    /// the frame left as it was, when it is missing or wrong.
+    pub fn verify(&self, frame: &mut OwnedFrame) -> bool {
+        let Some(at) = frame.payload.len().checked_sub(MAC_BYTES) else {
+            return false;
+        };
+        let tag = frame.payload.split_off(at);
+        if hmac::verify(&self.key, &signed_bytes(frame), &tag).is_ok() {
+            frame.header.payload_length = at as u32;
+            return true;
+        }
+        frame.payload.extend(tag);
+        false
+    }
+}
+
+fn signed_bytes(frame: &OwnedFrame) -> Vec<u8> {
+    let header = &frame.header;
+    let mut bytes = Vec::with_capacity(10 + frame.payload.len());
+    bytes.push(header.msg_type);
+    bytes.push(header.flags);
+    bytes.extend(header.request_id.to_be_bytes());
+    bytes.extend(&frame.payload);
+    bytes
+}
+
+fn labelled(nonce: &str) -> Vec<u8> {
+    [CORE_PROOF_LABEL, nonce.as_bytes()].concat()
+}
+
+/// A fresh random challenge, hex-encoded.
+pub fn nonce() -> io::Result<String> {
+    let mut bytes = [0u8; NONCE_BYTES];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| io::Error::other("no randomness for the auth challenge"))?;
+    Ok(hex(&bytes))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(text.get(at..at + 2)?, 16).ok())
+        .collect()
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
@@ -0,0 +1,225 @@
+use std::io;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use tracing::{error, info, warn};
+
+pub const DEFAULT_COOLDOWN_MS: u64 = 30_000;
+
+// what a query turned away while the probe runs is told to wait
+const PROBE_RETRY: Duration = Duration::from_secs(1);
+
+/// `[breaker]` section: fast-failing searches while the engine keeps
+/// failing, as with a corrupted index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct BreakerConfig {
+    /// Engine searches failing or timing out in a row that open the
+    /// circuit; it never opens when unset.
+    pub failure_threshold: Option<u32>,
+    /// How long an open circuit fast-fails searches before letting one
+    /// through to probe the engine.
+    pub cooldown_ms: u64,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: None,
+            cooldown_ms: DEFAULT_COOLDOWN_MS,
+        }
+    }
+}
+
+impl BreakerConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.failure_threshold == Some(0) {
+            return Err("breaker.failure_threshold must be at least 1".into());
+        }
+        if self.cooldown_ms == 0 {
+            return Err("breaker.cooldown_ms must be at least 1".into());
+        }
+        Ok(())
+    }
+}
+
+/// Whether searches reach the engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Circuit {
+    /// They do; counting the failures in a row.
+    Closed { failures: u32 },
+    /// They are fast-failed until `until`.
+    Open { until: Instant },
+    /// One search at a time probes the engine, `probing` while it runs.
+    HalfOpen { probing: bool },
+}
+
+impl Circuit {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Closed { .. } => "closed",
+            Self::Open { .. } => "open",
+            Self::HalfOpen { .. } => "half_open",
+        }
+    }
+}
+
+/// Opens after `failure_threshold` engine failures or timeouts in a row,
+/// turning searches away for `cooldown_ms` rather than letting each burn
+/// its deadline. Then a single search probes the engine: the circuit
+/// closes if it succeeds and opens for another cooldown if it fails.
+/// Searches cancelled, over their memory budget or rejected as bad requests
+/// say nothing of the engine and don't count either way, so one client's
+/// bad queries can't open the circuit for everyone.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: Option<u32>,
+    cooldown: Duration,
+    circuit: Mutex<Circuit>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(&BreakerConfig::default())
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(config: &BreakerConfig) -> Self {
+        Self {
+            threshold: config.failure_threshold,
+            cooldown: Duration::from_millis(config.cooldown_ms),
+            circuit: Mutex::new(Circuit::Closed { failures: 0 }),
+        }
+    }
+
+    pub fn circuit(&self) -> Circuit {
+        *self.lock()
+    }
+
+    /// Lets a search through to the engine, or says how long until one may
+    /// try again. The search's outcome goes back through
+    /// [`Ticket::record`].
+    pub fn admit(&self) -> Result<Ticket<'_>, Duration> {
+        let mut circuit = self.lock();
+        let probe = match *circuit {
+            Circuit::Closed { .. } => false,
+            Circuit::Open { until } => {
+                let now = Instant::now();
+                if now < until {
+                    return Err(until - now);
+                }
+                *circuit = Circuit::HalfOpen { probing: true };
+                true
+            }
+            Circuit::HalfOpen { probing: true } => return Err(PROBE_RETRY),
+            Circuit::HalfOpen { probing: false } => {
+                *circuit = Circuit::HalfOpen { probing: true };
+                true
+            }
+        };
+        if probe {
+            info!("probing the search engine");
+        }
+        Ok(Ticket {
+            breaker: self,
+            probe,
+        })
+    }
+
+    fn succeeded(&self, probe: bool) {
+        let mut circuit = self.lock();
+        if probe {
+            info!("search engine probe succeeded, circuit closed");
+        }
+        if probe || matches!(*circuit, Circuit::Closed { .. }) {
+            *circuit = Circuit::Closed { failures: 0 };
+        }
+    }
+
+    fn failed(&self, probe: bool, error: &io::Error) {
+        let Some(threshold) = self.threshold else {
+            return;
+        };
+        let mut circuit = self.lock();
+        let until = Instant::now() + self.cooldown;
+        match *circuit {
+            _ if probe => {
+                warn!(
+                    error = %error,
+                    cooldown_ms = self.cooldown.as_millis() as u64,
+                    "search engine probe failed, circuit open again"
+                );
+                *circuit = Circuit::Open { until };
+            }
+            Circuit::Closed { failures } if failures + 1 >= threshold => {
+                error!(
+                    failures = failures + 1,
+                    error = %error,
+                    cooldown_ms = self.cooldown.as_millis() as u64,
+                    "search engine failing, circuit open"
+                );
+                *circuit = Circuit::Open { until };
+            }
+            Circuit::Closed { failures } => {
+                *circuit = Circuit::Closed {
+                    failures: failures + 1,
+                };
+            }
+            // admitted before the circuit opened
+            _ => {}
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Circuit> {
+        self.circuit.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A search let through by [`CircuitBreaker::admit`]. A probe dropped
+/// without a verdict, as when its handler panics, lets the next search
+/// probe instead.
+#[must_use]
+pub struct Ticket<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: bool,
+}
+
+impl Ticket<'_> {
+    /// Counts the engine's answer: a success closes the circuit, an engine
+    /// failure or timeout counts towards opening it, and a search that was
+    /// cancelled, over budget or a bad request leaves it as it is. A timeout
+    /// the client set itself is no verdict either: drop the ticket instead.
+    pub fn record<T>(mut self, searched: &io::Result<T>) {
+        let probe = std::mem::take(&mut self.probe);
+        match searched {
+            Ok(_) => self.breaker.succeeded(probe),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::Interrupted
+                        | io::ErrorKind::OutOfMemory
+                        | io::ErrorKind::InvalidInput
+                        | io::ErrorKind::InvalidData
+                        | io::ErrorKind::Unsupported
+                ) =>
+            {
+                self.release(probe)
+            }
+            Err(e) => self.breaker.failed(probe, e),
+        }
+    }
+
+    fn release(&self, probe: bool) {
+        if probe {
+            *self.breaker.lock() = Circuit::HalfOpen { probing: false };
+        }
+    }
+}
+
+impl Drop for Ticket<'_> {
+    fn drop(&mut self) {
+        self.release(self.probe);
+    }
+}
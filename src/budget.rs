@@ -0,0 +1,89 @@
+use std::io::{self, Write};
+use std::mem::size_of;
+
+use serde_json::Value;
+
+/// Caps the memory one query may hold for its results: the hits kept
+/// between stages and the serialized reply.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryBudget {
+    limit: Option<usize>,
+}
+
+impl MemoryBudget {
+    /// A budget of `limit` bytes per query; unlimited for `None`.
+    pub fn new(limit: Option<usize>) -> Self {
+        Self { limit }
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Fails with `OutOfMemory` once `hits` take more than the budget.
+    pub fn check_hits(&self, hits: &[Value]) -> io::Result<()> {
+        let Some(limit) = self.limit else {
+            return Ok(());
+        };
+        // stop counting as soon as the answer is known
+        let mut used = 0;
+        for hit in hits {
+            used += value_bytes(hit);
+            if used > limit {
+                return Err(exceeded(limit));
+            }
+        }
+        Ok(())
+    }
+
+    /// A writer appending to `buf` that fails with `OutOfMemory` instead of
+    /// growing it past the budget.
+    pub fn writer<'a>(&self, buf: &'a mut Vec<u8>) -> BudgetWriter<'a> {
+        BudgetWriter {
+            buf,
+            limit: self.limit,
+        }
+    }
+}
+
+/// Rough heap footprint of a JSON value, its own slot included.
+pub fn value_bytes(value: &Value) -> usize {
+    size_of::<Value>()
+        + match value {
+            Value::String(s) => s.len(),
+            Value::Array(items) => items.iter().map(value_bytes).sum(),
+            Value::Object(map) => map
+                .iter()
+                .map(|(key, value)| size_of::<String>() + key.len() + value_bytes(value))
+                .sum(),
+            Value::Null | Value::Bool(_) | Value::Number(_) => 0,
+        }
+}
+
+pub struct BudgetWriter<'a> {
+    buf: &'a mut Vec<u8>,
+    limit: Option<usize>,
+}
+
+impl Write for BudgetWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if let Some(limit) = self.limit
+            && self.buf.len() + data.len() > limit
+        {
+            return Err(exceeded(limit));
+        }
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn exceeded(limit: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::OutOfMemory,
+        format!("query exceeded its memory budget of {limit} bytes"),
+    )
+}
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::analysis::Language;
+
+/// How long a "no results" outcome is trusted before the engine is asked again.
+pub const DEFAULT_NEGATIVE_TTL: Duration = Duration::from_secs(30);
+
+/// Upper bound on remembered misses, so bot traffic can't grow the map forever.
+pub const DEFAULT_NEGATIVE_CAPACITY: usize = 10_000;
+
+/// Remembers queries that recently produced no hits.
+///
+/// Kept apart from any positive result caching: misses are cheap to store but
+/// go stale quickly once new documents land, so they get a short TTL and are
+/// dropped wholesale whenever the index is reloaded.
+pub struct NegativeCache {
+    ttl: Duration,
+    capacity: usize,
+    misses: HashMap<String, Instant>,
+}
+
+impl NegativeCache {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity,
+            misses: HashMap::new(),
+        }
+    }
+
+    /// True if `query` missed within the TTL window.
+    pub fn is_miss(&mut self, query: &str) -> bool {
+        match self.misses.get(query) {
+            Some(at) if at.elapsed() < self.ttl => true,
+            Some(_) => {
+                self.misses.remove(query);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn record_miss(&mut self, query: &str) {
+        if self.misses.len() >= self.capacity {
+            let ttl = self.ttl;
+            self.misses.retain(|_, at| at.elapsed() < ttl);
+            if self.misses.len() >= self.capacity {
+                return;
+            }
+        }
+        self.misses.insert(query.to_owned(), Instant::now());
+    }
+
+    /// Forget every miss; call whenever the index is reloaded.
+    pub fn invalidate(&mut self) {
+        self.misses.clear();
+    }
+
+    /// Queries whose miss is still trusted.
+    pub fn queries(&self) -> impl Iterator<Item = &str> {
+        self.misses
+            .iter()
+            .filter(|(_, at)| at.elapsed() < self.ttl)
+            .map(|(query, _)| query.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.misses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.misses.is_empty()
+    }
+}
+
+impl Default for NegativeCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_NEGATIVE_TTL, DEFAULT_NEGATIVE_CAPACITY)
+    }
+}
+
+/// Distinct queries whose analyzed form is kept by default.
+pub const DEFAULT_QUERY_CACHE_CAPACITY: usize = 1_000;
+
+/// What a query's analyzed form depends on. The text is normalized first:
+/// runs of whitespace never change the terms, so they don't split entries.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QueryKey {
+    language: Option<Language>,
+    stem: Option<bool>,
+    text: String,
+}
+
+impl QueryKey {
+    pub fn new(language: Option<Language>, stem: Option<bool>, text: &str) -> Self {
+        Self {
+            language,
+            stem,
+            text: text.split_whitespace().collect::<Vec<_>>().join(" "),
+        }
+    }
+
+    /// The normalized query text.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+/// Hit and miss counts, as reported by the `metrics` operation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct QueryCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+/// Analyzed query text for recently seen queries, so hot queries skip
+/// language detection and analysis.
+///
+/// Analysis depends only on the query and the config, never on the index,
+/// so entries stay valid across index reloads.
+pub struct QueryCache {
+    capacity: usize,
+    queries: HashMap<QueryKey, Arc<str>>,
+    hits: u64,
+    misses: u64,
+}
+
+impl QueryCache {
+    /// A cache of up to `capacity` queries; 0 disables it.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            queries: HashMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn get(&mut self, key: &QueryKey) -> Option<Arc<str>> {
+        let query = self.queries.get(key).cloned();
+        if query.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        query
+    }
+
+    pub fn insert(&mut self, key: QueryKey, query: Arc<str>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.queries.len() >= self.capacity && !self.queries.contains_key(&key) {
+            // any entry will do: hot queries come straight back
+            if let Some(evicted) = self.queries.keys().next().cloned() {
+                self.queries.remove(&evicted);
+            }
+        }
+        self.queries.insert(key, query);
+    }
+
+    /// Drops every entry; the hit and miss counts carry on.
+    pub fn clear(&mut self) {
+        self.queries.clear();
+    }
+
+    pub fn stats(&self) -> QueryCacheStats {
+        QueryCacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.queries.len(),
+        }
+    }
+}
+
+impl Default for QueryCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_QUERY_CACHE_CAPACITY)
+    }
+}
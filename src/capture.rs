@@ -0,0 +1,251 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nerve_protocol::constants::{MAGIC, VERSION};
+use nerve_protocol::frame::{FrameHeader, OwnedFrame};
+use tracing::{info, warn};
+
+use crate::framing::FrameDecoder;
+
+/// First bytes of a capture file, followed by [`CAPTURE_VERSION`].
+pub const CAPTURE_MAGIC: &[u8; 8] = b"NERVECAP";
+pub const CAPTURE_VERSION: u8 = 1;
+
+// micros, connection, direction, msg_type, flags, request id, payload length
+const ENTRY_HEADER: usize = 8 + 4 + 1 + 1 + 1 + 8 + 4;
+
+/// Which way a captured frame went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Read from the core.
+    Inbound,
+    /// Written to the core.
+    Outbound,
+}
+
+impl Direction {
+    fn byte(self) -> u8 {
+        match self {
+            Direction::Inbound => b'<',
+            Direction::Outbound => b'>',
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            b'<' => Some(Direction::Inbound),
+            b'>' => Some(Direction::Outbound),
+            _ => None,
+        }
+    }
+}
+
+/// One frame read back from a capture file.
+#[derive(Debug)]
+pub struct Captured {
+    pub at: SystemTime,
+    /// Connections are numbered from 0 in the order they were made, across
+    /// every run that appended to the capture.
+    pub connection: u32,
+    pub direction: Direction,
+    pub frame: OwnedFrame,
+}
+
+/// Appends every frame read from or written to a core to one capture file
+/// (`--record <file>`), for debugging a protocol exchange offline.
+///
+/// Each entry is the frame's fields, not its bytes on the wire: the time
+/// (microseconds since the Unix epoch), the connection, the direction, the
+/// message type, flags, request id and payload, big-endian. Frames are
+/// captured as decoded on the way in and as fully written on the way out;
+/// the handshake's own PING/PONG are not.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    capture: Option<Arc<Capture>>,
+    connections: AtomicU32,
+}
+
+#[derive(Debug)]
+struct Capture {
+    path: PathBuf,
+    file: Mutex<File>,
+    failed: AtomicBool,
+}
+
+impl Recorder {
+    /// Appends to the capture file at `path`, starting it if it is empty;
+    /// records nothing when `None`. Fails on a file that isn't a capture.
+    pub fn open(path: Option<&Path>) -> io::Result<Self> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut connections = 0;
+        if file.metadata()?.len() == 0 {
+            file.write_all(CAPTURE_MAGIC)?;
+            file.write_all(&[CAPTURE_VERSION])?;
+        } else {
+            // numbering carries on from earlier runs, keeping their
+            // connections apart
+            let mut earlier = BufReader::new(File::open(path)?);
+            read_start(&mut earlier)?;
+            while let Some(entry) = read_entry(&mut earlier)? {
+                connections = connections.max(entry.connection + 1);
+            }
+        }
+        info!(path = %path.display(), "recording frames");
+        Ok(Self {
+            capture: Some(Arc::new(Capture {
+                path: path.to_path_buf(),
+                file: Mutex::new(file),
+                failed: AtomicBool::new(false),
+            })),
+            connections: AtomicU32::new(connections),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    /// The tape for a new connection, when recording.
+    pub fn tape(&self) -> Option<Tape> {
+        let capture = Arc::clone(self.capture.as_ref()?);
+        let connection = self.connections.fetch_add(1, Ordering::Relaxed);
+        Some(Tape {
+            capture,
+            connection,
+        })
+    }
+}
+
+/// Where one connection's frames are recorded.
+#[derive(Debug, Clone)]
+pub struct Tape {
+    capture: Arc<Capture>,
+    connection: u32,
+}
+
+impl Tape {
+    /// Records a frame read from the core.
+    pub fn inbound(&self, frame: &OwnedFrame) {
+        self.append(Direction::Inbound, frame);
+    }
+
+    /// Records an encoded frame written to the core, whichever protocol
+    /// version it was marked with.
+    pub fn outbound(&self, encoded: &[u8]) {
+        let mut frames = Vec::new();
+        FrameDecoder::new(encoded.len()).decode(encoded, &mut frames, |rejected| {
+            warn!(?rejected, "written frame not recorded")
+        });
+        for frame in &frames {
+            self.append(Direction::Outbound, frame);
+        }
+    }
+
+    fn append(&self, direction: Direction, frame: &OwnedFrame) {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let header = &frame.header;
+        let mut entry = Vec::with_capacity(ENTRY_HEADER + frame.payload.len());
+        entry.extend((at.as_micros() as u64).to_be_bytes());
+        entry.extend(self.connection.to_be_bytes());
+        entry.push(direction.byte());
+        entry.push(header.msg_type);
+        entry.push(header.flags);
+        entry.extend(header.request_id.to_be_bytes());
+        entry.extend((frame.payload.len() as u32).to_be_bytes());
+        entry.extend(&frame.payload);
+
+        let capture = &self.capture;
+        let mut file = capture.file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = file.write_all(&entry)
+            && !capture.failed.swap(true, Ordering::Relaxed)
+        {
+            warn!(path = %capture.path.display(), error = %e, "frame capture failed");
+        }
+    }
+}
+
+/// Reads every frame in a capture, in the order they were recorded. A
+/// capture cut short mid-entry, as by a crash, ends at the last whole one.
+pub fn read(mut input: impl Read) -> io::Result<Vec<Captured>> {
+    read_start(&mut input)?;
+    let mut captured = Vec::new();
+    while let Some(entry) = read_entry(&mut input)? {
+        captured.push(entry);
+    }
+    Ok(captured)
+}
+
+fn read_start(input: &mut impl Read) -> io::Result<()> {
+    let mut start = [0u8; CAPTURE_MAGIC.len() + 1];
+    input.read_exact(&mut start)?;
+    if start[..CAPTURE_MAGIC.len()] != CAPTURE_MAGIC[..] {
+        return Err(invalid("not a frame capture".into()));
+    }
+    if start[CAPTURE_MAGIC.len()] != CAPTURE_VERSION {
+        return Err(invalid(format!(
+            "unsupported capture version {}",
+            start[CAPTURE_MAGIC.len()]
+        )));
+    }
+    Ok(())
+}
+
+/// The next whole entry; `None` at the end of the capture.
+fn read_entry(input: &mut impl Read) -> io::Result<Option<Captured>> {
+    let mut header = [0u8; ENTRY_HEADER];
+    match input.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut fields = &header[..];
+    let micros = u64::from_be_bytes(take(&mut fields));
+    let connection = u32::from_be_bytes(take(&mut fields));
+    let [direction, msg_type, flags] = take(&mut fields);
+    let request_id = u64::from_be_bytes(take(&mut fields));
+    let payload_length = u32::from_be_bytes(take(&mut fields));
+    let direction = Direction::from_byte(direction)
+        .ok_or_else(|| invalid(format!("bad direction {direction:#04x} in capture")))?;
+    let mut payload = vec![0u8; payload_length as usize];
+    match input.read_exact(&mut payload) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    Ok(Some(Captured {
+        at: UNIX_EPOCH + Duration::from_micros(micros),
+        connection,
+        direction,
+        frame: OwnedFrame {
+            header: FrameHeader {
+                magic: MAGIC,
+                version: VERSION,
+                msg_type,
+                flags,
+                request_id,
+                payload_length,
+            },
+            payload,
+        },
+    }))
+}
+
+fn take<const N: usize>(fields: &mut &[u8]) -> [u8; N] {
+    let mut field = [0u8; N];
+    field.copy_from_slice(&fields[..N]);
+    *fields = &fields[N..];
+    field
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,213 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use bytes::Bytes;
+use nerve_protocol::frame::OwnedFrame;
+use serde::Deserialize;
+use tracing::debug;
+
+const GOLDEN_GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// `[chaos]` section (`chaos` feature, for tests): faults injected into the
+/// frames of every connection, to exercise the error and reconnect paths.
+/// Each rate is the chance, from 0 to 1, that a frame meets that fault.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(default)]
+pub struct ChaosConfig {
+    /// The same seed, connections and frames meet the same faults.
+    pub seed: u64,
+    pub drop_rate: f64,
+    pub duplicate_rate: f64,
+    /// Frames with one bit flipped: in the payload of a frame read, anywhere
+    /// in a frame written, header included.
+    pub corrupt_rate: f64,
+    pub delay_rate: f64,
+    /// Longest a delayed frame is held up; each delay is drawn up to it.
+    pub max_delay_ms: u64,
+}
+
+impl ChaosConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.rates().iter().any(|(_, rate)| *rate > 0.0)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self
+            .rates()
+            .iter()
+            .find(|(_, rate)| !(0.0..=1.0).contains(rate))
+        {
+            Some((name, rate)) => Err(format!("chaos {name} must be between 0 and 1, not {rate}")),
+            None => Ok(()),
+        }
+    }
+
+    fn rates(&self) -> [(&'static str, f64); 4] {
+        [
+            ("drop_rate", self.drop_rate),
+            ("duplicate_rate", self.duplicate_rate),
+            ("corrupt_rate", self.corrupt_rate),
+            ("delay_rate", self.delay_rate),
+        ]
+    }
+}
+
+/// Hands each new connection its [`Faults`], seeded from the config and the
+/// connection's number, so a run can be repeated.
+#[derive(Debug, Default)]
+pub struct Chaos {
+    config: ChaosConfig,
+    connections: AtomicU64,
+}
+
+impl Chaos {
+    pub fn new(config: ChaosConfig) -> Self {
+        Self {
+            config,
+            connections: AtomicU64::new(0),
+        }
+    }
+
+    /// Faults for the next connection; `None` with every rate at 0.
+    pub fn connection(&self) -> Option<Faults> {
+        if !self.config.is_enabled() {
+            return None;
+        }
+        let connection = self.connections.fetch_add(1, Ordering::Relaxed);
+        let seed = self.config.seed ^ connection.wrapping_mul(GOLDEN_GAMMA);
+        Some(Faults::new(self.config, seed))
+    }
+}
+
+/// Faults for one connection's frames, both ways.
+#[derive(Debug)]
+pub struct Faults {
+    config: ChaosConfig,
+    rng: Mutex<SplitMix64>,
+}
+
+impl Faults {
+    pub fn new(config: ChaosConfig, seed: u64) -> Self {
+        Self {
+            config,
+            rng: Mutex::new(SplitMix64(seed)),
+        }
+    }
+
+    /// Drops, corrupts, delays and duplicates frames read from the core,
+    /// before anything handles them.
+    pub fn inbound(&self, frames: &mut Vec<OwnedFrame>) {
+        let mut rng = self.rng();
+        let mut delay = Duration::ZERO;
+        let mut faulted = Vec::with_capacity(frames.len());
+        for mut frame in frames.drain(..) {
+            let request_id = frame.header.request_id;
+            if rng.hit(self.config.drop_rate) {
+                debug!(request_id, "chaos: frame read dropped");
+                continue;
+            }
+            if rng.hit(self.config.corrupt_rate) && flip(&mut rng, &mut frame.payload) {
+                debug!(request_id, "chaos: frame read corrupted");
+            }
+            delay += self.delay(&mut rng, request_id);
+            if rng.hit(self.config.duplicate_rate) {
+                debug!(request_id, "chaos: frame read twice");
+                faulted.push(frame.clone());
+            }
+            faulted.push(frame);
+        }
+        *frames = faulted;
+        drop(rng);
+        pause(delay);
+    }
+
+    /// The frames to queue for the core in place of `frame`: none when it
+    /// is dropped, two when duplicated, possibly corrupted. Delays block
+    /// the caller.
+    pub fn outbound(&self, frame: Bytes) -> Vec<Bytes> {
+        let mut rng = self.rng();
+        if rng.hit(self.config.drop_rate) {
+            debug!("chaos: frame written dropped");
+            return Vec::new();
+        }
+        let mut frame = frame;
+        if rng.hit(self.config.corrupt_rate) {
+            let mut bytes = frame.to_vec();
+            if flip(&mut rng, &mut bytes) {
+                debug!("chaos: frame written corrupted");
+            }
+            frame = Bytes::from(bytes);
+        }
+        let delay = self.delay(&mut rng, 0);
+        let frames = if rng.hit(self.config.duplicate_rate) {
+            debug!("chaos: frame written twice");
+            vec![frame.clone(), frame]
+        } else {
+            vec![frame]
+        };
+        drop(rng);
+        pause(delay);
+        frames
+    }
+
+    fn delay(&self, rng: &mut SplitMix64, request_id: u64) -> Duration {
+        if !rng.hit(self.config.delay_rate) || self.config.max_delay_ms == 0 {
+            return Duration::ZERO;
+        }
+        let delay = Duration::from_millis(rng.below(self.config.max_delay_ms + 1));
+        debug!(
+            request_id,
+            delay_ms = delay.as_millis() as u64,
+            "chaos: frame delayed"
+        );
+        delay
+    }
+
+    fn rng(&self) -> MutexGuard<'_, SplitMix64> {
+        self.rng.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn pause(delay: Duration) {
+    if !delay.is_zero() {
+        thread::sleep(delay);
+    }
+}
+
+/// Flips one bit of `bytes`; false when there is none to flip.
+fn flip(rng: &mut SplitMix64, bytes: &mut [u8]) -> bool {
+    if bytes.is_empty() {
+        return false;
+    }
+    let at = rng.below(bytes.len() as u64) as usize;
+    bytes[at] ^= 1 << rng.below(8);
+    true
+}
+
+/// Small, seedable and good enough to pick faults; not for anything that
+/// needs real randomness.
+#[derive(Debug)]
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(GOLDEN_GAMMA);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// True with chance `rate`.
+    fn hit(&mut self, rate: f64) -> bool {
+        // 53 random bits make a uniform float in [0, 1)
+        let draw = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        draw < rate
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
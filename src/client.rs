@@ -1,51 +1,954 @@
-use std::io::{Write};
-use std::os::unix::net::UnixStream;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, IoSlice};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TryRecvError, TrySendError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use bytes::Bytes;
+use mio::{Events, Interest, Poll, Token, Waker};
 use nerve_protocol::{MessageType, RequestId};
-use tracing::{info, warn};
-
+use nerve_protocol::constants::HEADER_SIZE;
+use nerve_protocol::frame::OwnedFrame;
 use nerve_protocol::io::FrameReader;
+use nerve_protocol::types::FrameFlags;
+use tracing::{Span, debug, info, warn};
 
-use crawler::SearchEngine;
-use std::path::Path;
-
+use crate::affinity;
+use crate::auth::FrameMac;
+use crate::capture::Tape;
+#[cfg(feature = "chaos")]
+use crate::chaos::Faults;
+use crate::config::Config;
+use crate::connections::{Health, Route, Routes, Traffic};
+use crate::context::Context;
+use crate::control::ControlSocket;
+use crate::framing::{Downgrade, FrameDecoder};
 use crate::handler;
+use crate::handshake::{self, Capabilities, Capability, Handshake};
+use crate::health::HealthServer;
+use crate::heartbeat::{self, Heartbeat};
+use crate::peercred;
+use crate::profile::Profiler;
+use crate::reconnect;
+use crate::request::Request;
+use crate::shutdown;
 use crate::state::RequestState;
+use crate::telemetry::{self, Spans};
+use crate::transport::{HALF_CLOSE_LINGER, Stream, Transport};
+use crate::warm;
+use crate::watchdog;
+
+const SOCKET: Token = Token(0);
+const REPLIES: Token = Token(1);
+const READ_BUFFER_BYTES: usize = 64 * 1024;
+/// Most queued frames handed to one vectored write.
+pub(crate) const MAX_WRITE_SLICES: usize = 64;
+/// Queued reply bytes that are written without waiting for more.
+const COALESCE_BYTES: usize = 16 * 1024;
+
+/// Connects to the core and serves it until the connection drops; with
+/// `[reconnect]` enabled, connects again after a backoff instead.
+///
+/// This thread runs a nonblocking event loop over the socket: cancels are
+/// applied on the spot, queries go to `config.workers` worker threads, and
+/// replies are buffered and written as the socket accepts them, so the
+/// adapter keeps reading even while the core is not draining its replies.
+///
+/// SIGTERM stops reading new queries; in-flight ones get `[shutdown]`
+/// `drain_timeout_ms` to finish and have their replies flushed before the
+/// connections close and this returns.
+pub fn run(config: &Config)-> std::io::Result<()>{
+    config.validate()?;
+    run_with(config, Context::from_config(config)?)
+}
+
+/// [`run`] with a context built by the caller, as `Context::from_config`
+/// plus what configuration can't express, such as an embedder.
+pub fn run_with(config: &Config, context: Context)-> std::io::Result<()>{
+    config.validate()?;
+    info!(
+        index = %config.index_path.display(),
+        shards = context.shards.len(),
+        peers = config.peers.len(),
+        workers = config.workers,
+        connections = config.connections.count,
+        "search index opened"
+    );
 
-pub fn run(socket_path: &str)-> std::io::Result<()>{
-    let mut stream = UnixStream::connect(socket_path)?;
-    info!("connected to NERVE-CORE");
+    let signals = shutdown::listen(&context.shutdown)?;
+    let served = run_connections(config, &context, session);
+    drop(signals);
+    // every worker has finished: nothing changes the counts any more
+    warm::save(&context);
+    context.profiler.save();
+    served
+}
 
-    let mut reader = FrameReader::new();
-    let mut state = RequestState::new();
+/// Keeps `[connections]` count connections to each core, each on its own
+/// thread running `session` (reconnecting as `[reconnect]` says), all
+/// feeding the same workers, so whichever core sends queries is served.
+/// Returns once every connection is done, with the first error any of them
+/// ended in.
+pub(crate) fn run_connections(
+    config: &Config,
+    context: &Context,
+    session: fn(&Config, &Context, Stream, Connection<'_>)->io::Result<()>,
+)->io::Result<()>{
+    let (jobs, pools) = queues(config.queue_depth);
+    let sockets = config.socket_paths();
+    let per_core = config.connections.count;
+    let routes = Routes::new(config.connections.balance, sockets.len(), per_core);
+    let health = HealthServer::bind(&config.health, config.queue_depth)?;
+    let control = ControlSocket::bind(&config.control)?;
+    // the probes, the watchdog, the warmer and the control socket run until
+    // the connections are done
+    let stopped = AtomicBool::new(false);
+    thread::scope(|s|{
+        spawn_workers(s, config, &pools, &routes, context);
+        if let Some(health) = &health{
+            s.spawn(|| health.serve(context, &stopped));
+        }
+        if let Some(control) = &control{
+            s.spawn(|| control.serve(context, &stopped));
+        }
+        s.spawn(|| watchdog::run(config.watchdog, context, &stopped));
+        s.spawn(|| warm::run(context, &stopped));
+        let connections: Vec<_> = (0..sockets.len() * per_core).map(|slot|{
+            let (jobs, routes, socket) = (jobs.clone(), &routes, &sockets[slot / per_core]);
+            s.spawn(move ||{
+                let health = context.connections.slot(slot);
+                reconnect::run_sessions(config.reconnect, socket, context.tls.as_ref(), &context.shutdown, |stream|{
+                    info!(connection = slot, socket = %socket.display(), "connected to NERVE-CORE");
+                    health.up();
+                    let connection = Connection{ slot, jobs: jobs.clone(), routes };
+                    let served = session(config, context, stream, connection);
+                    health.down(&served);
+                    served
+                })
+            })
+        }).collect();
+        // the workers exit once the last connection lets go of the queues
+        drop(jobs);
+
+        let mut served = Ok(());
+        for connection in connections{
+            let ended = connection.join()
+                .unwrap_or_else(|_| Err(io::Error::other("connection thread panicked")));
+            if served.is_ok(){
+                served = ended;
+            }
+        }
+        stopped.store(true, Ordering::Relaxed);
+        served
+    })
+}
 
-    let engine = SearchEngine::new(Path::new("/Users/shreyasbk/RustroverProjects/crawler/search_index"))
-        .expect("failed to init search engine");
+/// Serves one connection to the core until it drops. Request state and the
+/// reply channel live as long as the connection; the workers, the index
+/// and its caches carry over to the next.
+pub(crate) fn session(config: &Config, context: &Context, mut stream: Stream, connection: Connection<'_>)->io::Result<()>{
+    peercred::verify(&stream, &config.peer_credentials)?;
+    let write_timeout = config.write_timeout_ms.map(Duration::from_millis);
+    // bounds the blocking handshake; the event loop enforces it itself
+    stream.set_write_timeout(write_timeout)?;
+    stream.set_buffer_sizes(config.send_buffer_bytes, config.receive_buffer_bytes)?;
+    let mut decoder = FrameDecoder::new(config.max_payload_bytes).with_packets(stream.keeps_packets());
+    let offered = Capabilities::offered(context);
+    let handshake = handshake::exchange(&mut stream, config.handshake, offered, context.auth.as_ref(), &mut decoder)?;
+    let health = context.connections.slot(connection.slot);
+    let state = connection_state(context, health, &handshake);
+    let _watch = context.shutdown.watch(&stream, &state)?;
+    let _orphans = health.track(Arc::clone(&state));
+    stream.set_nonblocking(true)?;
+    let closing = stream.socket()?;
+    let mut stream = stream.into_mio();
+
+    let mut poll = Poll::new()?;
+    poll.registry().register(&mut stream, SOCKET, Interest::READABLE | Interest::WRITABLE)?;
+    let waker = Waker::new(poll.registry(), REPLIES)?;
+
+    let (reply_tx, reply_rx) = mpsc::channel::<Bytes>();
+    let replies = Replies::new(reply_tx, move ||{
+        let _ = waker.wake();
+    });
+    let link = connection.link(replies, state);
+    abort_orphans(health, |error| link.send(error));
+
+    let coalesce = config.write_coalesce_us.map(Duration::from_micros);
+    let heartbeat = Heartbeat::new(config.heartbeat);
+    serve(&mut poll, &mut stream, decoder, handshake.early, link, reply_rx, coalesce, write_timeout, heartbeat, &context.profiler)?;
+    let _ = closing.half_close(HALF_CLOSE_LINGER);
+    Ok(())
+}
+
+/// A buffer to read the frames `decoder` takes into. A read off a packet
+/// transport holds one packet at most, so there it fits the largest frame.
+pub(crate) fn read_buffer(decoder: &FrameDecoder)->Vec<u8>{
+    let bytes = match decoder.reads_packets(){
+        true => READ_BUFFER_BYTES.max(HEADER_SIZE + decoder.max_payload()),
+        false => READ_BUFFER_BYTES,
+    };
+    vec![0u8; bytes]
+}
+
+/// The state of a connection as its handshake settled it, counting its
+/// traffic in `health`, recording its frames and (`chaos` feature) faulting
+/// them as configured.
+pub(crate) fn connection_state(context: &Context, health: &Health, handshake: &Handshake)->Arc<RequestState>{
+    let state = RequestState::with_capabilities(handshake.capabilities)
+        .with_protocol_version(handshake.protocol_version)
+        .with_frame_mac(handshake.frame_mac.clone())
+        .with_tape(context.recorder.tape())
+        .with_traffic(health.traffic())
+        .with_counters(Arc::clone(&context.counters))
+        .with_progress(health.progress())
+        .with_limiter(Arc::clone(&context.ratelimit));
+    #[cfg(feature = "chaos")]
+    let state = state.with_faults(context.chaos.connection());
+    Arc::new(state)
+}
+
+/// The event loop: serves the frames read during the handshake, then reads
+/// frames while the core sends them and flushes replies until the core
+/// hangs up and every in-flight reply is written.
+///
+/// With `coalesce` set, replies are held until [`COALESCE_BYTES`] are queued
+/// or the oldest has waited that long, so bursts of small frames share one
+/// write.
+///
+/// Pings go out on `heartbeat`'s schedule; a core that stops answering them
+/// ends the connection with `TimedOut`. With `write_timeout` set, replies
+/// the core doesn't take in time are [expired](Outbox::expire).
+#[allow(clippy::too_many_arguments)]
+fn serve(
+    poll: &mut Poll,
+    stream: &mut impl Transport,
+    mut decoder: FrameDecoder,
+    early: Vec<OwnedFrame>,
+    link: Link<'_>,
+    replies: Receiver<Bytes>,
+    coalesce: Option<Duration>,
+    write_timeout: Option<Duration>,
+    mut heartbeat: Heartbeat,
+    profiler: &Profiler,
+)->io::Result<()>{
+    let mut events = Events::with_capacity(64);
+    // read buffer and frame batch live as long as the connection
+    let mut buf = read_buffer(&decoder);
+    let mut batch = early;
+    let state = link.shared_state();
+    let mut outbox = Outbox::for_connection(&state);
+    dispatch(&mut batch, &link, &mut heartbeat, |reply| outbox.push(reply));
+    let mut link = Some(link);
+    let mut workers_done = false;
+    let mut timeout = None;
 
     loop{
-        let frames = match reader.read_from(&mut stream){
-            Ok(f) => f,
+        state.progress().idle();
+        let polled = poll.poll(&mut events, timeout);
+        state.progress().busy();
+        if let Err(e) = polled{
+            if e.kind() == io::ErrorKind::Interrupted{
+                continue;
+            }
+            return Err(e);
+        }
+
+        let readable = events.iter().any(|event| event.token() == SOCKET && event.is_readable());
+        if let (true, Some(open)) = (readable, &link)
+            && !read_frames(stream, &mut decoder, &mut buf, &mut batch, open, &mut heartbeat, &mut outbox, profiler){
+            if let Some(notice) = going_away(open.state()){
+                outbox.push(notice);
+            }
+            // no more queries: let the workers finish this connection's
+            link = None;
+        }
+        if link.is_some(){
+            match heartbeat.tick(){
+                Ok(Some(ping)) => outbox.push(ping),
+                Ok(None) => {}
+                Err(e) =>{
+                    warn!(error = %e, "core not answering, dropping connection");
+                    return Err(e);
+                }
+            }
+        }
+
+        loop{
+            match replies.try_recv(){
+                Ok(reply) => outbox.push(reply),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) =>{
+                    workers_done = true;
+                    break;
+                }
+            }
+        }
+        timeout = None;
+        match coalesce.and_then(|delay| outbox.hold(delay)){
+            // nothing more is coming once the workers are gone
+            Some(left) if !workers_done => timeout = Some(left),
+            _ if profiler.is_enabled() && !outbox.is_empty() =>{
+                let start = Instant::now();
+                outbox.flush(stream)?;
+                profiler.record_io("write", start.elapsed());
+            }
+            _ => outbox.flush(stream)?,
+        }
+        if let Some(limit) = write_timeout{
+            match outbox.expire(limit){
+                // nothing more of theirs goes out
+                Ok(failed) => failed.into_iter().for_each(|id| state.cancel(id)),
+                Err(e) =>{
+                    warn!(error = %e, "core not reading, dropping connection");
+                    return Err(e);
+                }
+            }
+            if let Some(left) = outbox.until_expiry(limit){
+                timeout = Some(timeout.map_or(left, |due: Duration| due.min(left)));
+            }
+        }
+        if link.is_some()
+            && let Some(due) = heartbeat.until_due(){
+            timeout = Some(timeout.map_or(due, |left: Duration| left.min(due)));
+        }
+
+        if link.is_none() && workers_done && outbox.is_empty() && !stream.has_pending_writes(){
+            return Ok(());
+        }
+    }
+}
+
+/// Reads until the socket would block, then dispatches everything read as
+/// one batch. Returns false once the core hung up or the read failed;
+/// malformed frames are answered and skipped.
+#[allow(clippy::too_many_arguments)]
+fn read_frames(
+    stream: &mut impl Transport,
+    decoder: &mut FrameDecoder,
+    buf: &mut [u8],
+    batch: &mut Vec<OwnedFrame>,
+    link: &Link<'_>,
+    heartbeat: &mut Heartbeat,
+    outbox: &mut Outbox,
+    profiler: &Profiler,
+)->bool{
+    let open = loop{
+        let read = match stream.read_frames(buf){
+            Ok(0) => break false,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break true,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
             Err(e) =>{
-                warn!(error = %e, "protocol error, exiting");
-                break;
+                warn!(error = %e, "socket read failed, exiting");
+                break false;
             }
         };
+        link.state().traffic().read(read);
+        let start = profiler.is_enabled().then(Instant::now);
+        decoder.decode(&buf[..read], batch, |rejected|{
+            if let Some(reply) = handler::frame_rejected(&rejected){
+                outbox.push(reply);
+            }
+        });
+        if let Some(start) = start{
+            profiler.record_io("decode", start.elapsed());
+        }
+    };
+    dispatch(batch, link, heartbeat, |reply| outbox.push(reply));
+    open
+}
+
+/// The `going_away` notice for a connection that stopped reading to shut
+/// down, when its core agreed to be told.
+pub(crate) fn going_away(state: &RequestState)->Option<Bytes>{
+    if !state.is_shutting_down() || !state.capabilities().contains(Capability::GoingAway){
+        return None;
+    }
+    handler::going_away()
+}
+
+/// The request a queued frame answers, when it is a search reply: only
+/// those are failed for not being taken in time.
+fn search_reply(frame: &[u8])->Option<RequestId>{
+    let frames = FrameReader::new().read_from(&mut &frame[..]).ok()?;
+    let [reply] = frames.as_slice() else{
+        return None;
+    };
+    (reply.header.msg_type == MessageType::SearchResult as u8).then_some(RequestId(reply.header.request_id))
+}
+
+/// Whether a frame controls other requests or the connection rather than
+/// asking for work. Control frames are handled before queries read in the
+/// same batch.
+pub(crate) fn is_control(frame: &OwnedFrame)->bool{
+    matches!(
+        MessageType::try_from(frame.header.msg_type),
+        Ok(MessageType::Cancel | MessageType::Ping | MessageType::Pong)
+    )
+}
+
+/// A query waiting for a worker.
+pub(crate) struct Job{
+    frame: OwnedFrame,
+    // deadlines count from arrival, not from when a worker gets to it
+    received: Instant,
+    origin: Origin,
+}
+
+/// The connection a query came in on: its requests' state and its reply
+/// channel.
+#[derive(Clone)]
+pub(crate) struct Origin{
+    slot: usize,
+    state: Arc<RequestState>,
+    replies: Replies,
+}
+
+/// A connection's hold on the workers while its thread serves it.
+pub(crate) struct Connection<'a>{
+    pub(crate) slot: usize,
+    pub(crate) jobs: Queues,
+    pub(crate) routes: &'a Routes<Replies>,
+}
+
+impl<'a> Connection<'a>{
+    /// Opens the connection to queries and replies: its queries carry
+    /// `state` and `replies` to the workers, which may also send other
+    /// connections' replies through it.
+    pub(crate) fn link(self, replies: Replies, state: Arc<RequestState>)->Link<'a>{
+        let route = self.routes.open(self.slot, replies.clone());
+        Link{ jobs: self.jobs, origin: Origin{ slot: self.slot, state, replies }, _route: route }
+    }
+}
+
+/// A connection open to queries; dropping it once reading stops lets the
+/// reply channel close when the workers are done with the connection.
+pub(crate) struct Link<'a>{
+    jobs: Queues,
+    origin: Origin,
+    _route: Route<'a, Replies>,
+}
+
+impl Link<'_>{
+    pub(crate) fn state(&self)->&RequestState{
+        &self.origin.state
+    }
+
+    /// The request state, to outlive the link once reading stops.
+    pub(crate) fn shared_state(&self)->Arc<RequestState>{
+        Arc::clone(&self.origin.state)
+    }
+
+    /// Queues a frame for the connection, as a worker would.
+    pub(crate) fn send(&self, frame: Bytes){
+        self.origin.replies.send(frame);
+    }
+}
+
+/// Senders for the two worker pools: index maintenance waits apart from
+/// searches, so a burst of writes can't starve queries or the reverse.
+#[derive(Clone)]
+pub(crate) struct Queues{
+    search: SyncSender<Job>,
+    index: SyncSender<Job>,
+}
+
+impl Queues{
+    fn for_payload(&self, payload: &[u8])->&SyncSender<Job>{
+        if Request::is_indexing_payload(payload){
+            &self.index
+        } else {
+            &self.search
+        }
+    }
+}
 
-        for frame in frames{
-            match MessageType::try_from(frame.header.msg_type){
-                Ok(MessageType::SearchQuery)=>{
-                    if let Some(reply) = handler::handle_search(frame, &mut state, &engine){
-                        stream.write_all(&reply)?;
+/// The receiving ends of [`Queues`], each shared by one pool's workers.
+pub(crate) struct Pools{
+    search: Mutex<Receiver<Job>>,
+    index: Mutex<Receiver<Job>>,
+}
+
+/// Both pools' queues, each holding up to `depth` waiting jobs.
+pub(crate) fn queues(depth: usize)->(Queues, Pools){
+    let (search, search_rx) = mpsc::sync_channel(depth);
+    let (index, index_rx) = mpsc::sync_channel(depth);
+    (
+        Queues{ search, index },
+        Pools{ search: Mutex::new(search_rx), index: Mutex::new(index_rx) },
+    )
+}
+
+/// Starts the search and indexing workers on `s`, each pinned to its
+/// configured cores. They exit once every [`Queues`] handle is dropped and
+/// the queues are drained.
+pub(crate) fn spawn_workers<'scope, 'env>(
+    s: &'scope thread::Scope<'scope, 'env>,
+    config: &'env Config,
+    pools: &'env Pools,
+    routes: &'env Routes<Replies>,
+    context: &'env Context,
+){
+    let search = (config.workers, &pools.search, &config.search_cpus);
+    let index = (config.index_workers, &pools.index, &config.index_cpus);
+    for (count, queue, cpus) in [search, index]{
+        for _ in 0..count{
+            s.spawn(move ||{
+                if !cpus.is_empty()
+                    && let Err(e) = affinity::pin_current_thread(cpus){
+                    warn!(error = %e, ?cpus, "worker not pinned");
+                }
+                work(queue, routes, context)
+            });
+        }
+    }
+}
+
+/// Applies cancels and answers pings on the spot, and queues queries for
+/// the workers; queries finding the queue full are turned away. Replies
+/// made here go to `reply`; the workers answer through `link`. Leaves
+/// `frames` empty, keeping its allocation
+/// for the next batch.
+///
+/// Control frames go first, so a CANCEL that arrived behind a burst of
+/// queries still lands before any of them is queued. With frame MACs
+/// agreed, a frame whose MAC doesn't check out is answered with a
+/// `bad_mac` error and dropped.
+pub(crate) fn dispatch(
+    frames: &mut Vec<OwnedFrame>,
+    link: &Link<'_>,
+    heartbeat: &mut Heartbeat,
+    mut reply: impl FnMut(Bytes),
+){
+    link.state().traffic().received(frames.len());
+    link.state().progress().took(frames);
+    #[cfg(feature = "chaos")]
+    if let Some(faults) = link.state().faults(){
+        faults.inbound(frames);
+    }
+    let received = Instant::now();
+    if !frames.is_empty(){
+        heartbeat.heard();
+    }
+    if let Some(tape) = link.state().tape(){
+        frames.iter().for_each(|frame| tape.inbound(frame));
+    }
+    if let Some(mac) = link.state().frame_mac(){
+        frames.retain_mut(|frame| verified(mac, frame, &mut reply));
+    }
+    for frame in frames.iter(){
+        debug!(request_id = frame.header.request_id, msg_type = frame.header.msg_type, bytes = frame.payload.len(), "frame received");
+    }
+    frames.retain(|frame|{
+        if !is_control(frame){
+            return true;
+        }
+        match MessageType::try_from(frame.header.msg_type){
+            Ok(MessageType::Ping) =>{
+                if let Some(pong) = heartbeat::pong(frame){
+                    reply(pong);
+                }
+            }
+            Ok(MessageType::Pong) => heartbeat.pong(),
+            _ => link.state().cancel(RequestId(frame.header.request_id)),
+        }
+        false
+    });
+    for frame in frames.drain(..){
+        match MessageType::try_from(frame.header.msg_type){
+            Ok(MessageType::SearchQuery)=>{
+                let request_id = RequestId(frame.header.request_id);
+                if let Err(wait) = link.state().admit(&frame.payload){
+                    debug!(request_id = request_id.0, "rate limited, query rejected");
+                    if let Some(limited) = handler::rate_limited(request_id, wait){
+                        reply(limited);
                     }
+                    continue;
                 }
-                Ok(MessageType::Cancel)=>{
-                    state.cancel(RequestId(frame.header.request_id));
+                // before the job is out of reach: a worker may finish it first
+                link.state().queue(request_id);
+                let job = Job{ frame, received, origin: link.origin.clone() };
+                match link.jobs.for_payload(&job.frame.payload).try_send(job){
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) =>{
+                        link.state().settle(request_id);
+                        warn!(request_id = request_id.0, "request queue full, query rejected");
+                        if let Some(overloaded) = handler::overloaded(request_id){
+                            reply(overloaded);
+                        }
+                    }
+                    // the queue outlives the read loop
+                    Err(TrySendError::Disconnected(_)) => {}
                 }
-                _ =>{
-                    // ignore eveything else
+            }
+            _ =>{
+                // ignore eveything else
+            }
+        }
+    }
+}
+
+/// Checks and strips `frame`'s MAC, answering through `reply` when it
+/// doesn't check out.
+pub(crate) fn verified(mac: &FrameMac, frame: &mut OwnedFrame, reply: &mut impl FnMut(Bytes))->bool{
+    if mac.verify(frame){
+        return true;
+    }
+    let request_id = RequestId(frame.header.request_id);
+    warn!(request_id = request_id.0, "frame MAC missing or wrong, frame dropped");
+    if let Some(error) = handler::bad_mac(request_id){
+        reply(error);
+    }
+    false
+}
+
+pub(crate) fn work(
+    queue: &Mutex<Receiver<Job>>,
+    routes: &Routes<Replies>,
+    context: &Context,
+){
+    loop{
+        // hold the lock only while taking a job
+        let job = queue.lock().unwrap_or_else(|e| e.into_inner()).recv();
+        let Ok(Job{ frame, received, origin }) = job else {
+            return;
+        };
+        let request_id = RequestId(frame.header.request_id);
+        let replies = routes.pick(request_id, origin.slot, &origin.replies);
+        let mut connected = true;
+        handle_isolated(frame, received, &origin.state, context, |reply|{
+            // a cancel may have landed while the request ran
+            if connected && !origin.state.is_cancelled(request_id){
+                connected = replies.send(reply);
+            }
+        });
+    }
+}
+
+/// Runs a queued request in its span. A panic in its handler is confined
+/// to it (see [`handler::handle_queued`]), so the worker carries on with the
+/// next job. Either way the request is [settled](RequestState::settle)
+/// after.
+pub(crate) fn handle_isolated(
+    frame: OwnedFrame,
+    received: Instant,
+    state: &RequestState,
+    context: &Context,
+    emit: impl FnMut(Bytes),
+){
+    let request_id = RequestId(frame.header.request_id);
+    let span = state.spans().get(request_id).unwrap_or_else(|| telemetry::request(request_id));
+    telemetry::describe(&span, &frame);
+    span.in_scope(|| handler::handle_queued(frame, received, state, context, emit));
+    state.settle(request_id);
+}
+
+/// Tells the core about the requests the last connection in `health`'s
+/// slot dropped unanswered, with an `aborted_by_disconnect` ERROR each, so
+/// it stops waiting for their replies.
+pub(crate) fn abort_orphans(health: &Health, mut reply: impl FnMut(Bytes)){
+    let orphans = health.take_orphans();
+    if orphans.is_empty(){
+        return;
+    }
+    warn!(requests = ?orphans.iter().map(|id| id.0).collect::<Vec<_>>(), "failing requests orphaned by the last disconnect");
+    for request_id in orphans{
+        if let Some(error) = handler::aborted_by_disconnect(request_id){
+            reply(error);
+        }
+    }
+}
+
+/// A worker's handle on the reply channel. Sending, and dropping a handle,
+/// wakes the I/O loop.
+pub(crate) struct Replies{
+    tx: Option<Sender<Bytes>>,
+    wake: Arc<dyn Fn() + Send + Sync>,
+}
+
+impl Replies{
+    pub(crate) fn new(tx: Sender<Bytes>, wake: impl Fn() + Send + Sync + 'static)->Self{
+        Replies{ tx: Some(tx), wake: Arc::new(wake) }
+    }
+
+    fn send(&self, reply: Bytes)->bool{
+        let sent = self.tx.as_ref().is_some_and(|tx| tx.send(reply).is_ok());
+        (self.wake)();
+        sent
+    }
+}
+
+impl Clone for Replies{
+    fn clone(&self)->Self{
+        Replies{ tx: self.tx.clone(), wake: Arc::clone(&self.wake) }
+    }
+}
+
+impl Drop for Replies{
+    fn drop(&mut self){
+        // disconnect first, so the woken loop can see the channel close
+        drop(self.tx.take());
+        (self.wake)();
+    }
+}
+
+/// Replies waiting for the socket to accept them.
+///
+/// Workers hand over whole frames and each is written out completely before
+/// the next starts, so replies to concurrent requests go out in completion
+/// order and interleave only at frame boundaries.
+#[derive(Default)]
+pub(crate) struct Outbox{
+    // signs each frame as it is queued, with frame MACs agreed
+    mac: Option<FrameMac>,
+    // marks each frame with an older protocol version the core speaks
+    downgrade: Option<Downgrade>,
+    // records each frame once it is written whole
+    tape: Option<Tape>,
+    // counts bytes as written and frames once written whole
+    traffic: Arc<Traffic>,
+    // closes each request's span once its last reply is written
+    spans: Arc<Spans>,
+    // write spans of requests with replies queued
+    writes: HashMap<RequestId, Span>,
+    #[cfg(feature = "chaos")]
+    faults: Option<Arc<Faults>>,
+    frames: VecDeque<Bytes>,
+    // when each frame was queued; `None` once it can't expire
+    queued: VecDeque<Option<Instant>>,
+    // the request each frame is the last reply to, when it has a span
+    finals: VecDeque<Option<RequestId>>,
+    // bytes of the front frame already written
+    written: usize,
+    // unwritten bytes across all frames
+    bytes: usize,
+    // when the queue last went from empty to non-empty
+    oldest: Option<Instant>,
+    // when the core last took any bytes, or the queue filled up again
+    moved: Option<Instant>,
+}
+
+impl Outbox{
+    /// An outbox for the connection `state` belongs to: its frames are
+    /// signed if MACs were agreed, marked with the protocol version agreed,
+    /// and recorded and faulted as configured.
+    pub(crate) fn for_connection(state: &RequestState)->Self{
+        Outbox{
+            mac: state.frame_mac().cloned(),
+            downgrade: Downgrade::to(state.protocol_version()),
+            tape: state.tape().cloned(),
+            traffic: Arc::clone(state.traffic()),
+            spans: Arc::clone(state.spans()),
+            #[cfg(feature = "chaos")]
+            faults: state.faults().cloned(),
+            ..Outbox::default()
+        }
+    }
+
+    pub(crate) fn push(&mut self, frame: Bytes){
+        let last = self.trace_write(&frame);
+        let frame = match &self.mac{
+            Some(mac) => mac.sign(frame),
+            None => frame,
+        };
+        let frame = match &self.downgrade{
+            Some(downgrade) => downgrade.apply(frame),
+            None => frame,
+        };
+        #[cfg(feature = "chaos")]
+        if let Some(faults) = &self.faults{
+            for frame in faults.outbound(frame){
+                self.queue(frame, last);
+            }
+            return;
+        }
+        self.queue(frame, last);
+    }
+
+    /// Opens the write span of a request with a span at its first reply;
+    /// returns its id when `frame` is its last.
+    fn trace_write(&mut self, frame: &[u8])->Option<RequestId>{
+        if !telemetry::is_exporting(){
+            return None;
+        }
+        let mut decoded = Vec::new();
+        FrameDecoder::new(frame.len()).decode(frame, &mut decoded, |_|{});
+        let [reply] = decoded.as_slice() else{
+            return None;
+        };
+        let request_id = RequestId(reply.header.request_id);
+        let request = self.spans.get(request_id)?;
+        self.writes.entry(request_id).or_insert_with(|| telemetry::write(&request));
+        FrameFlags::from_bits_truncate(reply.header.flags).contains(FrameFlags::FINAL).then_some(request_id)
+    }
+
+    fn queue(&mut self, frame: Bytes, last: Option<RequestId>){
+        let now = Instant::now();
+        if self.frames.is_empty(){
+            self.oldest = Some(now);
+            self.moved = Some(now);
+        }
+        self.bytes += frame.len();
+        self.frames.push_back(frame);
+        self.queued.push_back(Some(now));
+        self.finals.push_back(last);
+    }
+
+    pub(crate) fn is_empty(&self)->bool{
+        self.frames.is_empty()
+    }
+
+    /// How much longer queued frames may wait for company before they are
+    /// written; `None` once [`COALESCE_BYTES`] are queued or the oldest has
+    /// waited `delay`, and when nothing is queued.
+    pub(crate) fn hold(&self, delay: Duration)->Option<Duration>{
+        let oldest = self.oldest?;
+        if self.bytes >= COALESCE_BYTES{
+            return None;
+        }
+        delay.checked_sub(oldest.elapsed()).filter(|left| !left.is_zero())
+    }
+
+    /// Fills `slices` with the unwritten bytes of the queued frames, in
+    /// order; returns how many were filled.
+    pub(crate) fn slices<'a>(&'a self, slices: &mut [IoSlice<'a>])->usize{
+        let mut frames = self.frames.iter();
+        let mut filled = 0;
+        if let Some(front) = frames.next(){
+            slices[0] = IoSlice::new(&front[self.written..]);
+            filled = 1;
+        }
+        for (slot, frame) in slices[filled..].iter_mut().zip(frames){
+            *slot = IoSlice::new(frame);
+            filled += 1;
+        }
+        filled
+    }
+
+    /// Marks `n` more bytes written, dropping every frame fully sent.
+    pub(crate) fn advance(&mut self, mut n: usize){
+        self.bytes -= n;
+        self.traffic.wrote(n);
+        if n > 0{
+            self.moved = Some(Instant::now());
+        }
+        while let Some(frame) = self.frames.front(){
+            let left = frame.len() - self.written;
+            if n < left{
+                self.written += n;
+                return;
+            }
+            n -= left;
+            if let Some(tape) = &self.tape{
+                tape.outbound(frame);
+            }
+            self.traffic.sent(1);
+            self.frames.pop_front();
+            self.queued.pop_front();
+            if let Some(Some(request_id)) = self.finals.pop_front(){
+                self.writes.remove(&request_id);
+                self.spans.close(request_id);
+            }
+            self.written = 0;
+        }
+        self.oldest = None;
+        self.moved = None;
+    }
+
+    /// Holds queued replies to `timeout`. A search reply that waited that
+    /// long without starting to go out is swapped for a `write_timeout`
+    /// ERROR, and the rest of its stream queued so far is dropped, so one
+    /// slow reply doesn't hold every other request's back; returns the
+    /// requests failed, for the caller to cancel. Fails with `TimedOut` once
+    /// the core took nothing at all for that long.
+    pub(crate) fn expire(&mut self, timeout: Duration)->io::Result<Vec<RequestId>>{
+        self.check_moving(timeout)?;
+        let mut failed = Vec::new();
+        // the frame being written has to go out whole
+        let started = usize::from(self.written > 0);
+        if !self.queued.iter().skip(started).flatten().any(|queued| queued.elapsed() >= timeout){
+            return Ok(failed);
+        }
+
+        let mut kept = started;
+        for i in started..self.frames.len(){
+            let expired = self.queued[i].is_some_and(|queued| queued.elapsed() >= timeout);
+            let reply = if expired || !failed.is_empty(){ search_reply(&self.frames[i]) } else{ None };
+            let replacement = match reply{
+                Some(id) if failed.contains(&id) => None,
+                Some(id) if expired =>{
+                    warn!(request_id = id.0, timeout_ms = timeout.as_millis() as u64, "reply not taken in time, failing the request");
+                    failed.push(id);
+                    // the error ends the request, whatever was still to come
+                    let last = self.spans.get(id).map(|_| id);
+                    handler::write_timed_out(id).map(|error|{
+                        let error = match &self.mac{
+                            Some(mac) => mac.sign(error),
+                            None => error,
+                        };
+                        (error, None, last)
+                    })
                 }
+                // only search replies are failed; others wait their turn
+                _ if expired => Some((self.frames[i].clone(), None, self.finals[i])),
+                _ => Some((self.frames[i].clone(), self.queued[i], self.finals[i])),
+            };
+            if let Some((frame, queued, last)) = replacement{
+                self.frames[kept] = frame;
+                self.queued[kept] = queued;
+                self.finals[kept] = last;
+                kept += 1;
             }
         }
+        self.frames.truncate(kept);
+        self.queued.truncate(kept);
+        self.finals.truncate(kept);
+        self.bytes = self.frames.iter().map(Bytes::len).sum::<usize>() - self.written;
+        Ok(failed)
     }
-    Ok(())
-}
\ No newline at end of file
+
+    /// `TimedOut` once the core took none of the queued bytes for `timeout`.
+    pub(crate) fn check_moving(&self, timeout: Duration)->io::Result<()>{
+        if self.moved.is_some_and(|moved| moved.elapsed() >= timeout){
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("core took no replies for {} ms", timeout.as_millis()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// How long until [`expire`](Self::expire) with `timeout` has anything
+    /// to do; `None` when nothing is queued.
+    pub(crate) fn until_expiry(&self, timeout: Duration)->Option<Duration>{
+        let started = usize::from(self.written > 0);
+        let oldest = self.queued.iter().skip(started).flatten().chain(&self.moved).min()?;
+        Some(timeout.saturating_sub(oldest.elapsed()))
+    }
+
+    /// Writes queued frames until none are left or the socket would block.
+    ///
+    /// Each frame is one contiguous header + payload buffer, and up to
+    /// [`MAX_WRITE_SLICES`] frames go out per vectored write.
+    fn flush(&mut self, stream: &mut impl Transport)->io::Result<()>{
+        while !self.is_empty(){
+            let mut slices = [IoSlice::new(&[]); MAX_WRITE_SLICES];
+            let filled = self.slices(&mut slices);
+            match stream.write_frames(&slices[..filled]){
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => self.advance(n),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        // the stream may still hold bytes of its own, as TLS records
+        match stream.flush_frames(){
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            flushed => flushed,
+        }
+    }
+}
@@ -0,0 +1,688 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::affinity::MAX_CPU;
+use crate::analysis::{self, AnalysisConfig};
+use crate::audit::AuditConfig;
+use crate::auth::AuthConfig;
+use crate::breaker::BreakerConfig;
+use crate::cache::DEFAULT_QUERY_CACHE_CAPACITY;
+#[cfg(feature = "chaos")]
+use crate::chaos::ChaosConfig;
+use crate::connections::ConnectionsConfig;
+use crate::control::ControlConfig;
+use crate::directory::IndexAccess;
+use crate::discovery::{self, SocketSource};
+use crate::federation::PeerConfig;
+use crate::filters::DEFAULT_DATE_FIELD;
+use crate::handshake::HandshakeConfig;
+use crate::health::HealthConfig;
+use crate::heartbeat::HeartbeatConfig;
+use crate::introspect::open_index;
+use crate::peercred::PeerCredentialsConfig;
+use crate::rank::ScoringWeights;
+use crate::profile::ProfileConfig;
+use crate::querylog::QueryLogConfig;
+use crate::ratelimit::RateLimitConfig;
+use crate::reconnect::ReconnectConfig;
+use crate::shutdown::ShutdownConfig;
+use crate::slowlog::SlowLogConfig;
+use crate::statsd::StatsdConfig;
+use crate::telemetry::OtlpConfig;
+use crate::transport::{Endpoint, TlsConfig};
+use crate::warm::DEFAULT_WARM_QUERIES;
+use crate::watchdog::WatchdogConfig;
+use crate::shards::ReaderReload;
+use crate::writer::{
+    CommitPolicy, DEFAULT_WRITER_HEAP_BYTES, MAX_WRITER_HEAP_BYTES, MIN_WRITER_HEAP_BYTES,
+    writer_threads,
+};
+
+/// Where cores have always listened; the last place the socket is
+/// [discovered](crate::discovery).
+pub const DEFAULT_SOCKET_PATH: &str = "/tmp/nerve.sock";
+/// `socket_path` meaning stdin and stdout.
+pub const STDIO_SOCKET: &str = "-";
+
+/// Adapter configuration, loaded from an optional TOML file and overridden by
+/// command-line flags.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// The core's socket; [discovered](crate::discovery) when neither
+    /// `--socket` nor the config file gives one.
+    #[serde(default)]
+    pub socket_path: PathBuf,
+    #[serde(skip)]
+    pub socket_source: SocketSource,
+    /// Sockets of further cores served alongside `socket_path`, all from
+    /// the same index.
+    #[serde(default)]
+    pub core_socket_paths: Vec<PathBuf>,
+    /// Directory holding the tantivy search index. Always explicit: the
+    /// adapter never guesses it from the working directory.
+    pub index_path: PathBuf,
+    /// Additional index directories searched alongside `index_path`; hits
+    /// from every shard are merged by score.
+    #[serde(default)]
+    pub shard_paths: Vec<PathBuf>,
+    /// Downstream adapters/cores that queries are federated to.
+    #[serde(default)]
+    pub peers: Vec<PeerConfig>,
+    /// Default composite ranking weights; requests may override each one.
+    #[serde(default)]
+    pub scoring: ScoringWeights,
+    /// How many engine candidates are rescored by the composite formula.
+    #[serde(default = "default_rerank_depth")]
+    pub rerank_depth: usize,
+    /// Largest `limit` a search runs with; larger requests are clamped and
+    /// their reply says so.
+    #[serde(default = "default_max_limit")]
+    pub max_limit: usize,
+    /// rhai script computing each hit's final score (`scripting` feature).
+    #[serde(default)]
+    pub rescore_script: Option<PathBuf>,
+    /// Time budget for running the rescore script over one result set.
+    #[serde(default = "default_rescore_timeout_ms")]
+    pub rescore_timeout_ms: u64,
+    /// Most popular recent searches re-run after each commit, warming the
+    /// new index readers before traffic reaches them; 0 turns warming off.
+    #[serde(default = "default_warm_queries")]
+    pub warm_queries: usize,
+    /// File the popular searches and known misses are saved to on shutdown
+    /// and restored from at startup. Nothing is kept when unset.
+    #[serde(default)]
+    pub warm_state_path: Option<PathBuf>,
+    /// When buffered index writes are committed automatically.
+    #[serde(default)]
+    pub commit: CommitPolicy,
+    /// Memory the index writer buffers documents in before flushing a
+    /// segment.
+    #[serde(default = "default_writer_heap_bytes")]
+    pub writer_heap_bytes: usize,
+    /// Whether the adapter reopens its engines after each commit.
+    #[serde(default)]
+    pub reader_reload: ReaderReload,
+    /// Whether index files the adapter opens itself are mapped or read.
+    #[serde(default)]
+    pub index_access: IndexAccess,
+    /// Threads executing searches and other reads concurrently.
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+    /// Threads executing index mutations and snapshots, apart from the
+    /// search workers so heavy writes never hold up queries.
+    #[serde(default = "default_index_workers")]
+    pub index_workers: usize,
+    /// CPU cores the search workers are pinned to; unpinned when empty.
+    #[serde(default)]
+    pub search_cpus: Vec<usize>,
+    /// CPU cores the indexing workers are pinned to; unpinned when empty.
+    #[serde(default)]
+    pub index_cpus: Vec<usize>,
+    /// Queries allowed to wait for a worker, per pool; beyond this they are
+    /// answered with an `overloaded` error at once.
+    #[serde(default = "default_queue_depth")]
+    pub queue_depth: usize,
+    /// Engines, each with its own index reader, opened per shard so
+    /// concurrent searches don't share one reader.
+    #[serde(default = "default_searchers_per_shard")]
+    pub searchers_per_shard: usize,
+    /// Engine searches allowed to run at once across all requests (a query
+    /// fans out to one per shard and sort pass); unbounded when unset.
+    #[serde(default)]
+    pub max_in_flight_searches: Option<usize>,
+    /// Deadline for every request, counted from its arrival; queries may set
+    /// their own `timeout_ms`. No deadline when unset.
+    #[serde(default)]
+    pub request_timeout_ms: Option<u64>,
+    /// Memory one query may hold for its hits and serialized reply; past it
+    /// the query is answered with a `memory_budget` error. Unlimited when
+    /// unset.
+    #[serde(default)]
+    pub query_memory_limit_bytes: Option<usize>,
+    /// Small replies may wait this long for others to share their socket
+    /// write; written as soon as they are ready when unset.
+    #[serde(default)]
+    pub write_coalesce_us: Option<u64>,
+    /// How long a reply may wait for the core to take it before its request
+    /// is failed with a `write_timeout` error; a core that takes nothing for
+    /// that long is dropped. Unlimited when unset.
+    #[serde(default)]
+    pub write_timeout_ms: Option<u64>,
+    /// Kernel send buffer for the connection to the core (`SO_SNDBUF`);
+    /// the system default when unset.
+    #[serde(default)]
+    pub send_buffer_bytes: Option<usize>,
+    /// Kernel receive buffer for the connection to the core (`SO_RCVBUF`);
+    /// the system default when unset.
+    #[serde(default)]
+    pub receive_buffer_bytes: Option<usize>,
+    /// Slow request logging.
+    #[serde(default)]
+    pub slowlog: SlowLogConfig,
+    /// Sampled per-phase request timings.
+    #[serde(default)]
+    pub profile: ProfileConfig,
+    /// Where request spans are exported (`otel` feature).
+    #[serde(default)]
+    pub otlp: OtlpConfig,
+    /// StatsD agent per-request metrics are sent to.
+    #[serde(default)]
+    pub statsd: StatsdConfig,
+    /// Append-only record of every request.
+    #[serde(default)]
+    pub audit: AuditConfig,
+    /// Searches' queries, logged apart from the application logs.
+    #[serde(default)]
+    pub querylog: QueryLogConfig,
+    /// HTTP `/healthz` and `/readyz` probes.
+    #[serde(default)]
+    pub health: HealthConfig,
+    /// Reporting connections whose frame loop stalls.
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    /// Local socket taking operator commands.
+    #[serde(default)]
+    pub control: ControlConfig,
+    /// Queries admitted per second, overall and per query.
+    #[serde(default)]
+    pub ratelimit: RateLimitConfig,
+    /// Fast-failing searches while the engine keeps failing.
+    #[serde(default)]
+    pub breaker: BreakerConfig,
+    /// Capture file every frame to and from the core is appended to
+    /// (`--record`); nothing is recorded when unset.
+    #[serde(default)]
+    pub record_path: Option<PathBuf>,
+    /// Capture to replay instead of serving a core (`--replay`, command
+    /// line only).
+    #[serde(skip)]
+    pub replay_path: Option<PathBuf>,
+    /// Reconnecting to the core after the connection drops.
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+    /// Pinging the core to detect a dead peer behind an open socket.
+    #[serde(default)]
+    pub heartbeat: HeartbeatConfig,
+    /// HELLO exchange with the core on connect.
+    #[serde(default)]
+    pub handshake: HandshakeConfig,
+    /// Parallel connections to the core and which one replies go out on.
+    #[serde(default)]
+    pub connections: ConnectionsConfig,
+    /// Certificates for `tls://` cores.
+    #[serde(default)]
+    pub tls: TlsConfig,
+    /// Users and groups a Unix socket core may run as.
+    #[serde(default)]
+    pub peer_credentials: PeerCredentialsConfig,
+    /// Faults injected into every connection's frames (`chaos` feature).
+    #[cfg(feature = "chaos")]
+    #[serde(default)]
+    pub chaos: ChaosConfig,
+    /// Secret the core proves it shares before its queries are served.
+    #[serde(default)]
+    pub auth: AuthConfig,
+    /// How long SIGTERM lets in-flight queries finish.
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    /// Largest frame payload accepted from the core; bigger frames are
+    /// skipped unread and answered with a `payload_too_large` error.
+    #[serde(default = "default_max_payload_bytes")]
+    pub max_payload_bytes: usize,
+    /// Stored hit field that date-range filters apply to.
+    #[serde(default = "default_date_field")]
+    pub date_field: String,
+    /// Per-language query analysis.
+    #[serde(default)]
+    pub analysis: AnalysisConfig,
+    /// Distinct queries whose analyzed form is cached; 0 turns the cache off.
+    #[serde(default = "default_query_cache_capacity")]
+    pub query_cache_capacity: usize,
+    /// Reject every operation that would modify the index files (replicas).
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+pub const DEFAULT_RERANK_DEPTH: usize = 100;
+
+fn default_rerank_depth() -> usize {
+    DEFAULT_RERANK_DEPTH
+}
+
+pub const DEFAULT_MAX_LIMIT: usize = 1_000;
+
+fn default_max_limit() -> usize {
+    DEFAULT_MAX_LIMIT
+}
+
+pub const DEFAULT_RESCORE_TIMEOUT_MS: u64 = 10;
+
+fn default_rescore_timeout_ms() -> u64 {
+    DEFAULT_RESCORE_TIMEOUT_MS
+}
+
+fn default_warm_queries() -> usize {
+    DEFAULT_WARM_QUERIES
+}
+
+pub const DEFAULT_WORKERS: usize = 4;
+
+fn default_workers() -> usize {
+    DEFAULT_WORKERS
+}
+
+fn default_writer_heap_bytes() -> usize {
+    DEFAULT_WRITER_HEAP_BYTES
+}
+
+pub const DEFAULT_INDEX_WORKERS: usize = 1;
+
+fn default_index_workers() -> usize {
+    DEFAULT_INDEX_WORKERS
+}
+
+pub const DEFAULT_QUEUE_DEPTH: usize = 64;
+
+fn default_queue_depth() -> usize {
+    DEFAULT_QUEUE_DEPTH
+}
+
+pub const DEFAULT_SEARCHERS_PER_SHARD: usize = 1;
+
+fn default_searchers_per_shard() -> usize {
+    DEFAULT_SEARCHERS_PER_SHARD
+}
+
+pub const DEFAULT_MAX_PAYLOAD_BYTES: usize = 16 * 1024 * 1024;
+
+fn default_max_payload_bytes() -> usize {
+    DEFAULT_MAX_PAYLOAD_BYTES
+}
+
+fn default_query_cache_capacity() -> usize {
+    DEFAULT_QUERY_CACHE_CAPACITY
+}
+
+fn default_date_field() -> String {
+    DEFAULT_DATE_FIELD.to_string()
+}
+
+impl Config {
+    pub fn new(socket_path: impl Into<PathBuf>, index_path: impl Into<PathBuf>) -> Self {
+        Self {
+            socket_path: socket_path.into(),
+            socket_source: SocketSource::Config,
+            index_path: index_path.into(),
+            shard_paths: Vec::new(),
+            core_socket_paths: Vec::new(),
+            peers: Vec::new(),
+            scoring: ScoringWeights::default(),
+            rerank_depth: DEFAULT_RERANK_DEPTH,
+            max_limit: DEFAULT_MAX_LIMIT,
+            rescore_script: None,
+            rescore_timeout_ms: DEFAULT_RESCORE_TIMEOUT_MS,
+            warm_queries: DEFAULT_WARM_QUERIES,
+            warm_state_path: None,
+            commit: CommitPolicy::default(),
+            writer_heap_bytes: DEFAULT_WRITER_HEAP_BYTES,
+            reader_reload: ReaderReload::default(),
+            index_access: IndexAccess::default(),
+            workers: DEFAULT_WORKERS,
+            index_workers: DEFAULT_INDEX_WORKERS,
+            search_cpus: Vec::new(),
+            index_cpus: Vec::new(),
+            queue_depth: DEFAULT_QUEUE_DEPTH,
+            searchers_per_shard: DEFAULT_SEARCHERS_PER_SHARD,
+            max_in_flight_searches: None,
+            request_timeout_ms: None,
+            query_memory_limit_bytes: None,
+            write_coalesce_us: None,
+            write_timeout_ms: None,
+            send_buffer_bytes: None,
+            receive_buffer_bytes: None,
+            slowlog: SlowLogConfig::default(),
+            profile: ProfileConfig::default(),
+            otlp: OtlpConfig::default(),
+            statsd: StatsdConfig::default(),
+            audit: AuditConfig::default(),
+            querylog: QueryLogConfig::default(),
+            health: HealthConfig::default(),
+            watchdog: WatchdogConfig::default(),
+            control: ControlConfig::default(),
+            ratelimit: RateLimitConfig::default(),
+            breaker: BreakerConfig::default(),
+            record_path: None,
+            replay_path: None,
+            reconnect: ReconnectConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+            handshake: HandshakeConfig::default(),
+            connections: ConnectionsConfig::default(),
+            tls: TlsConfig::default(),
+            peer_credentials: PeerCredentialsConfig::default(),
+            #[cfg(feature = "chaos")]
+            chaos: ChaosConfig::default(),
+            auth: AuthConfig::default(),
+            shutdown: ShutdownConfig::default(),
+            max_payload_bytes: DEFAULT_MAX_PAYLOAD_BYTES,
+            date_field: DEFAULT_DATE_FIELD.to_string(),
+            analysis: AnalysisConfig::default(),
+            query_cache_capacity: DEFAULT_QUERY_CACHE_CAPACITY,
+            read_only: false,
+        }
+    }
+
+    /// Every index directory to search: the primary index first, then shards.
+    pub fn index_paths(&self) -> Vec<PathBuf> {
+        std::iter::once(self.index_path.clone())
+            .chain(self.shard_paths.iter().cloned())
+            .collect()
+    }
+
+    /// Every core to serve: `socket_path` first, then the further cores.
+    pub fn socket_paths(&self) -> Vec<PathBuf> {
+        std::iter::once(self.socket_path.clone())
+            .chain(self.core_socket_paths.iter().cloned())
+            .collect()
+    }
+
+    /// Whether frames come from stdin and replies go to stdout instead of a
+    /// core's socket (`socket_path = "-"`).
+    pub fn uses_stdio(&self) -> bool {
+        self.socket_path == Path::new(STDIO_SOCKET)
+    }
+
+    /// Whether any core is reached over TLS.
+    pub fn uses_tls(&self) -> bool {
+        self.socket_paths()
+            .iter()
+            .any(|socket| matches!(Endpoint::parse(socket), Endpoint::Tls(_)))
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let mut config: Self = toml::from_str(&text)
+            .map_err(|e| invalid(format!("invalid config {}: {e}", path.display())))?;
+        if config.socket_path.as_os_str().is_empty() {
+            (config.socket_path, config.socket_source) = discovery::discover();
+        }
+        Ok(config)
+    }
+
+    /// Builds the config from process arguments (without the program name).
+    ///
+    /// `--config <file>` is read first; `--socket` and `--index` override it
+    /// (without either, the socket is [discovered](crate::discovery)),
+    /// each `--core <socket>` adds a core to serve, `--stdio` serves stdin
+    /// and stdout instead, each `--shard <dir>` adds an index shard, `--workers <n>` sets the
+    /// worker count, `--queue-depth <n>` the request queue bound,
+    /// `--max-in-flight <n>` the concurrent engine searches, `--read-only`
+    /// forbids index mutations and `--profile <file>` turns on profiling.
+    pub fn from_args<I>(args: I) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut config_file = None;
+        let mut socket_path = None;
+        let mut index_path = None;
+        let mut shard_paths = Vec::new();
+        let mut core_socket_paths = Vec::new();
+        let mut read_only = false;
+        let mut workers = None;
+        let mut queue_depth = None;
+        let mut max_in_flight = None;
+        let mut profile = None;
+        let mut record = None;
+        let mut replay = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| invalid(format!("missing value for {arg}")))
+            };
+            match arg.as_str() {
+                "--config" => config_file = Some(PathBuf::from(value()?)),
+                "--socket" => socket_path = Some(PathBuf::from(value()?)),
+                "--index" => index_path = Some(PathBuf::from(value()?)),
+                "--shard" => shard_paths.push(PathBuf::from(value()?)),
+                "--core" => core_socket_paths.push(PathBuf::from(value()?)),
+                "--stdio" => socket_path = Some(PathBuf::from(STDIO_SOCKET)),
+                "--read-only" => read_only = true,
+                "--profile" => profile = Some(PathBuf::from(value()?)),
+                "--record" => record = Some(PathBuf::from(value()?)),
+                "--replay" => replay = Some(PathBuf::from(value()?)),
+                "--workers" => {
+                    let value = value()?;
+                    workers = Some(value.parse().map_err(|_| {
+                        invalid(format!("invalid --workers value: {value}"))
+                    })?);
+                }
+                "--queue-depth" => {
+                    let value = value()?;
+                    queue_depth = Some(value.parse().map_err(|_| {
+                        invalid(format!("invalid --queue-depth value: {value}"))
+                    })?);
+                }
+                "--max-in-flight" => {
+                    let value = value()?;
+                    max_in_flight = Some(value.parse().map_err(|_| {
+                        invalid(format!("invalid --max-in-flight value: {value}"))
+                    })?);
+                }
+                _ => return Err(invalid(format!("unknown argument: {arg}"))),
+            }
+        }
+
+        let mut config = match (config_file, index_path.take()) {
+            (Some(file), index) => {
+                let mut config = Self::load(&file)?;
+                if let Some(index) = index {
+                    config.index_path = index;
+                }
+                config
+            }
+            (None, Some(index)) => {
+                let (socket, source) = discovery::discover();
+                let mut config = Self::new(socket, index);
+                config.socket_source = source;
+                config
+            }
+            (None, None) => {
+                return Err(invalid(
+                    "no search index configured (pass --index <dir> or set index_path in --config)"
+                        .into(),
+                ));
+            }
+        };
+        if let Some(socket) = socket_path {
+            config.socket_path = socket;
+            config.socket_source = SocketSource::Flag;
+        }
+        config.shard_paths.extend(shard_paths);
+        config.core_socket_paths.extend(core_socket_paths);
+        config.read_only |= read_only;
+        if let Some(workers) = workers {
+            config.workers = workers;
+        }
+        if let Some(queue_depth) = queue_depth {
+            config.queue_depth = queue_depth;
+        }
+        if max_in_flight.is_some() {
+            config.max_in_flight_searches = max_in_flight;
+        }
+        if profile.is_some() {
+            config.profile.path = profile;
+        }
+        if record.is_some() {
+            config.record_path = record;
+        }
+        config.replay_path = replay;
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Fails fast on settings that would only blow up later, mid-connection.
+    pub fn validate(&self) -> io::Result<()> {
+        if self.workers == 0 {
+            return Err(invalid("workers must be at least 1".into()));
+        }
+        if self.index_workers == 0 {
+            return Err(invalid("index_workers must be at least 1".into()));
+        }
+        for cpu in self.search_cpus.iter().chain(&self.index_cpus) {
+            if !cfg!(target_os = "linux") {
+                return Err(invalid("search_cpus / index_cpus need Linux".into()));
+            }
+            if *cpu > MAX_CPU {
+                return Err(invalid(format!("no CPU {cpu} to pin workers to")));
+            }
+        }
+        if self.queue_depth == 0 {
+            return Err(invalid("queue_depth must be at least 1".into()));
+        }
+        if self.max_limit == 0 {
+            return Err(invalid("max_limit must be at least 1".into()));
+        }
+        if self.searchers_per_shard == 0 {
+            return Err(invalid("searchers_per_shard must be at least 1".into()));
+        }
+        if self.max_payload_bytes == 0 {
+            return Err(invalid("max_payload_bytes must be at least 1".into()));
+        }
+        if self.query_memory_limit_bytes == Some(0) {
+            return Err(invalid("query_memory_limit_bytes must be at least 1".into()));
+        }
+        if self.max_in_flight_searches == Some(0) {
+            return Err(invalid("max_in_flight_searches must be at least 1".into()));
+        }
+        if self.write_timeout_ms == Some(0) {
+            return Err(invalid("write_timeout_ms must be at least 1".into()));
+        }
+        for (name, bytes) in [
+            ("send_buffer_bytes", self.send_buffer_bytes),
+            ("receive_buffer_bytes", self.receive_buffer_bytes),
+        ] {
+            if bytes.is_some_and(|bytes| bytes == 0 || bytes > i32::MAX as usize) {
+                return Err(invalid(format!("{name} must be between 1 and {}", i32::MAX)));
+            }
+        }
+        self.index_access.validate().map_err(invalid)?;
+        self.otlp.validate().map_err(invalid)?;
+        self.statsd.validate().map_err(invalid)?;
+        self.audit.validate().map_err(invalid)?;
+        self.querylog.validate().map_err(invalid)?;
+        self.health.validate().map_err(invalid)?;
+        self.watchdog.validate().map_err(invalid)?;
+        self.ratelimit.validate().map_err(invalid)?;
+        self.breaker.validate().map_err(invalid)?;
+        #[cfg(feature = "chaos")]
+        self.chaos.validate().map_err(invalid)?;
+        if self.reconnect.initial_backoff_ms == 0
+            || self.reconnect.max_backoff_ms < self.reconnect.initial_backoff_ms
+        {
+            return Err(invalid(
+                "reconnect backoff must be at least 1 ms and max_backoff_ms at least initial_backoff_ms"
+                    .into(),
+            ));
+        }
+        if self.heartbeat.interval_ms == Some(0)
+            || self.heartbeat.idle_timeout_ms == Some(0)
+            || self.heartbeat.max_missed == 0
+        {
+            return Err(invalid(
+                "heartbeat interval_ms, idle_timeout_ms and max_missed must be at least 1".into(),
+            ));
+        }
+        if self.heartbeat.interval_ms.is_some() && self.heartbeat.idle_timeout_ms.is_some() {
+            return Err(invalid(
+                "set heartbeat interval_ms or idle_timeout_ms, not both".into(),
+            ));
+        }
+        if self.handshake.timeout_ms == 0 {
+            return Err(invalid("handshake timeout_ms must be at least 1".into()));
+        }
+        if self.connections.count == 0 {
+            return Err(invalid("connections count must be at least 1".into()));
+        }
+        if self.uses_stdio() && !self.core_socket_paths.is_empty() {
+            return Err(invalid(
+                "stdio serves a single peer: no core_socket_paths with socket_path = \"-\"".into(),
+            ));
+        }
+        self.auth.token()?;
+        if self.uses_stdio() && self.auth.is_enabled() {
+            return Err(invalid(
+                "auth is proven in a core's handshake; stdio has none".into(),
+            ));
+        }
+        if self.uses_tls() {
+            if !cfg!(feature = "tls") {
+                return Err(invalid(
+                    "tls:// cores configured but built without the `tls` feature".into(),
+                ));
+            }
+            if self.tls.ca_path.is_none() {
+                return Err(invalid("tls:// cores need tls.ca_path".into()));
+            }
+        }
+        if self.tls.cert_path.is_some() != self.tls.key_path.is_some() {
+            return Err(invalid(
+                "tls.cert_path and tls.key_path must be set together".into(),
+            ));
+        }
+        let sockets = self.socket_paths();
+        for (i, socket) in sockets.iter().enumerate() {
+            if sockets[..i].contains(socket) {
+                return Err(invalid(format!(
+                    "core socket {} listed twice",
+                    socket.display()
+                )));
+            }
+        }
+        if self.writer_heap_bytes < MIN_WRITER_HEAP_BYTES {
+            return Err(invalid(format!(
+                "writer_heap_bytes must be at least {MIN_WRITER_HEAP_BYTES}"
+            )));
+        }
+        if self.writer_heap_bytes / writer_threads() >= MAX_WRITER_HEAP_BYTES {
+            return Err(invalid(format!(
+                "writer_heap_bytes gives each of {} indexing threads more than {MAX_WRITER_HEAP_BYTES} bytes",
+                writer_threads()
+            )));
+        }
+        for path in self.index_paths() {
+            if !path.is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("search index not found at {}", path.display()),
+                ));
+            }
+        }
+        if !self.analysis.fields.is_empty() {
+            let schema = open_index(&self.index_path)?.schema();
+            analysis::validate_fields(&self.analysis, &schema)?;
+        }
+        if let Some(script) = &self.rescore_script {
+            if !cfg!(feature = "scripting") {
+                return Err(invalid(format!(
+                    "rescore_script {} configured but built without the `scripting` feature",
+                    script.display()
+                )));
+            }
+            if !script.is_file() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("rescore script not found at {}", script.display()),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
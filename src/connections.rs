@@ -0,0 +1,389 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nerve_protocol::types::RequestId;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::state::RequestState;
+use crate::watchdog::Progress;
+
+/// `[connections]` section: how many connections the adapter keeps to each
+/// core and how replies are spread over them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ConnectionsConfig {
+    /// Connections opened to each core, each served on its own thread and
+    /// all sharing the workers.
+    pub count: usize,
+    pub balance: Balance,
+}
+
+impl Default for ConnectionsConfig {
+    fn default() -> Self {
+        Self {
+            count: 1,
+            balance: Balance::default(),
+        }
+    }
+}
+
+/// Which connection a query's reply goes out on. Replies always stay with
+/// the core that sent the query.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Balance {
+    /// The connection the query came in on.
+    #[default]
+    Origin,
+    /// The core's connections that are up, in turn, one request at a time.
+    RoundRobin,
+    /// The connection picked by the request id, so a core can tell where
+    /// a reply will arrive.
+    RequestId,
+}
+
+/// Health of each connection the adapter keeps to its cores, by slot.
+pub struct Connections {
+    slots: Vec<Health>,
+}
+
+impl Connections {
+    pub fn new(count: usize) -> Self {
+        Self {
+            slots: (0..count).map(|_| Health::default()).collect(),
+        }
+    }
+
+    /// `per_core` slots for each of `sockets`, in order.
+    pub fn for_cores(sockets: &[PathBuf], per_core: usize) -> Self {
+        let slots = sockets
+            .iter()
+            .flat_map(|socket| std::iter::repeat_n(socket, per_core))
+            .map(|socket| Health {
+                socket: Some(socket.clone()),
+                ..Health::default()
+            })
+            .collect();
+        Self { slots }
+    }
+
+    pub fn slot(&self, slot: usize) -> &Health {
+        &self.slots[slot]
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Connections currently up.
+    pub fn connected(&self) -> usize {
+        self.slots
+            .iter()
+            .filter(|health| health.connected.load(Ordering::Relaxed))
+            .count()
+    }
+
+    pub fn snapshot(&self) -> Vec<HealthSnapshot> {
+        self.slots
+            .iter()
+            .enumerate()
+            .map(|(slot, health)| health.snapshot(slot))
+            .collect()
+    }
+}
+
+impl Default for Connections {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+/// One connection's state and history.
+#[derive(Default)]
+pub struct Health {
+    // the core's socket, when there may be several cores
+    socket: Option<PathBuf>,
+    connected: AtomicBool,
+    // milliseconds since the Unix epoch of the last connect
+    connected_at: AtomicU64,
+    sessions: AtomicU64,
+    failures: AtomicU64,
+    last_error: Mutex<Option<String>>,
+    disconnects: Mutex<BTreeMap<&'static str, u64>>,
+    traffic: Arc<Traffic>,
+    progress: Arc<Progress>,
+    // the connection being served, while there is one
+    serving: Mutex<Weak<RequestState>>,
+    // requests the last connection dropped unanswered
+    orphans: Mutex<Vec<RequestId>>,
+}
+
+impl Health {
+    /// Notes a connection established.
+    pub fn up(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.connected_at
+            .store(now.as_millis() as u64, Ordering::Relaxed);
+        self.connected.store(true, Ordering::Relaxed);
+        self.sessions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Notes how a connection ended.
+    pub fn down(&self, served: &io::Result<()>) {
+        self.connected.store(false, Ordering::Relaxed);
+        self.progress.idle();
+        *self
+            .disconnects
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(disconnect_reason(served))
+            .or_default() += 1;
+        if let Err(e) = served {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+            *self.last_error() = Some(e.to_string());
+        }
+    }
+
+    /// What every connection in this slot carried, counted as it goes.
+    pub fn traffic(&self) -> Arc<Traffic> {
+        Arc::clone(&self.traffic)
+    }
+
+    /// Where the frame loop of every connection in this slot is.
+    pub fn progress(&self) -> Arc<Progress> {
+        Arc::clone(&self.progress)
+    }
+
+    /// Keeps whatever `state`'s connection leaves unanswered when the
+    /// returned guard drops, for the next connection in this slot to report.
+    pub fn track(&self, state: Arc<RequestState>) -> Orphans<'_> {
+        *self.serving.lock().unwrap_or_else(|e| e.into_inner()) = Arc::downgrade(&state);
+        Orphans {
+            health: self,
+            state,
+        }
+    }
+
+    /// Requests the last connection in this slot dropped unanswered; each
+    /// is handed out once.
+    pub fn take_orphans(&self) -> Vec<RequestId> {
+        std::mem::take(&mut *self.orphans())
+    }
+
+    /// The requests the connection being served has not answered yet.
+    pub fn unanswered(&self) -> Vec<RequestId> {
+        let serving = self.serving.lock().unwrap_or_else(|e| e.into_inner());
+        serving
+            .upgrade()
+            .map(|state| state.unanswered_requests())
+            .unwrap_or_default()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    fn snapshot(&self, slot: usize) -> HealthSnapshot {
+        let sessions = self.sessions.load(Ordering::Relaxed);
+        let traffic = &self.traffic;
+        HealthSnapshot {
+            connection: slot,
+            socket: self.socket.clone(),
+            connected: self.is_connected(),
+            connected_at_ms: Some(self.connected_at.load(Ordering::Relaxed))
+                .filter(|_| sessions > 0),
+            sessions,
+            reconnects: sessions.saturating_sub(1),
+            failures: self.failures.load(Ordering::Relaxed),
+            last_error: self.last_error().clone(),
+            disconnects: self
+                .disconnects
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+            bytes_in: traffic.bytes_in.load(Ordering::Relaxed),
+            bytes_out: traffic.bytes_out.load(Ordering::Relaxed),
+            frames_in: traffic.frames_in.load(Ordering::Relaxed),
+            frames_out: traffic.frames_out.load(Ordering::Relaxed),
+        }
+    }
+
+    fn last_error(&self) -> MutexGuard<'_, Option<String>> {
+        self.last_error.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn orphans(&self) -> MutexGuard<'_, Vec<RequestId>> {
+        self.orphans.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A connection's requests, checked for ones left unanswered as it ends.
+pub struct Orphans<'a> {
+    health: &'a Health,
+    state: Arc<RequestState>,
+}
+
+impl Drop for Orphans<'_> {
+    fn drop(&mut self) {
+        let orphaned = self.state.unanswered_requests();
+        if !orphaned.is_empty() {
+            warn!(
+                requests = orphaned.len(),
+                "connection dropped with requests unanswered"
+            );
+            self.health.orphans().extend(orphaned);
+        }
+    }
+}
+
+/// A connection's health as reported by the `metrics` operation.
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthSnapshot {
+    pub connection: usize,
+    pub socket: Option<PathBuf>,
+    pub connected: bool,
+    /// When the connection was last established, in milliseconds since
+    /// the Unix epoch.
+    pub connected_at_ms: Option<u64>,
+    /// Times the connection was established.
+    pub sessions: u64,
+    /// Times it was established again after the first.
+    pub reconnects: u64,
+    /// Times it ended in an error.
+    pub failures: u64,
+    pub last_error: Option<String>,
+    /// How often it ended, by reason: `hung_up`, `timed_out`, `refused`,
+    /// `reset` or `error`.
+    pub disconnects: BTreeMap<&'static str, u64>,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub frames_in: u64,
+    pub frames_out: u64,
+}
+
+/// Why a connection ended, as counted in [`HealthSnapshot::disconnects`].
+fn disconnect_reason(served: &io::Result<()>) -> &'static str {
+    let Err(e) = served else {
+        return "hung_up";
+    };
+    match e.kind() {
+        io::ErrorKind::TimedOut => "timed_out",
+        io::ErrorKind::Unsupported | io::ErrorKind::PermissionDenied => "refused",
+        io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::BrokenPipe
+        | io::ErrorKind::UnexpectedEof => "reset",
+        _ => "error",
+    }
+}
+
+/// Bytes and frames carried to and from a core, counted by the I/O loops:
+/// bytes as read and written, frames as decoded and as fully written.
+#[derive(Debug, Default)]
+pub struct Traffic {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    frames_in: AtomicU64,
+    frames_out: AtomicU64,
+}
+
+impl Traffic {
+    pub fn read(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn received(&self, frames: usize) {
+        self.frames_in.fetch_add(frames as u64, Ordering::Relaxed);
+    }
+
+    pub fn wrote(&self, bytes: usize) {
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn sent(&self, frames: usize) {
+        self.frames_out.fetch_add(frames as u64, Ordering::Relaxed);
+    }
+}
+
+/// Reply senders of the connections currently reading, for workers to
+/// spread replies over as the [`Balance`] says.
+pub struct Routes<R> {
+    balance: Balance,
+    per_core: usize,
+    next: AtomicUsize,
+    senders: Mutex<Vec<Option<R>>>,
+}
+
+impl<R: Clone> Routes<R> {
+    /// Routes for `cores` cores with `per_core` connections each; slots
+    /// are numbered core by core.
+    pub fn new(balance: Balance, cores: usize, per_core: usize) -> Self {
+        Self {
+            balance,
+            per_core,
+            next: AtomicUsize::new(0),
+            senders: Mutex::new(vec![None; cores * per_core]),
+        }
+    }
+
+    /// Offers `sender` for replies to other connections' queries until the
+    /// returned route is dropped.
+    pub fn open(&self, slot: usize, sender: R) -> Route<'_, R> {
+        self.senders()[slot] = Some(sender);
+        Route { routes: self, slot }
+    }
+
+    /// The sender for replies to `request_id`, a query that came in on
+    /// connection `slot`, whose own sender is `origin`. Picks among the
+    /// same core's connections; falls back to `origin` when none is open.
+    pub fn pick(&self, request_id: RequestId, slot: usize, origin: &R) -> R {
+        let turn = match self.balance {
+            Balance::Origin => return origin.clone(),
+            Balance::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed) as u64,
+            Balance::RequestId => request_id.0,
+        };
+        let senders = self.senders();
+        let first = slot / self.per_core * self.per_core;
+        let open: Vec<&R> = senders[first..first + self.per_core]
+            .iter()
+            .flatten()
+            .collect();
+        if open.is_empty() {
+            return origin.clone();
+        }
+        open[(turn % open.len() as u64) as usize].clone()
+    }
+
+    fn senders(&self) -> MutexGuard<'_, Vec<Option<R>>> {
+        self.senders.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A connection's place in [`Routes`]; dropping it closes the connection
+/// to replies for other connections' queries.
+pub struct Route<'a, R> {
+    routes: &'a Routes<R>,
+    slot: usize,
+}
+
+impl<R> Drop for Route<'_, R> {
+    fn drop(&mut self) {
+        let mut senders = self
+            .routes
+            .senders
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        senders[self.slot] = None;
+    }
+}
@@ -0,0 +1,238 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::analysis::Analyzers;
+use crate::audit::AuditLog;
+use crate::auth::Secret;
+use crate::breaker::CircuitBreaker;
+use crate::budget::MemoryBudget;
+use crate::cache::{NegativeCache, QueryCache};
+use crate::capture::Recorder;
+#[cfg(feature = "chaos")]
+use crate::chaos::Chaos;
+use crate::config::{Config, DEFAULT_MAX_LIMIT, DEFAULT_RERANK_DEPTH};
+use crate::connections::Connections;
+use crate::federation::Federation;
+use crate::filters::DEFAULT_DATE_FIELD;
+use crate::inflight::InFlight;
+use crate::metrics::{Counters, Latencies, ShardLatencies};
+use crate::profile::Profiler;
+use crate::querylog::QueryLog;
+use crate::rank::ScoringWeights;
+use crate::ratelimit::RateLimiter;
+#[cfg(feature = "scripting")]
+use crate::script::Rescorer;
+use crate::shards::{ReaderReload, Shards};
+use crate::shutdown::Shutdown;
+use crate::slowlog::SlowLog;
+use crate::statsd::Statsd;
+use crate::transport::TlsClient;
+use crate::usage::Usage;
+use crate::vector::{Embedder, VectorIndex};
+use crate::warm::{self, DEFAULT_WARM_QUERIES, PopularQueries};
+use crate::writer::DocumentWriter;
+
+/// Everything a request handler needs besides the frame itself.
+///
+/// Shared read-only by the worker threads; the few mutable parts lock.
+pub struct Context {
+    pub shards: Shards,
+    pub federation: Federation,
+    pub misses: Mutex<NegativeCache>,
+    pub queries: Mutex<QueryCache>,
+    /// Searches running right now, shared with identical arrivals.
+    pub in_flight: InFlight,
+    pub vectors: Option<VectorIndex>,
+    pub embedder: Option<Box<dyn Embedder>>,
+    pub scoring: ScoringWeights,
+    pub rerank_depth: usize,
+    /// Largest `limit` a search is run with; larger requests are clamped.
+    pub max_limit: usize,
+    pub analyzers: Analyzers,
+    /// Hit field holding the crawl/publish time, for date filters.
+    pub date_field: String,
+    #[cfg(feature = "scripting")]
+    pub rescorer: Option<Rescorer>,
+    pub writer: DocumentWriter,
+    /// Whether committing also reopens the shards' engines.
+    pub reader_reload: ReaderReload,
+    /// Refuse index mutations (see [`Request::is_mutation`]).
+    ///
+    /// [`Request::is_mutation`]: crate::request::Request::is_mutation
+    pub read_only: bool,
+    /// Default per-request deadline, counted from arrival.
+    pub request_timeout: Option<Duration>,
+    /// Memory each query may hold for its hits and serialized reply.
+    pub memory_budget: MemoryBudget,
+    pub slowlog: SlowLog,
+    pub latency: Latencies,
+    /// Engine time per shard, for the `metrics` operation.
+    pub shard_latency: ShardLatencies,
+    /// Request counts behind the `stats` operation.
+    pub counters: Arc<Counters>,
+    /// When the adapter started, for the `stats` operation's uptime.
+    pub started: Instant,
+    pub profiler: Profiler,
+    /// Per-request metrics sent to a StatsD agent.
+    pub statsd: Statsd,
+    /// Every finished request, appended to the audit log.
+    pub audit: AuditLog,
+    /// Every search's query, in its own rotated file.
+    pub querylog: QueryLog,
+    /// Admits queries before they are queued, across every connection.
+    pub ratelimit: Arc<RateLimiter>,
+    /// Fast-fails searches while the engine keeps failing.
+    pub breaker: CircuitBreaker,
+    /// Captures every connection's frames (`--record`).
+    pub recorder: Recorder,
+    /// Faults for every connection's frames (`chaos` feature).
+    #[cfg(feature = "chaos")]
+    pub chaos: Chaos,
+    /// Health of each connection to the core.
+    pub connections: Connections,
+    /// Client for `tls://` cores, when there are any.
+    pub tls: Option<TlsClient>,
+    /// Secret the core proves it shares in the handshake, with `[auth]` on.
+    pub auth: Option<Secret>,
+    /// Drained on SIGTERM; every connection is watched by it.
+    pub shutdown: Shutdown,
+    pub popular: PopularQueries,
+    /// Which modes, sorts, filters and hit domains searches use.
+    pub usage: Usage,
+    /// Popular searches re-run after a commit; 0 turns warming off.
+    pub warm_queries: usize,
+    /// Where popular searches and known misses are kept across restarts.
+    pub warm_state: Option<PathBuf>,
+}
+
+impl Context {
+    pub fn new(shards: Shards) -> Self {
+        let primary = shards
+            .iter()
+            .next()
+            .map(|s| s.path.clone())
+            .unwrap_or_default();
+        Self {
+            shards,
+            federation: Federation::default(),
+            misses: Mutex::new(NegativeCache::default()),
+            queries: Mutex::new(QueryCache::default()),
+            in_flight: InFlight::default(),
+            vectors: None,
+            embedder: None,
+            scoring: ScoringWeights::default(),
+            rerank_depth: DEFAULT_RERANK_DEPTH,
+            max_limit: DEFAULT_MAX_LIMIT,
+            analyzers: Analyzers::default(),
+            date_field: DEFAULT_DATE_FIELD.to_string(),
+            #[cfg(feature = "scripting")]
+            rescorer: None,
+            writer: DocumentWriter::new(primary),
+            reader_reload: ReaderReload::default(),
+            read_only: false,
+            request_timeout: None,
+            memory_budget: MemoryBudget::default(),
+            slowlog: SlowLog::default(),
+            latency: Latencies::default(),
+            shard_latency: ShardLatencies::default(),
+            counters: Arc::default(),
+            started: Instant::now(),
+            profiler: Profiler::default(),
+            statsd: Statsd::default(),
+            audit: AuditLog::default(),
+            querylog: QueryLog::default(),
+            ratelimit: Arc::default(),
+            breaker: CircuitBreaker::default(),
+            recorder: Recorder::default(),
+            #[cfg(feature = "chaos")]
+            chaos: Chaos::default(),
+            connections: Connections::default(),
+            tls: None,
+            auth: None,
+            shutdown: Shutdown::default(),
+            popular: PopularQueries::default(),
+            usage: Usage::default(),
+            warm_queries: DEFAULT_WARM_QUERIES,
+            warm_state: None,
+        }
+    }
+
+    /// Turns text-only vector queries into embeddings with `embedder`.
+    pub fn with_embedder(mut self, embedder: impl Embedder + 'static) -> Self {
+        self.embedder = Some(Box::new(embedder));
+        self
+    }
+
+    /// The negative cache, shared by all workers.
+    pub fn misses(&self) -> MutexGuard<'_, NegativeCache> {
+        self.misses.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The analyzed query cache, shared by all workers.
+    pub fn queries(&self) -> MutexGuard<'_, QueryCache> {
+        self.queries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Directory of the primary index: the target of writes and admin ops.
+    pub fn primary_index(&self) -> &Path {
+        self.shards
+            .iter()
+            .next()
+            .map(|shard| shard.path.as_path())
+            .unwrap_or(Path::new(""))
+    }
+
+    pub fn from_config(config: &Config) -> io::Result<Self> {
+        let shards = Shards::open(&config.index_paths())?
+            .with_searchers(config.searchers_per_shard)?
+            .with_max_in_flight(config.max_in_flight_searches)
+            .with_access(config.index_access);
+        let mut context = Self::new(shards);
+        context.federation = Federation::new(config.peers.clone());
+        context.vectors = VectorIndex::load(&config.index_path)?;
+        context.scoring = config.scoring;
+        context.rerank_depth = config.rerank_depth;
+        context.max_limit = config.max_limit;
+        context.analyzers = Analyzers::new(config.analysis.clone());
+        context.queries = Mutex::new(QueryCache::new(config.query_cache_capacity));
+        context.date_field = config.date_field.clone();
+        context.writer = DocumentWriter::with_policy(&config.index_path, config.commit)
+            .with_heap_bytes(config.writer_heap_bytes)
+            .with_access(config.index_access);
+        context.reader_reload = config.reader_reload;
+        context.read_only = config.read_only;
+        context.request_timeout = config.request_timeout_ms.map(Duration::from_millis);
+        context.memory_budget = MemoryBudget::new(config.query_memory_limit_bytes);
+        context.slowlog = SlowLog::new(&config.slowlog);
+        context.profiler = Profiler::new(&config.profile);
+        context.statsd = Statsd::open(&config.statsd)?;
+        context.audit = AuditLog::open(&config.audit)?;
+        context.querylog = QueryLog::open(&config.querylog)?;
+        context.ratelimit = Arc::new(RateLimiter::new(&config.ratelimit));
+        context.breaker = CircuitBreaker::new(&config.breaker);
+        context.recorder = Recorder::open(config.record_path.as_deref())?;
+        #[cfg(feature = "chaos")]
+        {
+            context.chaos = Chaos::new(config.chaos);
+        }
+        context.connections =
+            Connections::for_cores(&config.socket_paths(), config.connections.count);
+        #[cfg(feature = "tls")]
+        if config.uses_tls() {
+            context.tls = Some(TlsClient::new(&config.tls)?);
+        }
+        context.auth = Secret::from_config(&config.auth)?;
+        context.shutdown = Shutdown::new(config.shutdown);
+        context.warm_queries = config.warm_queries;
+        context.warm_state = config.warm_state_path.clone();
+        #[cfg(feature = "scripting")]
+        if let Some(script) = &config.rescore_script {
+            let timeout = Duration::from_millis(config.rescore_timeout_ms);
+            context.rescorer = Some(Rescorer::load(script, timeout)?);
+        }
+        warm::restore(&context)?;
+        Ok(context)
+    }
+}
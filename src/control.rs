@@ -0,0 +1,229 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tracing::level_filters::LevelFilter;
+use tracing::{info, warn};
+
+use crate::context::Context;
+use crate::handler;
+use crate::request::DEFAULT_TOP_QUERIES;
+use crate::telemetry;
+
+// how often an idle listener or session looks for the adapter stopping
+const POLL: Duration = Duration::from_millis(100);
+
+/// `[control]` section: a local socket operators send commands to without
+/// going through the core.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ControlConfig {
+    /// Unix socket commands are taken on, readable by the adapter's user
+    /// only; not opened when unset.
+    pub socket: Option<PathBuf>,
+}
+
+/// An operator command, one JSON object per line, as
+/// `{"command": "set_log_level", "level": "debug"}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    /// The `stats` counters, connections up and the log level.
+    Status,
+    /// Reopens the index readers, as after a commit.
+    ReloadIndex,
+    /// Empties the analyzed-query and negative caches.
+    FlushCache,
+    /// Logs at `level` (`error` to `trace`, or `off`) from now on.
+    SetLogLevel { level: String },
+    /// Every connection's health, unanswered requests and frame loop.
+    DumpState,
+    /// The `limit` (default 10) most asked and latest search queries.
+    TopQueries { limit: Option<usize> },
+}
+
+/// Takes commands on the control socket, answering each with one JSON
+/// line: `"ok": true` and what the command reports, or `"ok": false` and
+/// an `error`. The socket file is removed when this drops.
+#[derive(Debug)]
+pub struct ControlSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl ControlSocket {
+    /// Opens the socket `config` names, if it names one, replacing one left
+    /// behind by an earlier run.
+    pub fn bind(config: &ControlConfig) -> io::Result<Option<Self>> {
+        let Some(path) = &config.socket else {
+            return Ok(None);
+        };
+        if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        listener.set_nonblocking(true)?;
+        info!(socket = %path.display(), "taking operator commands");
+        Ok(Some(Self {
+            listener,
+            path: path.clone(),
+        }))
+    }
+
+    /// Answers commands until `stop` is set, each connection on its own
+    /// thread.
+    pub fn serve(&self, context: &Context, stop: &AtomicBool) {
+        thread::scope(|s| {
+            while !stop.load(Ordering::Relaxed) {
+                match self.listener.accept() {
+                    Ok((stream, _)) => {
+                        s.spawn(move || {
+                            if let Err(e) = session(stream, context, stop) {
+                                warn!(error = %e, "control connection failed");
+                            }
+                        });
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        warn!(error = %e, "control connection not accepted");
+                        thread::sleep(POLL);
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn session(stream: UnixStream, context: &Context, stop: &AtomicBool) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(POLL))?;
+    let mut replies = stream.try_clone()?;
+    let mut commands = BufReader::new(stream);
+    let mut line = String::new();
+    while !stop.load(Ordering::Relaxed) {
+        // a read timing out keeps what it got of the line
+        match commands.read_line(&mut line) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue;
+            }
+            Err(e) => return Err(e),
+        }
+        if !line.trim().is_empty() {
+            let mut reply = run(&line, context).to_string();
+            reply.push('\n');
+            replies.write_all(reply.as_bytes())?;
+        }
+        line.clear();
+    }
+    Ok(())
+}
+
+/// Runs the command on `line` and returns its reply.
+pub fn run(line: &str, context: &Context) -> Value {
+    let command = match serde_json::from_str::<Command>(line) {
+        Ok(command) => command,
+        Err(e) => return json!({"ok": false, "error": format!("bad command: {e}")}),
+    };
+    info!(?command, "operator command");
+    match execute(command, context) {
+        Ok(Value::Object(mut reply)) => {
+            reply.insert("ok".into(), json!(true));
+            Value::Object(reply)
+        }
+        Ok(reply) => json!({"ok": true, "result": reply}),
+        Err(e) => json!({"ok": false, "error": e.to_string()}),
+    }
+}
+
+fn execute(command: Command, context: &Context) -> io::Result<Value> {
+    match command {
+        Command::Status => {
+            let mut status = handler::stats(context);
+            status["connected"] = json!(context.connections.connected());
+            status["connections"] = json!(context.connections.len());
+            status["shards"] = json!(context.shards.len());
+            status["log_level"] = json!(telemetry::log_level().map(|level| level.to_string()));
+            Ok(status)
+        }
+        Command::ReloadIndex => {
+            context.shards.reload()?;
+            // as after a commit: earlier misses may hit now
+            context.misses().invalidate();
+            context.popular.mark_stale();
+            Ok(json!({"shards": context.shards.len()}))
+        }
+        Command::FlushCache => {
+            let queries = context.queries().stats().entries;
+            context.queries().clear();
+            let misses = context.misses().len();
+            context.misses().invalidate();
+            Ok(json!({"query_cache_entries": queries, "negative_cache_entries": misses}))
+        }
+        Command::SetLogLevel { level } => {
+            let level: LevelFilter = level.parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown log level {level:?}"),
+                )
+            })?;
+            telemetry::set_log_level(level)?;
+            Ok(json!({"log_level": level.to_string()}))
+        }
+        Command::DumpState => {
+            let connections: Vec<_> = context
+                .connections
+                .snapshot()
+                .into_iter()
+                .map(|snapshot| {
+                    let health = context.connections.slot(snapshot.connection);
+                    let progress = health.progress();
+                    let mut state = json!(snapshot);
+                    state["unanswered"] = json!(
+                        health
+                            .unanswered()
+                            .iter()
+                            .map(|id| id.0)
+                            .collect::<Vec<_>>()
+                    );
+                    state["busy_since_ms"] = json!(progress.busy_since());
+                    state["last_frame"] =
+                        json!(progress.last_frame().map(|(request_id, msg_type)| {
+                            json!({"request_id": request_id, "msg_type": msg_type})
+                        }));
+                    state
+                })
+                .collect();
+            Ok(json!({
+                "stats": handler::stats(context),
+                "connections": connections,
+                "shutting_down": context.shutdown.is_started(),
+            }))
+        }
+        Command::TopQueries { limit } => Ok(handler::top_queries(
+            context,
+            limit.unwrap_or(DEFAULT_TOP_QUERIES),
+        )),
+    }
+}
@@ -0,0 +1,86 @@
+use serde_json::Value;
+
+use crate::introspect::fnv1a;
+
+/// Hits whose simhashes differ in at most this many bits are near-duplicates.
+pub const DEFAULT_MAX_DISTANCE: u32 = 3;
+
+/// Stored field holding a precomputed 64-bit simhash (number or hex string).
+pub const SIMHASH_FIELD: &str = "simhash";
+
+/// Fields hashed, first present wins, when a hit carries no stored simhash.
+const TEXT_FIELDS: &[&str] = &["content", "title"];
+
+/// 64-bit simhash over word 3-shingles of `text` (single words for shorter
+/// texts), so pages sharing most of their wording land a few bits apart.
+pub fn simhash(text: &str) -> u64 {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    let shingle = words.len().min(3);
+    if shingle == 0 {
+        return 0;
+    }
+
+    let mut weights = [0i32; 64];
+    for window in words.windows(shingle) {
+        let hash = fnv1a(window.join(" ").as_bytes());
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            if hash >> bit & 1 == 1 {
+                *weight += 1;
+            } else {
+                *weight -= 1;
+            }
+        }
+    }
+    weights
+        .iter()
+        .enumerate()
+        .filter(|(_, weight)| **weight > 0)
+        .fold(0, |hash, (bit, _)| hash | 1 << bit)
+}
+
+/// The hit's stored simhash, else one computed from its text.
+pub fn hit_simhash(hit: &Value) -> Option<u64> {
+    match hit.get(SIMHASH_FIELD).map(first) {
+        Some(Value::Number(n)) => return n.as_u64(),
+        Some(Value::String(s)) => return u64::from_str_radix(s.trim_start_matches("0x"), 16).ok(),
+        _ => {}
+    }
+    TEXT_FIELDS
+        .iter()
+        .find_map(|field| hit.get(*field).map(first)?.as_str())
+        .map(simhash)
+}
+
+// stored tantivy values come back as single-element arrays
+fn first(value: &Value) -> &Value {
+    match value {
+        Value::Array(values) => values.first().unwrap_or(&Value::Null),
+        value => value,
+    }
+}
+
+/// Drops every hit within `max_distance` bits of a better-ranked one.
+///
+/// Order is preserved; hits without text or simhash are always kept.
+pub fn dedup(hits: Vec<Value>, max_distance: u32) -> Vec<Value> {
+    let mut kept_hashes: Vec<u64> = Vec::with_capacity(hits.len());
+    hits.into_iter()
+        .filter(|hit| {
+            let Some(hash) = hit_simhash(hit) else {
+                return true;
+            };
+            if kept_hashes
+                .iter()
+                .any(|kept| (kept ^ hash).count_ones() <= max_distance)
+            {
+                return false;
+            }
+            kept_hashes.push(hash);
+            true
+        })
+        .collect()
+}
@@ -0,0 +1,200 @@
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Deserialize;
+use tantivy::directory::error::{
+    DeleteError, LockError, OpenDirectoryError, OpenReadError, OpenWriteError,
+};
+use tantivy::directory::{
+    Directory, DirectoryLock, FileHandle, Lock, MmapDirectory, OwnedBytes, WatchCallback,
+    WatchHandle, WritePtr,
+};
+use tantivy::{HasLen, Index};
+
+use crate::introspect::tantivy_error;
+
+/// `[index_access]` section: how the adapter reads index files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct IndexAccess {
+    pub mode: AccessMode,
+    /// Access pattern hint passed to `madvise` for mapped files.
+    pub advice: Option<MmapAdvice>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessMode {
+    /// Map files into memory and let the page cache decide what stays.
+    #[default]
+    Mmap,
+    /// Read each requested range with `pread` into a fresh buffer: nothing
+    /// is mapped, which suits network filesystems and hosts short on memory.
+    Buffered,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MmapAdvice {
+    Normal,
+    Random,
+    Sequential,
+    WillNeed,
+}
+
+impl IndexAccess {
+    pub fn validate(&self) -> Result<(), String> {
+        match (self.mode, self.advice) {
+            (AccessMode::Buffered, Some(_)) => {
+                Err("index_access.advice only applies to mode = \"mmap\"".into())
+            }
+            (AccessMode::Mmap, Some(_)) if !cfg!(unix) => {
+                Err("index_access.advice needs a unix host".into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Opens the index at `path`, reading its files as `access` says.
+pub fn open_index(path: &Path, access: IndexAccess) -> io::Result<Index> {
+    let mmap = mmap_directory(path, access.advice).map_err(io::Error::other)?;
+    let index = match access.mode {
+        AccessMode::Mmap => Index::open(mmap),
+        AccessMode::Buffered => Index::open(BufferedDirectory {
+            root: path.to_path_buf(),
+            inner: mmap,
+        }),
+    };
+    index.map_err(tantivy_error)
+}
+
+#[cfg(unix)]
+fn mmap_directory(
+    path: &Path,
+    advice: Option<MmapAdvice>,
+) -> Result<MmapDirectory, OpenDirectoryError> {
+    use tantivy::directory::Advice;
+
+    let Some(advice) = advice else {
+        return MmapDirectory::open(path);
+    };
+    let advice = match advice {
+        MmapAdvice::Normal => Advice::Normal,
+        MmapAdvice::Random => Advice::Random,
+        MmapAdvice::Sequential => Advice::Sequential,
+        MmapAdvice::WillNeed => Advice::WillNeed,
+    };
+    MmapDirectory::open_with_madvice(path, advice)
+}
+
+#[cfg(not(unix))]
+fn mmap_directory(
+    path: &Path,
+    _advice: Option<MmapAdvice>,
+) -> Result<MmapDirectory, OpenDirectoryError> {
+    MmapDirectory::open(path)
+}
+
+/// An [`MmapDirectory`] whose files are read with positioned reads instead
+/// of being mapped. Writes, locks and change notifications are the mmap
+/// directory's own, which never maps anything for them.
+#[derive(Debug, Clone)]
+struct BufferedDirectory {
+    root: PathBuf,
+    inner: MmapDirectory,
+}
+
+impl Directory for BufferedDirectory {
+    fn get_file_handle(&self, path: &Path) -> Result<Arc<dyn FileHandle>, OpenReadError> {
+        let full = self.root.join(path);
+        let open = || -> io::Result<BufferedFile> {
+            let file = File::open(&full)?;
+            let len = file.metadata()?.len() as usize;
+            Ok(BufferedFile { file, len })
+        };
+        match open() {
+            Ok(file) => Ok(Arc::new(file)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Err(OpenReadError::FileDoesNotExist(path.to_path_buf()))
+            }
+            Err(e) => Err(OpenReadError::wrap_io_error(e, path.to_path_buf())),
+        }
+    }
+
+    fn delete(&self, path: &Path) -> Result<(), DeleteError> {
+        self.inner.delete(path)
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool, OpenReadError> {
+        self.inner.exists(path)
+    }
+
+    fn open_write(&self, path: &Path) -> Result<WritePtr, OpenWriteError> {
+        self.inner.open_write(path)
+    }
+
+    fn atomic_read(&self, path: &Path) -> Result<Vec<u8>, OpenReadError> {
+        self.inner.atomic_read(path)
+    }
+
+    fn atomic_write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        self.inner.atomic_write(path, data)
+    }
+
+    fn sync_directory(&self) -> io::Result<()> {
+        self.inner.sync_directory()
+    }
+
+    fn acquire_lock(&self, lock: &Lock) -> Result<DirectoryLock, LockError> {
+        self.inner.acquire_lock(lock)
+    }
+
+    fn watch(&self, watch_callback: WatchCallback) -> tantivy::Result<WatchHandle> {
+        self.inner.watch(watch_callback)
+    }
+}
+
+#[derive(Debug)]
+struct BufferedFile {
+    file: File,
+    len: usize,
+}
+
+impl HasLen for BufferedFile {
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl FileHandle for BufferedFile {
+    fn read_bytes(&self, range: Range<usize>) -> io::Result<OwnedBytes> {
+        let mut buf = vec![0u8; range.len()];
+        read_at(&self.file, &mut buf, range.start as u64)?;
+        Ok(OwnedBytes::new(buf))
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::config::DEFAULT_SOCKET_PATH;
+
+/// Environment variable naming the core's socket.
+pub const SOCKET_ENV: &str = "NERVE_SOCKET";
+/// The core's socket under `$XDG_RUNTIME_DIR`.
+pub const RUNTIME_SOCKET: &str = "nerve/core.sock";
+
+/// Where the core's socket path came from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SocketSource {
+    /// `--socket` or `--stdio`.
+    Flag,
+    /// `socket_path` in the config file, or a [`Config`](crate::config::Config)
+    /// built in code.
+    #[default]
+    Config,
+    /// [`SOCKET_ENV`].
+    Env,
+    /// [`RUNTIME_SOCKET`] under `$XDG_RUNTIME_DIR`.
+    RuntimeDir,
+    /// [`DEFAULT_SOCKET_PATH`], where cores have always listened.
+    Legacy,
+}
+
+impl fmt::Display for SocketSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SocketSource::Flag => "command line",
+            SocketSource::Config => "config",
+            SocketSource::Env => SOCKET_ENV,
+            SocketSource::RuntimeDir => "XDG_RUNTIME_DIR",
+            SocketSource::Legacy => "legacy default",
+        })
+    }
+}
+
+/// Finds the core's socket when neither the command line nor the config
+/// names one, from the process environment.
+pub fn discover() -> (PathBuf, SocketSource) {
+    discover_with(|name| std::env::var_os(name), Path::exists)
+}
+
+/// [`discover`], reading variables from `env` and checking for sockets with
+/// `exists`: [`SOCKET_ENV`] when set, then [`RUNTIME_SOCKET`] under
+/// `$XDG_RUNTIME_DIR` when a socket is there, then [`DEFAULT_SOCKET_PATH`].
+pub fn discover_with(
+    env: impl Fn(&str) -> Option<OsString>,
+    exists: impl Fn(&Path) -> bool,
+) -> (PathBuf, SocketSource) {
+    let set = |name| env(name).filter(|value| !value.is_empty());
+    if let Some(socket) = set(SOCKET_ENV) {
+        return (PathBuf::from(socket), SocketSource::Env);
+    }
+    if let Some(runtime) = set("XDG_RUNTIME_DIR") {
+        let socket = Path::new(&runtime).join(RUNTIME_SOCKET);
+        if exists(&socket) {
+            return (socket, SocketSource::RuntimeDir);
+        }
+    }
+    (PathBuf::from(DEFAULT_SOCKET_PATH), SocketSource::Legacy)
+}
@@ -0,0 +1,122 @@
+use std::io::{self, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use nerve_protocol::codec::encode;
+use nerve_protocol::io::FrameReader;
+use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::{debug, warn};
+
+use crate::shards::score;
+
+pub const DEFAULT_PEER_TIMEOUT_MS: u64 = 250;
+
+/// A downstream adapter (or core) that queries are forwarded to.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PeerConfig {
+    pub socket_path: PathBuf,
+    #[serde(default = "default_peer_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_peer_timeout_ms() -> u64 {
+    DEFAULT_PEER_TIMEOUT_MS
+}
+
+/// Forwards SEARCH_QUERY payloads to peer sockets and collects their hits.
+///
+/// Each query opens a short-lived connection per peer, so a slow or dead peer
+/// only costs its own timeout and never poisons later queries. Peers that
+/// fail or time out are skipped; federation never fails the local search.
+#[derive(Debug, Clone, Default)]
+pub struct Federation {
+    peers: Vec<PeerConfig>,
+}
+
+impl Federation {
+    pub fn new(peers: Vec<PeerConfig>) -> Self {
+        Self { peers }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Queries every peer in parallel and returns the concatenation of
+    /// whatever hits arrived in time.
+    pub fn search(&self, request_id: RequestId, payload: &[u8]) -> Vec<Value> {
+        thread::scope(|s| {
+            let handles: Vec<_> = self
+                .peers
+                .iter()
+                .map(|peer| s.spawn(move || (peer, query_peer(peer, request_id, payload))))
+                .collect();
+
+            let mut hits = Vec::new();
+            for handle in handles {
+                match handle.join() {
+                    Ok((peer, Ok(peer_hits))) => {
+                        debug!(peer = %peer.socket_path.display(), hits = peer_hits.len(), "peer answered");
+                        hits.extend(peer_hits);
+                    }
+                    Ok((peer, Err(e))) => {
+                        warn!(peer = %peer.socket_path.display(), error = %e, "peer skipped");
+                    }
+                    Err(_) => warn!("peer query panicked"),
+                }
+            }
+            hits
+        })
+    }
+}
+
+fn query_peer(peer: &PeerConfig, request_id: RequestId, payload: &[u8]) -> io::Result<Vec<Value>> {
+    let timeout = Duration::from_millis(peer.timeout_ms);
+    let mut stream = UnixStream::connect(&peer.socket_path)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let query = encode(MessageType::SearchQuery, FrameFlags::FINAL, request_id, payload)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    stream.write_all(&query)?;
+
+    let mut reader = FrameReader::new();
+    let mut hits = Vec::new();
+    loop {
+        for frame in reader.read_from(&mut stream)? {
+            if frame.header.request_id != request_id.0
+                || frame.header.msg_type != MessageType::SearchResult as u8
+            {
+                continue;
+            }
+            match serde_json::from_slice(&frame.payload)? {
+                Value::Array(peer_hits) => hits.extend(peer_hits),
+                other => hits.push(other),
+            }
+            if FrameFlags::from_bits_truncate(frame.header.flags).contains(FrameFlags::FINAL) {
+                return Ok(hits);
+            }
+        }
+    }
+}
+
+/// Merges peer hits into the local ones: dedupes by url (local wins), orders
+/// by score and keeps the top `limit`.
+pub fn merge(local: Vec<Value>, remote: Vec<Value>, limit: usize) -> Vec<Value> {
+    let mut seen = std::collections::HashSet::new();
+    let mut merged: Vec<Value> = local
+        .into_iter()
+        .chain(remote)
+        .filter(|hit| match hit.get("url").and_then(Value::as_str) {
+            Some(url) => seen.insert(url.to_owned()),
+            None => true,
+        })
+        .collect();
+    merged.sort_by(|a, b| score(b).total_cmp(&score(a)));
+    merged.truncate(limit);
+    merged
+}
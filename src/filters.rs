@@ -0,0 +1,108 @@
+use serde::Deserialize;
+use serde::de::{self, Deserializer};
+use serde_json::Value;
+
+/// Stored hit field holding the page's crawl/publish time.
+pub const DEFAULT_DATE_FIELD: &str = "crawled_at";
+
+/// Result filters applied by the adapter on top of the engine's ranking.
+///
+/// Date bounds are unix seconds on the wire, or `YYYY-MM-DD` /
+/// RFC 3339 UTC (`2024-05-01T12:00:00Z`) strings. `after` is inclusive,
+/// `before` exclusive. Hits without a readable date never pass a range.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Filters {
+    #[serde(deserialize_with = "timestamp")]
+    pub after: Option<i64>,
+    #[serde(deserialize_with = "timestamp")]
+    pub before: Option<i64>,
+}
+
+impl Filters {
+    pub fn is_empty(&self) -> bool {
+        self.after.is_none() && self.before.is_none()
+    }
+
+    pub fn matches(&self, hit: &Value, date_field: &str) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let Some(at) = hit.get(date_field).and_then(hit_timestamp) else {
+            return false;
+        };
+        self.after.is_none_or(|after| at >= after) && self.before.is_none_or(|before| at < before)
+    }
+
+    pub fn apply(&self, hits: Vec<Value>, date_field: &str) -> Vec<Value> {
+        if self.is_empty() {
+            return hits;
+        }
+        hits.into_iter()
+            .filter(|hit| self.matches(hit, date_field))
+            .collect()
+    }
+}
+
+fn timestamp<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => hit_timestamp(&value)
+            .map(Some)
+            .ok_or_else(|| de::Error::custom(format!("invalid date: {value}"))),
+    }
+}
+
+/// Reads a date as stored in a hit: unix seconds, or a date string (tantivy
+/// renders date fields as RFC 3339; stored values may be single-element
+/// arrays).
+fn hit_timestamp(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(n) => n.as_i64().or_else(|| n.as_f64().map(|f| f as i64)),
+        Value::String(s) => parse_date(s),
+        Value::Array(values) => values.first().and_then(hit_timestamp),
+        _ => None,
+    }
+}
+
+/// Parses `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM:SS[.frac]Z` into unix seconds.
+pub fn parse_date(text: &str) -> Option<i64> {
+    let (date, time) = match text.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let seconds = match time {
+        None => 0,
+        Some(time) => {
+            let time = time
+                .strip_suffix('Z')
+                .or_else(|| time.strip_suffix("+00:00"))?;
+            let time = time.split('.').next()?;
+            let mut parts = time.splitn(3, ':');
+            let h: i64 = parts.next()?.parse().ok()?;
+            let m: i64 = parts.next()?.parse().ok()?;
+            let s: i64 = parts.next().unwrap_or("0").parse().ok()?;
+            h * 3600 + m * 60 + s
+        }
+    };
+    Some(days_from_civil(year, month, day) * 86_400 + seconds)
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
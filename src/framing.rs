@@ -0,0 +1,395 @@
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::OnceLock;
+
+use bytes::Bytes;
+use nerve_protocol::codec::encode;
+use nerve_protocol::constants::{HEADER_SIZE, MAGIC, VERSION};
+use nerve_protocol::frame::OwnedFrame;
+use nerve_protocol::io::FrameReader;
+use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+use tracing::warn;
+
+use crate::handshake::OLDEST_PROTOCOL_VERSION;
+
+/// A frame [`FrameDecoder`] did not hand over.
+#[derive(Debug)]
+pub enum Rejected {
+    /// The header claimed more than `limit` bytes; the payload was skipped.
+    Oversized {
+        request_id: RequestId,
+        length: usize,
+        limit: usize,
+    },
+    /// The frame could not be decoded and was dropped. The request id is
+    /// known when the header itself was sound.
+    Malformed {
+        request_id: Option<RequestId>,
+        error: io::Error,
+    },
+}
+
+/// A [`FrameReader`] that refuses payloads over a size limit and survives
+/// malformed input.
+///
+/// Headers are checked before any of a frame reaches the reader, so an
+/// oversized frame is never buffered: its payload is skipped as it streams
+/// past and the connection stays usable for the frames behind it. A frame
+/// the reader fails on is skipped the same way, and bytes that don't start
+/// with the protocol magic are dropped until a header turns up again.
+///
+/// Over a transport that keeps packets apart, each read is decoded on its
+/// own and a frame cut short by the end of its packet is dropped rather
+/// than continued from the next.
+pub struct FrameDecoder {
+    reader: FrameReader,
+    layout: Option<HeaderLayout>,
+    max_payload: usize,
+    packets: bool,
+    // header of the next frame, while only part of it has arrived
+    header: Vec<u8>,
+    // request id of the frame whose payload is streaming past
+    current: RequestId,
+    payload_left: usize,
+    skipping: bool,
+    // bytes dropped while looking for the next header
+    discarded: usize,
+}
+
+impl FrameDecoder {
+    pub fn new(max_payload: usize) -> Self {
+        let layout = HeaderLayout::probe();
+        if layout.is_none() {
+            warn!("frame header layout not recognized, payload size limit disabled");
+        }
+        Self {
+            reader: FrameReader::new(),
+            layout,
+            max_payload,
+            packets: false,
+            header: Vec::with_capacity(HEADER_SIZE),
+            current: RequestId(0),
+            payload_left: 0,
+            skipping: false,
+            discarded: 0,
+        }
+    }
+
+    /// Decodes each read as one or more whole frames, as they come off a
+    /// packet transport.
+    pub fn with_packets(mut self, packets: bool) -> Self {
+        self.packets = packets;
+        self
+    }
+
+    pub fn max_payload(&self) -> usize {
+        self.max_payload
+    }
+
+    pub fn reads_packets(&self) -> bool {
+        self.packets
+    }
+
+    /// Decodes the next bytes of the stream, appending complete frames to
+    /// `frames` and reporting every frame turned away to `rejected`.
+    pub fn decode(
+        &mut self,
+        mut bytes: &[u8],
+        frames: &mut Vec<OwnedFrame>,
+        mut rejected: impl FnMut(Rejected),
+    ) {
+        let Some(layout) = self.layout else {
+            // with no header layout there is no telling where the next frame
+            // starts: the rest of the read goes and the reader starts over
+            match read(&mut self.reader, bytes) {
+                Ok(decoded) => frames.extend(decoded),
+                Err(error) => self.malformed(None, error, &mut rejected),
+            }
+            return;
+        };
+        while !bytes.is_empty() {
+            if self.payload_left > 0 {
+                let n = self.payload_left.min(bytes.len());
+                if !self.skipping {
+                    match read(&mut self.reader, &bytes[..n]) {
+                        Ok(decoded) => frames.extend(decoded),
+                        Err(error) => self.malformed(Some(self.current), error, &mut rejected),
+                    }
+                }
+                self.payload_left -= n;
+                bytes = &bytes[n..];
+                continue;
+            }
+
+            let n = (HEADER_SIZE - self.header.len()).min(bytes.len());
+            self.header.extend_from_slice(&bytes[..n]);
+            bytes = &bytes[n..];
+            if self.header.len() < HEADER_SIZE {
+                break;
+            }
+            if !layout.has_magic(&self.header) {
+                self.resync(layout);
+                continue;
+            }
+            if self.discarded > 0 {
+                warn!(skipped = self.discarded, "frame stream resynchronized");
+                self.discarded = 0;
+            }
+            layout.upgrade(&mut self.header);
+            let (request_id, length) = layout.read(&self.header);
+            self.current = request_id;
+            self.skipping = length > self.max_payload;
+            if self.skipping {
+                warn!(
+                    request_id = request_id.0,
+                    length,
+                    limit = self.max_payload,
+                    "oversized frame skipped"
+                );
+                rejected(Rejected::Oversized {
+                    request_id,
+                    length,
+                    limit: self.max_payload,
+                });
+            } else {
+                match read(&mut self.reader, &self.header) {
+                    Ok(decoded) => frames.extend(decoded),
+                    Err(error) => self.malformed(Some(request_id), error, &mut rejected),
+                }
+            }
+            self.header.clear();
+            self.payload_left = length;
+        }
+        if self.packets {
+            self.end_packet(&mut rejected);
+        }
+    }
+
+    /// Drops what is left of a frame its packet didn't hold.
+    fn end_packet(&mut self, rejected: &mut impl FnMut(Rejected)) {
+        if !self.header.is_empty() {
+            let error = io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "packet ends inside a frame header",
+            );
+            self.malformed(None, error, rejected);
+        } else if self.payload_left > 0 && !self.skipping {
+            let error = io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "packet ends inside a frame payload",
+            );
+            self.malformed(Some(self.current), error, rejected);
+        }
+        self.header.clear();
+        self.payload_left = 0;
+        self.skipping = false;
+    }
+
+    /// Drops the frame being read: whatever the reader buffered of it goes,
+    /// and the rest of its payload is skipped.
+    fn malformed(
+        &mut self,
+        request_id: Option<RequestId>,
+        error: io::Error,
+        rejected: &mut impl FnMut(Rejected),
+    ) {
+        warn!(request_id = request_id.map(|id| id.0), error = %error, "malformed frame skipped");
+        self.reader = FrameReader::new();
+        self.skipping = true;
+        rejected(Rejected::Malformed { request_id, error });
+    }
+
+    /// Drops header bytes up to the next place a frame could start: the next
+    /// magic, or a trailing part of one.
+    fn resync(&mut self, layout: HeaderLayout) {
+        let magic = layout.magic_bytes();
+        let start = (1..=self.header.len())
+            .find(|&i| {
+                let rest = self.header.get(i + layout.magic..).unwrap_or_default();
+                let n = rest.len().min(magic.len());
+                rest[..n] == magic[..n]
+            })
+            .unwrap_or(self.header.len());
+        if self.discarded == 0 {
+            warn!("frame stream out of sync, skipping to the next header");
+        }
+        self.discarded += start;
+        self.header.drain(..start);
+    }
+}
+
+/// Rewrites the version in the header of frames going to a core that
+/// speaks an older protocol version.
+#[derive(Debug, Clone, Copy)]
+pub struct Downgrade {
+    at: usize,
+    version: u8,
+}
+
+impl Downgrade {
+    /// `None` when `version` is the current one, or the header layout
+    /// wasn't recognized.
+    pub fn to(version: u8) -> Option<Self> {
+        if version == VERSION {
+            return None;
+        }
+        let at = HeaderLayout::probe()?.version?;
+        Some(Self { at, version })
+    }
+
+    /// `frame`, one whole encoded frame, marked with the older version.
+    pub fn apply(&self, frame: Bytes) -> Bytes {
+        let mut bytes = Vec::from(frame);
+        if let Some(version) = bytes.get_mut(self.at) {
+            *version = self.version;
+        }
+        Bytes::from(bytes)
+    }
+}
+
+/// The message type of `frame`, an encoded frame, read off its header;
+/// `None` when the header layout wasn't recognized.
+pub fn message_type(frame: &[u8]) -> Option<u8> {
+    static LAYOUT: OnceLock<Option<HeaderLayout>> = OnceLock::new();
+    let at = LAYOUT.get_or_init(HeaderLayout::probe).as_ref()?.msg_type?;
+    frame.get(..HEADER_SIZE)?.get(at).copied()
+}
+
+/// Feeds `bytes` to the reader; a reader panicking on them has met a
+/// malformed frame like any other.
+fn read(reader: &mut FrameReader, mut bytes: &[u8]) -> io::Result<Vec<OwnedFrame>> {
+    panic::catch_unwind(AssertUnwindSafe(|| reader.read_from(&mut bytes)))
+        .unwrap_or_else(|_| Err(io::Error::other("frame reader panicked")))
+}
+
+/// Where the request id and payload length sit in a frame header, and the
+/// protocol version and message type when they could be told apart.
+#[derive(Debug, Clone, Copy)]
+struct HeaderLayout {
+    magic: usize,
+    request_id: usize,
+    length: usize,
+    version: Option<usize>,
+    msg_type: Option<usize>,
+    big_endian: bool,
+}
+
+const MAGIC_LEN: usize = size_of_val(&MAGIC);
+const PROBE_REQUEST_ID: u64 = 0x0102_0304_0506_0708;
+const PROBE_LENGTH: u32 = 0x0123;
+
+impl HeaderLayout {
+    /// Learns the layout from the protocol's own encoder rather than
+    /// restating it here: a frame is encoded with distinctive field values
+    /// and the fields are located in its header.
+    fn probe() -> Option<Self> {
+        let payload = [0u8; PROBE_LENGTH as usize];
+        let frame = encode(
+            MessageType::Ping,
+            FrameFlags::empty(),
+            RequestId(PROBE_REQUEST_ID),
+            &payload,
+        )
+        .ok()?;
+        let header = frame.get(..HEADER_SIZE)?;
+        // differs from the first in type and flags, so that of the bytes
+        // left only the version is the same in both
+        let other = encode(
+            MessageType::Pong,
+            FrameFlags::FINAL,
+            RequestId(PROBE_REQUEST_ID),
+            &payload,
+        )
+        .ok()?;
+        let other = other.get(..HEADER_SIZE)?;
+        [false, true].into_iter().find_map(|big_endian| {
+            let (magic, request_id, length) = if big_endian {
+                (
+                    MAGIC.to_be_bytes(),
+                    PROBE_REQUEST_ID.to_be_bytes(),
+                    PROBE_LENGTH.to_be_bytes(),
+                )
+            } else {
+                (
+                    MAGIC.to_le_bytes(),
+                    PROBE_REQUEST_ID.to_le_bytes(),
+                    PROBE_LENGTH.to_le_bytes(),
+                )
+            };
+            let (magic, request_id, length) = (
+                find(header, &magic)?,
+                find(header, &request_id)?,
+                find(header, &length)?,
+            );
+            let known = [(magic, MAGIC_LEN), (request_id, 8), (length, 4)];
+            let version = (0..HEADER_SIZE).find(|&at| {
+                header[at] == VERSION
+                    && other[at] == VERSION
+                    && !known
+                        .iter()
+                        .any(|&(start, len)| (start..start + len).contains(&at))
+            });
+            let msg_type = (0..HEADER_SIZE).find(|&at| {
+                header[at] == MessageType::Ping as u8
+                    && other[at] == MessageType::Pong as u8
+                    && !known
+                        .iter()
+                        .any(|&(start, len)| (start..start + len).contains(&at))
+            });
+            Some(Self {
+                magic,
+                request_id,
+                length,
+                version,
+                msg_type,
+                big_endian,
+            })
+        })
+    }
+
+    /// Marks a header from an older protocol version this adapter still
+    /// speaks as current, for the reader.
+    fn upgrade(&self, header: &mut [u8]) {
+        if let Some(at) = self.version
+            && (OLDEST_PROTOCOL_VERSION..VERSION).contains(&header[at])
+        {
+            header[at] = VERSION;
+        }
+    }
+
+    fn magic_bytes(&self) -> [u8; MAGIC_LEN] {
+        if self.big_endian {
+            MAGIC.to_be_bytes()
+        } else {
+            MAGIC.to_le_bytes()
+        }
+    }
+
+    fn has_magic(&self, header: &[u8]) -> bool {
+        header[self.magic..self.magic + MAGIC_LEN] == self.magic_bytes()
+    }
+
+    fn read(&self, header: &[u8]) -> (RequestId, usize) {
+        let mut request_id = [0u8; 8];
+        request_id.copy_from_slice(&header[self.request_id..self.request_id + 8]);
+        let mut length = [0u8; 4];
+        length.copy_from_slice(&header[self.length..self.length + 4]);
+        if self.big_endian {
+            (
+                RequestId(u64::from_be_bytes(request_id)),
+                u32::from_be_bytes(length) as usize,
+            )
+        } else {
+            (
+                RequestId(u64::from_le_bytes(request_id)),
+                u32::from_le_bytes(length) as usize,
+            )
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
@@ -1,36 +1,811 @@
-use crawler::SearchEngine;
+use std::any::Any;
+use std::cell::Cell;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+
 use crawler::search::filters::SortBy;
 use nerve_protocol::codec::encode;
 use nerve_protocol::frame::OwnedFrame;
 use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+use serde_json::Value;
+use tracing::{Span, debug, warn};
 
-use crate::state::RequestState;
+use crate::admin;
+use crate::audit::Outcome;
+use crate::budget::MemoryBudget;
+use crate::cache::QueryKey;
+use crate::context::Context;
+use crate::dedup;
+use crate::federation;
+use crate::framing::{self, Rejected};
+use crate::handshake::{Capabilities, Capability};
+use crate::introspect;
+use crate::rank::{self, Fusion};
+use crate::request::{Request, SearchMode, SearchRequest};
+use crate::shards::{EngineBoosts, ReaderReload};
+use crate::slowlog::Trace;
+use crate::state::{CancelToken, RequestState};
+use crate::vector::VectorIndex;
+use crate::writer::WriteAck;
 
 pub fn handle_search(
     frame: OwnedFrame,
-    state: &mut RequestState,
-    engine: &SearchEngine,
-)->Option<Vec<u8>>{
+    state: &RequestState,
+    context: &Context,
+)->Option<Bytes>{
+    let mut replies = Vec::new();
+    handle_streaming(frame, state, context, |reply| replies.push(reply));
+    match replies.len(){
+        0 | 1 => replies.pop(),
+        _ => Some(replies.concat().into()),
+    }
+}
+
+/// Like [`handle_search`], but hands each reply to `emit` as soon as it is
+/// encoded, so long operations stream their progress frames.
+pub fn handle_streaming(
+    frame: OwnedFrame,
+    state: &RequestState,
+    context: &Context,
+    emit: impl FnMut(Bytes),
+){
+    handle_queued(frame, Instant::now(), state, context, emit);
+}
+
+/// [`handle_streaming`] for a frame that has been waiting since `received`;
+/// the wait counts against the request's deadline.
+///
+/// A panic while handling the request, in parsing it or in the engine, is
+/// confined to it: the panic is logged and counted, and the request is
+/// answered with an `internal_error` ERROR frame instead.
+pub fn handle_queued(
+    frame: OwnedFrame,
+    received: Instant,
+    state: &RequestState,
+    context: &Context,
+    mut emit: impl FnMut(Bytes),
+){
     let request_id = RequestId(frame.header.request_id);
+    let handled = panic::catch_unwind(AssertUnwindSafe(||{
+        handle_unguarded(frame, received, state, context, &mut emit)
+    }));
+    if let Err(panic) = handled{
+        state.finish(request_id);
+        context.counters.panic();
+        warn!(request_id = request_id.0, panic = panic_message(&*panic), "request handler panicked");
+        if let Some(reply) = internal_error(request_id){
+            context.counters.error();
+            emit(reply);
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send))->&str{
+    panic.downcast_ref::<&str>().copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("non-string panic")
+}
 
+fn handle_unguarded(
+    frame: OwnedFrame,
+    received: Instant,
+    state: &RequestState,
+    context: &Context,
+    mut emit: impl FnMut(Bytes),
+){
+    let request_id = RequestId(frame.header.request_id);
     if state.is_cancelled(request_id){
-        return None;
-    }
-
-    // v0.1 defaults
-    let query = std::str::from_utf8(&frame.payload).ok()?;
-    
-    let result = engine.search(
-        query,
-        10,
-        0,
-        crawler::search::filters::SearchFilter::new(),
-        SortBy::Relevance,
-        true,
-        false,
-    ).ok()?;
+        return;
+    }
+    context.counters.request();
+    let failed = Cell::new(false);
+    let mut emit = |reply: Bytes|{
+        let msg_type = framing::message_type(&reply);
+        debug!(request_id = request_id.0, msg_type, bytes = reply.len(), "reply sent");
+        if msg_type == Some(MessageType::Error as u8){
+            context.counters.error();
+            failed.set(true);
+        }
+        emit(reply)
+    };
+
+    let mut trace = Trace::default();
+    trace.stages.push(("queued", received.elapsed()));
+
+    // a CANCEL arriving from here on trips the token and stops the search
+    let cancel = state.begin(request_id);
+    let capabilities = state.capabilities();
+    let reply = respond(request_id, frame, received, context, capabilities, &cancel, &mut trace, &mut emit);
+    state.finish(request_id);
+    let elapsed = received.elapsed();
+    if !trace.op.is_empty(){
+        context.latency.record(trace.op, elapsed);
+        Span::current().record("op", trace.op);
+    }
+    context.slowlog.record(request_id, &trace, elapsed);
+    context.statsd.record(&trace, elapsed);
+    if context.profiler.sample(){
+        context.profiler.record(&trace, elapsed);
+    }
+    if let Some(reply) = reply{
+        emit(reply);
+    }
+    let outcome = match (state.is_cancelled(request_id), failed.get()){
+        (true, _) => Outcome::Cancelled,
+        (false, true) => Outcome::Error,
+        (false, false) => Outcome::Ok,
+    };
+    context.audit.record(request_id, &trace, elapsed, outcome);
+    context.querylog.record(request_id, &trace, elapsed);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn respond(
+    request_id: RequestId,
+    frame: OwnedFrame,
+    received: Instant,
+    context: &Context,
+    capabilities: Capabilities,
+    cancel: &CancelToken,
+    trace: &mut Trace,
+    emit: &mut impl FnMut(Bytes),
+)->Option<Bytes>{
+    let request = match trace.time("parse", || Request::parse(&frame.payload)){
+        Ok(request) => request,
+        Err(reason) =>{
+            // named, so the audit log and metrics account for it
+            trace.op = "invalid";
+            debug!(request_id = request_id.0, reason = %reason, "request rejected");
+            return reply_error(request_id, "invalid_request", &reason);
+        }
+    };
+    trace.op = request.op();
+    if context.read_only && request.is_mutation(){
+        warn!(request_id = request_id.0, op = request.op(), "mutation rejected: read-only");
+        return reply_error(
+            request_id,
+            "read_only",
+            &format!("{} rejected: adapter is read-only", request.op()),
+        );
+    }
+
+    let timeout = match &request{
+        Request::Search(search) => search.timeout_ms.map(Duration::from_millis),
+        _ => None,
+    }.or(context.request_timeout);
+    let cancel = match timeout{
+        Some(timeout) => cancel.with_deadline(received + timeout),
+        None => cancel.clone(),
+    };
+    let cancel = &cancel;
+    // expired while queued: don't start work nobody will wait for
+    if cancel.is_expired(){
+        return reply_timeout(request_id);
+    }
+
+    match request{
+        Request::Search(mut request) =>{
+            let requested_limit = request.limit;
+            request.limit = request.limit.min(context.max_limit);
+            // a core that didn't agree to streaming gets one frame
+            request.stream &= capabilities.contains(Capability::Streaming);
+            trace.search(&request);
+            run_search(request_id, &frame.payload, request, requested_limit, context, cancel, trace, emit)
+        }
+        Request::IndexStats => reply_json(request_id, introspect::index_stats(&context.shards)),
+        Request::Schema => reply_json(request_id, introspect::schema_info(&context.shards)),
+        Request::TermStats { field, terms } => {
+            reply_json(request_id, introspect::term_stats(&context.shards, &field, &terms))
+        }
+        Request::IndexDocument { doc } => {
+            let ack = context.writer.index_document(doc);
+            reply_write(request_id, ack, context)
+        }
+        Request::UpdateDocument { doc } => {
+            let ack = context.writer.update_document(doc);
+            reply_write(request_id, ack, context)
+        }
+        Request::DeleteDocument { url } => {
+            let ack = context.writer.delete_document(&url);
+            reply_write(request_id, ack, context)
+        }
+        Request::Commit => {
+            let ack = context.writer.commit();
+            reply_write(request_id, ack, context)
+        }
+        Request::Snapshot { target } => {
+            // non-final frames carry progress, roughly every tenth of the files
+            let mut reported = 0;
+            let report = admin::snapshot(context.primary_index(), &target, |progress| {
+                let step = progress.files_done * 10 / progress.files_total.max(1);
+                if step > reported {
+                    reported = step;
+                    if let Some(frame) = progress_frame(request_id, progress) {
+                        emit(frame);
+                    }
+                }
+            });
+            reply_json(request_id, report)
+        }
+        Request::Metrics => {
+            let metrics = serde_json::json!({
+                "latency": context.latency.summary(),
+                "shards": context.shard_latency.summary(),
+                "query_cache": context.queries().stats(),
+                "profile": context.profiler.is_enabled().then(|| context.profiler.totals()),
+                "connections": context.connections.snapshot(),
+            });
+            reply_json(request_id, Ok(metrics))
+        }
+        Request::Stats => reply_json(request_id, Ok(stats(context))),
+        Request::TopQueries { limit } => reply_json(request_id, Ok(top_queries(context, limit))),
+        Request::Merge { max_segments } => {
+            let report = admin::merge(&context.writer, context.primary_index(), max_segments);
+            reply_json(request_id, report)
+        }
+    }
+}
+
+/// The `stats` operation's counters.
+pub(crate) fn stats(context: &Context)->Value{
+    let counters = context.counters.snapshot();
+    serde_json::json!({
+        "requests": counters.requests,
+        "errors": counters.errors,
+        "panics": counters.panics,
+        "cancellations": counters.cancellations,
+        "rate_limited": counters.rate_limited,
+        "circuit": context.breaker.circuit().as_str(),
+        "usage": context.usage.report(),
+        "queue_depth": counters.queue_depth,
+        "query_cache": context.queries().stats(),
+        "uptime_ms": context.started.elapsed().as_millis() as u64,
+    })
+}
+
+/// The `limit` most asked search queries and the `limit` latest, as the
+/// `top_queries` operation and control command report them.
+pub(crate) fn top_queries(context: &Context, limit: usize)->Value{
+    let popular: Vec<Value> = context.popular.counts(limit).into_iter()
+        .map(|(query, count)| serde_json::json!({"query": query, "count": count}))
+        .collect();
+    let recent: Vec<Value> = context.usage.recent(limit).into_iter()
+        .map(|(ts_ms, query)| serde_json::json!({"query": query, "ts_ms": ts_ms}))
+        .collect();
+    serde_json::json!({"popular": popular, "recent": recent})
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_search(
+    request_id: RequestId,
+    payload: &[u8],
+    request: SearchRequest,
+    requested_limit: usize,
+    context: &Context,
+    cancel: &CancelToken,
+    trace: &mut Trace,
+    emit: &mut impl FnMut(Bytes),
+)->Option<Bytes>{
+    let cache_key = request.cache_key();
+    context.popular.record(&cache_key, &request, payload);
+    context.usage.search(&request);
+
+    // known miss: answer with an empty result set without touching the engine
+    if context.misses().is_miss(&cache_key){
+        trace.hits = Some(0);
+        return encode(MessageType::SearchResult, FrameFlags::FINAL, request_id, b"[]").ok().map(Bytes::from);
+    }
+
+    // identical queries arriving together share one engine run
+    let search = || context.in_flight.run(&cache_key, cancel, || search_local(&request, context, cancel));
+    let ticket = match context.breaker.admit(){
+        Ok(ticket) => ticket,
+        Err(wait) => return reply_circuit_open(request_id, wait),
+    };
+    let searched = trace.time("search", search);
+    match &searched{
+        // the client's own deadline says nothing about the engine
+        Err(e) if e.kind() == io::ErrorKind::TimedOut && request.timeout_ms.is_some() => drop(ticket),
+        _ => ticket.record(&searched),
+    }
+    let mut hits = match searched{
+        Ok(hits) => hits,
+        Err(e) if e.kind() == io::ErrorKind::Interrupted =>{
+            debug!(request_id = request_id.0, "search cancelled");
+            return None;
+        }
+        Err(e) if e.kind() == io::ErrorKind::TimedOut => return reply_timeout(request_id),
+        Err(e) if e.kind() == io::ErrorKind::OutOfMemory => return reply_over_budget(request_id, &e),
+        Err(e) =>{
+            warn!(request_id = request_id.0, error = %e, "search failed");
+            return reply_error(request_id, error_code(&e, "search_failed"), &e.to_string());
+        }
+    };
+
+    #[cfg(feature = "scripting")]
+    if let (Some(rescorer), true) = (&context.rescorer, request.rescore){
+        // a broken or slow script must not fail the search: keep engine order
+        match trace.time("rescore", || rescorer.rescore(hits.clone())){
+            Ok(rescored) => hits = rescored,
+            Err(e) => warn!(request_id = request_id.0, error = %e, "rescore skipped"),
+        }
+    }
+
+    if !context.federation.is_empty() && !cancel.is_cancelled(){
+        let remote = trace.time("federation", || context.federation.search(request_id, payload));
+        hits = federation::merge(hits, remote, request.limit);
+        if let Err(e) = context.memory_budget.check_hits(&hits){
+            return reply_over_budget(request_id, &e);
+        }
+    }
+
+    if request.dedup{
+        hits = trace.time("dedup", || dedup::dedup(hits, request.dedup_distance));
+    }
+    trace.hits = Some(hits.len());
+    context.usage.served(&hits);
+
+    if hits.is_empty(){
+        context.misses().record_miss(&cache_key);
+    }
+
+    let truncated = requested_limit > request.limit && !hits.is_empty();
+    if request.stream{
+        let clamp = truncated.then_some((request.limit, requested_limit));
+        let streamed = trace.time("serialize", || stream_hits(request_id, hits, clamp, context.memory_budget, cancel, emit));
+        return match streamed{
+            Ok(reply) => Some(reply),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted =>{
+                debug!(request_id = request_id.0, "search cancelled");
+                None
+            }
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => reply_timeout(request_id),
+            Err(e) if e.kind() == io::ErrorKind::OutOfMemory => reply_over_budget(request_id, &e),
+            Err(e) =>{
+                warn!(request_id = request_id.0, error = %e, "reply encoding failed");
+                None
+            }
+        };
+    }
+
+    // a clamped limit turns the bare hit list into an envelope saying so
+    let results = if truncated{
+        serde_json::json!({
+            "hits": hits,
+            "truncated": true,
+            "limit": request.limit,
+            "requested_limit": requested_limit,
+        })
+    } else {
+        Value::Array(hits)
+    };
 
     // serialize results
-    let payload = serde_json::to_vec(&result).ok()?;
-    Some(encode(MessageType::SearchResult, FrameFlags::FINAL, request_id, &payload).ok()?)
-}
\ No newline at end of file
+    let budget = context.memory_budget;
+    let encoded = trace.time("serialize", ||{
+        encode_json_within(MessageType::SearchResult, FrameFlags::FINAL, request_id, &results, budget)
+    });
+    match encoded{
+        Ok(reply) => Some(reply),
+        Err(e) if e.kind() == io::ErrorKind::OutOfMemory => reply_over_budget(request_id, &e),
+        Err(e) =>{
+            warn!(request_id = request_id.0, error = %e, "reply encoding failed");
+            None
+        }
+    }
+}
+
+/// Serialized hits a streamed search reply holds per frame, roughly.
+pub const STREAM_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Serializes `hits` one at a time, emitting a non-final frame with a JSON
+/// array whenever [`STREAM_CHUNK_BYTES`] have built up, so the full reply is
+/// never encoded at once. Returns the final frame; with `clamp` set it is
+/// the truncation envelope around the last hits. The budget applies to each
+/// frame. Stops with the error [`CancelToken::check`] gives once the request
+/// is cancelled or its deadline passes.
+fn stream_hits(
+    request_id: RequestId,
+    hits: Vec<Value>,
+    clamp: Option<(usize, usize)>,
+    budget: MemoryBudget,
+    cancel: &CancelToken,
+    emit: &mut impl FnMut(Bytes),
+) -> io::Result<Bytes> {
+    let frame = |flags, payload: &[u8]| {
+        encode(MessageType::SearchResult, flags, request_id, payload)
+            .map(Bytes::from)
+            .map_err(io::Error::other)
+    };
+    let mut payload = Vec::with_capacity(STREAM_CHUNK_BYTES);
+    payload.push(b'[');
+    for hit in hits {
+        if payload.len() >= STREAM_CHUNK_BYTES {
+            cancel.check()?;
+            payload.push(b']');
+            emit(frame(FrameFlags::empty(), &payload)?);
+            payload.clear();
+            payload.push(b'[');
+        }
+        if payload.len() > 1 {
+            payload.push(b',');
+        }
+        serde_json::to_writer(budget.writer(&mut payload), &hit)?;
+    }
+    payload.push(b']');
+
+    let payload = match clamp {
+        Some((limit, requested_limit)) => {
+            let mut envelope = b"{\"hits\":".to_vec();
+            envelope.extend_from_slice(&payload);
+            let tail = format!(
+                ",\"truncated\":true,\"limit\":{limit},\"requested_limit\":{requested_limit}}}"
+            );
+            envelope.extend_from_slice(tail.as_bytes());
+            envelope
+        }
+        None => payload,
+    };
+    frame(FrameFlags::FINAL, &payload)
+}
+
+// serialized payloads above this size don't keep their buffer around
+const MAX_RETAINED_PAYLOAD: usize = 1 << 20;
+
+thread_local! {
+    // each worker serializes into the same buffer instead of a fresh Vec per reply
+    static PAYLOAD: std::cell::RefCell<Vec<u8>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Encodes one frame with `value` serialized as its JSON payload.
+fn encode_json<T: serde::Serialize + ?Sized>(
+    msg_type: MessageType,
+    flags: FrameFlags,
+    request_id: RequestId,
+    value: &T,
+) -> Option<Bytes> {
+    encode_json_within(msg_type, flags, request_id, value, MemoryBudget::default()).ok()
+}
+
+/// [`encode_json`], failing with `OutOfMemory` as soon as the payload
+/// outgrows `budget`.
+fn encode_json_within<T: serde::Serialize + ?Sized>(
+    msg_type: MessageType,
+    flags: FrameFlags,
+    request_id: RequestId,
+    value: &T,
+    budget: MemoryBudget,
+) -> io::Result<Bytes> {
+    PAYLOAD.with_borrow_mut(|payload| {
+        payload.clear();
+        let frame = serde_json::to_writer(budget.writer(payload), value)
+            .map_err(io::Error::from)
+            .and_then(|()| encode(msg_type, flags, request_id, payload).map_err(io::Error::other))
+            // the encoded frame moves into the Bytes as is; replies are never copied again
+            .map(Bytes::from);
+        if payload.capacity() > MAX_RETAINED_PAYLOAD {
+            *payload = Vec::new();
+        }
+        frame
+    })
+}
+
+/// Answers with `result`, or with an ERROR frame saying why the operation
+/// failed, so every request gets a FINAL frame.
+fn reply_json<T: serde::Serialize>(request_id: RequestId, result: io::Result<T>) -> Option<Bytes> {
+    let value = match result {
+        Ok(value) => value,
+        Err(e) => {
+            warn!(request_id = request_id.0, error = %e, "operation failed");
+            return reply_error(request_id, error_code(&e, "operation_failed"), &e.to_string());
+        }
+    };
+    encode_json(MessageType::SearchResult, FrameFlags::FINAL, request_id, &value)
+}
+
+/// The ERROR code for a failed request: what its kind says about the
+/// request, or `fallback` when the failure was the adapter's.
+fn error_code(error: &io::Error, fallback: &'static str) -> &'static str {
+    match error.kind() {
+        io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => "invalid_request",
+        io::ErrorKind::NotFound => "not_found",
+        io::ErrorKind::Unsupported => "unsupported",
+        _ => fallback,
+    }
+}
+
+/// How long an overloaded adapter asks the core to wait before retrying.
+pub const OVERLOAD_RETRY_AFTER_MS: u64 = 100;
+
+/// ERROR frame ending a request the connection it came in on dropped before
+/// it was answered; sent on the next connection.
+pub fn aborted_by_disconnect(request_id: RequestId) -> Option<Bytes> {
+    reply_error(
+        request_id,
+        "aborted_by_disconnect",
+        "request aborted by disconnect",
+    )
+}
+
+/// ERROR frame turning away a query the request queue has no room for.
+pub fn overloaded(request_id: RequestId) -> Option<Bytes> {
+    let error = serde_json::json!({
+        "code": "overloaded",
+        "message": "request queue is full",
+        "retry_after_ms": OVERLOAD_RETRY_AFTER_MS,
+    });
+    encode_json(MessageType::Error, FrameFlags::FINAL, request_id, &error)
+}
+
+/// ERROR frame turning away a query over the rate limit; the core may
+/// retry after `wait`.
+pub fn rate_limited(request_id: RequestId, wait: Duration) -> Option<Bytes> {
+    let error = serde_json::json!({
+        "code": "overloaded",
+        "message": "rate limit exceeded",
+        "retry_after_ms": wait.as_millis().max(1) as u64,
+    });
+    encode_json(MessageType::Error, FrameFlags::FINAL, request_id, &error)
+}
+
+/// ERROR frame for a query whose payload was skipped for being larger than
+/// `limit`.
+pub fn payload_too_large(request_id: RequestId, length: usize, limit: usize) -> Option<Bytes> {
+    let message = format!("frame payload of {length} bytes exceeds the {limit} byte limit");
+    reply_error(request_id, "payload_too_large", &message)
+}
+
+/// ERROR frame for a frame the decoder turned away, if it is known whom to
+/// answer.
+pub fn frame_rejected(rejected: &Rejected) -> Option<Bytes> {
+    match rejected {
+        Rejected::Oversized {
+            request_id,
+            length,
+            limit,
+        } => payload_too_large(*request_id, *length, *limit),
+        Rejected::Malformed {
+            request_id: Some(request_id),
+            error,
+        } => reply_error(*request_id, "malformed_frame", &error.to_string()),
+        Rejected::Malformed { request_id: None, .. } => None,
+    }
+}
+
+/// ERROR frame for a frame whose MAC is missing or wrong.
+pub fn bad_mac(request_id: RequestId) -> Option<Bytes> {
+    reply_error(request_id, "bad_mac", "frame MAC missing or wrong")
+}
+
+/// ERROR frame, on no request, telling the core the adapter is closing the
+/// connection to shut down.
+pub fn going_away() -> Option<Bytes> {
+    reply_error(RequestId(0), "going_away", "adapter shutting down")
+}
+
+/// ERROR frame for a request whose reply waited `write_timeout_ms` without
+/// the core taking it.
+pub fn write_timed_out(request_id: RequestId) -> Option<Bytes> {
+    reply_error(request_id, "write_timeout", "reply not taken by the core in time")
+}
+
+/// ERROR frame for a request whose handler panicked.
+pub fn internal_error(request_id: RequestId) -> Option<Bytes> {
+    reply_error(request_id, "internal_error", "request handler failed")
+}
+
+/// Answers with an ERROR frame carrying `{"code": ..., "message": ...}`.
+fn reply_error(request_id: RequestId, code: &str, message: &str) -> Option<Bytes> {
+    let error = serde_json::json!({ "code": code, "message": message });
+    encode_json(MessageType::Error, FrameFlags::FINAL, request_id, &error)
+}
+
+fn reply_over_budget(request_id: RequestId, error: &io::Error) -> Option<Bytes> {
+    warn!(request_id = request_id.0, error = %error, "query over memory budget");
+    reply_error(request_id, "memory_budget", &error.to_string())
+}
+
+fn reply_circuit_open(request_id: RequestId, wait: Duration) -> Option<Bytes> {
+    debug!(request_id = request_id.0, "circuit open, search fast-failed");
+    let error = serde_json::json!({
+        "code": "circuit_open",
+        "message": "search engine failing, searches are fast-failed until it recovers",
+        "retry_after_ms": wait.as_millis().max(1) as u64,
+    });
+    encode_json(MessageType::Error, FrameFlags::FINAL, request_id, &error)
+}
+
+fn reply_timeout(request_id: RequestId) -> Option<Bytes> {
+    warn!(request_id = request_id.0, "request deadline exceeded");
+    reply_error(request_id, "timeout", "request deadline exceeded")
+}
+
+fn progress_frame<T: serde::Serialize>(request_id: RequestId, progress: &T) -> Option<Bytes> {
+    encode_json(MessageType::SearchResult, FrameFlags::empty(), request_id, progress)
+}
+
+/// Acknowledges a write; committed writes may answer queries that used to
+/// miss, so the negative cache is dropped.
+fn reply_write(
+    request_id: RequestId,
+    ack: io::Result<WriteAck>,
+    context: &Context,
+) -> Option<Bytes> {
+    if ack.as_ref().is_ok_and(|ack| ack.committed) {
+        if context.reader_reload == ReaderReload::OnCommit
+            && let Err(e) = context.shards.reload()
+        {
+            warn!(request_id = request_id.0, error = %e, "engines not reloaded after commit");
+        }
+        context.misses().invalidate();
+        context.popular.mark_stale();
+    }
+    reply_json(request_id, ack)
+}
+
+pub(crate) fn search_local(
+    request: &SearchRequest,
+    context: &Context,
+    cancel: &CancelToken,
+) -> io::Result<Vec<Value>> {
+    // lexical passes see the query as analyzed for its language
+    let query = analyzed_query(request, context);
+    let query = &*query;
+    // filtering happens on our side, so filtered passes dig deeper
+    let window = request.offset + request.limit;
+    let filtered = !request.filters.is_empty();
+    let depth = if filtered { window.max(context.rerank_depth) } else { window };
+    let filter = |hits: Vec<Value>| request.filters.apply(hits, &context.date_field);
+    let boosts = request.boosts();
+    let budget = context.memory_budget;
+    match request.mode {
+        SearchMode::Lexical => {
+            let weights = context.scoring.with(&request.weights);
+            if weights.is_pure_bm25() && !filtered {
+                let hits = search_shards(
+                    context,
+                    query,
+                    request.limit,
+                    request.offset,
+                    SortBy::Relevance,
+                    boosts,
+                    cancel,
+                )?;
+                budget.check_hits(&hits)?;
+                return Ok(hits);
+            }
+
+            // rescore a deeper candidate set so boosted hits can surface
+            let depth = depth.max(context.rerank_depth);
+            let candidates =
+                search_shards(context, query, depth, 0, SortBy::Relevance, boosts, cancel)?;
+            cancel.check()?;
+            budget.check_hits(&candidates)?;
+            let candidates = filter(candidates);
+            let ranked = if weights.is_pure_bm25() {
+                candidates
+            } else {
+                rank::composite(candidates, &weights)
+            };
+            Ok(ranked
+                .into_iter()
+                .skip(request.offset)
+                .take(request.limit)
+                .collect())
+        }
+        SearchMode::Vector => {
+            let vectors = vector_index(context)?;
+            let query_vector = query_vector(request, context)?;
+            if !filtered {
+                let hits = vectors.search(&query_vector, request.limit, request.offset, cancel)?;
+                budget.check_hits(&hits)?;
+                return Ok(hits);
+            }
+            let candidates = vectors.search(&query_vector, depth, 0, cancel)?;
+            budget.check_hits(&candidates)?;
+            Ok(filter(candidates)
+                .into_iter()
+                .skip(request.offset)
+                .take(request.limit)
+                .collect())
+        }
+        SearchMode::Hybrid => {
+            // both passes fetch the whole window; fusion decides the order
+            let lexical =
+                search_shards(context, query, depth, 0, SortBy::Relevance, boosts, cancel)?;
+            budget.check_hits(&lexical)?;
+            let vector =
+                vector_index(context)?.search(&query_vector(request, context)?, depth, 0, cancel)?;
+            budget.check_hits(&vector)?;
+            Ok(rank::fuse(filter(lexical), filter(vector), request.fusion)
+                .into_iter()
+                .skip(request.offset)
+                .take(request.limit)
+                .collect())
+        }
+        SearchMode::SortFusion => {
+            let k = match request.fusion {
+                Fusion::Rrf { k } => k,
+                Fusion::Weighted { .. } => rank::DEFAULT_RRF_K,
+            };
+            let rankings = std::thread::scope(|s| {
+                let passes: Vec<_> = request
+                    .sorts
+                    .iter()
+                    .map(|&order| {
+                        s.spawn(move || {
+                            search_shards(context, query, depth, 0, order.into(), boosts, cancel)
+                        })
+                    })
+                    .collect();
+                passes
+                    .into_iter()
+                    .map(|pass| match pass.join() {
+                        Ok(result) => {
+                            let hits = result?;
+                            budget.check_hits(&hits)?;
+                            Ok(filter(hits))
+                        }
+                        Err(_) => Err(io::Error::other("sort pass panicked")),
+                    })
+                    .collect::<io::Result<Vec<_>>>()
+            })?;
+            Ok(rank::rrf(rankings, k)
+                .into_iter()
+                .skip(request.offset)
+                .take(request.limit)
+                .collect())
+        }
+    }
+}
+
+/// One engine pass over every shard, recording how long each took.
+fn search_shards(
+    context: &Context,
+    query: &str,
+    limit: usize,
+    offset: usize,
+    sort: SortBy,
+    boosts: EngineBoosts,
+    cancel: &CancelToken,
+) -> io::Result<Vec<Value>> {
+    let (hits, timings) = context.shards.search(query, limit, offset, sort, boosts, cancel)?;
+    context.shard_latency.record(&timings);
+    Ok(hits)
+}
+
+/// The query as analyzed for its language, cached across requests.
+fn analyzed_query(request: &SearchRequest, context: &Context) -> Arc<str> {
+    let key = QueryKey::new(request.language, request.stem, &request.query);
+    if let Some(query) = context.queries().get(&key) {
+        return query;
+    }
+    // analyze outside the lock; a concurrent miss just does the work twice
+    let query: Arc<str> = context
+        .analyzers
+        .rewrite_query(request.language, request.stem, key.text())
+        .into();
+    context.queries().insert(key, Arc::clone(&query));
+    query
+}
+
+fn vector_index(context: &Context) -> io::Result<&VectorIndex> {
+    context.vectors.as_ref().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::Unsupported, "no vector sidecar loaded")
+    })
+}
+
+fn query_vector(request: &SearchRequest, context: &Context) -> io::Result<Vec<f32>> {
+    match (&request.vector, &context.embedder) {
+        (Some(vector), _) => Ok(vector.clone()),
+        (None, Some(embedder)) => embedder.embed(&request.query),
+        (None, None) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "text vector query without an embedder",
+        )),
+    }
+}
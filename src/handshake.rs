@@ -0,0 +1,469 @@
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use nerve_protocol::codec::encode;
+use nerve_protocol::constants::VERSION;
+use nerve_protocol::frame::OwnedFrame;
+use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::auth::{self, FrameMac, Secret};
+use crate::client;
+use crate::context::Context;
+use crate::framing::FrameDecoder;
+use crate::handler;
+use crate::transport::Stream;
+
+/// Request id of the adapter's HELLO; the core's answer carries it back.
+pub const HELLO_REQUEST_ID: RequestId = RequestId(u64::MAX);
+pub const DEFAULT_HANDSHAKE_TIMEOUT_MS: u64 = 2_000;
+/// The oldest frame protocol version the adapter still serves, leaving out
+/// what the core can't parse.
+pub const OLDEST_PROTOCOL_VERSION: u8 = VERSION.saturating_sub(1);
+const ADAPTER_NAME: &str = "nerve-search-adapter";
+
+/// `[handshake]` section: introducing the adapter to the core before
+/// serving it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct HandshakeConfig {
+    /// Exchange HELLOs on connect and refuse cores that can't be served.
+    pub enabled: bool,
+    /// How long the core has to answer the adapter's HELLO.
+    pub timeout_ms: u64,
+}
+
+impl Default for HandshakeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            timeout_ms: DEFAULT_HANDSHAKE_TIMEOUT_MS,
+        }
+    }
+}
+
+/// Optional behaviours either side may support. Each is used on a
+/// connection only once both sides listed it in their HELLOs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// Search replies split over several frames (`"stream": true`).
+    Streaming,
+    Compression,
+    BatchQueries,
+    Suggest,
+    /// `vector` and `hybrid` search modes.
+    VectorSearch,
+    /// Every frame signed with a key derived from the `[auth]` secret.
+    FrameMac,
+    /// A `going_away` ERROR before the adapter closes the connection to
+    /// shut down.
+    GoingAway,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 7] = [
+        Capability::Streaming,
+        Capability::Compression,
+        Capability::BatchQueries,
+        Capability::Suggest,
+        Capability::VectorSearch,
+        Capability::FrameMac,
+        Capability::GoingAway,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::Streaming => "streaming",
+            Capability::Compression => "compression",
+            Capability::BatchQueries => "batch_queries",
+            Capability::Suggest => "suggest",
+            Capability::VectorSearch => "vector_search",
+            Capability::FrameMac => "frame_mac",
+            Capability::GoingAway => "going_away",
+        }
+    }
+
+    /// The first protocol version whose frames carry it; on an older
+    /// connection it is left out.
+    pub fn min_version(self) -> u8 {
+        match self {
+            // the STREAM flag, MAC trailers and connection-level ERRORs
+            Capability::Streaming | Capability::FrameMac | Capability::GoingAway => VERSION,
+            Capability::Compression
+            | Capability::BatchQueries
+            | Capability::Suggest
+            | Capability::VectorSearch => OLDEST_PROTOCOL_VERSION,
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// A set of [`Capability`]s, one bit each.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities(u8);
+
+impl Capabilities {
+    pub fn all() -> Self {
+        Capability::ALL
+            .into_iter()
+            .fold(Self::default(), Self::with)
+    }
+
+    /// What this adapter offers: streaming and going-away notices always,
+    /// vector search when the index has a vector sidecar, frame MACs when
+    /// `[auth]` asks for them.
+    pub fn offered(context: &Context) -> Self {
+        let mut offered = Self::default()
+            .with(Capability::Streaming)
+            .with(Capability::GoingAway);
+        if context.vectors.is_some() {
+            offered = offered.with(Capability::VectorSearch);
+        }
+        if context.auth.as_ref().is_some_and(Secret::signs_frames) {
+            offered = offered.with(Capability::FrameMac);
+        }
+        offered
+    }
+
+    pub fn with(self, capability: Capability) -> Self {
+        Self(self.0 | capability.bit())
+    }
+
+    pub fn without(self, capability: Capability) -> Self {
+        Self(self.0 & !capability.bit())
+    }
+
+    pub fn contains(self, capability: Capability) -> bool {
+        self.0 & capability.bit() != 0
+    }
+
+    /// Capabilities in both sets.
+    pub fn intersect(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    /// The set named by `names`; names this adapter doesn't know are left
+    /// out.
+    pub fn from_names<S: AsRef<str>>(names: &[S]) -> Self {
+        Capability::ALL
+            .into_iter()
+            .filter(|capability| names.iter().any(|name| name.as_ref() == capability.name()))
+            .fold(Self::default(), Self::with)
+    }
+
+    /// Those of the set that `version` can carry.
+    pub fn for_version(self, version: u8) -> Self {
+        Capability::ALL
+            .into_iter()
+            .filter(|capability| self.contains(*capability) && capability.min_version() <= version)
+            .fold(Self::default(), Self::with)
+    }
+
+    pub fn names(self) -> Vec<&'static str> {
+        Capability::ALL
+            .into_iter()
+            .filter(|capability| self.contains(*capability))
+            .map(Capability::name)
+            .collect()
+    }
+}
+
+/// What each side says about itself in its HELLO.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    pub name: String,
+    pub version: String,
+    /// Frame protocol versions spoken.
+    pub protocol_versions: Vec<u8>,
+    /// [`Capability`] names supported.
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    /// Shared-secret challenge or proof, with `[auth]` on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<Auth>,
+}
+
+impl Hello {
+    pub fn adapter(offered: Capabilities) -> Self {
+        Self {
+            name: ADAPTER_NAME.to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            protocol_versions: (OLDEST_PROTOCOL_VERSION..=VERSION).collect(),
+            capabilities: offered.names().into_iter().map(String::from).collect(),
+            auth: None,
+        }
+    }
+}
+
+/// The shared-secret exchange: the adapter's HELLO carries a fresh `nonce`
+/// and the core's must carry the [proof](Secret::prove) for it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Auth {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof: Option<String>,
+}
+
+/// HELLO payloads travel as `{"hello": {...}}`.
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    hello: Hello,
+}
+
+/// The outcome of a handshake.
+#[derive(Debug)]
+pub struct Handshake {
+    /// The core's HELLO; `None` when the handshake is off or the core
+    /// answered without introducing itself.
+    pub core: Option<Hello>,
+    /// The newest frame protocol version both sides speak; frames to the
+    /// core are marked with it.
+    pub protocol_version: u8,
+    /// Capabilities usable on the connection: those both sides listed, or
+    /// everything offered when the core didn't introduce itself.
+    pub capabilities: Capabilities,
+    /// Frames the core sent before its answer, still to be served.
+    pub early: Vec<OwnedFrame>,
+    /// Signs and checks the connection's frames once `frame_mac` is agreed.
+    pub frame_mac: Option<FrameMac>,
+}
+
+/// The adapter's HELLO: a PING whose payload introduces the adapter, so a
+/// core that doesn't know the handshake still answers it.
+pub fn hello_frame(hello: Hello) -> Option<Bytes> {
+    let payload = serde_json::to_vec(&Envelope { hello }).ok()?;
+    encode(
+        MessageType::Ping,
+        FrameFlags::FINAL,
+        HELLO_REQUEST_ID,
+        &payload,
+    )
+    .ok()
+    .map(Bytes::from)
+}
+
+/// The newest protocol version `core` and the adapter both speak.
+pub fn shared_version(core: &Hello) -> Option<u8> {
+    core.protocol_versions
+        .iter()
+        .copied()
+        .filter(|version| (OLDEST_PROTOCOL_VERSION..=VERSION).contains(version))
+        .max()
+}
+
+/// Whether `frame` answers the adapter's HELLO.
+pub fn is_answer(frame: &OwnedFrame) -> bool {
+    RequestId(frame.header.request_id) == HELLO_REQUEST_ID
+        && matches!(
+            MessageType::try_from(frame.header.msg_type),
+            Ok(MessageType::Pong | MessageType::Error)
+        )
+}
+
+/// Checks the core's answer to the adapter's HELLO. A PONG merely echoing
+/// it comes from a core that predates the handshake and is served as
+/// before; an ERROR, or a HELLO sharing no protocol version with the
+/// adapter, fails with `Unsupported`.
+pub fn check_answer(frame: &OwnedFrame) -> io::Result<Option<Hello>> {
+    let unsupported = |message: String| io::Error::new(io::ErrorKind::Unsupported, message);
+    if MessageType::try_from(frame.header.msg_type) == Ok(MessageType::Error) {
+        return Err(unsupported(format!(
+            "core refused the handshake: {}",
+            String::from_utf8_lossy(&frame.payload)
+        )));
+    }
+    if !(OLDEST_PROTOCOL_VERSION..=VERSION).contains(&frame.header.version) {
+        return Err(unsupported(format!(
+            "core speaks protocol version {}, adapter speaks {OLDEST_PROTOCOL_VERSION} to {VERSION}",
+            frame.header.version
+        )));
+    }
+    let Ok(Envelope { hello }) = serde_json::from_slice(&frame.payload) else {
+        return Ok(None);
+    };
+    if hello.name == ADAPTER_NAME {
+        return Ok(None);
+    }
+    if shared_version(&hello).is_none() {
+        return Err(unsupported(format!(
+            "core {} {} speaks protocol versions {:?}, adapter speaks {OLDEST_PROTOCOL_VERSION} to {VERSION}",
+            hello.name, hello.version, hello.protocol_versions
+        )));
+    }
+    Ok(Some(hello))
+}
+
+/// Checks that the core's HELLO proves it shares `secret`, answering the
+/// challenge `nonce`; fails with `PermissionDenied` when it doesn't.
+pub fn check_proof(core: Option<Hello>, secret: &Secret, nonce: &str) -> io::Result<Option<Hello>> {
+    let proof = core
+        .as_ref()
+        .and_then(|core| core.auth.as_ref())
+        .and_then(|auth| auth.proof.as_deref());
+    match proof {
+        Some(proof) if secret.verify(nonce, proof) => Ok(core),
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "core's auth proof does not match the shared secret",
+        )),
+        None => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "core did not authenticate with the shared secret",
+        )),
+    }
+}
+
+/// Sends the adapter's HELLO, offering `offered`, on a blocking `stream`
+/// and waits for the core's answer, decoding with `decoder` so that queries
+/// arriving first are kept for the event loop. Does nothing when the
+/// handshake is off and there is no `secret`.
+///
+/// With a `secret` the core must answer the HELLO's challenge with the
+/// proof that it shares it; otherwise the connection fails with
+/// `PermissionDenied` and nothing the core sent is served.
+///
+/// A core that only speaks an older protocol version is served in a
+/// compatibility mode: capabilities its version can't carry are left out,
+/// and logged.
+pub fn exchange(
+    stream: &mut Stream,
+    config: HandshakeConfig,
+    offered: Capabilities,
+    secret: Option<&Secret>,
+    decoder: &mut FrameDecoder,
+) -> io::Result<Handshake> {
+    let mut handshake = Handshake {
+        core: None,
+        protocol_version: VERSION,
+        capabilities: offered,
+        early: Vec::new(),
+        frame_mac: None,
+    };
+    if !config.enabled && secret.is_none() {
+        // a core that never introduced itself can't expect the notice
+        handshake.capabilities = offered.without(Capability::GoingAway);
+        return Ok(handshake);
+    }
+    let mut hello = Hello::adapter(offered);
+    let nonce = match secret {
+        Some(_) => Some(auth::nonce()?),
+        None => None,
+    };
+    hello.auth = nonce.clone().map(|nonce| Auth {
+        nonce: Some(nonce),
+        proof: None,
+    });
+    let hello = hello_frame(hello).ok_or_else(|| io::Error::other("HELLO not encoded"))?;
+    stream.write_all(&hello)?;
+
+    let deadline = Instant::now() + Duration::from_millis(config.timeout_ms);
+    let mut buf = client::read_buffer(decoder);
+    let mut frames = Vec::new();
+    let answered = loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "core did not answer the handshake in {} ms",
+                    config.timeout_ms
+                ),
+            ));
+        }
+        stream.set_read_timeout(Some(left))?;
+        let read = match stream.read(&mut buf) {
+            Ok(0) => break Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(read) => read,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => break Err(e),
+        };
+        let mut rejected = Vec::new();
+        decoder.decode(&buf[..read], &mut frames, |r| {
+            rejected.extend(handler::frame_rejected(&r))
+        });
+        for reply in rejected {
+            stream.write_all(&reply)?;
+        }
+        if let Some(at) = frames.iter().position(is_answer) {
+            let answer = frames.remove(at);
+            break check_answer(&answer).and_then(|core| match (secret, &nonce) {
+                (Some(secret), Some(nonce)) => check_proof(core, secret, nonce),
+                _ => Ok(core),
+            });
+        }
+    };
+    stream.set_read_timeout(None)?;
+
+    let answered = answered.and_then(|core| {
+        let Some(secret) = secret.filter(|secret| secret.signs_frames()) else {
+            return Ok(core);
+        };
+        // the core's HELLO isn't signed, so dropping frame_mac from it, or
+        // the protocol versions that carry it, must not downgrade the
+        // connection
+        let agreed = core.as_ref().is_some_and(|core| {
+            core.capabilities
+                .iter()
+                .any(|c| c == Capability::FrameMac.name())
+                && shared_version(core)
+                    .is_some_and(|version| version >= Capability::FrameMac.min_version())
+        });
+        if !agreed {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "core did not agree to frame MACs",
+            ));
+        }
+        handshake.frame_mac = nonce.as_deref().map(|nonce| secret.frame_mac(nonce));
+        Ok(core)
+    });
+    match answered {
+        Ok(Some(core)) => {
+            let agreed = offered.intersect(Capabilities::from_names(&core.capabilities));
+            handshake.protocol_version = shared_version(&core).unwrap_or(VERSION);
+            handshake.capabilities = agreed.for_version(handshake.protocol_version);
+            if handshake.protocol_version < VERSION {
+                let disabled: Vec<_> = agreed
+                    .names()
+                    .into_iter()
+                    .filter(|name| !handshake.capabilities.names().contains(name))
+                    .collect();
+                warn!(
+                    core = %core.name,
+                    protocol_version = handshake.protocol_version,
+                    adapter_version = VERSION,
+                    ?disabled,
+                    "core speaks an older protocol, serving it in compatibility mode"
+                );
+            }
+            info!(
+                core = %core.name,
+                version = %core.version,
+                capabilities = ?handshake.capabilities.names(),
+                "handshake complete"
+            );
+            handshake.core = Some(core);
+        }
+        Ok(None) => info!("core answered the handshake without introducing itself"),
+        Err(e) => {
+            warn!(error = %e, "handshake failed, not serving this core");
+            return Err(e);
+        }
+    }
+    handshake.early = frames;
+    Ok(handshake)
+}
@@ -0,0 +1,179 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::context::Context;
+
+// how often an idle listener looks for the adapter stopping
+const ACCEPT_POLL: Duration = Duration::from_millis(100);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_REQUEST_BYTES: usize = 4096;
+
+/// `[health]` section: HTTP liveness and readiness endpoints for
+/// orchestration platforms.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    /// `host:port` `/healthz` and `/readyz` are served on; not served when
+    /// unset.
+    pub listen: Option<String>,
+    /// Queries waiting or running from which the adapter reports itself
+    /// overloaded; `queue_depth` when unset.
+    pub max_queue_depth: Option<u64>,
+}
+
+impl HealthConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(listen) = &self.listen
+            && listen.parse::<SocketAddr>().is_err()
+        {
+            return Err(format!("health.listen must be an ip:port, not {listen}"));
+        }
+        if self.max_queue_depth == Some(0) {
+            return Err("health.max_queue_depth must be at least 1".into());
+        }
+        Ok(())
+    }
+}
+
+/// Whether the adapter should be sent queries, and why not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Readiness {
+    /// Connections to the core up right now.
+    pub connected: usize,
+    pub index_open: bool,
+    /// Queries waiting or running.
+    pub queue_depth: u64,
+    pub max_queue_depth: u64,
+    pub shutting_down: bool,
+}
+
+impl Readiness {
+    pub fn of(context: &Context, max_queue_depth: u64) -> Self {
+        Self {
+            connected: context.connections.connected(),
+            index_open: !context.shards.is_empty(),
+            queue_depth: context.counters.snapshot().queue_depth,
+            max_queue_depth,
+            shutting_down: context.shutdown.is_started(),
+        }
+    }
+
+    /// Connected to a core, with the index open, not overloaded and not
+    /// shutting down.
+    pub fn is_ready(&self) -> bool {
+        self.connected > 0
+            && self.index_open
+            && self.queue_depth < self.max_queue_depth
+            && !self.shutting_down
+    }
+}
+
+/// Serves `/healthz`, answered `200` as long as the process runs, and
+/// `/readyz`, answered `200` when [ready](Readiness::is_ready) and `503`
+/// otherwise, with a JSON body saying why. One request per connection.
+#[derive(Debug)]
+pub struct HealthServer {
+    listener: TcpListener,
+    max_queue_depth: u64,
+}
+
+impl HealthServer {
+    /// Listens as `config` says, if it says to; `queue_depth` is the
+    /// overload threshold unless `config` sets one.
+    pub fn bind(config: &HealthConfig, queue_depth: usize) -> io::Result<Option<Self>> {
+        let Some(listen) = &config.listen else {
+            return Ok(None);
+        };
+        let listener = TcpListener::bind(listen)?;
+        listener.set_nonblocking(true)?;
+        info!(address = %listener.local_addr()?, "serving /healthz and /readyz");
+        Ok(Some(Self {
+            listener,
+            max_queue_depth: config.max_queue_depth.unwrap_or(queue_depth as u64),
+        }))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Answers probes until `stop` is set.
+    pub fn serve(&self, context: &Context, stop: &AtomicBool) {
+        while !stop.load(Ordering::Relaxed) {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = self.answer(stream, context) {
+                        warn!(error = %e, "health probe not answered");
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    warn!(error = %e, "health probe not accepted");
+                    thread::sleep(ACCEPT_POLL);
+                }
+            }
+        }
+    }
+
+    fn answer(&self, mut stream: TcpStream, context: &Context) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        while !request.windows(4).any(|end| end == b"\r\n\r\n") && request.len() < MAX_REQUEST_BYTES
+        {
+            match stream.read(&mut buf)? {
+                0 => break,
+                read => request.extend_from_slice(&buf[..read]),
+            }
+        }
+        let request = String::from_utf8_lossy(&request);
+        let mut line = request.lines().next().unwrap_or_default().split(' ');
+        let (method, path) = (
+            line.next().unwrap_or_default(),
+            line.next().unwrap_or_default(),
+        );
+        let (status, body) = match (method, path) {
+            ("GET" | "HEAD", "/healthz") => ("200 OK", "ok\n".to_string()),
+            ("GET" | "HEAD", "/readyz") => {
+                let readiness = Readiness::of(context, self.max_queue_depth);
+                let status = match readiness.is_ready() {
+                    true => "200 OK",
+                    false => "503 Service Unavailable",
+                };
+                let body = json!({
+                    "ready": readiness.is_ready(),
+                    "connected": readiness.connected,
+                    "index_open": readiness.index_open,
+                    "queue_depth": readiness.queue_depth,
+                    "max_queue_depth": readiness.max_queue_depth,
+                    "shutting_down": readiness.shutting_down,
+                });
+                (status, format!("{body}\n"))
+            }
+            (_, "/healthz" | "/readyz") => ("405 Method Not Allowed", String::new()),
+            _ => ("404 Not Found", String::new()),
+        };
+        let content_type = match body.starts_with('{') {
+            true => "application/json",
+            false => "text/plain",
+        };
+        let mut response = format!(
+            "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        );
+        if method != "HEAD" {
+            response.push_str(&body);
+        }
+        stream.write_all(response.as_bytes())
+    }
+}
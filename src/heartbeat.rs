@@ -0,0 +1,121 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use nerve_protocol::codec::encode;
+use nerve_protocol::frame::OwnedFrame;
+use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+use serde::Deserialize;
+
+pub const DEFAULT_MAX_MISSED: u32 = 3;
+
+/// `[heartbeat]` section: pinging the core to notice a peer that is gone
+/// while its socket stays open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct HeartbeatConfig {
+    /// Time between pings; no pings are sent when unset.
+    pub interval_ms: Option<u64>,
+    /// How long the core may stay silent before it is pinged, instead of
+    /// pinging on a fixed `interval_ms`. Every frame read from the core
+    /// counts as an answer.
+    pub idle_timeout_ms: Option<u64>,
+    /// Pings in a row the core may leave unanswered before the connection
+    /// is given up on.
+    pub max_missed: u32,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval_ms: None,
+            idle_timeout_ms: None,
+            max_missed: DEFAULT_MAX_MISSED,
+        }
+    }
+}
+
+/// Ping schedule for one connection.
+#[derive(Debug)]
+pub struct Heartbeat {
+    interval: Option<Duration>,
+    // pinging only once the core has gone quiet
+    idle: bool,
+    max_missed: u32,
+    // pings sent since the last pong
+    missed: u32,
+    next: Instant,
+}
+
+impl Heartbeat {
+    pub fn new(config: HeartbeatConfig) -> Self {
+        let idle = config.interval_ms.is_none() && config.idle_timeout_ms.is_some();
+        let interval = config
+            .interval_ms
+            .or(config.idle_timeout_ms)
+            .map(Duration::from_millis);
+        Self {
+            interval,
+            idle,
+            max_missed: config.max_missed,
+            missed: 0,
+            next: Instant::now() + interval.unwrap_or_default(),
+        }
+    }
+
+    /// How long until the next ping is due; `None` when pings are off.
+    pub fn until_due(&self) -> Option<Duration> {
+        self.interval?;
+        Some(self.next.saturating_duration_since(Instant::now()))
+    }
+
+    /// Notes a pong from the core.
+    pub fn pong(&mut self) {
+        self.missed = 0;
+    }
+
+    /// Notes frames read from the core. With an idle timeout they show it
+    /// is alive, so the next ping waits for the core to go quiet again.
+    pub fn heard(&mut self) {
+        if let (true, Some(interval)) = (self.idle, self.interval) {
+            self.missed = 0;
+            self.next = Instant::now() + interval;
+        }
+    }
+
+    /// The ping to send if one is due. Fails with `TimedOut` once
+    /// `max_missed` pings in a row have gone unanswered.
+    pub fn tick(&mut self) -> io::Result<Option<Bytes>> {
+        let Some(interval) = self.interval else {
+            return Ok(None);
+        };
+        let now = Instant::now();
+        if now < self.next {
+            return Ok(None);
+        }
+        if self.missed >= self.max_missed {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("core left {} pings unanswered", self.missed),
+            ));
+        }
+        self.missed += 1;
+        self.next = now + interval;
+        Ok(frame(MessageType::Ping, RequestId(0), &[]))
+    }
+}
+
+/// PONG answering a PING from the core, echoing its request id and payload.
+pub fn pong(ping: &OwnedFrame) -> Option<Bytes> {
+    frame(
+        MessageType::Pong,
+        RequestId(ping.header.request_id),
+        &ping.payload,
+    )
+}
+
+fn frame(msg_type: MessageType, request_id: RequestId, payload: &[u8]) -> Option<Bytes> {
+    encode(msg_type, FrameFlags::FINAL, request_id, payload)
+        .ok()
+        .map(Bytes::from)
+}
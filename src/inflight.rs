@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::state::CancelToken;
+
+/// How often a search waiting on an identical one looks for a cancel.
+const WAIT_POLL: Duration = Duration::from_millis(10);
+
+type Outcome = Result<Arc<Vec<Value>>, (io::ErrorKind, String)>;
+
+#[derive(Default)]
+struct Flight {
+    outcome: Mutex<Option<Outcome>>,
+    done: Condvar,
+}
+
+impl Flight {
+    fn finish(&self, outcome: Outcome) {
+        *self.outcome.lock().unwrap_or_else(|e| e.into_inner()) = Some(outcome);
+        self.done.notify_all();
+    }
+}
+
+/// Searches currently running, by cache key, so identical queries arriving
+/// together run the engine once.
+#[derive(Default)]
+pub struct InFlight {
+    flights: Mutex<HashMap<String, Arc<Flight>>>,
+}
+
+impl InFlight {
+    /// Runs `search` for `key`, unless an identical search is already
+    /// running: then waits for it and returns a copy of its hits.
+    ///
+    /// If the search being waited on was cancelled or timed out, the waiter
+    /// runs `search` itself, under its own token.
+    pub fn run(
+        &self,
+        key: &str,
+        cancel: &CancelToken,
+        search: impl FnOnce() -> io::Result<Vec<Value>>,
+    ) -> io::Result<Vec<Value>> {
+        let (flight, leader) = {
+            let mut flights = self.flights();
+            match flights.get(key) {
+                Some(flight) => (Arc::clone(flight), false),
+                None => {
+                    let flight = Arc::new(Flight::default());
+                    flights.insert(key.to_string(), Arc::clone(&flight));
+                    (flight, true)
+                }
+            }
+        };
+        if !leader {
+            return match wait(&flight, cancel)? {
+                Ok(hits) => Ok(hits.to_vec()),
+                Err((io::ErrorKind::Interrupted | io::ErrorKind::TimedOut, _)) => search(),
+                Err((kind, message)) => Err(io::Error::new(kind, message)),
+            };
+        }
+
+        let lead = Lead {
+            in_flight: self,
+            key,
+            flight,
+        };
+        let result = search();
+        lead.finish(&result);
+        result
+    }
+
+    fn flights(&self) -> MutexGuard<'_, HashMap<String, Arc<Flight>>> {
+        self.flights.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn wait(flight: &Flight, cancel: &CancelToken) -> io::Result<Outcome> {
+    let mut outcome = flight.outcome.lock().unwrap_or_else(|e| e.into_inner());
+    loop {
+        if let Some(outcome) = outcome.as_ref() {
+            return Ok(outcome.clone());
+        }
+        cancel.check()?;
+        outcome = flight
+            .done
+            .wait_timeout(outcome, WAIT_POLL)
+            .unwrap_or_else(|e| e.into_inner())
+            .0;
+    }
+}
+
+/// The running copy of a search; always hands waiters an outcome, even if
+/// the search panics.
+struct Lead<'a> {
+    in_flight: &'a InFlight,
+    key: &'a str,
+    flight: Arc<Flight>,
+}
+
+impl Lead<'_> {
+    fn finish(&self, result: &io::Result<Vec<Value>>) {
+        self.in_flight.flights().remove(self.key);
+        // nobody can join any more; only copy the hits if someone is waiting
+        if Arc::strong_count(&self.flight) > 1 {
+            let outcome = match result {
+                Ok(hits) => Ok(Arc::new(hits.clone())),
+                Err(e) => Err((e.kind(), e.to_string())),
+            };
+            self.flight.finish(outcome);
+        }
+    }
+}
+
+impl Drop for Lead<'_> {
+    fn drop(&mut self) {
+        let mut flights = self.in_flight.flights();
+        if flights
+            .get(self.key)
+            .is_some_and(|flight| Arc::ptr_eq(flight, &self.flight))
+        {
+            flights.remove(self.key);
+            drop(flights);
+            self.flight.finish(Err((
+                io::ErrorKind::Interrupted,
+                "identical search abandoned".to_string(),
+            )));
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use std::io;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use serde::Serialize;
+use tantivy::postings::Postings;
+use tantivy::schema::{FieldType, IndexRecordOption, Schema};
+use tantivy::{DocSet, Index, TERMINATED, Term};
+
+use crate::directory;
+use crate::shards::Shards;
+
+/// Health snapshot of one index directory, as returned by `index_stats`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShardStats {
+    pub path: String,
+    pub num_docs: u64,
+    pub num_deleted_docs: u64,
+    pub segments: usize,
+    pub size_bytes: u64,
+    /// Unix seconds of the last commit (mtime of `meta.json`).
+    pub last_commit: Option<u64>,
+    pub opstamp: u64,
+    /// Stable fingerprint of the index schema; changes whenever a field or
+    /// its options change.
+    pub schema_version: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexStats {
+    pub num_docs: u64,
+    pub segments: usize,
+    pub size_bytes: u64,
+    pub shards: Vec<ShardStats>,
+}
+
+pub fn index_stats(shards: &Shards) -> io::Result<IndexStats> {
+    let shards = shards
+        .iter()
+        .map(|shard| shard_stats(&shard.path))
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok(IndexStats {
+        num_docs: shards.iter().map(|s| s.num_docs).sum(),
+        segments: shards.iter().map(|s| s.segments).sum(),
+        size_bytes: shards.iter().map(|s| s.size_bytes).sum(),
+        shards,
+    })
+}
+
+pub fn shard_stats(path: &Path) -> io::Result<ShardStats> {
+    let index = open_index(path)?;
+    let metas = index.load_metas().map_err(tantivy_error)?;
+    let schema = serde_json::to_vec(&index.schema())?;
+
+    let last_commit = std::fs::metadata(path.join("meta.json"))
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs());
+
+    Ok(ShardStats {
+        path: path.display().to_string(),
+        num_docs: metas.segments.iter().map(|s| u64::from(s.num_docs())).sum(),
+        num_deleted_docs: metas
+            .segments
+            .iter()
+            .map(|s| u64::from(s.num_deleted_docs()))
+            .sum(),
+        segments: metas.segments.len(),
+        size_bytes: dir_size(path)?,
+        last_commit,
+        opstamp: metas.opstamp,
+        schema_version: format!("{:016x}", fnv1a(&schema)),
+    })
+}
+
+pub fn open_index(path: &Path) -> io::Result<Index> {
+    Index::open_in_dir(path).map_err(tantivy_error)
+}
+
+pub fn tantivy_error(e: tantivy::TantivyError) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+fn dir_size(path: &Path) -> io::Result<u64> {
+    let mut total = 0;
+    for entry in std::fs::read_dir(path)? {
+        let meta = entry?.metadata()?;
+        if meta.is_file() {
+            total += meta.len();
+        }
+    }
+    Ok(total)
+}
+
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// One schema field as reported by the `schema` operation.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldInfo {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub field_type: String,
+    pub indexed: bool,
+    pub stored: bool,
+    pub fast: bool,
+    /// Tokenizer and postings detail, for indexed text fields.
+    pub tokenizer: Option<String>,
+    pub record: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SchemaInfo {
+    pub schema_version: String,
+    pub fields: Vec<FieldInfo>,
+    /// Values accepted for `sorts` in structured requests.
+    pub sorts: Vec<&'static str>,
+}
+
+/// Describes the schema of the primary index as it exists on disk.
+pub fn schema_info(shards: &Shards) -> io::Result<SchemaInfo> {
+    let shard = shards
+        .iter()
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no index shards"))?;
+    let schema = open_index(&shard.path)?.schema();
+
+    Ok(SchemaInfo {
+        schema_version: format!("{:016x}", fnv1a(&serde_json::to_vec(&schema)?)),
+        fields: schema_fields(&schema),
+        sorts: vec!["relevance", "pagerank", "quality"],
+    })
+}
+
+pub fn schema_fields(schema: &Schema) -> Vec<FieldInfo> {
+    schema
+        .fields()
+        .map(|(_, entry)| {
+            let indexing = match entry.field_type() {
+                FieldType::Str(options) => options.get_indexing_options(),
+                _ => None,
+            };
+            FieldInfo {
+                name: entry.name().to_owned(),
+                field_type: entry.field_type().value_type().name().to_lowercase(),
+                indexed: entry.is_indexed(),
+                stored: entry.is_stored(),
+                fast: entry.is_fast(),
+                tokenizer: indexing.map(|i| i.tokenizer().to_owned()),
+                record: indexing.map(|i| format!("{:?}", i.index_option()).to_lowercase()),
+            }
+        })
+        .collect()
+}
+
+/// Corpus statistics for one analysed term.
+#[derive(Debug, Clone, Serialize)]
+pub struct TermStats {
+    pub field: String,
+    pub term: String,
+    /// Documents containing the term (deleted documents included).
+    pub doc_freq: u64,
+    /// Occurrences of the term across all documents.
+    pub total_term_freq: u64,
+}
+
+/// Looks up `terms` in `field` across every shard. Each input is run through
+/// the field's analyzer first, so `"Rust"` reports the indexed token `rust`;
+/// an input that analyses to several tokens reports each of them.
+pub fn term_stats(shards: &Shards, field: &str, terms: &[String]) -> io::Result<Vec<TermStats>> {
+    let mut stats: Vec<TermStats> = Vec::new();
+    for shard in shards.iter() {
+        let index = directory::open_index(&shard.path, shards.access())?;
+        for (i, shard_stats) in index_term_stats(&index, field, terms)?
+            .into_iter()
+            .enumerate()
+        {
+            match stats.get_mut(i) {
+                Some(total) => {
+                    total.doc_freq += shard_stats.doc_freq;
+                    total.total_term_freq += shard_stats.total_term_freq;
+                }
+                None => stats.push(shard_stats),
+            }
+        }
+    }
+    Ok(stats)
+}
+
+pub fn shard_term_stats(
+    path: &Path,
+    field_name: &str,
+    terms: &[String],
+) -> io::Result<Vec<TermStats>> {
+    index_term_stats(&open_index(path)?, field_name, terms)
+}
+
+fn index_term_stats(
+    index: &Index,
+    field_name: &str,
+    terms: &[String],
+) -> io::Result<Vec<TermStats>> {
+    let field = index.schema().get_field(field_name).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown field: {field_name}"),
+        )
+    })?;
+    let mut analyzer = index.tokenizer_for_field(field).map_err(tantivy_error)?;
+    let searcher = index.reader().map_err(tantivy_error)?.searcher();
+
+    let mut stats = Vec::new();
+    for text in terms {
+        let mut tokens = Vec::new();
+        analyzer
+            .token_stream(text)
+            .process(&mut |token| tokens.push(token.text.clone()));
+
+        for token in tokens {
+            let term = Term::from_field_text(field, &token);
+            let doc_freq = searcher.doc_freq(&term).map_err(tantivy_error)?;
+
+            let mut total_term_freq = 0;
+            for segment in searcher.segment_readers() {
+                let inverted = segment.inverted_index(field).map_err(tantivy_error)?;
+                if let Some(mut postings) =
+                    inverted.read_postings(&term, IndexRecordOption::WithFreqs)?
+                {
+                    while postings.doc() != TERMINATED {
+                        total_term_freq += u64::from(postings.term_freq());
+                        postings.advance();
+                    }
+                }
+            }
+
+            stats.push(TermStats {
+                field: field_name.to_owned(),
+                term: token,
+                doc_freq,
+                total_term_freq,
+            });
+        }
+    }
+    Ok(stats)
+}
@@ -1,3 +1,58 @@
+pub mod admin;
+pub mod affinity;
+pub mod analysis;
+pub mod audit;
+pub mod auth;
+pub mod breaker;
+pub mod budget;
+#[cfg(feature = "tokio")]
+pub mod async_client;
+pub mod cache;
+pub mod capture;
+#[cfg(feature = "chaos")]
+pub mod chaos;
 pub mod client;
+pub mod config;
+pub mod connections;
+pub mod context;
+pub mod control;
+pub mod dedup;
+pub mod directory;
+pub mod discovery;
+pub mod federation;
+pub mod filters;
+pub mod framing;
 pub mod handler;
+pub mod handshake;
+pub mod health;
+pub mod heartbeat;
+pub mod inflight;
+pub mod introspect;
+pub mod metrics;
+pub mod peercred;
+pub mod profile;
+pub mod querylog;
+pub mod rank;
+pub mod ratelimit;
+pub mod reconnect;
+pub mod replay;
+pub mod request;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod shards;
+pub mod shutdown;
+pub mod slowlog;
 pub mod state;
+pub mod statsd;
+pub mod stdio;
+pub mod telemetry;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod transport;
+#[cfg(feature = "io-uring")]
+pub mod uring;
+pub mod usage;
+pub mod vector;
+pub mod warm;
+pub mod watchdog;
+pub mod writer;
@@ -1,11 +1,29 @@
-use nerve_search_adapter::client;
+use nerve_search_adapter::config::Config;
 use tracing::info;
 
 fn main()->std::io::Result<()>{
-    tracing_subscriber::fmt::init();
+    let config = Config::from_args(std::env::args().skip(1))?;
+    if let Some(capture) = &config.replay_path{
+        tracing_subscriber::fmt::init();
+        info!(capture = %capture.display(), "replaying through NERVE-SEARCH-ADAPTER");
+        return nerve_search_adapter::replay::run(&config, capture);
+    }
+    if config.uses_stdio(){
+        // stdout carries the frames
+        tracing_subscriber::fmt().with_writer(std::io::stderr).init();
+        info!("starting NERVE-SEARCH-ADAPTER on stdin/stdout");
+        return nerve_search_adapter::stdio::run(&config);
+    }
+    let _exporter = nerve_search_adapter::telemetry::init(&config.otlp)?;
+    info!(socket = %config.socket_path.display(), source = %config.socket_source, "starting NERVE-SEARCH-ADAPTER");
 
-    let socket_path = "/tmp/nerve.sock";
-    info!("starting NERVE-SEARCH-ADAPTER");
+    #[cfg(feature = "tokio")]
+    return tokio::runtime::Runtime::new()?
+        .block_on(nerve_search_adapter::async_client::run(&config));
 
-    client::run(socket_path)
-}
\ No newline at end of file
+    #[cfg(all(feature = "io-uring", not(feature = "tokio")))]
+    return nerve_search_adapter::uring::run(&config);
+
+    #[cfg(not(any(feature = "tokio", feature = "io-uring")))]
+    nerve_search_adapter::client::run(&config)
+}
@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::shards::ShardTiming;
+
+// 32 linear sub-buckets per power of two: quantiles are within ~3% of the
+// recorded value at every magnitude
+const SUB_BUCKET_BITS: u32 = 5;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+const BUCKETS: usize = (64 - SUB_BUCKET_BITS as usize + 1) * SUB_BUCKETS;
+
+/// HDR-style log-linear histogram of microsecond latencies.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; BUCKETS],
+            total: 0,
+            max: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, micros: u64) {
+        self.counts[bucket(micros)] += 1;
+        self.total += 1;
+        self.max = self.max.max(micros);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Smallest recorded value (to bucket precision) that at least `q` of the
+    /// recordings do not exceed; 0 when empty.
+    pub fn quantile(&self, q: f64) -> u64 {
+        if self.total == 0 {
+            return 0;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * self.total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return bucket_high(i).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            count: self.total,
+            p50_us: self.quantile(0.50),
+            p90_us: self.quantile(0.90),
+            p99_us: self.quantile(0.99),
+            max_us: self.max,
+        }
+    }
+}
+
+fn bucket(value: u64) -> usize {
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+    let shift = 63 - value.leading_zeros() - SUB_BUCKET_BITS;
+    let sub = (value >> shift) as usize - SUB_BUCKETS;
+    (shift as usize + 1) * SUB_BUCKETS + sub
+}
+
+/// Highest value landing in bucket `i`.
+fn bucket_high(i: usize) -> u64 {
+    if i < SUB_BUCKETS {
+        return i as u64;
+    }
+    let shift = (i / SUB_BUCKETS - 1) as u32;
+    let next = (i % SUB_BUCKETS + SUB_BUCKETS + 1) as u128;
+    u64::try_from(next << shift).map_or(u64::MAX, |next| next - 1)
+}
+
+/// Latency quantiles as reported by the `metrics` operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub p50_us: u64,
+    pub p90_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+}
+
+/// Request latency, arrival to reply, per operation.
+#[derive(Debug, Default)]
+pub struct Latencies {
+    by_op: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+impl Latencies {
+    pub fn record(&self, op: &'static str, elapsed: Duration) {
+        let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        self.by_op().entry(op).or_default().record(micros);
+    }
+
+    pub fn summary(&self) -> BTreeMap<&'static str, LatencySummary> {
+        self.by_op()
+            .iter()
+            .map(|(op, histogram)| (*op, histogram.summary()))
+            .collect()
+    }
+
+    fn by_op(&self) -> MutexGuard<'_, BTreeMap<&'static str, Histogram>> {
+        self.by_op.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Engine time per shard, across every search pass that reached it.
+#[derive(Debug, Default)]
+pub struct ShardLatencies {
+    by_shard: Mutex<BTreeMap<usize, Histogram>>,
+}
+
+impl ShardLatencies {
+    pub fn record(&self, timings: &[ShardTiming]) {
+        let mut by_shard = self.by_shard();
+        for timing in timings {
+            let micros = u64::try_from(timing.elapsed.as_micros()).unwrap_or(u64::MAX);
+            by_shard.entry(timing.shard).or_default().record(micros);
+        }
+    }
+
+    pub fn summary(&self) -> BTreeMap<usize, LatencySummary> {
+        self.by_shard()
+            .iter()
+            .map(|(shard, histogram)| (*shard, histogram.summary()))
+            .collect()
+    }
+
+    fn by_shard(&self) -> MutexGuard<'_, BTreeMap<usize, Histogram>> {
+        self.by_shard.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Request counts since start, shared by every connection and worker, as
+/// reported by the `stats` operation.
+#[derive(Debug, Default)]
+pub struct Counters {
+    requests: AtomicU64,
+    errors: AtomicU64,
+    panics: AtomicU64,
+    cancellations: AtomicU64,
+    rate_limited: AtomicU64,
+    queued: AtomicU64,
+    settled: AtomicU64,
+}
+
+impl Counters {
+    /// A query reached a worker.
+    pub fn request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A query was answered with an ERROR frame.
+    pub fn error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A query's handler panicked; it was answered with an ERROR frame.
+    pub fn panic(&self) {
+        self.panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A query was cancelled before it was answered.
+    pub fn cancellation(&self) {
+        self.cancellations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A query was turned away by the rate limiter.
+    pub fn rate_limit(&self) {
+        self.rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A query was handed to the workers.
+    pub fn queue(&self) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A query handed to the workers is done with, whatever came of it.
+    pub fn settle(&self) {
+        self.settled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> CountersSnapshot {
+        let queued = self.queued.load(Ordering::Relaxed);
+        CountersSnapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            panics: self.panics.load(Ordering::Relaxed),
+            cancellations: self.cancellations.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            queue_depth: queued.saturating_sub(self.settled.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// [`Counters`] at one moment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CountersSnapshot {
+    pub requests: u64,
+    pub errors: u64,
+    /// Of the `errors`, queries whose handler panicked.
+    pub panics: u64,
+    pub cancellations: u64,
+    /// Queries turned away by the rate limiter, never queued.
+    pub rate_limited: u64,
+    /// Queries waiting for a worker or running.
+    pub queue_depth: u64,
+}
@@ -0,0 +1,112 @@
+use std::io;
+use std::os::fd::AsRawFd;
+
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::transport::Stream;
+
+/// `[peer_credentials]` section: who the process at the far end of a Unix
+/// socket core may run as. Checked once connected, before the handshake;
+/// TCP and TLS cores carry no credentials and aren't checked.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct PeerCredentialsConfig {
+    /// User ids the core may run as; any when empty.
+    pub uids: Vec<u32>,
+    /// Group ids the core may run as; any when empty.
+    pub gids: Vec<u32>,
+}
+
+impl PeerCredentialsConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.uids.is_empty() || !self.gids.is_empty()
+    }
+
+    /// Whether a peer running as `credentials` may be served.
+    pub fn allows(&self, credentials: PeerCredentials) -> bool {
+        (self.uids.is_empty() || self.uids.contains(&credentials.uid))
+            && (self.gids.is_empty() || self.gids.contains(&credentials.gid))
+    }
+}
+
+/// The process at the far end of a Unix socket, as the kernel saw it when
+/// the connection was made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCredentials {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// The credentials of the peer of the Unix socket `socket` (`SO_PEERCRED`).
+#[cfg(target_os = "linux")]
+pub fn peer_credentials(socket: &impl AsRawFd) -> io::Result<PeerCredentials> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: cred and len describe a writable ucred, as SO_PEERCRED expects
+    let got = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&mut cred as *mut libc::ucred).cast(),
+            &mut len,
+        )
+    };
+    if got != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(PeerCredentials {
+        pid: cred.pid,
+        uid: cred.uid,
+        gid: cred.gid,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn peer_credentials(_socket: &impl AsRawFd) -> io::Result<PeerCredentials> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "peer credentials are only supported on Linux",
+    ))
+}
+
+/// Refuses a Unix socket core whose process runs as a user or group
+/// `config` doesn't list, with `PermissionDenied`. Does nothing when no
+/// allowlist is configured or the core isn't on a Unix socket.
+pub fn verify(stream: &Stream, config: &PeerCredentialsConfig) -> io::Result<()> {
+    let (Stream::Unix(socket) | Stream::SeqPacket(socket)) = stream else {
+        return Ok(());
+    };
+    if !config.is_enabled() {
+        return Ok(());
+    }
+    let credentials = peer_credentials(socket)?;
+    if !config.allows(credentials) {
+        warn!(
+            pid = credentials.pid,
+            uid = credentials.uid,
+            gid = credentials.gid,
+            "core runs as an unexpected user, not serving it"
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "core process {} runs as uid {} gid {}, not in peer_credentials",
+                credentials.pid, credentials.uid, credentials.gid
+            ),
+        ));
+    }
+    info!(
+        pid = credentials.pid,
+        uid = credentials.uid,
+        gid = credentials.gid,
+        "core credentials verified"
+    );
+    Ok(())
+}
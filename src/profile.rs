@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::slowlog::Trace;
+
+/// `[profile]` section: sampling of where request time goes.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ProfileConfig {
+    /// File the folded stacks are written to on exit. Profiling is off when
+    /// unset.
+    pub path: Option<PathBuf>,
+    /// Profile one request in this many; I/O loop passes are always timed.
+    pub sample_every: u64,
+}
+
+impl Default for ProfileConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            sample_every: 1,
+        }
+    }
+}
+
+/// Time per request phase, summed over sampled requests and kept as folded
+/// stacks (`op;phase micros` per line), the input format of flamegraph
+/// tools such as inferno or speedscope.
+#[derive(Default)]
+pub struct Profiler {
+    path: Option<PathBuf>,
+    sample_every: u64,
+    seen: AtomicU64,
+    stacks: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Profiler {
+    pub fn new(config: &ProfileConfig) -> Self {
+        Self {
+            path: config.path.clone(),
+            sample_every: config.sample_every.max(1),
+            ..Self::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// Whether the next request is to be profiled.
+    pub fn sample(&self) -> bool {
+        self.is_enabled()
+            && self
+                .seen
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(self.sample_every)
+    }
+
+    /// Adds a finished request's stages; whatever the stages don't cover
+    /// counts as the operation's own time.
+    pub fn record(&self, trace: &Trace, elapsed: Duration) {
+        if trace.op.is_empty() {
+            return;
+        }
+        let mut stacks = self.stacks();
+        let mut staged = Duration::ZERO;
+        for (stage, took) in &trace.stages {
+            staged += *took;
+            *stacks.entry(format!("{};{stage}", trace.op)).or_default() += micros(*took);
+        }
+        *stacks.entry(trace.op.to_string()).or_default() += micros(elapsed.saturating_sub(staged));
+    }
+
+    /// Adds time the I/O loop spent in `phase` (`decode`, `write`).
+    pub fn record_io(&self, phase: &'static str, took: Duration) {
+        *self.stacks().entry(format!("io;{phase}")).or_default() += micros(took);
+    }
+
+    /// Microseconds per stack so far.
+    pub fn totals(&self) -> BTreeMap<String, u64> {
+        self.stacks().clone()
+    }
+
+    /// The profile as folded stacks, one `stack micros` line each.
+    pub fn folded(&self) -> String {
+        let mut folded = String::new();
+        for (stack, micros) in self.stacks().iter() {
+            let _ = writeln!(folded, "{stack} {micros}");
+        }
+        folded
+    }
+
+    /// Writes the folded stacks to the configured file.
+    pub fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let tmp = path.with_extension("tmp");
+        let saved: io::Result<()> =
+            fs::write(&tmp, self.folded()).and_then(|()| fs::rename(&tmp, path));
+        match saved {
+            Ok(()) => info!(path = %path.display(), "profile saved"),
+            Err(e) => {
+                let _ = fs::remove_file(&tmp);
+                warn!(path = %path.display(), error = %e, "profile not saved");
+            }
+        }
+    }
+
+    fn stacks(&self) -> MutexGuard<'_, BTreeMap<String, u64>> {
+        self.stacks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn micros(took: Duration) -> u64 {
+    took.as_micros() as u64
+}
@@ -0,0 +1,207 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use nerve_protocol::types::RequestId;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::slowlog::Trace;
+
+pub const DEFAULT_QUERY_LOG_MAX_BYTES: u64 = 64 * 1024 * 1024;
+pub const DEFAULT_QUERY_LOG_KEEP: usize = 7;
+
+/// `[querylog]` section: every search's query written to its own file,
+/// apart from the application logs, rotated by size and age.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct QueryLogConfig {
+    /// File queries are written to; nothing is logged when unset.
+    pub path: Option<PathBuf>,
+    /// Rotate once the file holds this many bytes (0 = never by size).
+    pub max_bytes: u64,
+    /// Rotate once the file has been written to this long; never by time
+    /// when unset.
+    pub rotate_every_secs: Option<u64>,
+    /// Rotated files kept, as `<path>.1` (newest) to `<path>.<keep>`.
+    pub keep: usize,
+    /// Rotated files last written longer ago than this are deleted.
+    pub max_age_secs: Option<u64>,
+}
+
+impl Default for QueryLogConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_bytes: DEFAULT_QUERY_LOG_MAX_BYTES,
+            rotate_every_secs: None,
+            keep: DEFAULT_QUERY_LOG_KEEP,
+            max_age_secs: None,
+        }
+    }
+}
+
+impl QueryLogConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.rotate_every_secs == Some(0) {
+            return Err("querylog.rotate_every_secs must be at least 1".into());
+        }
+        if self.max_age_secs == Some(0) {
+            return Err("querylog.max_age_secs must be at least 1".into());
+        }
+        Ok(())
+    }
+}
+
+/// Writes a JSON line per search: `ts_ms` (Unix milliseconds),
+/// `request_id`, `query`, `hits` and `latency_us`.
+///
+/// The file is rotated before a line would take it past `max_bytes`, or
+/// once it is `rotate_every_secs` old: it becomes `<path>.1`, earlier
+/// rotations move up one, and those past `keep` or older than
+/// `max_age_secs` are deleted.
+#[derive(Debug, Default)]
+pub struct QueryLog {
+    sink: Option<Mutex<Sink>>,
+    config: QueryLogConfig,
+    failed: AtomicBool,
+}
+
+#[derive(Debug)]
+struct Sink {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    opened: Instant,
+}
+
+impl QueryLog {
+    pub fn open(config: &QueryLogConfig) -> io::Result<Self> {
+        let Some(path) = &config.path else {
+            return Ok(Self::default());
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        info!(path = %path.display(), "logging queries");
+        let log = Self {
+            sink: Some(Mutex::new(Sink {
+                path: path.clone(),
+                file,
+                written,
+                opened: Instant::now(),
+            })),
+            config: config.clone(),
+            failed: AtomicBool::new(false),
+        };
+        log.prune(path);
+        Ok(log)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.sink.is_some()
+    }
+
+    pub fn record(&self, request_id: RequestId, trace: &Trace, elapsed: Duration) {
+        let Some(sink) = &self.sink else {
+            return;
+        };
+        let Some(query) = &trace.query else {
+            return;
+        };
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut line = json!({
+            "ts_ms": at.as_millis() as u64,
+            "request_id": request_id.0,
+            "query": query,
+            "hits": trace.hits,
+            "latency_us": elapsed.as_micros() as u64,
+        })
+        .to_string();
+        line.push('\n');
+
+        let mut sink = sink.lock().unwrap_or_else(|e| e.into_inner());
+        let written = match self.is_due(&sink, line.len() as u64) {
+            true => self.rotate(&mut sink),
+            false => Ok(()),
+        }
+        .and_then(|()| sink.file.write_all(line.as_bytes()));
+        match written {
+            Ok(()) => sink.written += line.len() as u64,
+            Err(e) if !self.failed.swap(true, Ordering::Relaxed) => {
+                warn!(path = %sink.path.display(), error = %e, "query log write failed")
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// Whether the file is to be rotated before `next` more bytes go in.
+    fn is_due(&self, sink: &Sink, next: u64) -> bool {
+        let full = self.config.max_bytes > 0
+            && sink.written > 0
+            && sink.written + next > self.config.max_bytes;
+        let old = self
+            .config
+            .rotate_every_secs
+            .is_some_and(|every| sink.opened.elapsed() >= Duration::from_secs(every));
+        full || old
+    }
+
+    fn rotate(&self, sink: &mut Sink) -> io::Result<()> {
+        let keep = self.config.keep;
+        if keep == 0 {
+            fs::remove_file(&sink.path)?;
+        } else {
+            match fs::remove_file(rotated(&sink.path, keep)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            for n in (1..keep).rev() {
+                match fs::rename(rotated(&sink.path, n), rotated(&sink.path, n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(&sink.path, rotated(&sink.path, 1))?;
+        }
+        sink.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&sink.path)?;
+        sink.written = 0;
+        sink.opened = Instant::now();
+        self.prune(&sink.path);
+        Ok(())
+    }
+
+    /// Deletes rotated files older than `max_age_secs`.
+    fn prune(&self, path: &Path) {
+        let Some(max_age) = self.config.max_age_secs.map(Duration::from_secs) else {
+            return;
+        };
+        for n in 1..=self.config.keep {
+            let rotated = rotated(path, n);
+            let age = fs::metadata(&rotated)
+                .and_then(|meta| meta.modified())
+                .map(|modified| modified.elapsed().unwrap_or_default());
+            if age.is_ok_and(|age| age > max_age)
+                && let Err(e) = fs::remove_file(&rotated)
+            {
+                warn!(path = %rotated.display(), error = %e, "expired query log not deleted");
+            }
+        }
+    }
+}
+
+/// The `n`th rotation of `path`, as `<path>.<n>`.
+pub fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut rotated = OsString::from(path.as_os_str());
+    rotated.push(format!(".{n}"));
+    PathBuf::from(rotated)
+}
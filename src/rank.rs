@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::shards::{score, signal};
+
+pub const DEFAULT_RRF_K: f64 = 60.0;
+pub const DEFAULT_LEXICAL_WEIGHT: f64 = 0.5;
+
+/// How lexical and vector result lists are combined in hybrid mode.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Fusion {
+    /// Reciprocal rank fusion: `sum(1 / (k + rank))`, ignores raw scores.
+    Rrf {
+        #[serde(default = "default_rrf_k")]
+        k: f64,
+    },
+    /// Min-max normalise each list's scores, then blend
+    /// `lexical_weight * lexical + (1 - lexical_weight) * vector`.
+    Weighted {
+        #[serde(default = "default_lexical_weight")]
+        lexical_weight: f64,
+    },
+}
+
+impl Default for Fusion {
+    fn default() -> Self {
+        Fusion::Rrf { k: DEFAULT_RRF_K }
+    }
+}
+
+fn default_rrf_k() -> f64 {
+    DEFAULT_RRF_K
+}
+
+fn default_lexical_weight() -> f64 {
+    DEFAULT_LEXICAL_WEIGHT
+}
+
+/// Reranks the union of both lists; hits are identified by `url` and the
+/// fused value replaces `score`. Returned best first, untruncated.
+pub fn fuse(lexical: Vec<Value>, vector: Vec<Value>, fusion: Fusion) -> Vec<Value> {
+    match fusion {
+        Fusion::Rrf { k } => rrf(vec![lexical, vector], k),
+        Fusion::Weighted { lexical_weight } => {
+            let w = lexical_weight.clamp(0.0, 1.0);
+            let lexical = normalize(lexical);
+            let vector = normalize(vector);
+            combine(&[(lexical, w), (vector, 1.0 - w)], |_, score| score)
+        }
+    }
+}
+
+/// Reciprocal rank fusion of any number of rankings of the same query.
+pub fn rrf(lists: Vec<Vec<Value>>, k: f64) -> Vec<Value> {
+    let weighted: Vec<(Vec<Value>, f64)> = lists.into_iter().map(|list| (list, 1.0)).collect();
+    combine(&weighted, |rank, _| 1.0 / (k + rank as f64 + 1.0))
+}
+
+fn combine(lists: &[(Vec<Value>, f64)], contribution: impl Fn(usize, f64) -> f64) -> Vec<Value> {
+    let mut order: Vec<String> = Vec::new();
+    let mut fused: HashMap<String, (Value, f64)> = HashMap::new();
+    for (list, weight) in lists {
+        for (rank, hit) in list.iter().enumerate() {
+            let key = identity(hit, &order);
+            let add = weight * contribution(rank, score(hit));
+            match fused.get_mut(&key) {
+                Some((_, total)) => *total += add,
+                None => {
+                    order.push(key.clone());
+                    fused.insert(key, (hit.clone(), add));
+                }
+            }
+        }
+    }
+
+    let mut hits: Vec<Value> = order
+        .into_iter()
+        .filter_map(|key| fused.remove(&key))
+        .map(|(mut hit, total)| {
+            if let Some(obj) = hit.as_object_mut() {
+                obj.insert("score".into(), Value::from(total));
+            }
+            hit
+        })
+        .collect();
+    hits.sort_by(|a, b| score(b).total_cmp(&score(a)));
+    hits
+}
+
+/// Rescales scores into `[0, 1]` so lists from different scorers blend fairly.
+pub fn normalize(mut hits: Vec<Value>) -> Vec<Value> {
+    let (min, max) = hits
+        .iter()
+        .map(score)
+        .fold((f64::MAX, f64::MIN), |(lo, hi), s| (lo.min(s), hi.max(s)));
+    let span = max - min;
+    for hit in &mut hits {
+        let scaled = if span > 0.0 { (score(hit) - min) / span } else { 1.0 };
+        if let Some(obj) = hit.as_object_mut() {
+            obj.insert("score".into(), Value::from(scaled));
+        }
+    }
+    hits
+}
+
+fn identity(hit: &Value, seen: &[String]) -> String {
+    match hit.get("url").and_then(Value::as_str) {
+        Some(url) => url.to_owned(),
+        // no url: never merge with anything else
+        None => format!("\u{0}{}", seen.len()),
+    }
+}
+
+/// Weights of the composite relevance formula
+/// `bm25 * norm(bm25) + pagerank * pagerank + tfidf * tfidf + quality * quality`,
+/// where `norm` min-max scales bm25 across the candidate set.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ScoringWeights {
+    pub bm25: f64,
+    pub pagerank: f64,
+    pub tfidf: f64,
+    pub quality: f64,
+}
+
+impl Default for ScoringWeights {
+    /// Mirrors the v0.1 engine flags: pagerank boost on, tfidf off.
+    fn default() -> Self {
+        Self {
+            bm25: 1.0,
+            pagerank: 1.0,
+            tfidf: 0.0,
+            quality: 0.0,
+        }
+    }
+}
+
+/// Per-request weight overrides; unset fields keep the configured default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct WeightOverrides {
+    pub bm25: Option<f64>,
+    pub pagerank: Option<f64>,
+    pub tfidf: Option<f64>,
+    pub quality: Option<f64>,
+}
+
+impl ScoringWeights {
+    pub fn with(self, overrides: &WeightOverrides) -> Self {
+        Self {
+            bm25: overrides.bm25.unwrap_or(self.bm25),
+            pagerank: overrides.pagerank.unwrap_or(self.pagerank),
+            tfidf: overrides.tfidf.unwrap_or(self.tfidf),
+            quality: overrides.quality.unwrap_or(self.quality),
+        }
+    }
+
+    /// True when the formula reduces to engine relevance order.
+    pub fn is_pure_bm25(&self) -> bool {
+        self.pagerank == 0.0 && self.tfidf == 0.0 && self.quality == 0.0
+    }
+}
+
+/// Rescores hits with the composite formula, best first. The engine score is
+/// kept as `bm25`; the composite becomes `score`.
+pub fn composite(hits: Vec<Value>, weights: &ScoringWeights) -> Vec<Value> {
+    let mut hits: Vec<Value> = hits
+        .into_iter()
+        .map(|mut hit| {
+            let bm25 = score(&hit);
+            if let Some(obj) = hit.as_object_mut() {
+                obj.insert("bm25".into(), Value::from(bm25));
+            }
+            hit
+        })
+        .collect();
+    hits = normalize(hits);
+
+    for hit in &mut hits {
+        let total = weights.bm25 * score(hit)
+            + weights.pagerank * signal(hit, "pagerank")
+            + weights.tfidf * signal(hit, "tfidf")
+            + weights.quality * signal(hit, "quality");
+        if let Some(obj) = hit.as_object_mut() {
+            obj.insert("score".into(), Value::from(total));
+        }
+    }
+    hits.sort_by(|a, b| score(b).total_cmp(&score(a)));
+    hits
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::audit;
+use crate::introspect::fnv1a;
+
+pub const DEFAULT_MAX_TRACKED_QUERIES: usize = 10_000;
+
+/// `[ratelimit]` section: token buckets queries are admitted through
+/// before they are queued, across every connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Queries admitted per second in all; unlimited when unset.
+    pub queries_per_sec: Option<u32>,
+    /// Queries admitted at once above the rate; one second's worth when
+    /// unset.
+    pub burst: Option<u32>,
+    /// Queries per second admitted for any one query; unlimited when unset.
+    pub per_query_per_sec: Option<u32>,
+    /// Burst for any one query; one second's worth when unset.
+    pub per_query_burst: Option<u32>,
+    /// Distinct queries tracked at once; past it, new queries are only
+    /// held to the overall rate.
+    pub max_tracked_queries: usize,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            queries_per_sec: None,
+            burst: None,
+            per_query_per_sec: None,
+            per_query_burst: None,
+            max_tracked_queries: DEFAULT_MAX_TRACKED_QUERIES,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("queries_per_sec", self.queries_per_sec),
+            ("burst", self.burst),
+            ("per_query_per_sec", self.per_query_per_sec),
+            ("per_query_burst", self.per_query_burst),
+        ] {
+            if value == Some(0) {
+                return Err(format!("ratelimit.{name} must be at least 1"));
+            }
+        }
+        if self.burst.is_some() && self.queries_per_sec.is_none() {
+            return Err("ratelimit.burst needs ratelimit.queries_per_sec".into());
+        }
+        if self.per_query_burst.is_some() && self.per_query_per_sec.is_none() {
+            return Err("ratelimit.per_query_burst needs ratelimit.per_query_per_sec".into());
+        }
+        Ok(())
+    }
+}
+
+/// Admits queries at the configured rates, overall and per query. A query
+/// is told apart by its payload, lowercased with runs of whitespace
+/// collapsed, so a client retrying one query in a loop is held to
+/// `per_query_per_sec` without slowing everyone else.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    global: Option<(Limit, Mutex<Bucket>)>,
+    per_query: Option<(Limit, Mutex<HashMap<u64, Bucket>>)>,
+    max_tracked: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Limit {
+    rate: f64,
+    burst: f64,
+}
+
+impl Limit {
+    fn new(rate: Option<u32>, burst: Option<u32>) -> Option<Self> {
+        let rate = rate?;
+        Some(Self {
+            rate: f64::from(rate),
+            burst: f64::from(burst.unwrap_or(rate)),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(limit: Limit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, limit: Limit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst);
+        self.updated = now;
+    }
+
+    /// Takes a token, or says how long until there is one.
+    fn take(&mut self, limit: Limit, now: Instant) -> Result<(), Duration> {
+        self.refill(limit, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / limit.rate))
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        let now = Instant::now();
+        Self {
+            global: Limit::new(config.queries_per_sec, config.burst)
+                .map(|limit| (limit, Mutex::new(Bucket::full(limit, now)))),
+            per_query: Limit::new(config.per_query_per_sec, config.per_query_burst)
+                .map(|limit| (limit, Mutex::default())),
+            max_tracked: config.max_tracked_queries,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.global.is_some() || self.per_query.is_some()
+    }
+
+    /// Admits the query with `payload`, or says how long until it would
+    /// be. A query turned away overall doesn't use up its own allowance.
+    pub fn admit(&self, payload: &[u8]) -> Result<(), Duration> {
+        let now = Instant::now();
+        let taken = match &self.per_query {
+            Some((limit, buckets)) => {
+                let fingerprint = fingerprint(payload);
+                let mut buckets = buckets.lock().unwrap_or_else(|e| e.into_inner());
+                if !buckets.contains_key(&fingerprint) && buckets.len() >= self.max_tracked {
+                    // queries idle long enough to be back at a full bucket
+                    // lose nothing by being forgotten
+                    buckets.retain(|_, bucket| {
+                        bucket.refill(*limit, now);
+                        bucket.tokens < limit.burst
+                    });
+                }
+                if buckets.len() < self.max_tracked || buckets.contains_key(&fingerprint) {
+                    buckets
+                        .entry(fingerprint)
+                        .or_insert_with(|| Bucket::full(*limit, now))
+                        .take(*limit, now)?;
+                    Some(fingerprint)
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+        if let Some((limit, bucket)) = &self.global {
+            let admitted = bucket
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .take(*limit, now);
+            if admitted.is_err()
+                && let (Some(fingerprint), Some((limit, buckets))) = (taken, &self.per_query)
+            {
+                let mut buckets = buckets.lock().unwrap_or_else(|e| e.into_inner());
+                if let Some(bucket) = buckets.get_mut(&fingerprint) {
+                    bucket.tokens = (bucket.tokens + 1.0).min(limit.burst);
+                }
+            }
+            admitted?;
+        }
+        Ok(())
+    }
+}
+
+fn fingerprint(payload: &[u8]) -> u64 {
+    fnv1a(audit::normalize(&String::from_utf8_lossy(payload)).as_bytes())
+}
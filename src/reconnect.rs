@@ -0,0 +1,178 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::shutdown::Shutdown;
+use crate::transport::{Endpoint, Stream, TlsClient};
+
+pub const DEFAULT_INITIAL_BACKOFF_MS: u64 = 100;
+pub const DEFAULT_MAX_BACKOFF_MS: u64 = 30_000;
+
+/// `[reconnect]` section: whether the adapter outlives its connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    /// Connect again when the core hangs up or the connection fails,
+    /// instead of exiting.
+    pub enabled: bool,
+    /// Failed attempts in a row before giving up; unlimited when unset.
+    pub max_attempts: Option<u32>,
+    /// Wait before the first retry; doubles with every failure.
+    pub initial_backoff_ms: u64,
+    /// Longest wait between attempts.
+    pub max_backoff_ms: u64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_attempts: None,
+            initial_backoff_ms: DEFAULT_INITIAL_BACKOFF_MS,
+            max_backoff_ms: DEFAULT_MAX_BACKOFF_MS,
+        }
+    }
+}
+
+/// Exponential backoff with jitter between connection attempts.
+#[derive(Debug)]
+pub struct Backoff {
+    config: ReconnectConfig,
+    failures: u32,
+}
+
+impl Backoff {
+    pub fn new(config: ReconnectConfig) -> Self {
+        Self {
+            config,
+            failures: 0,
+        }
+    }
+
+    /// Counts a failed attempt and returns how long to wait before the
+    /// next, or `None` when reconnecting is off or out of attempts.
+    ///
+    /// The wait is drawn from the upper half of the current backoff, so
+    /// adapters cut off together don't all retry at the same instant.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if !self.config.enabled
+            || self
+                .config
+                .max_attempts
+                .is_some_and(|max| self.failures >= max)
+        {
+            return None;
+        }
+        let ceiling = self
+            .config
+            .initial_backoff_ms
+            .saturating_mul(1u64 << self.failures.min(32))
+            .min(self.config.max_backoff_ms);
+        self.failures += 1;
+        let half = ceiling / 2;
+        Some(Duration::from_millis(half + jitter(ceiling - half)))
+    }
+
+    /// Starts over after a connection was established.
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+}
+
+/// A random value in `0..=max`.
+fn jitter(max: u64) -> u64 {
+    RandomState::new().hash_one(max) % (max + 1)
+}
+
+/// Connects to the core at `socket` (over TLS with `tls` for a `tls://`
+/// socket) and runs `session` on the connection
+/// until it drops, then reconnects after a backoff for as long as `config`
+/// allows, the core wasn't [refused](is_refused) and `shutdown` hasn't
+/// started. Returns how the last session, or connection attempt, ended.
+pub fn run_sessions(
+    config: ReconnectConfig,
+    socket: &Path,
+    tls: Option<&TlsClient>,
+    shutdown: &Shutdown,
+    mut session: impl FnMut(Stream) -> io::Result<()>,
+) -> io::Result<()> {
+    let endpoint = Endpoint::parse(socket);
+    let mut backoff = Backoff::new(config);
+    loop {
+        let stream = match connect(&endpoint, tls, &mut backoff, shutdown) {
+            Ok(stream) => stream,
+            // shutting down before there was anything to serve
+            Err(_) if shutdown.is_started() => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let served = session(stream);
+        if is_refused(&served) || shutdown.is_started() {
+            return served;
+        }
+        let Some(delay) = backoff.next_delay() else {
+            return served;
+        };
+        log_lost(&served, delay);
+        if shutdown.sleep(delay) {
+            return served;
+        }
+    }
+}
+
+/// Whether the session ended because the core can't be served at all, as
+/// after a failed handshake or credential check: connecting again won't
+/// change that.
+pub fn is_refused(served: &io::Result<()>) -> bool {
+    served.as_ref().is_err_and(|e| {
+        matches!(
+            e.kind(),
+            io::ErrorKind::Unsupported | io::ErrorKind::PermissionDenied
+        )
+    })
+}
+
+/// Logs how a connection ended before reconnecting in `delay`.
+pub fn log_lost(served: &io::Result<()>, delay: Duration) {
+    let retry_in_ms = delay.as_millis() as u64;
+    match served {
+        Ok(()) => warn!(retry_in_ms, "core hung up, reconnecting"),
+        Err(e) => warn!(error = %e, retry_in_ms, "connection failed, reconnecting"),
+    }
+}
+
+/// Connects to the core, retrying per `backoff` while the socket is not
+/// there yet. Returns the last error once out of attempts, or once
+/// `shutdown` starts.
+pub fn connect(
+    endpoint: &Endpoint,
+    tls: Option<&TlsClient>,
+    backoff: &mut Backoff,
+    shutdown: &Shutdown,
+) -> io::Result<Stream> {
+    loop {
+        match endpoint.connect(tls) {
+            Ok(stream) => {
+                backoff.reset();
+                return Ok(stream);
+            }
+            Err(e) => {
+                let Some(delay) = backoff.next_delay() else {
+                    return Err(e);
+                };
+                warn!(
+                    error = %e,
+                    retry_in_ms = delay.as_millis() as u64,
+                    "core not reachable"
+                );
+                if shutdown.sleep(delay) {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, BufReader, Cursor};
+use std::path::Path;
+
+use nerve_protocol::codec::encode;
+use nerve_protocol::frame::OwnedFrame;
+use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+use tracing::{info, warn};
+
+use crate::capture::{self, Captured, Direction};
+use crate::config::Config;
+use crate::context::Context;
+use crate::framing::FrameDecoder;
+use crate::stdio;
+
+// payload bytes shown per reply when logging a mismatch
+const SHOWN_PAYLOAD: usize = 200;
+
+/// What replaying a capture found.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub connections: usize,
+    /// Requests the capture or the replay answered.
+    pub requests: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// A request answered differently than in the capture.
+#[derive(Debug)]
+pub struct Mismatch {
+    pub connection: u32,
+    pub request_id: RequestId,
+    pub captured: Vec<Reply>,
+    pub replayed: Vec<Reply>,
+}
+
+/// The parts of an answer that are compared, frame by frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    pub msg_type: u8,
+    pub flags: u8,
+    pub payload: Vec<u8>,
+}
+
+/// Replays the capture at `path` against the configured index (`--replay
+/// <file>`) and logs every request answered differently; fails if there
+/// were any.
+pub fn run(config: &Config, path: &Path) -> io::Result<()> {
+    config.validate()?;
+    let captured = capture::read(BufReader::new(File::open(path)?))?;
+    let context = Context::from_config(config)?;
+    info!(
+        path = %path.display(),
+        frames = captured.len(),
+        index = %config.index_path.display(),
+        "replaying capture"
+    );
+
+    let report = replay(config, &context, &captured)?;
+    for mismatch in &report.mismatches {
+        warn!(
+            connection = mismatch.connection,
+            request_id = mismatch.request_id.0,
+            captured = %describe(&mismatch.captured),
+            replayed = %describe(&mismatch.replayed),
+            "reply differs from the capture"
+        );
+    }
+    if !report.mismatches.is_empty() {
+        return Err(io::Error::other(format!(
+            "{} of {} replayed requests answered differently",
+            report.mismatches.len(),
+            report.requests
+        )));
+    }
+    info!(
+        connections = report.connections,
+        requests = report.requests,
+        "replay matched the capture"
+    );
+    Ok(())
+}
+
+/// Serves each captured connection's inbound frames in turn, through the
+/// same loop and workers as stdio (so without a core, handshake or
+/// heartbeat), and compares every request's answers with the captured
+/// ones. Answers are matched by request id, not order; frames on request
+/// id 0 belong to the connection rather than a request and are left out.
+pub fn replay(config: &Config, context: &Context, captured: &[Captured]) -> io::Result<Report> {
+    let mut connections: BTreeMap<u32, Vec<&Captured>> = BTreeMap::new();
+    for entry in captured {
+        connections.entry(entry.connection).or_default().push(entry);
+    }
+
+    let mut report = Report {
+        connections: connections.len(),
+        ..Report::default()
+    };
+    for (connection, entries) in connections {
+        let mut input = Vec::new();
+        for entry in entries.iter().filter(|e| e.direction == Direction::Inbound) {
+            match reencode(&entry.frame) {
+                Some(encoded) => input.extend(encoded),
+                None => warn!(
+                    connection,
+                    msg_type = entry.frame.header.msg_type,
+                    "captured frame of unknown type not replayed"
+                ),
+            }
+        }
+        let mut output = Vec::new();
+        stdio::serve(config, context, Cursor::new(input), &mut output)?;
+        let mut replayed = Vec::new();
+        FrameDecoder::new(config.max_payload_bytes).decode(&output, &mut replayed, |_| {});
+
+        let expected = answers(
+            entries
+                .iter()
+                .filter(|e| e.direction == Direction::Outbound)
+                .map(|e| &e.frame),
+        );
+        let actual = answers(replayed.iter());
+        let ids: BTreeSet<u64> = expected.keys().chain(actual.keys()).copied().collect();
+        report.requests += ids.len();
+        for id in ids {
+            let (captured, replayed) = (
+                expected.get(&id).cloned().unwrap_or_default(),
+                actual.get(&id).cloned().unwrap_or_default(),
+            );
+            if captured != replayed {
+                report.mismatches.push(Mismatch {
+                    connection,
+                    request_id: RequestId(id),
+                    captured,
+                    replayed,
+                });
+            }
+        }
+    }
+    Ok(report)
+}
+
+fn reencode(frame: &OwnedFrame) -> Option<Vec<u8>> {
+    let msg_type = MessageType::try_from(frame.header.msg_type).ok()?;
+    encode(
+        msg_type,
+        FrameFlags::from_bits_truncate(frame.header.flags),
+        RequestId(frame.header.request_id),
+        &frame.payload,
+    )
+    .ok()
+}
+
+/// Each request's answers, in the order they went out.
+fn answers<'a>(frames: impl Iterator<Item = &'a OwnedFrame>) -> BTreeMap<u64, Vec<Reply>> {
+    let mut answers: BTreeMap<u64, Vec<Reply>> = BTreeMap::new();
+    for frame in frames.filter(|frame| frame.header.request_id != 0) {
+        answers
+            .entry(frame.header.request_id)
+            .or_default()
+            .push(Reply {
+                msg_type: frame.header.msg_type,
+                flags: frame.header.flags,
+                payload: frame.payload.clone(),
+            });
+    }
+    answers
+}
+
+fn describe(replies: &[Reply]) -> String {
+    if replies.is_empty() {
+        return "no reply".into();
+    }
+    replies
+        .iter()
+        .map(|reply| {
+            let shown = &reply.payload[..reply.payload.len().min(SHOWN_PAYLOAD)];
+            format!(
+                "type {} flags {:#04x} {}",
+                reply.msg_type,
+                reply.flags,
+                String::from_utf8_lossy(shown)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
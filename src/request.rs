@@ -0,0 +1,397 @@
+use std::path::PathBuf;
+
+use crawler::search::filters::SortBy;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::admin::DEFAULT_MERGE_SEGMENTS;
+use crate::analysis::Language;
+use crate::dedup::DEFAULT_MAX_DISTANCE;
+use crate::filters::Filters;
+use crate::rank::{Fusion, WeightOverrides};
+use crate::shards::EngineBoosts;
+
+pub const DEFAULT_LIMIT: usize = 10;
+
+/// Queries a `top_queries` operation lists of each kind, by default.
+pub const DEFAULT_TOP_QUERIES: usize = 10;
+
+/// How the query is matched against the index.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMode {
+    #[default]
+    Lexical,
+    /// Nearest-neighbour search over the vector sidecar. Uses `vector` when
+    /// given, otherwise embeds `query`.
+    Vector,
+    /// Runs both passes and reranks their union with `fusion`.
+    Hybrid,
+    /// Runs the lexical query once per entry of `sorts` and fuses the
+    /// rankings with reciprocal rank fusion.
+    SortFusion,
+}
+
+impl SearchMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lexical => "lexical",
+            Self::Vector => "vector",
+            Self::Hybrid => "hybrid",
+            Self::SortFusion => "sort_fusion",
+        }
+    }
+}
+
+/// A decoded SEARCH_QUERY payload.
+///
+/// Structured payloads may carry an `"op"` naming a non-search operation;
+/// without one (or with `"op": "search"`) the payload is a search request.
+/// Every operation is answered with a SEARCH_RESULT frame, so none of them
+/// needs a protocol change.
+#[derive(Debug, Clone)]
+pub enum Request {
+    Search(SearchRequest),
+    /// Document/segment counts, size on disk and commit info per shard.
+    IndexStats,
+    /// Field names, types and indexing options of the active schema.
+    Schema,
+    /// Document and total term frequencies of `terms` in `field`.
+    TermStats {
+        field: String,
+        terms: Vec<String>,
+    },
+    /// Writes `doc` (a JSON object keyed by schema field) into the index.
+    IndexDocument {
+        doc: Map<String, Value>,
+    },
+    /// Deletes documents by exact `url`.
+    DeleteDocument {
+        url: String,
+    },
+    /// Replaces the document sharing `doc`'s url (delete + add).
+    UpdateDocument {
+        doc: Map<String, Value>,
+    },
+    /// Commits buffered writes, making them searchable.
+    Commit,
+    /// Copies the committed primary index into `target`.
+    Snapshot {
+        target: PathBuf,
+    },
+    /// Merges committed segments of the primary index down to `max_segments`.
+    Merge {
+        max_segments: usize,
+    },
+    /// Adapter runtime metrics: request latency quantiles per operation.
+    Metrics,
+    /// Request, error and cancellation counts, queue depth, cache hits and
+    /// uptime.
+    Stats,
+    /// The `limit` most asked and most recent search queries.
+    TopQueries {
+        limit: usize,
+    },
+}
+
+#[derive(Deserialize)]
+struct TermStatsArgs {
+    field: String,
+    terms: Vec<String>,
+}
+
+impl Request {
+    /// Whether the operation writes to the index directory.
+    pub fn is_mutation(&self) -> bool {
+        matches!(
+            self,
+            Request::IndexDocument { .. }
+                | Request::UpdateDocument { .. }
+                | Request::DeleteDocument { .. }
+                | Request::Commit
+                | Request::Merge { .. }
+        )
+    }
+
+    /// Whether the operation belongs on the indexing workers: mutations and
+    /// snapshots.
+    pub fn is_indexing(&self) -> bool {
+        self.is_mutation() || matches!(self, Request::Snapshot { .. })
+    }
+
+    /// [`is_indexing`](Self::is_indexing) judged from the `"op"` field alone,
+    /// so the I/O loop can route a payload without decoding all of it.
+    pub fn is_indexing_payload(payload: &[u8]) -> bool {
+        #[derive(Deserialize)]
+        struct Op<'a> {
+            #[serde(borrow)]
+            op: Option<&'a str>,
+        }
+        let Ok(Op { op: Some(op) }) = serde_json::from_slice(payload) else {
+            return false;
+        };
+        matches!(
+            op,
+            "index_document"
+                | "update_document"
+                | "delete_document"
+                | "commit"
+                | "merge"
+                | "snapshot"
+        )
+    }
+
+    /// Name of the operation, as given in the `"op"` field.
+    pub fn op(&self) -> &'static str {
+        match self {
+            Request::Search(_) => "search",
+            Request::IndexStats => "index_stats",
+            Request::Schema => "schema",
+            Request::TermStats { .. } => "term_stats",
+            Request::IndexDocument { .. } => "index_document",
+            Request::DeleteDocument { .. } => "delete_document",
+            Request::UpdateDocument { .. } => "update_document",
+            Request::Commit => "commit",
+            Request::Snapshot { .. } => "snapshot",
+            Request::Merge { .. } => "merge",
+            Request::Metrics => "metrics",
+            Request::Stats => "stats",
+            Request::TopQueries { .. } => "top_queries",
+        }
+    }
+
+    /// Decodes a SEARCH_QUERY payload, or says why it can't be served.
+    pub fn parse(payload: &[u8]) -> Result<Self, String> {
+        let text = std::str::from_utf8(payload).map_err(|_| "payload is not UTF-8".to_string())?;
+        if !text.trim_start().starts_with('{') {
+            return Ok(Request::Search(SearchRequest::text(text)));
+        }
+        let value: Value =
+            serde_json::from_str(text).map_err(|e| format!("malformed JSON: {e}"))?;
+        match value.get("op").and_then(Value::as_str) {
+            None | Some("search") => SearchRequest::from_value(value).map(Request::Search),
+            Some("index_stats") => Ok(Request::IndexStats),
+            Some("schema") => Ok(Request::Schema),
+            Some("commit") => Ok(Request::Commit),
+            Some("metrics") => Ok(Request::Metrics),
+            Some("stats") => Ok(Request::Stats),
+            Some("top_queries") => Ok(Request::TopQueries {
+                limit: count(&value, "limit")?.unwrap_or(DEFAULT_TOP_QUERIES),
+            }),
+            Some("snapshot") => match value.get("target") {
+                Some(Value::String(target)) => Ok(Request::Snapshot {
+                    target: PathBuf::from(target),
+                }),
+                _ => Err("snapshot needs a \"target\" path".into()),
+            },
+            Some("merge") => Ok(Request::Merge {
+                max_segments: count(&value, "max_segments")?.unwrap_or(DEFAULT_MERGE_SEGMENTS),
+            }),
+            Some("term_stats") => {
+                let args: TermStatsArgs =
+                    serde_json::from_value(value).map_err(|e| format!("term_stats: {e}"))?;
+                Ok(Request::TermStats {
+                    field: args.field,
+                    terms: args.terms,
+                })
+            }
+            Some("index_document") => match value.get("doc") {
+                Some(Value::Object(doc)) => Ok(Request::IndexDocument { doc: doc.clone() }),
+                _ => Err("index_document needs a \"doc\" object".into()),
+            },
+            Some("update_document") => match value.get("doc") {
+                Some(Value::Object(doc)) => Ok(Request::UpdateDocument { doc: doc.clone() }),
+                _ => Err("update_document needs a \"doc\" object".into()),
+            },
+            Some("delete_document") => match value.get("url") {
+                Some(Value::String(url)) => Ok(Request::DeleteDocument { url: url.clone() }),
+                _ => Err("delete_document needs a \"url\" string".into()),
+            },
+            Some(op) => Err(format!("unknown op {op:?}")),
+        }
+    }
+}
+
+/// The non-negative integer `field` of an operation, if given.
+fn count(value: &Value, field: &str) -> Result<Option<usize>, String> {
+    match value.get(field) {
+        None => Ok(None),
+        Some(n) => n
+            .as_u64()
+            .and_then(|n| usize::try_from(n).ok())
+            .map(Some)
+            .ok_or_else(|| format!("{field} must be a non-negative integer")),
+    }
+}
+
+/// Result orderings a client may ask for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Relevance,
+    Pagerank,
+    Quality,
+}
+
+impl SortOrder {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Relevance => "relevance",
+            Self::Pagerank => "pagerank",
+            Self::Quality => "quality",
+        }
+    }
+}
+
+impl From<SortOrder> for SortBy {
+    fn from(order: SortOrder) -> Self {
+        match order {
+            SortOrder::Relevance => SortBy::Relevance,
+            SortOrder::Pagerank => SortBy::PageRank,
+            SortOrder::Quality => SortBy::Quality,
+        }
+    }
+}
+
+fn default_fusion_sorts() -> Vec<SortOrder> {
+    vec![
+        SortOrder::Relevance,
+        SortOrder::Pagerank,
+        SortOrder::Quality,
+    ]
+}
+
+/// A SEARCH_QUERY payload.
+///
+/// Payloads that are a JSON object are decoded as a structured request; any
+/// other UTF-8 payload is the v0.1 form, the bare query text with defaults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SearchRequest {
+    pub query: String,
+    pub limit: usize,
+    pub offset: usize,
+    pub mode: SearchMode,
+    pub vector: Option<Vec<f32>>,
+    pub fusion: Fusion,
+    /// Overrides of the configured composite ranking weights.
+    pub weights: WeightOverrides,
+    /// Orderings fused in `sort_fusion` mode.
+    pub sorts: Vec<SortOrder>,
+    /// Run the operator rescore script, when one is configured.
+    pub rescore: bool,
+    /// Language of `query`, selecting its analyzer (see `[analysis]`).
+    pub language: Option<Language>,
+    /// Overrides configured stemming; `false` matches terms exactly.
+    pub stem: Option<bool>,
+    /// Date range the hits must fall in.
+    pub filters: Filters,
+    /// Drop near-duplicate pages (mirrors, reposts) by simhash.
+    pub dedup: bool,
+    /// Max differing simhash bits for two hits to count as duplicates.
+    pub dedup_distance: u32,
+    /// Let the engine boost by pagerank itself (see [`EngineBoosts`]).
+    pub use_pagerank: bool,
+    /// Let the engine boost by tf-idf itself.
+    pub use_tfidf: bool,
+    /// Deadline in milliseconds from arrival, overriding the configured
+    /// `request_timeout_ms`.
+    pub timeout_ms: Option<u64>,
+    /// Send the hits over several frames as they are serialized, for
+    /// exports too large to encode as one reply.
+    pub stream: bool,
+}
+
+impl Default for SearchRequest {
+    fn default() -> Self {
+        Self {
+            query: String::new(),
+            limit: DEFAULT_LIMIT,
+            offset: 0,
+            mode: SearchMode::Lexical,
+            vector: None,
+            fusion: Fusion::default(),
+            weights: WeightOverrides::default(),
+            sorts: default_fusion_sorts(),
+            rescore: true,
+            language: None,
+            stem: None,
+            filters: Filters::default(),
+            dedup: false,
+            dedup_distance: DEFAULT_MAX_DISTANCE,
+            use_pagerank: false,
+            use_tfidf: false,
+            timeout_ms: None,
+            stream: false,
+        }
+    }
+}
+
+impl SearchRequest {
+    pub fn text(query: impl Into<String>) -> Self {
+        Self {
+            query: query.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        match Request::parse(payload).ok()? {
+            Request::Search(request) => Some(request),
+            _ => None,
+        }
+    }
+
+    fn from_value(value: Value) -> Result<Self, String> {
+        let mut request: Self =
+            serde_json::from_value(value).map_err(|e| format!("search: {e}"))?;
+        if request.vector.is_some() && request.mode == SearchMode::Lexical {
+            request.mode = SearchMode::Vector;
+        }
+        Ok(request)
+    }
+
+    pub fn boosts(&self) -> EngineBoosts {
+        EngineBoosts {
+            pagerank: self.use_pagerank,
+            tfidf: self.use_tfidf,
+        }
+    }
+
+    /// Key identifying requests that must produce identical results.
+    pub fn cache_key(&self) -> String {
+        let key = match self.mode {
+            SearchMode::Lexical => format!(
+                "{}\u{1f}{}\u{1f}{}\u{1f}{:?}",
+                self.query, self.limit, self.offset, self.weights
+            ),
+            SearchMode::Vector | SearchMode::Hybrid => format!(
+                "{:?}\u{1f}{}\u{1f}{:?}\u{1f}{:?}\u{1f}{}\u{1f}{}",
+                self.mode, self.query, self.vector, self.fusion, self.limit, self.offset
+            ),
+            SearchMode::SortFusion => format!(
+                "{:?}\u{1f}{}\u{1f}{:?}\u{1f}{:?}\u{1f}{}\u{1f}{}",
+                self.mode, self.query, self.sorts, self.fusion, self.limit, self.offset
+            ),
+        };
+        let key = match (self.language, self.stem) {
+            (None, None) => key,
+            (language, stem) => format!("{key}\u{1f}{language:?}\u{1f}{stem:?}"),
+        };
+        let key = if self.filters.is_empty() {
+            key
+        } else {
+            format!("{key}\u{1f}{:?}", self.filters)
+        };
+        let key = if self.dedup {
+            format!("{key}\u{1f}dedup{}", self.dedup_distance)
+        } else {
+            key
+        };
+        if self.boosts() == EngineBoosts::default() {
+            key
+        } else {
+            format!("{key}\u{1f}{:?}", self.boosts())
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::cell::Cell;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use rhai::{Dynamic, Engine, Scope, AST};
+use serde_json::Value;
+
+use crate::shards::score;
+
+const MAX_OPERATIONS_PER_HIT: u64 = 100_000;
+
+thread_local! {
+    // deadline of the rescoring pass running on this thread
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Operator-supplied rhai script that computes the final score of each hit.
+///
+/// The script sees the hit's fields as the `hit` map and its current score as
+/// `score`, and evaluates to the new score:
+///
+/// ```rhai
+/// score * (1.0 + hit.pagerank) + if hit.domain == "docs.rs" { 0.5 } else { 0.0 }
+/// ```
+///
+/// Scripts run sandboxed: no module imports, bounded operations and depth,
+/// and the whole pass over a result set is cut off after `timeout`.
+pub struct Rescorer {
+    engine: Engine,
+    ast: AST,
+    timeout: Duration,
+}
+
+impl Rescorer {
+    pub fn load(path: &Path, timeout: Duration) -> io::Result<Self> {
+        let source = std::fs::read_to_string(path)?;
+        Self::compile(&source, timeout).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {e}", path.display()))
+        })
+    }
+
+    pub fn compile(source: &str, timeout: Duration) -> io::Result<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS_PER_HIT);
+        engine.set_max_expr_depths(32, 32);
+        engine.set_max_call_levels(16);
+        engine.set_max_string_size(4096);
+        engine.set_max_array_size(1024);
+        engine.set_max_map_size(256);
+        engine.set_max_modules(0);
+        engine.disable_symbol("eval");
+        engine.on_progress(|_| {
+            let expired = DEADLINE.with(|d| d.get().is_some_and(|at| Instant::now() >= at));
+            expired.then(|| Dynamic::from("rescore timeout"))
+        });
+
+        let ast = engine
+            .compile(source)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        Ok(Self { engine, ast, timeout })
+    }
+
+    /// Replaces each hit's `score` with the script result and re-sorts.
+    ///
+    /// Fails as a whole (leaving the caller to keep the engine order) if the
+    /// script errors, returns a non-number, or runs out of time.
+    pub fn rescore(&self, mut hits: Vec<Value>) -> io::Result<Vec<Value>> {
+        DEADLINE.with(|d| d.set(Some(Instant::now() + self.timeout)));
+        let result = self.rescore_all(&mut hits);
+        DEADLINE.with(|d| d.set(None));
+        result?;
+
+        hits.sort_by(|a, b| score(b).total_cmp(&score(a)));
+        Ok(hits)
+    }
+
+    fn rescore_all(&self, hits: &mut [Value]) -> io::Result<()> {
+        for hit in hits.iter_mut() {
+            let fields = rhai::serde::to_dynamic(&*hit).map_err(script_error)?;
+            let mut scope = Scope::new();
+            scope.push_constant("hit", fields);
+            scope.push_constant("score", score(hit));
+
+            let result: Dynamic = self
+                .engine
+                .eval_ast_with_scope(&mut scope, &self.ast)
+                .map_err(script_error)?;
+            let new_score = result
+                .as_float()
+                .or_else(|_| result.as_int().map(|i| i as f64))
+                .map_err(|t| script_error(format!("script returned {t}, expected a number")))?;
+
+            if let Some(obj) = hit.as_object_mut() {
+                obj.insert("score".into(), Value::from(new_score));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn script_error(e: impl std::fmt::Display) -> io::Error {
+    io::Error::other(format!("rescore script: {e}"))
+}
@@ -0,0 +1,416 @@
+use std::io;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crawler::SearchEngine;
+use crawler::search::filters::{SearchFilter, SortBy};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::debug;
+
+use crate::directory::IndexAccess;
+use crate::state::CancelToken;
+
+/// One index directory and the engines serving it.
+pub struct Shard {
+    pub path: PathBuf,
+    engines: EnginePool,
+}
+
+impl Shard {
+    /// Checks out an engine for one search.
+    pub fn engine(&self) -> PooledEngine {
+        self.engines.checkout()
+    }
+}
+
+/// Engines opened ahead of time on one index directory.
+///
+/// Each engine has its own index reader, so concurrent searches are spread
+/// across them instead of all going through one. A reload opens a complete
+/// new set and swaps it in at once; searches holding an engine from the old
+/// set finish on it.
+struct EnginePool {
+    engines: RwLock<Arc<Vec<SearchEngine>>>,
+    next: AtomicUsize,
+}
+
+impl EnginePool {
+    fn new(engines: Vec<SearchEngine>) -> Self {
+        Self {
+            engines: RwLock::new(Arc::new(engines)),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    fn open(path: &Path, size: usize) -> io::Result<Self> {
+        Ok(Self::new(open_engines(path, size)?))
+    }
+
+    fn checkout(&self) -> PooledEngine {
+        let engines = Arc::clone(&self.engines.read().unwrap_or_else(|e| e.into_inner()));
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % engines.len();
+        PooledEngine { engines, index }
+    }
+
+    fn size(&self) -> usize {
+        self.engines.read().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    fn replace(&self, engines: Vec<SearchEngine>) {
+        *self.engines.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(engines);
+    }
+}
+
+/// An engine checked out of a shard's pool.
+pub struct PooledEngine {
+    engines: Arc<Vec<SearchEngine>>,
+    index: usize,
+}
+
+impl Deref for PooledEngine {
+    type Target = SearchEngine;
+
+    fn deref(&self) -> &SearchEngine {
+        &self.engines[self.index]
+    }
+}
+
+/// Timing for a single shard's part of a fanned-out query.
+#[derive(Debug, Clone)]
+pub struct ShardTiming {
+    pub shard: usize,
+    pub hits: usize,
+    pub elapsed: Duration,
+}
+
+/// Ranking boosts applied inside the engine itself.
+///
+/// Off by default: the adapter blends pagerank/tfidf in `rank::composite`,
+/// and turning these on as well counts the signal twice.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EngineBoosts {
+    pub pagerank: bool,
+    pub tfidf: bool,
+}
+
+/// When the adapter reopens its engines to pick up committed writes.
+///
+/// Each engine's own reader reloads on the policy `crawler::SearchEngine`
+/// builds it with, which the adapter can't change or switch off; this only
+/// decides whether the adapter swaps in fresh engines on top of that.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReaderReload {
+    /// Leave reloading to the engine.
+    #[default]
+    Engine,
+    /// Reopen every shard's engines after each commit the adapter makes,
+    /// before the commit is acknowledged, so the write is searchable as soon
+    /// as the ack arrives.
+    OnCommit,
+}
+
+/// The set of index shards a query is fanned out to.
+///
+/// A single shard is searched inline and its hits are returned untouched;
+/// with several, every shard is queried in parallel for `offset + limit` hits
+/// and the union is re-sorted by score before the requested window is cut.
+///
+/// Shards are the unit of parallelism for one query. Segments inside a shard
+/// are collected by the engine, which keeps its tantivy `Index` and searcher
+/// to itself, so the adapter can't hand it a multi-threaded executor; split
+/// a large index into shards to spread a single query across cores.
+pub struct Shards {
+    shards: Vec<Shard>,
+    // caps engine calls running at once, across all requests
+    slots: Option<EngineSlots>,
+    // how the adapter's own index handles read files; the engines open theirs
+    access: IndexAccess,
+}
+
+impl Shards {
+    pub fn open(paths: &[PathBuf]) -> io::Result<Self> {
+        let mut shards = Vec::with_capacity(paths.len());
+        for path in paths {
+            shards.push(Shard {
+                path: path.clone(),
+                engines: EnginePool::open(path, 1)?,
+            });
+        }
+        if shards.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no index shards configured",
+            ));
+        }
+        Ok(Self {
+            shards,
+            slots: None,
+            access: IndexAccess::default(),
+        })
+    }
+
+    /// Wraps an already-open engine as a one-shard set.
+    pub fn single(path: impl Into<PathBuf>, engine: SearchEngine) -> Self {
+        Self {
+            shards: vec![Shard {
+                path: path.into(),
+                engines: EnginePool::new(vec![engine]),
+            }],
+            slots: None,
+            access: IndexAccess::default(),
+        }
+    }
+
+    /// Lets at most `max` engine searches run at once (unbounded for `None`);
+    /// further shard passes wait for a slot.
+    pub fn with_max_in_flight(mut self, max: Option<usize>) -> Self {
+        self.slots = max.map(EngineSlots::new);
+        self
+    }
+
+    /// Sets how indexes the adapter opens itself read their files.
+    pub fn with_access(mut self, access: IndexAccess) -> Self {
+        self.access = access;
+        self
+    }
+
+    pub fn access(&self) -> IndexAccess {
+        self.access
+    }
+
+    /// Opens `searchers` engines per shard, so that many searches can run
+    /// on a shard without sharing a reader.
+    pub fn with_searchers(mut self, searchers: usize) -> io::Result<Self> {
+        for shard in &mut self.shards {
+            shard.engines = EnginePool::open(&shard.path, searchers)?;
+        }
+        Ok(self)
+    }
+
+    /// Reopens every shard's engines, keeping the pool sizes, and swaps the
+    /// new sets in once all of them opened; on error nothing changes.
+    pub fn reload(&self) -> io::Result<()> {
+        let fresh = self
+            .shards
+            .iter()
+            .map(|shard| open_engines(&shard.path, shard.engines.size()))
+            .collect::<io::Result<Vec<_>>>()?;
+        for (shard, engines) in self.shards.iter().zip(fresh) {
+            shard.engines.replace(engines);
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Shard> {
+        self.shards.iter()
+    }
+
+    pub fn search(
+        &self,
+        query: &str,
+        limit: usize,
+        offset: usize,
+        sort: SortBy,
+        boosts: EngineBoosts,
+        cancel: &CancelToken,
+    ) -> io::Result<(Vec<Value>, Vec<ShardTiming>)> {
+        if let [shard] = self.shards.as_slice() {
+            let _slot = self.slot(cancel)?;
+            let (hits, timing) =
+                search_shard(0, shard, query, limit, offset, sort, boosts, cancel)?;
+            return Ok((hits, vec![timing]));
+        }
+
+        let window = offset + limit;
+        let results: Vec<io::Result<(Vec<Value>, ShardTiming)>> = thread::scope(|s| {
+            let handles: Vec<_> = self
+                .shards
+                .iter()
+                .enumerate()
+                .map(|(i, shard)| {
+                    s.spawn(move || {
+                        let _slot = self.slot(cancel)?;
+                        search_shard(i, shard, query, window, 0, sort, boosts, cancel)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| {
+                    h.join()
+                        .unwrap_or_else(|_| Err(io::Error::other("shard search panicked")))
+                })
+                .collect()
+        });
+
+        cancel.check()?;
+        let mut per_shard = Vec::with_capacity(results.len());
+        let mut timings = Vec::with_capacity(results.len());
+        for result in results {
+            let (hits, timing) = result?;
+            per_shard.push(hits);
+            timings.push(timing);
+        }
+        Ok((merge(per_shard, sort, offset, limit), timings))
+    }
+
+    fn slot(&self, cancel: &CancelToken) -> io::Result<Option<Slot<'_>>> {
+        self.slots
+            .as_ref()
+            .map(|slots| slots.acquire(cancel))
+            .transpose()
+    }
+}
+
+/// Counting semaphore around engine calls.
+struct EngineSlots {
+    free: Mutex<usize>,
+    freed: Condvar,
+}
+
+/// How often a search waiting for a slot looks for a cancel.
+const SLOT_WAIT_POLL: Duration = Duration::from_millis(10);
+
+impl EngineSlots {
+    fn new(max: usize) -> Self {
+        Self {
+            free: Mutex::new(max),
+            freed: Condvar::new(),
+        }
+    }
+
+    fn acquire(&self, cancel: &CancelToken) -> io::Result<Slot<'_>> {
+        let mut free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+        while *free == 0 {
+            cancel.check()?;
+            free = self
+                .freed
+                .wait_timeout(free, SLOT_WAIT_POLL)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        *free -= 1;
+        Ok(Slot(self))
+    }
+}
+
+struct Slot<'a>(&'a EngineSlots);
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        *self.0.free.lock().unwrap_or_else(|e| e.into_inner()) += 1;
+        self.0.freed.notify_one();
+    }
+}
+
+pub fn open_engine(path: &Path) -> io::Result<SearchEngine> {
+    if !path.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("search index not found at {}", path.display()),
+        ));
+    }
+    SearchEngine::new(path).map_err(|e| {
+        io::Error::other(format!(
+            "failed to open search index at {}: {e}",
+            path.display()
+        ))
+    })
+}
+
+fn open_engines(path: &Path, count: usize) -> io::Result<Vec<SearchEngine>> {
+    (0..count.max(1)).map(|_| open_engine(path)).collect()
+}
+
+// the engine runs its collection to the end, so cancellation is only seen
+// before a shard starts and once every shard is back; for the same reason a
+// static-field sort can't be cut short here once its top-k is final
+#[allow(clippy::too_many_arguments)]
+fn search_shard(
+    index: usize,
+    shard: &Shard,
+    query: &str,
+    limit: usize,
+    offset: usize,
+    sort: SortBy,
+    boosts: EngineBoosts,
+    cancel: &CancelToken,
+) -> io::Result<(Vec<Value>, ShardTiming)> {
+    cancel.check()?;
+    let start = Instant::now();
+    let result = shard
+        .engine()
+        .search(
+            query,
+            limit,
+            offset,
+            SearchFilter::new(),
+            sort,
+            boosts.pagerank,
+            boosts.tfidf,
+        )
+        .map_err(|e| io::Error::other(format!("shard {index} search failed: {e}")))?;
+    let hits = match serde_json::to_value(&result)? {
+        Value::Array(hits) => hits,
+        other => vec![other],
+    };
+
+    let timing = ShardTiming {
+        shard: index,
+        hits: hits.len(),
+        elapsed: start.elapsed(),
+    };
+    debug!(
+        shard = index,
+        path = %shard.path.display(),
+        hits = timing.hits,
+        elapsed_us = timing.elapsed.as_micros() as u64,
+        "shard searched"
+    );
+    Ok((hits, timing))
+}
+
+/// Merges the hits each shard found in the order `sort` asks for, ties
+/// going to the higher score, and keeps `limit` of them after `offset`.
+pub fn merge(per_shard: Vec<Vec<Value>>, sort: SortBy, offset: usize, limit: usize) -> Vec<Value> {
+    let key = |hit: &Value| match sort {
+        SortBy::Relevance => score(hit),
+        SortBy::PageRank => signal(hit, "pagerank"),
+        SortBy::Quality => signal(hit, "quality"),
+    };
+    let mut merged: Vec<Value> = per_shard.into_iter().flatten().collect();
+    merged.sort_by(|a, b| {
+        key(b)
+            .total_cmp(&key(a))
+            .then_with(|| score(b).total_cmp(&score(a)))
+    });
+    merged.into_iter().skip(offset).take(limit).collect()
+}
+
+/// The engine score of a serialized hit; hits without one sort last.
+pub(crate) fn score(hit: &Value) -> f64 {
+    hit.get("score").and_then(Value::as_f64).unwrap_or(0.0)
+}
+
+/// A ranking signal stored on the hit; quality is indexed as text.
+pub(crate) fn signal(hit: &Value, field: &str) -> f64 {
+    match hit.get(field) {
+        Some(Value::Number(n)) => n.as_f64().unwrap_or(0.0),
+        Some(Value::String(s)) => s.parse().unwrap_or(0.0),
+        _ => 0.0,
+    }
+}
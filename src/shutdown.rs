@@ -0,0 +1,240 @@
+use std::io;
+use std::net::Shutdown as Close;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use signal_hook::consts::{SIGINT, SIGTERM, SIGUSR2};
+use signal_hook::iterator::{Handle, Signals};
+use tracing::{info, warn};
+
+use crate::state::RequestState;
+use crate::telemetry;
+use crate::transport::Stream;
+
+pub const DEFAULT_DRAIN_TIMEOUT_MS: u64 = 10_000;
+
+/// `[shutdown]` section: how long SIGTERM or SIGINT waits for in-flight
+/// work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    /// How long in-flight queries may run once the adapter stops reading;
+    /// whatever is still running after that is cancelled.
+    pub drain_timeout_ms: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            drain_timeout_ms: DEFAULT_DRAIN_TIMEOUT_MS,
+        }
+    }
+}
+
+/// The process-wide shutdown, shared by every connection.
+///
+/// Once [started](Shutdown::drain), each watched connection has its socket
+/// shut for reading, so its loop sees the core hang up: no new queries are
+/// read, in-flight ones finish and their replies are flushed, a core that
+/// agreed to `going_away` is told, and the connection closes without
+/// reconnecting.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    config: ShutdownConfig,
+    watched: Mutex<Watched>,
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct Watched {
+    started: bool,
+    hurried: bool,
+    next: u64,
+    connections: Vec<(u64, Stream, Weak<RequestState>)>,
+}
+
+impl Shutdown {
+    pub fn new(config: ShutdownConfig) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                config,
+                ..Inner::default()
+            }),
+        }
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.watched().started
+    }
+
+    /// Watches a connection until the returned guard drops: shutting down
+    /// stops reading from `stream` and, past the drain timeout, cancels
+    /// the requests in `state`. A connection made after shutdown started
+    /// is stopped right away.
+    pub fn watch(&self, stream: &Stream, state: &Arc<RequestState>) -> io::Result<Watch> {
+        let socket = stream.socket()?;
+        let mut watched = self.watched();
+        if watched.started {
+            stop(&socket, state);
+        }
+        let id = watched.next;
+        watched.next += 1;
+        watched
+            .connections
+            .push((id, socket, Arc::downgrade(state)));
+        Ok(Watch {
+            shutdown: self.clone(),
+            id,
+        })
+    }
+
+    /// Stops every connection reading and waits up to the drain timeout
+    /// for them to finish, then cancels what is still running. Returns
+    /// once the connections are gone or cancelled.
+    pub fn drain(&self) {
+        let timeout = Duration::from_millis(self.inner.config.drain_timeout_ms);
+        let deadline = Instant::now() + timeout;
+        let mut watched = self.watched();
+        if watched.started {
+            return;
+        }
+        watched.started = true;
+        info!(
+            connections = watched.connections.len(),
+            drain_timeout_ms = self.inner.config.drain_timeout_ms,
+            "shutting down, draining in-flight queries"
+        );
+        for (_, socket, state) in &watched.connections {
+            if let Some(state) = state.upgrade() {
+                stop(socket, &state);
+            }
+        }
+        self.inner.changed.notify_all();
+
+        while !watched.connections.is_empty() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() || watched.hurried {
+                warn!(
+                    connections = watched.connections.len(),
+                    "drain cut short, cancelling in-flight queries"
+                );
+                for (_, _, state) in &watched.connections {
+                    if let Some(state) = state.upgrade() {
+                        state.cancel_all();
+                    }
+                }
+                return;
+            }
+            watched = self
+                .inner
+                .changed
+                .wait_timeout(watched, left)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    /// Cuts a [drain](Shutdown::drain) short: what is still running is
+    /// cancelled now rather than at the timeout.
+    pub fn hurry(&self) {
+        self.watched().hurried = true;
+        self.inner.changed.notify_all();
+    }
+
+    /// Sleeps for `delay`, waking early once shutdown starts. Returns
+    /// whether it has.
+    pub fn sleep(&self, delay: Duration) -> bool {
+        let watched = self.watched();
+        let (watched, _) = self
+            .inner
+            .changed
+            .wait_timeout_while(watched, delay, |watched| !watched.started)
+            .unwrap_or_else(|e| e.into_inner());
+        watched.started
+    }
+
+    fn watched(&self) -> MutexGuard<'_, Watched> {
+        self.inner.watched.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A connection under [`Shutdown::watch`]; dropping it lets the drain move
+/// on without it.
+#[derive(Debug)]
+pub struct Watch {
+    shutdown: Shutdown,
+    id: u64,
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        let mut watched = self.shutdown.watched();
+        watched.connections.retain(|(id, _, _)| *id != self.id);
+        self.shutdown.inner.changed.notify_all();
+    }
+}
+
+fn stop(socket: &Stream, state: &RequestState) {
+    state.shut_down();
+    // fails only once the connection is gone, which is as good
+    let _ = socket.shutdown(Close::Read);
+}
+
+/// Drains `shutdown` when the process gets SIGTERM or SIGINT, until
+/// dropped. A second signal hurries the drain along, as for an operator
+/// pressing Ctrl-C again. SIGUSR2 cycles the log level instead; see
+/// [`telemetry::cycled`].
+pub struct SignalListener {
+    handle: Handle,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Starts listening for SIGTERM, SIGINT and SIGUSR2 on a thread of its
+/// own.
+pub fn listen(shutdown: &Shutdown) -> io::Result<SignalListener> {
+    let mut signals = Signals::new([SIGTERM, SIGINT, SIGUSR2])?;
+    let handle = signals.handle();
+    let shutdown = shutdown.clone();
+    let thread = thread::Builder::new()
+        .name("signals".into())
+        .spawn(move || {
+            thread::scope(|s| {
+                let mut draining = false;
+                for signal in signals.forever() {
+                    if signal == SIGUSR2 {
+                        match telemetry::cycle_log_level() {
+                            Ok(level) => info!(%level, "log level changed"),
+                            Err(e) => warn!(error = %e, "log level not changed"),
+                        }
+                    } else if draining {
+                        info!(signal, "signalled again, not waiting for the drain");
+                        shutdown.hurry();
+                    } else {
+                        info!(signal, "signalled to shut down");
+                        draining = true;
+                        s.spawn(|| shutdown.drain());
+                    }
+                }
+            })
+        })?;
+    Ok(SignalListener {
+        handle,
+        thread: Some(thread),
+    })
+}
+
+impl Drop for SignalListener {
+    fn drop(&mut self) {
+        self.handle.close();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
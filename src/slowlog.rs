@@ -0,0 +1,122 @@
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use nerve_protocol::types::RequestId;
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::filters::Filters;
+use crate::introspect::fnv1a;
+use crate::request::SearchRequest;
+use crate::telemetry;
+
+/// `[slowlog]` section: which requests are logged as slow.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SlowLogConfig {
+    /// Requests taking at least this long, from arrival to reply, are logged
+    /// at WARN. Off when unset.
+    pub threshold_ms: Option<u64>,
+    /// Log a hash of the query text instead of the text itself.
+    pub hash_queries: bool,
+}
+
+/// What a request did, gathered while it runs, for the slow query log,
+/// metrics and audit log.
+#[derive(Debug, Default)]
+pub struct Trace {
+    pub op: &'static str,
+    pub query: Option<String>,
+    pub params: String,
+    pub filters: Filters,
+    pub hits: Option<usize>,
+    /// Time spent per stage, in the order the stages ran.
+    pub stages: Vec<(&'static str, Duration)>,
+}
+
+impl Trace {
+    /// Runs `stage`, recording how long it took under `name`.
+    pub fn time<T>(&mut self, name: &'static str, stage: impl FnOnce() -> T) -> T {
+        let _span = telemetry::stage(name).entered();
+        let start = Instant::now();
+        let result = stage();
+        self.stages.push((name, start.elapsed()));
+        result
+    }
+
+    pub fn search(&mut self, request: &SearchRequest) {
+        self.op = "search";
+        self.query = Some(request.query.clone());
+        self.params = params(request);
+        self.filters = request.filters;
+    }
+}
+
+fn params(request: &SearchRequest) -> String {
+    let mut params = format!(
+        "mode={:?} limit={} offset={}",
+        request.mode, request.limit, request.offset
+    );
+    if let Some(language) = request.language {
+        let _ = write!(params, " language={language:?}");
+    }
+    if !request.filters.is_empty() {
+        let _ = write!(params, " filters={:?}", request.filters);
+    }
+    if request.dedup {
+        let _ = write!(params, " dedup={}", request.dedup_distance);
+    }
+    params
+}
+
+/// Logs requests slower than the configured threshold.
+#[derive(Debug, Clone, Default)]
+pub struct SlowLog {
+    threshold: Option<Duration>,
+    hash_queries: bool,
+}
+
+impl SlowLog {
+    pub fn new(config: &SlowLogConfig) -> Self {
+        Self {
+            threshold: config.threshold_ms.map(Duration::from_millis),
+            hash_queries: config.hash_queries,
+        }
+    }
+
+    pub fn is_slow(&self, elapsed: Duration) -> bool {
+        self.threshold.is_some_and(|threshold| elapsed >= threshold)
+    }
+
+    /// The query as it appears in the log: verbatim, or `fnv1a:<hex>` when
+    /// query text must not reach the logs.
+    pub fn render_query(&self, query: &str) -> String {
+        if self.hash_queries {
+            format!("fnv1a:{:016x}", fnv1a(query.as_bytes()))
+        } else {
+            query.to_string()
+        }
+    }
+
+    pub fn record(&self, request_id: RequestId, trace: &Trace, elapsed: Duration) {
+        if trace.op.is_empty() || !self.is_slow(elapsed) {
+            return;
+        }
+        let stages = trace
+            .stages
+            .iter()
+            .map(|(name, took)| format!("{name}={}us", took.as_micros()))
+            .collect::<Vec<_>>()
+            .join(" ");
+        warn!(
+            request_id = request_id.0,
+            op = trace.op,
+            query = trace.query.as_deref().map(|q| self.render_query(q)),
+            params = %trace.params,
+            hits = trace.hits,
+            elapsed_ms = elapsed.as_millis() as u64,
+            stages = %stages,
+            "slow request"
+        );
+    }
+}
@@ -1,22 +1,299 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use nerve_protocol::constants::VERSION;
 use nerve_protocol::types::RequestId;
 
+use crate::auth::FrameMac;
+use crate::capture::Tape;
+use crate::connections::Traffic;
+use crate::metrics::Counters;
+use crate::ratelimit::RateLimiter;
+use crate::telemetry::Spans;
+use crate::watchdog::Progress;
+#[cfg(feature = "chaos")]
+use crate::chaos::Faults;
+use crate::handshake::Capabilities;
+
+/// Cancellation state shared by the reader and every worker, and what the
+/// connection's handshake settled.
 pub struct RequestState {
-    cancelled: HashSet<RequestId>,
+    cancelled: Mutex<HashSet<RequestId>>,
+    running: Mutex<HashMap<RequestId, CancelToken>>,
+    // handed to the workers and not yet answered or cancelled
+    unanswered: Mutex<HashSet<RequestId>>,
+    all_cancelled: AtomicBool,
+    shutting_down: AtomicBool,
+    capabilities: Capabilities,
+    protocol_version: u8,
+    frame_mac: Option<FrameMac>,
+    tape: Option<Tape>,
+    traffic: Arc<Traffic>,
+    spans: Arc<Spans>,
+    counters: Arc<Counters>,
+    progress: Arc<Progress>,
+    limiter: Arc<RateLimiter>,
+    #[cfg(feature = "chaos")]
+    faults: Option<Arc<Faults>>,
 }
 
 impl RequestState{
+    /// State for a connection that negotiated nothing: every capability
+    /// the request asks for is used.
     pub fn new()->Self{
+        Self::with_capabilities(Capabilities::all())
+    }
+
+    pub fn with_capabilities(capabilities: Capabilities)->Self{
         Self{
-            cancelled : HashSet::new(),
+            cancelled : Mutex::new(HashSet::new()),
+            running: Mutex::new(HashMap::new()),
+            unanswered: Mutex::new(HashSet::new()),
+            all_cancelled: AtomicBool::new(false),
+            shutting_down: AtomicBool::new(false),
+            capabilities,
+            protocol_version: VERSION,
+            frame_mac: None,
+            tape: None,
+            traffic: Arc::default(),
+            spans: Arc::default(),
+            counters: Arc::default(),
+            progress: Arc::default(),
+            limiter: Arc::default(),
+            #[cfg(feature = "chaos")]
+            faults: None,
+        }
+    }
+
+    /// Marks frames to the core with an older `protocol_version`, as agreed
+    /// in the handshake.
+    pub fn with_protocol_version(mut self, protocol_version: u8)->Self{
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    /// Signs and checks every frame with `frame_mac`, as agreed in the
+    /// handshake.
+    pub fn with_frame_mac(mut self, frame_mac: Option<FrameMac>)->Self{
+        self.frame_mac = frame_mac;
+        self
+    }
+
+    /// Records the connection's frames on `tape` (`--record`).
+    pub fn with_tape(mut self, tape: Option<Tape>)->Self{
+        self.tape = tape;
+        self
+    }
+
+    /// Counts the connection's bytes and frames in `traffic`.
+    pub fn with_traffic(mut self, traffic: Arc<Traffic>)->Self{
+        self.traffic = traffic;
+        self
+    }
+
+    /// Counts the connection's queries, and those cancelled, in `counters`.
+    pub fn with_counters(mut self, counters: Arc<Counters>)->Self{
+        self.counters = counters;
+        self
+    }
+
+    /// Marks the connection's frame loop's progress in `progress`.
+    pub fn with_progress(mut self, progress: Arc<Progress>)->Self{
+        self.progress = progress;
+        self
+    }
+
+    /// Admits the connection's queries through `limiter`.
+    pub fn with_limiter(mut self, limiter: Arc<RateLimiter>)->Self{
+        self.limiter = limiter;
+        self
+    }
+
+    /// Injects `faults` into the connection's frames (`chaos` feature).
+    #[cfg(feature = "chaos")]
+    pub fn with_faults(mut self, faults: Option<Faults>)->Self{
+        self.faults = faults.map(Arc::new);
+        self
+    }
+
+    /// Capabilities the core agreed to on this connection.
+    pub fn capabilities(&self)->Capabilities{
+        self.capabilities
+    }
+
+    pub fn protocol_version(&self)->u8{
+        self.protocol_version
+    }
+
+    pub fn frame_mac(&self)->Option<&FrameMac>{
+        self.frame_mac.as_ref()
+    }
+
+    pub fn tape(&self)->Option<&Tape>{
+        self.tape.as_ref()
+    }
+
+    pub fn traffic(&self)->&Arc<Traffic>{
+        &self.traffic
+    }
+
+    pub fn progress(&self)->&Arc<Progress>{
+        &self.progress
+    }
+
+    /// Admits a query through the limiter, counting those turned away;
+    /// `Err` holds how long until it would be admitted.
+    pub fn admit(&self, payload: &[u8])->Result<(), Duration>{
+        self.limiter.admit(payload).inspect_err(|_| self.counters.rate_limit())
+    }
+
+    /// The spans of the connection's requests, when exporting them.
+    pub fn spans(&self)->&Arc<Spans>{
+        &self.spans
+    }
+
+    #[cfg(feature = "chaos")]
+    pub fn faults(&self)->Option<&Arc<Faults>>{
+        self.faults.as_ref()
+    }
+
+    pub fn cancel(&self, id:RequestId){
+        let mut cancelled = self.cancelled();
+        cancelled.insert(id);
+        // a cancelled request expects no more replies
+        if self.unanswered().remove(&id){
+            self.counters.cancellation();
+        }
+        self.spans.close(id);
+        if let Some(token) = self.running().get(&id){
+            token.cancel();
         }
     }
 
-    pub fn cancel(&mut self, id:RequestId){
-        self.cancelled.insert(id);
+    /// Cancels every running request and any that begins from now on, as
+    /// when a shutdown's drain runs out of time.
+    pub fn cancel_all(&self){
+        let _cancelled = self.cancelled();
+        self.all_cancelled.store(true, Ordering::Relaxed);
+        for token in self.running().values(){
+            token.cancel();
+        }
     }
 
-    pub fn is_cancelled(&mut self, id: RequestId) -> bool {
-        self.cancelled.contains(&id)
+    /// Marks the connection as closing for a shutdown rather than because
+    /// the core hung up.
+    pub fn shut_down(&self){
+        self.shutting_down.store(true, Ordering::Relaxed);
     }
-}
\ No newline at end of file
+
+    pub fn is_shutting_down(&self)->bool{
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+
+    pub fn is_cancelled(&self, id: RequestId) -> bool {
+        self.all_cancelled.load(Ordering::Relaxed) || self.cancelled().contains(&id)
+    }
+
+    /// Registers a request as running; its token trips when a CANCEL for it
+    /// arrives (or already has).
+    pub fn begin(&self, id: RequestId) -> CancelToken {
+        // same lock order as `cancel`, so a cancel can't slip in between
+        let cancelled = self.cancelled();
+        let token = CancelToken::default();
+        if cancelled.contains(&id) || self.all_cancelled.load(Ordering::Relaxed) {
+            token.cancel();
+        }
+        self.running().insert(id, token.clone());
+        token
+    }
+
+    pub fn finish(&self, id: RequestId) {
+        self.running().remove(&id);
+    }
+
+    /// Notes a query handed to the workers: it is unanswered until
+    /// [settled](Self::settle) or cancelled. Opens its span when exporting.
+    pub fn queue(&self, id: RequestId) {
+        self.unanswered().insert(id);
+        self.counters.queue();
+        self.spans.open(id);
+    }
+
+    /// Notes a query's handling over, whatever it replied.
+    pub fn settle(&self, id: RequestId) {
+        self.unanswered().remove(&id);
+        self.counters.settle();
+    }
+
+    /// Queries handed to the workers that are neither answered nor
+    /// cancelled, by id.
+    pub fn unanswered_requests(&self) -> Vec<RequestId> {
+        let mut ids: Vec<_> = self.unanswered().iter().copied().collect();
+        ids.sort_by_key(|id| id.0);
+        ids
+    }
+
+    fn cancelled(&self) -> std::sync::MutexGuard<'_, HashSet<RequestId>> {
+        self.cancelled.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn running(&self) -> std::sync::MutexGuard<'_, HashMap<RequestId, CancelToken>> {
+        self.running.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn unanswered(&self) -> std::sync::MutexGuard<'_, HashSet<RequestId>> {
+        self.unanswered.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for RequestState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Trips when its request is cancelled or its deadline passes. Long-running
+/// work polls it and stops early instead of finishing a search nobody will
+/// read.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// This token, also tripping at `deadline`.
+    pub fn with_deadline(&self, deadline: Instant) -> Self {
+        Self {
+            cancelled: Arc::clone(&self.cancelled),
+            deadline: Some(deadline),
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed) || self.is_expired()
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// `Err(Interrupted)` once cancelled and `Err(TimedOut)` once past the
+    /// deadline, for `?` between search stages.
+    pub fn check(&self) -> io::Result<()> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "request cancelled"));
+        }
+        if self.is_expired() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "request deadline exceeded"));
+        }
+        Ok(())
+    }
+}
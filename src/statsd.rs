@@ -0,0 +1,164 @@
+use std::fmt::Write as _;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::slowlog::Trace;
+
+pub const DEFAULT_STATSD_PREFIX: &str = "nerve_search_adapter";
+
+/// `[statsd]` section: per-request timings and counters sent over UDP to a
+/// StatsD or DogStatsD agent.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct StatsdConfig {
+    /// The agent's `host:port`; nothing is sent when unset.
+    pub address: Option<String>,
+    /// Put before every metric name.
+    pub prefix: String,
+    /// DogStatsD tags (`#op:search`) instead of the operation in the name.
+    pub dogstatsd: bool,
+    /// Tags added to every metric, as `env:prod` (DogStatsD only).
+    pub tags: Vec<String>,
+}
+
+impl Default for StatsdConfig {
+    fn default() -> Self {
+        Self {
+            address: None,
+            prefix: DEFAULT_STATSD_PREFIX.to_string(),
+            dogstatsd: false,
+            tags: Vec::new(),
+        }
+    }
+}
+
+impl StatsdConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.tags.is_empty() && !self.dogstatsd {
+            return Err("statsd.tags need statsd.dogstatsd".into());
+        }
+        if let Some(tag) = self
+            .tags
+            .iter()
+            .find(|tag| tag.is_empty() || tag.contains([',', '|', '#']))
+        {
+            return Err(format!("statsd tag {tag:?} is empty or holds one of , | #"));
+        }
+        Ok(())
+    }
+}
+
+/// Sends each finished request's metrics as one datagram: a `requests`
+/// counter, its `request_time` and each stage's time (`stage_time`) in
+/// milliseconds, and for searches the `hits` returned as a histogram.
+///
+/// Plain StatsD names them `<prefix>.<op>.<metric>` (stages as
+/// `<prefix>.<op>.<stage>_time`); DogStatsD names them `<prefix>.<metric>`
+/// tagged with `op` (and `stage`). Sending never blocks a worker: a
+/// datagram the socket can't take is dropped.
+#[derive(Debug, Default)]
+pub struct Statsd {
+    socket: Option<UdpSocket>,
+    prefix: String,
+    dogstatsd: bool,
+    tags: String,
+    failed: AtomicBool,
+}
+
+impl Statsd {
+    pub fn open(config: &StatsdConfig) -> io::Result<Self> {
+        let Some(address) = &config.address else {
+            return Ok(Self::default());
+        };
+        let agent = address.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("statsd address {address} resolves to nothing"),
+            )
+        })?;
+        let any: SocketAddr = match agent {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let socket = UdpSocket::bind(any)?;
+        socket.connect(agent)?;
+        socket.set_nonblocking(true)?;
+        info!(address = %address, "sending metrics to statsd");
+        Ok(Self {
+            socket: Some(socket),
+            prefix: config.prefix.clone(),
+            dogstatsd: config.dogstatsd,
+            tags: config.tags.join(","),
+            failed: AtomicBool::new(false),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    pub fn record(&self, trace: &Trace, elapsed: Duration) {
+        let Some(socket) = &self.socket else {
+            return;
+        };
+        if trace.op.is_empty() {
+            return;
+        }
+        let datagram = self.datagram(trace, elapsed);
+        if let Err(e) = socket.send(datagram.as_bytes())
+            && e.kind() != io::ErrorKind::WouldBlock
+            && !self.failed.swap(true, Ordering::Relaxed)
+        {
+            warn!(error = %e, "sending metrics to statsd failed");
+        }
+    }
+
+    /// The lines [`record`](Self::record) sends for a request.
+    fn datagram(&self, trace: &Trace, elapsed: Duration) -> String {
+        let mut lines = String::new();
+        self.line(&mut lines, trace.op, "requests", None, "1|c");
+        self.line(&mut lines, trace.op, "request_time", None, &millis(elapsed));
+        for (stage, took) in &trace.stages {
+            self.line(
+                &mut lines,
+                trace.op,
+                "stage_time",
+                Some(stage),
+                &millis(*took),
+            );
+        }
+        if let Some(hits) = trace.hits {
+            self.line(&mut lines, trace.op, "hits", None, &format!("{hits}|h"));
+        }
+        lines
+    }
+
+    fn line(&self, lines: &mut String, op: &str, metric: &str, stage: Option<&str>, value: &str) {
+        if !lines.is_empty() {
+            lines.push('\n');
+        }
+        if !self.dogstatsd {
+            let _ = match stage {
+                Some(stage) => write!(lines, "{}.{op}.{stage}_time:{value}", self.prefix),
+                None => write!(lines, "{}.{op}.{metric}:{value}", self.prefix),
+            };
+            return;
+        }
+        let _ = write!(lines, "{}.{metric}:{value}|#op:{op}", self.prefix);
+        if let Some(stage) = stage {
+            let _ = write!(lines, ",stage:{stage}");
+        }
+        if !self.tags.is_empty() {
+            let _ = write!(lines, ",{}", self.tags);
+        }
+    }
+}
+
+fn millis(took: Duration) -> String {
+    format!("{:.3}|ms", took.as_secs_f64() * 1000.0)
+}
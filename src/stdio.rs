@@ -0,0 +1,101 @@
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use tracing::{info, warn};
+
+use crate::client::{self, Connection};
+use crate::config::Config;
+use crate::connections::{Balance, Routes};
+use crate::context::Context;
+use crate::handshake::HandshakeConfig;
+use crate::heartbeat::HeartbeatConfig;
+use crate::peercred::PeerCredentialsConfig;
+use crate::shutdown;
+use crate::transport::Stream;
+use crate::warm;
+
+/// Serves frames read from stdin, writing replies to stdout, until stdin
+/// ends (`socket_path = "-"`, or `--stdio`). Queries run on the usual
+/// workers; there is no core to handshake with or ping.
+pub fn run(config: &Config) -> io::Result<()> {
+    config.validate()?;
+    let context = Context::from_config(config)?;
+    info!(
+        index = %config.index_path.display(),
+        shards = context.shards.len(),
+        workers = config.workers,
+        "search index opened, serving stdin"
+    );
+
+    let signals = shutdown::listen(&context.shutdown)?;
+    let served = serve(config, &context, io::stdin(), io::stdout().lock());
+    drop(signals);
+    warm::save(&context);
+    context.profiler.save();
+    served
+}
+
+/// Serves the frames read from `input` until it ends or shutdown starts,
+/// writing each reply to `output` as a whole frame, and returns once every
+/// reply is written.
+///
+/// The two ends are bridged onto a socket pair, so the frames go through
+/// the same event loop as a core's.
+pub fn serve(
+    config: &Config,
+    context: &Context,
+    mut input: impl Read + Send + 'static,
+    mut output: impl Write,
+) -> io::Result<()> {
+    let mut config = config.clone();
+    // pings from the other side are answered, none are sent
+    config.handshake = HandshakeConfig::default();
+    config.heartbeat = HeartbeatConfig::default();
+    // the far end of the pair is this process
+    config.peer_credentials = PeerCredentialsConfig::default();
+    let (adapter, peer) = UnixStream::pair()?;
+    let peer_input = peer.try_clone()?;
+    let (jobs, pools) = client::queues(config.queue_depth);
+    let routes = Routes::new(Balance::Origin, 1, 1);
+
+    // the warmer runs until the session is done
+    let stopped = AtomicBool::new(false);
+    thread::scope(|s| {
+        client::spawn_workers(s, &config, &pools, &routes, context);
+        s.spawn(|| warm::run(context, &stopped));
+        // not scoped: a shutdown doesn't wait for stdin to end
+        let reader = thread::spawn(move || {
+            let copied = io::copy(&mut input, &mut &peer_input).map(drop);
+            // the adapter's side reads the end of stdin as a hang-up
+            let _ = peer_input.shutdown(Shutdown::Write);
+            copied
+        });
+        let session = s.spawn(|| {
+            let connection = Connection {
+                slot: 0,
+                jobs,
+                routes: &routes,
+            };
+            client::session(&config, context, Stream::Unix(adapter), connection)
+        });
+
+        // ends once the session is over and its side of the pair closed
+        let written = io::copy(&mut &peer, &mut output).and_then(|_| output.flush());
+        if let Err(e) = &written {
+            warn!(error = %e, "stdout write failed");
+            // the session's writes fail from here on
+            let _ = peer.shutdown(Shutdown::Both);
+        }
+        let panicked = |name: &str| Err(io::Error::other(format!("{name} panicked")));
+        let served = session.join().unwrap_or_else(|_| panicked("session"));
+        stopped.store(true, Ordering::Relaxed);
+        if context.shutdown.is_started() {
+            return served.and(written);
+        }
+        let read = reader.join().unwrap_or_else(|_| panicked("stdin reader"));
+        served.and(written).and(read)
+    })
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use nerve_protocol::frame::OwnedFrame;
+use nerve_protocol::types::{MessageType, RequestId};
+use serde::Deserialize;
+use tracing::{Span, field, info_span};
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{Layer, Registry, fmt, reload};
+
+use crate::introspect::fnv1a;
+
+pub const DEFAULT_SERVICE_NAME: &str = "nerve-search-adapter";
+
+// set once spans have somewhere to go; until then none are made
+static EXPORTING: AtomicBool = AtomicBool::new(false);
+// the level of the lines logged to stdout, once logging is set up
+static LOG_LEVEL: OnceLock<reload::Handle<Targets, Registry>> = OnceLock::new();
+
+/// `[otlp]` section: a span per request exported to an OpenTelemetry
+/// collector (`otel` feature).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct OtlpConfig {
+    /// OTLP/HTTP traces endpoint, as `http://collector:4318/v1/traces`;
+    /// nothing is exported when unset.
+    pub endpoint: Option<String>,
+    /// `service.name` the spans are reported under.
+    pub service_name: String,
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            service_name: DEFAULT_SERVICE_NAME.to_string(),
+        }
+    }
+}
+
+impl OtlpConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let Some(endpoint) = &self.endpoint else {
+            return Ok(());
+        };
+        if !cfg!(feature = "otel") {
+            return Err("otlp.endpoint set but built without the `otel` feature".into());
+        }
+        if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+            return Err(format!(
+                "otlp.endpoint must be an http(s) URL, not {endpoint}"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Whether request spans are being exported.
+pub fn is_exporting() -> bool {
+    EXPORTING.load(Ordering::Relaxed)
+}
+
+/// Logs to stdout and, with an endpoint configured, exports request spans
+/// over OTLP. Spans not yet exported are flushed when the returned
+/// [`Exporter`] drops.
+pub fn init(config: &OtlpConfig) -> io::Result<Exporter> {
+    #[cfg(feature = "otel")]
+    if let Some(endpoint) = &config.endpoint {
+        return otlp::init(endpoint, &config.service_name);
+    }
+    let _ = config;
+    tracing_subscriber::registry().with(log_layer()).init();
+    Ok(Exporter::default())
+}
+
+/// Logs to stdout as `RUST_LOG` says (INFO by default), filtered so
+/// [`set_log_level`] can change it.
+fn log_layer() -> impl Layer<Registry> {
+    let targets = match std::env::var("RUST_LOG") {
+        Ok(directives) => directives.parse().unwrap_or_else(|e| {
+            eprintln!("ignoring RUST_LOG={directives:?}: {e}");
+            Targets::new().with_default(LevelFilter::INFO)
+        }),
+        Err(_) => Targets::new().with_default(LevelFilter::INFO),
+    };
+    let (targets, handle) = reload::Layer::new(targets);
+    let _ = LOG_LEVEL.set(handle);
+    fmt::layer().with_filter(targets)
+}
+
+/// Logs lines at `level` and above to stdout from now on, whatever
+/// `RUST_LOG` said. Spans exported over OTLP are not affected.
+pub fn set_log_level(level: LevelFilter) -> io::Result<()> {
+    let handle = LOG_LEVEL
+        .get()
+        .ok_or_else(|| io::Error::other("logging not set up to change levels"))?;
+    handle
+        .reload(Targets::new().with_default(level))
+        .map_err(io::Error::other)
+}
+
+/// The level lines are logged to stdout at, once logging is set up.
+pub fn log_level() -> Option<LevelFilter> {
+    LOG_LEVEL.get()?.clone_current()?.default_level()
+}
+
+/// Steps the stdout log level on to the next louder one, as on SIGUSR2,
+/// and returns it; see [`cycled`].
+pub fn cycle_log_level() -> io::Result<LevelFilter> {
+    let level = cycled(log_level().unwrap_or(LevelFilter::INFO));
+    set_log_level(level)?;
+    Ok(level)
+}
+
+/// The level after `level` when cycling: `info`, `debug`, `trace`, then
+/// back to `info`. Anything quieter than `info` goes to `debug`.
+pub fn cycled(level: LevelFilter) -> LevelFilter {
+    if level == LevelFilter::TRACE {
+        LevelFilter::INFO
+    } else if level == LevelFilter::DEBUG {
+        LevelFilter::TRACE
+    } else {
+        LevelFilter::DEBUG
+    }
+}
+
+/// Keeps span export running; see [`init`].
+#[derive(Default)]
+pub struct Exporter {
+    #[cfg(feature = "otel")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Drop for Exporter {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider.take() {
+            EXPORTING.store(false, Ordering::Relaxed);
+            if let Err(e) = provider.shutdown() {
+                tracing::warn!(error = %e, "flushing request spans failed");
+            }
+        }
+    }
+}
+
+#[cfg(feature = "otel")]
+mod otlp {
+    use std::io;
+    use std::sync::atomic::Ordering;
+
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::Resource;
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use tracing::info;
+    use tracing_subscriber::Layer;
+    use tracing_subscriber::filter::LevelFilter;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    use super::{EXPORTING, Exporter};
+
+    pub(super) fn init(endpoint: &str, service_name: &str) -> io::Result<Exporter> {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .map_err(io::Error::other)?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(service_name.to_string())
+                    .build(),
+            )
+            .build();
+        let tracer = provider.tracer(super::DEFAULT_SERVICE_NAME);
+        tracing_subscriber::registry()
+            .with(super::log_layer())
+            .with(
+                tracing_opentelemetry::layer()
+                    .with_tracer(tracer)
+                    .with_filter(LevelFilter::INFO),
+            )
+            .init();
+        EXPORTING.store(true, Ordering::Relaxed);
+        info!(endpoint, "exporting request spans");
+        Ok(Exporter {
+            provider: Some(provider),
+        })
+    }
+}
+
+/// A span for one stage of the current request, as `parse` or `search`;
+/// disabled when not exporting.
+pub fn stage(name: &'static str) -> Span {
+    if !is_exporting() {
+        return Span::none();
+    }
+    info_span!("stage", otel.name = name)
+}
+
+/// One connection's requests with a span open, by request id.
+///
+/// A request's span opens as its query is handed to the workers, carries
+/// the request id and operation, takes each stage of its handling as a
+/// child, then a `write` span from its first reply queued to its last
+/// written, and closes with that; a cancelled request's closes then.
+#[derive(Debug, Default)]
+pub struct Spans(Mutex<HashMap<RequestId, Span>>);
+
+impl Spans {
+    /// Opens `id`'s span, when exporting.
+    pub fn open(&self, id: RequestId) {
+        if !is_exporting() {
+            return;
+        }
+        self.spans().insert(id, request(id));
+    }
+
+    pub fn get(&self, id: RequestId) -> Option<Span> {
+        self.spans().get(&id).cloned()
+    }
+
+    /// Closes `id`'s span once every stage holding it has ended.
+    pub fn close(&self, id: RequestId) {
+        self.spans().remove(&id);
+    }
+
+    fn spans(&self) -> MutexGuard<'_, HashMap<RequestId, Span>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A span for request `id`, with its message type, query hash and operation
+/// recorded as they are learned. Logs made while handling the request carry
+/// its fields, exported or not.
+pub fn request(id: RequestId) -> Span {
+    info_span!(
+        parent: None,
+        "request",
+        request_id = id.0,
+        msg_type = field::Empty,
+        query_hash = field::Empty,
+        op = field::Empty,
+    )
+}
+
+/// Records `frame`'s message type and an FNV-1a hash of its payload on its
+/// request's `span`, so the request can be followed without its query text
+/// in the logs.
+pub fn describe(span: &Span, frame: &OwnedFrame) {
+    if span.is_disabled() {
+        return;
+    }
+    match MessageType::try_from(frame.header.msg_type) {
+        Ok(msg_type) => span.record("msg_type", field::debug(msg_type)),
+        Err(_) => span.record("msg_type", frame.header.msg_type),
+    };
+    span.record(
+        "query_hash",
+        field::display(format_args!("{:016x}", fnv1a(&frame.payload))),
+    );
+}
+
+/// The span of writing `request`'s replies.
+pub fn write(request: &Span) -> Span {
+    info_span!(parent: request, "write")
+}
@@ -0,0 +1,96 @@
+use std::io;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::transport::TlsConfig;
+
+/// Client side of the TLS links to `tls://` cores (`tls` feature): the
+/// trusted CA and, for mutual auth, the adapter's own certificate.
+#[derive(Debug, Clone)]
+pub struct TlsClient {
+    config: Arc<ClientConfig>,
+    server_name: Option<String>,
+}
+
+impl TlsClient {
+    pub fn new(tls: &TlsConfig) -> io::Result<Self> {
+        let ca = tls
+            .ca_path
+            .as_deref()
+            .ok_or_else(|| invalid("tls.ca_path is required for tls:// cores".into()))?;
+        let mut roots = RootCertStore::empty();
+        for cert in certificates(ca)? {
+            roots
+                .add(cert)
+                .map_err(|e| invalid(format!("bad CA certificate in {}: {e}", ca.display())))?;
+        }
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?
+            .with_root_certificates(roots);
+        let config = match (&tls.cert_path, &tls.key_path) {
+            (Some(cert), Some(key)) => {
+                let key = PrivateKeyDer::from_pem_file(key)
+                    .map_err(|e| invalid(format!("bad key {}: {e}", key.display())))?;
+                builder
+                    .with_client_auth_cert(certificates(cert)?, key)
+                    .map_err(|e| invalid(format!("bad client certificate: {e}")))?
+            }
+            _ => builder.with_no_client_auth(),
+        };
+        Ok(Self {
+            config: Arc::new(config),
+            server_name: tls.server_name.clone(),
+        })
+    }
+
+    /// Runs the TLS handshake over `stream`, a blocking connection to
+    /// `address`, so certificate problems surface on connect.
+    pub fn connect(
+        &self,
+        address: &str,
+        mut stream: TcpStream,
+    ) -> io::Result<StreamOwned<ClientConnection, TcpStream>> {
+        let host = match &self.server_name {
+            Some(name) => name.as_str(),
+            None => host(address),
+        };
+        let name = ServerName::try_from(host.to_string())
+            .map_err(|e| invalid(format!("bad TLS server name {host}: {e}")))?;
+        let mut conn =
+            ClientConnection::new(Arc::clone(&self.config), name).map_err(io::Error::other)?;
+        while conn.is_handshaking() {
+            conn.complete_io(&mut stream)?;
+        }
+        Ok(StreamOwned::new(conn, stream))
+    }
+}
+
+/// `host` out of `host:port` or `[v6]:port`.
+fn host(address: &str) -> &str {
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+fn certificates(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let bad = |e| invalid(format!("bad certificate file {}: {e}", path.display()));
+    let certs = CertificateDer::pem_file_iter(path)
+        .map_err(bad)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(bad)?;
+    if certs.is_empty() {
+        return Err(invalid(format!("no certificates in {}", path.display())));
+    }
+    Ok(certs)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
@@ -0,0 +1,610 @@
+use std::fmt;
+use std::io::{self, IoSlice, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use mio::event::Source;
+use mio::{Interest, Registry, Token};
+use serde::Deserialize;
+
+#[cfg(feature = "tls")]
+pub use crate::tls::TlsClient;
+
+const TCP_SCHEME: &str = "tcp://";
+const TLS_SCHEME: &str = "tls://";
+const SEQPACKET_SCHEME: &str = "seqpacket://";
+
+/// Longest a connection that wrote its last reply waits for the core to
+/// hang up too; see [`Stream::half_close`].
+pub const HALF_CLOSE_LINGER: Duration = Duration::from_millis(500);
+
+/// `[tls]` section: certificates for `tls://` cores (`tls` feature).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// PEM CA certificates the core's certificate must chain to.
+    pub ca_path: Option<PathBuf>,
+    /// PEM certificate chain the adapter presents, for cores requiring
+    /// mutual auth; needs `key_path`.
+    pub cert_path: Option<PathBuf>,
+    /// PEM (PKCS#8, PKCS#1 or SEC1) private key for `cert_path`.
+    pub key_path: Option<PathBuf>,
+    /// Name the core's certificate is checked against; defaults to the
+    /// host in the socket address.
+    pub server_name: Option<String>,
+}
+
+/// Stands in for the TLS client in builds without the `tls` feature, where
+/// there never is one.
+#[cfg(not(feature = "tls"))]
+#[derive(Debug, Clone)]
+pub enum TlsClient {}
+
+/// Where a core listens: a Unix socket path, or `tcp://host:port` for a
+/// core on another machine, `tls://host:port` for one reached over TLS,
+/// `seqpacket://<path>` for a Unix socket keeping frames apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Unix(PathBuf),
+    Tcp(String),
+    Tls(String),
+    SeqPacket(PathBuf),
+}
+
+impl Endpoint {
+    /// Reads a configured socket: `tcp://host:port` and `tls://host:port`
+    /// name network endpoints, `seqpacket://<path>` a `SOCK_SEQPACKET` Unix
+    /// socket, anything else a Unix socket path.
+    pub fn parse(socket: &Path) -> Self {
+        let socket_str = socket.to_str().unwrap_or_default();
+        if let Some(address) = socket_str.strip_prefix(TCP_SCHEME) {
+            Endpoint::Tcp(address.to_string())
+        } else if let Some(address) = socket_str.strip_prefix(TLS_SCHEME) {
+            Endpoint::Tls(address.to_string())
+        } else if let Some(path) = socket_str.strip_prefix(SEQPACKET_SCHEME) {
+            Endpoint::SeqPacket(PathBuf::from(path))
+        } else {
+            Endpoint::Unix(socket.to_path_buf())
+        }
+    }
+
+    /// Connects, running the TLS handshake with `tls` for `tls://` cores.
+    pub fn connect(&self, tls: Option<&TlsClient>) -> io::Result<Stream> {
+        match self {
+            Endpoint::Unix(path) => UnixStream::connect(path).map(Stream::Unix),
+            Endpoint::Tcp(address) => tcp_connect(address).map(Stream::Tcp),
+            Endpoint::SeqPacket(path) => seqpacket_connect(path).map(Stream::SeqPacket),
+            Endpoint::Tls(address) => {
+                let Some(tls) = tls else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        format!("no TLS client for {TLS_SCHEME}{address}"),
+                    ));
+                };
+                #[cfg(feature = "tls")]
+                return Ok(Stream::Tls(Box::new(
+                    tls.connect(address, tcp_connect(address)?)?,
+                )));
+                #[cfg(not(feature = "tls"))]
+                match *tls {}
+            }
+        }
+    }
+
+    /// [`connect`](Self::connect) on the tokio runtime (`tokio` feature).
+    /// The stream is handed back as a std one, still nonblocking.
+    #[cfg(feature = "tokio")]
+    pub async fn connect_async(&self) -> io::Result<Stream> {
+        match self {
+            Endpoint::Tls(address) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{TLS_SCHEME}{address}: TLS needs the threaded client"),
+            )),
+            Endpoint::Unix(path) => {
+                let stream = tokio::net::UnixStream::connect(path).await?;
+                stream.into_std().map(Stream::Unix)
+            }
+            Endpoint::Tcp(address) => {
+                let stream = tokio::net::TcpStream::connect(address).await?;
+                stream.set_nodelay(true)?;
+                stream.into_std().map(Stream::Tcp)
+            }
+            // tokio has no SOCK_SEQPACKET connect; a local one doesn't block
+            Endpoint::SeqPacket(path) => {
+                let stream = seqpacket_connect(path)?;
+                stream.set_nonblocking(true)?;
+                Ok(Stream::SeqPacket(stream))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Unix(path) => write!(f, "{}", path.display()),
+            Endpoint::Tcp(address) => write!(f, "{TCP_SCHEME}{address}"),
+            Endpoint::Tls(address) => write!(f, "{TLS_SCHEME}{address}"),
+            Endpoint::SeqPacket(path) => write!(f, "{SEQPACKET_SCHEME}{}", path.display()),
+        }
+    }
+}
+
+fn tcp_connect(address: &str) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect(address)?;
+    // frames are written whole; don't hold small replies back
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+/// Connects a `SOCK_SEQPACKET` Unix socket to `path`; fails with the OS's
+/// error where the kernel has no such sockets.
+fn seqpacket_connect(path: &Path) -> io::Result<UnixStream> {
+    // SAFETY: an all-zero sockaddr_un is a valid, empty one
+    let mut address: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    address.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    let bytes = path.as_os_str().as_bytes();
+    // the path needs its terminating NUL to fit
+    if bytes.len() >= address.sun_path.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("socket path too long: {}", path.display()),
+        ));
+    }
+    for (slot, byte) in address.sun_path.iter_mut().zip(bytes) {
+        *slot = *byte as libc::c_char;
+    }
+    // SAFETY: plain socket(2); the fd is owned as soon as it is checked
+    let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: fd was just opened and nothing else owns it
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+    // SAFETY: address is an initialized sockaddr_un, passed with its size
+    let connected = unsafe {
+        libc::connect(
+            socket.as_raw_fd(),
+            (&address as *const libc::sockaddr_un).cast(),
+            std::mem::size_of::<libc::sockaddr_un>() as libc::socklen_t,
+        )
+    };
+    if connected != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(UnixStream::from(socket))
+}
+
+/// The first of `bufs` with anything in it, written alone where each write
+/// is a packet.
+fn first<'a>(bufs: &'a [IoSlice<'_>]) -> &'a [u8] {
+    bufs.iter()
+        .find(|buf| !buf.is_empty())
+        .map_or(&[], |buf| &buf[..])
+}
+
+/// A connection to the core over any transport.
+#[derive(Debug)]
+pub enum Stream {
+    Unix(UnixStream),
+    /// A `SOCK_SEQPACKET` Unix socket, behind std's stream type: each read
+    /// and write is one whole packet.
+    SeqPacket(UnixStream),
+    Tcp(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<rustls::StreamOwned<rustls::ClientConnection, TcpStream>>),
+}
+
+impl Stream {
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Unix(stream) | Stream::SeqPacket(stream) => stream.set_nonblocking(nonblocking),
+            Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.sock.set_nonblocking(nonblocking),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Unix(stream) | Stream::SeqPacket(stream) => stream.set_read_timeout(timeout),
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.sock.set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Unix(stream) | Stream::SeqPacket(stream) => stream.set_write_timeout(timeout),
+            Stream::Tcp(stream) => stream.set_write_timeout(timeout),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.sock.set_write_timeout(timeout),
+        }
+    }
+
+    /// Sizes the kernel's send and receive buffers for the socket
+    /// (`SO_SNDBUF`, `SO_RCVBUF`), keeping the default where `None`. The
+    /// kernel may round the sizes, and caps them at `net.core.wmem_max` and
+    /// `net.core.rmem_max`.
+    pub fn set_buffer_sizes(&self, send: Option<usize>, receive: Option<usize>) -> io::Result<()> {
+        if let Some(bytes) = send {
+            set_socket_option(self, libc::SO_SNDBUF, bytes)?;
+        }
+        if let Some(bytes) = receive {
+            set_socket_option(self, libc::SO_RCVBUF, bytes)?;
+        }
+        Ok(())
+    }
+
+    /// The send and receive buffer sizes the kernel settled on.
+    pub fn buffer_sizes(&self) -> io::Result<(usize, usize)> {
+        Ok((
+            socket_option(self, libc::SO_SNDBUF)?,
+            socket_option(self, libc::SO_RCVBUF)?,
+        ))
+    }
+
+    /// Whether the transport keeps frames apart, each read and write one
+    /// whole packet.
+    pub fn keeps_packets(&self) -> bool {
+        matches!(self, Stream::SeqPacket(_))
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Unix(stream) | Stream::SeqPacket(stream) => stream.shutdown(how),
+            Stream::Tcp(stream) => stream.shutdown(how),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.sock.shutdown(how),
+        }
+    }
+
+    /// A second handle on the socket under the stream (the bare TCP socket
+    /// under TLS), to shut it down from another thread.
+    pub fn socket(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+            Stream::SeqPacket(stream) => stream.try_clone().map(Stream::SeqPacket),
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.sock.try_clone().map(Stream::Tcp),
+        }
+    }
+
+    /// Ends a connection whose replies are all written: shuts the write
+    /// side, so the peer reads every reply and then the end of the stream,
+    /// and reads off and drops whatever the peer still sends until it hangs
+    /// up too or `linger` passes. Closing on unread bytes resets a TCP
+    /// connection, and the reset can throw away replies the peer has yet to
+    /// read. Works on a nonblocking socket as well.
+    pub fn half_close(&self, linger: Duration) -> io::Result<()> {
+        self.shutdown(Shutdown::Write)?;
+        let deadline = Instant::now() + linger;
+        let mut scratch = [0u8; 4096];
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(());
+            }
+            let mut ready = libc::pollfd {
+                fd: self.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let millis = left.as_millis().clamp(1, i32::MAX as u128) as libc::c_int;
+            // SAFETY: ready is one live pollfd
+            match unsafe { libc::poll(&mut ready, 1, millis) } {
+                0 => return Ok(()),
+                n if n < 0 => match io::Error::last_os_error() {
+                    e if e.kind() == io::ErrorKind::Interrupted => continue,
+                    e => return Err(e),
+                },
+                _ => {}
+            }
+            // SAFETY: scratch is writable for its whole length
+            let read = unsafe {
+                libc::recv(
+                    self.as_raw_fd(),
+                    scratch.as_mut_ptr().cast(),
+                    scratch.len(),
+                    libc::MSG_DONTWAIT,
+                )
+            };
+            match read {
+                0 => return Ok(()),
+                n if n > 0 => {}
+                _ => match io::Error::last_os_error() {
+                    e if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+                    ) =>
+                    {
+                        continue;
+                    }
+                    // reset or gone: nothing left to read off
+                    _ => return Ok(()),
+                },
+            }
+        }
+    }
+
+    /// The stream registered with a mio poll; it must be nonblocking.
+    pub fn into_mio(self) -> MioStream {
+        match self {
+            Stream::Unix(stream) => MioStream::Unix(mio::net::UnixStream::from_std(stream)),
+            Stream::SeqPacket(stream) => {
+                MioStream::SeqPacket(mio::net::UnixStream::from_std(stream))
+            }
+            Stream::Tcp(stream) => MioStream::Tcp(mio::net::TcpStream::from_std(stream)),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => {
+                let rustls::StreamOwned { conn, sock } = *stream;
+                MioStream::Tls(Box::new(rustls::StreamOwned::new(
+                    conn,
+                    mio::net::TcpStream::from_std(sock),
+                )))
+            }
+        }
+    }
+
+    /// The stream split into tokio read and write halves (`tokio`
+    /// feature); it must be nonblocking.
+    #[cfg(feature = "tokio")]
+    pub fn into_tokio(self) -> io::Result<(TokioReader, TokioWriter)> {
+        Ok(match self {
+            Stream::Unix(stream) | Stream::SeqPacket(stream) => {
+                let (reader, writer) = tokio::net::UnixStream::from_std(stream)?.into_split();
+                (Box::new(reader), Box::new(writer))
+            }
+            Stream::Tcp(stream) => {
+                let (reader, writer) = tokio::net::TcpStream::from_std(stream)?.into_split();
+                (Box::new(reader), Box::new(writer))
+            }
+            #[cfg(feature = "tls")]
+            Stream::Tls(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "TLS streams need the threaded client",
+                ));
+            }
+        })
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub type TokioReader = Box<dyn tokio::io::AsyncRead + Send + Unpin>;
+#[cfg(feature = "tokio")]
+pub type TokioWriter = Box<dyn tokio::io::AsyncWrite + Send + Unpin>;
+
+impl From<UnixStream> for Stream {
+    fn from(stream: UnixStream) -> Self {
+        Stream::Unix(stream)
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Self {
+        Stream::Tcp(stream)
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Unix(stream) | Stream::SeqPacket(stream) => stream.read(buf),
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Unix(stream) | Stream::SeqPacket(stream) => stream.write(buf),
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        match self {
+            Stream::Unix(stream) => stream.write_vectored(bufs),
+            // one packet per frame
+            Stream::SeqPacket(stream) => stream.write(first(bufs)),
+            Stream::Tcp(stream) => stream.write_vectored(bufs),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.write_vectored(bufs),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.flush(),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Stream::Unix(stream) | Stream::SeqPacket(stream) => stream.as_raw_fd(),
+            Stream::Tcp(stream) => stream.as_raw_fd(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.sock.as_raw_fd(),
+        }
+    }
+}
+
+fn set_socket_option(socket: &impl AsRawFd, option: libc::c_int, bytes: usize) -> io::Result<()> {
+    let value = libc::c_int::try_from(bytes)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "socket buffer too large"))?;
+    // SAFETY: value is a live c_int, as SO_SNDBUF and SO_RCVBUF expect
+    let set = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            (&value as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if set != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn socket_option(socket: &impl AsRawFd, option: libc::c_int) -> io::Result<usize> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: value and len describe a writable c_int
+    let got = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            (&mut value as *mut libc::c_int).cast(),
+            &mut len,
+        )
+    };
+    if got != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value.max(0) as usize)
+}
+
+/// A connection the event loop serves frames over: registered with a mio
+/// poll, then read and written without blocking until the peer hangs up.
+/// [`Endpoint::connect`] opens the ones to cores; anything else carrying
+/// frames implements this to be served by the same loop.
+pub trait Transport: Source {
+    /// Reads the next bytes of frames into `buf`: `Ok(0)` once the peer
+    /// hung up, `WouldBlock` while nothing is waiting.
+    fn read_frames(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Writes as much of `frames` as the transport takes at once; returns
+    /// the bytes written, or `WouldBlock` when it takes none.
+    fn write_frames(&mut self, frames: &[IoSlice<'_>]) -> io::Result<usize>;
+
+    /// Pushes out bytes the transport buffers itself, as TLS records.
+    fn flush_frames(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether written bytes are still buffered above the socket.
+    fn has_pending_writes(&self) -> bool {
+        false
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()>;
+}
+
+/// [`Stream`] for the readiness-polling event loop.
+#[derive(Debug)]
+pub enum MioStream {
+    Unix(mio::net::UnixStream),
+    SeqPacket(mio::net::UnixStream),
+    Tcp(mio::net::TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<rustls::StreamOwned<rustls::ClientConnection, mio::net::TcpStream>>),
+}
+
+impl Transport for MioStream {
+    fn read_frames(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            MioStream::Unix(stream) | MioStream::SeqPacket(stream) => stream.read(buf),
+            MioStream::Tcp(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            MioStream::Tls(stream) => stream.read(buf),
+        }
+    }
+
+    fn write_frames(&mut self, frames: &[IoSlice<'_>]) -> io::Result<usize> {
+        match self {
+            MioStream::Unix(stream) => stream.write_vectored(frames),
+            // one packet per frame
+            MioStream::SeqPacket(stream) => stream.write(first(frames)),
+            MioStream::Tcp(stream) => stream.write_vectored(frames),
+            #[cfg(feature = "tls")]
+            MioStream::Tls(stream) => stream.write_vectored(frames),
+        }
+    }
+
+    fn flush_frames(&mut self) -> io::Result<()> {
+        match self {
+            #[cfg(feature = "tls")]
+            MioStream::Tls(stream) => stream.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    fn has_pending_writes(&self) -> bool {
+        match self {
+            #[cfg(feature = "tls")]
+            MioStream::Tls(stream) => stream.conn.wants_write(),
+            _ => false,
+        }
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            MioStream::Unix(stream) | MioStream::SeqPacket(stream) => stream.shutdown(how),
+            MioStream::Tcp(stream) => stream.shutdown(how),
+            #[cfg(feature = "tls")]
+            MioStream::Tls(stream) => stream.sock.shutdown(how),
+        }
+    }
+}
+
+impl Source for MioStream {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            MioStream::Unix(stream) | MioStream::SeqPacket(stream) => {
+                stream.register(registry, token, interests)
+            }
+            MioStream::Tcp(stream) => stream.register(registry, token, interests),
+            #[cfg(feature = "tls")]
+            MioStream::Tls(stream) => stream.sock.register(registry, token, interests),
+        }
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            MioStream::Unix(stream) | MioStream::SeqPacket(stream) => {
+                stream.reregister(registry, token, interests)
+            }
+            MioStream::Tcp(stream) => stream.reregister(registry, token, interests),
+            #[cfg(feature = "tls")]
+            MioStream::Tls(stream) => stream.sock.reregister(registry, token, interests),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        match self {
+            MioStream::Unix(stream) | MioStream::SeqPacket(stream) => stream.deregister(registry),
+            MioStream::Tcp(stream) => stream.deregister(registry),
+            #[cfg(feature = "tls")]
+            MioStream::Tls(stream) => stream.sock.deregister(registry),
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use std::fs::File;
+use std::io::{self, IoSlice, Write};
+use std::net::Shutdown;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use io_uring::{IoUring, opcode, squeue, types};
+use nerve_protocol::frame::OwnedFrame;
+use tracing::{info, warn};
+
+use crate::client::{self, Connection, Link, MAX_WRITE_SLICES, Outbox, Replies};
+use crate::config::Config;
+use crate::context::Context;
+use crate::framing::FrameDecoder;
+use crate::handler;
+use crate::handshake::{self, Capabilities};
+use crate::heartbeat::Heartbeat;
+use crate::peercred;
+use crate::profile::Profiler;
+use crate::shutdown;
+use crate::transport::{HALF_CLOSE_LINGER, Stream};
+use crate::warm;
+
+const SOCKET_READ: u64 = 0;
+const WAKE_READ: u64 = 1;
+const SOCKET_WRITE: u64 = 2;
+const PING_TIMER: u64 = 3;
+const RING_ENTRIES: u32 = 8;
+
+/// [`client::run`] with socket reads and writes submitted through io_uring
+/// (`io-uring` feature) instead of readiness polling.
+///
+/// One read and one write are kept in flight on the socket; workers signal
+/// new replies through an eventfd the ring also reads.
+pub fn run(config: &Config) -> io::Result<()> {
+    config.validate()?;
+    if config.uses_tls() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "tls:// cores need the threaded client",
+        ));
+    }
+    let context = Context::from_config(config)?;
+    info!(
+        index = %config.index_path.display(),
+        shards = context.shards.len(),
+        peers = config.peers.len(),
+        workers = config.workers,
+        connections = config.connections.count,
+        "search index opened (io_uring)"
+    );
+
+    let signals = shutdown::listen(&context.shutdown)?;
+    let served = client::run_connections(config, &context, session);
+    drop(signals);
+    warm::save(&context);
+    context.profiler.save();
+    served
+}
+
+/// Serves one connection; see [`client::run`].
+fn session(
+    config: &Config,
+    context: &Context,
+    mut stream: Stream,
+    connection: Connection<'_>,
+) -> io::Result<()> {
+    peercred::verify(&stream, &config.peer_credentials)?;
+    let write_timeout = config.write_timeout_ms.map(Duration::from_millis);
+    stream.set_write_timeout(write_timeout)?;
+    stream.set_buffer_sizes(config.send_buffer_bytes, config.receive_buffer_bytes)?;
+    let mut decoder = FrameDecoder::new(config.max_payload_bytes).with_packets(stream.keeps_packets());
+    let offered = Capabilities::offered(context);
+    let handshake = handshake::exchange(&mut stream, config.handshake, offered, context.auth.as_ref(), &mut decoder)?;
+    let wake = Arc::new(eventfd()?);
+
+    let health = context.connections.slot(connection.slot);
+    let state = client::connection_state(context, health, &handshake);
+    let (reply_tx, reply_rx) = mpsc::channel::<Bytes>();
+    let replies = Replies::new(reply_tx, {
+        let wake = Arc::clone(&wake);
+        move || {
+            let _ = (&*wake).write(&1u64.to_ne_bytes());
+        }
+    });
+
+    let _watch = context.shutdown.watch(&stream, &state)?;
+    let _orphans = health.track(Arc::clone(&state));
+    let link = connection.link(replies, state);
+    client::abort_orphans(health, |error| link.send(error));
+
+    let heartbeat = Heartbeat::new(config.heartbeat);
+    serve(
+        &stream,
+        &wake,
+        decoder,
+        handshake.early,
+        link,
+        reply_rx,
+        write_timeout,
+        heartbeat,
+        &context.profiler,
+    )?;
+    let _ = stream.half_close(HALF_CLOSE_LINGER);
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn serve(
+    stream: &Stream,
+    wake: &File,
+    mut decoder: FrameDecoder,
+    mut early: Vec<OwnedFrame>,
+    link: Link<'_>,
+    replies: Receiver<Bytes>,
+    write_timeout: Option<Duration>,
+    mut heartbeat: Heartbeat,
+    profiler: &Profiler,
+) -> io::Result<()> {
+    let mut ring = IoUring::new(RING_ENTRIES)?;
+    let mut outbox = Outbox::for_connection(link.state());
+    let write_slices = if stream.keeps_packets() { 1 } else { MAX_WRITE_SLICES };
+    let state = link.shared_state();
+    client::dispatch(&mut early, &link, &mut heartbeat, |reply| {
+        outbox.push(reply)
+    });
+    let mut link = Some(link);
+    let mut workers_done = false;
+    let mut failure = None;
+
+    // the kernel fills and drains these buffers asynchronously, so the loop
+    // only returns once no operation on them is in flight
+    let mut buf = client::read_buffer(&decoder);
+    let mut wake_buf = [0u8; 8];
+    let mut iovecs = [libc::iovec {
+        iov_base: std::ptr::null_mut(),
+        iov_len: 0,
+    }; MAX_WRITE_SLICES];
+    let (mut reading, mut waiting, mut writing) = (false, false, false);
+    // the kernel copies the timespec when the timer is submitted, so a timer
+    // still pending when the loop returns is dropped with the ring
+    let mut timer;
+    let mut timing = false;
+
+    loop {
+        if link.is_some() && failure.is_none() {
+            match heartbeat.tick() {
+                Ok(Some(ping)) => outbox.push(ping),
+                Ok(None) => {}
+                Err(e) => {
+                    warn!(error = %e, "core not answering, dropping connection");
+                    failure = Some(e);
+                    outbox = Outbox::default();
+                    link = None;
+                    // completes the pending read so the loop can wind down
+                    let _ = stream.shutdown(Shutdown::Both);
+                }
+            }
+        }
+        loop {
+            match replies.try_recv() {
+                // after a write failure nothing more reaches the core
+                Ok(reply) if failure.is_none() => outbox.push(reply),
+                Ok(_) => {}
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    workers_done = true;
+                    break;
+                }
+            }
+        }
+        if let Some(limit) = write_timeout
+            && failure.is_none()
+        {
+            // frames under a pending write stay where the kernel reads them
+            let expired = if writing {
+                outbox.check_moving(limit).map(|()| Vec::new())
+            } else {
+                outbox.expire(limit)
+            };
+            match expired {
+                // nothing more of theirs goes out
+                Ok(failed) => failed.into_iter().for_each(|id| state.cancel(id)),
+                Err(e) => {
+                    warn!(error = %e, "core not reading, dropping connection");
+                    failure = Some(e);
+                    outbox = Outbox::default();
+                    link = None;
+                    // completes the pending operations so the loop can wind down
+                    let _ = stream.shutdown(Shutdown::Both);
+                }
+            }
+        }
+
+        let mut entries: Vec<squeue::Entry> = Vec::with_capacity(4);
+        if link.is_some() && !reading {
+            let fd = types::Fd(stream.as_raw_fd());
+            entries.push(
+                opcode::Read::new(fd, buf.as_mut_ptr(), buf.len() as u32)
+                    .build()
+                    .user_data(SOCKET_READ),
+            );
+            reading = true;
+        }
+        if !workers_done && !waiting {
+            let fd = types::Fd(wake.as_raw_fd());
+            entries.push(
+                opcode::Read::new(fd, wake_buf.as_mut_ptr(), wake_buf.len() as u32)
+                    .build()
+                    .user_data(WAKE_READ),
+            );
+            waiting = true;
+        }
+        if !writing && !outbox.is_empty() {
+            let mut slices = [IoSlice::new(&[]); MAX_WRITE_SLICES];
+            // a packet transport takes one frame per write
+            let filled = outbox.slices(&mut slices[..write_slices]);
+            for (iovec, slice) in iovecs.iter_mut().zip(&slices[..filled]) {
+                *iovec = libc::iovec {
+                    iov_base: slice.as_ptr() as *mut libc::c_void,
+                    iov_len: slice.len(),
+                };
+            }
+            let fd = types::Fd(stream.as_raw_fd());
+            entries.push(
+                opcode::Writev::new(fd, iovecs.as_ptr(), filled as u32)
+                    .build()
+                    .user_data(SOCKET_WRITE),
+            );
+            writing = true;
+        }
+
+        let ping_due = link.as_ref().and_then(|_| heartbeat.until_due());
+        // rechecked at least this often, however long the ping timer runs
+        let expiry = write_timeout.map(|limit| outbox.until_expiry(limit).unwrap_or(limit));
+        if !timing && let Some(due) = ping_due.into_iter().chain(expiry).min() {
+            timer = types::Timespec::from(due);
+            entries.push(opcode::Timeout::new(&timer).build().user_data(PING_TIMER));
+            timing = true;
+        }
+
+        if !(reading || waiting || writing) {
+            return failure.map_or(Ok(()), Err);
+        }
+
+        // SAFETY: every buffer referenced above lives until its completion
+        // is reaped below, and the outbox keeps the frames being written in
+        // place until then
+        unsafe {
+            ring.submission()
+                .push_multiple(&entries)
+                .map_err(|_| io::Error::other("io_uring submission queue full"))?;
+        }
+        state.progress().idle();
+        let submitted = ring.submit_and_wait(1);
+        state.progress().busy();
+        match submitted {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+
+        let completions: Vec<(u64, i32)> = ring
+            .completion()
+            .map(|cqe| (cqe.user_data(), cqe.result()))
+            .collect();
+        for (op, result) in completions {
+            match op {
+                SOCKET_READ => {
+                    reading = false;
+                    let Some(open) = &link else {
+                        continue;
+                    };
+                    if result <= 0 {
+                        if result < 0 {
+                            let e = io::Error::from_raw_os_error(-result);
+                            warn!(error = %e, "socket read failed, exiting");
+                        }
+                        if let Some(notice) = client::going_away(open.state()) {
+                            outbox.push(notice);
+                        }
+                        // no more queries: let the workers finish this connection's
+                        link = None;
+                        continue;
+                    }
+                    open.state().traffic().read(result as usize);
+                    let mut frames = Vec::new();
+                    let start = profiler.is_enabled().then(Instant::now);
+                    decoder.decode(&buf[..result as usize], &mut frames, |rejected| {
+                        if let Some(reply) = handler::frame_rejected(&rejected)
+                            && failure.is_none()
+                        {
+                            outbox.push(reply);
+                        }
+                    });
+                    if let Some(start) = start {
+                        profiler.record_io("decode", start.elapsed());
+                    }
+                    client::dispatch(&mut frames, open, &mut heartbeat, |reply| {
+                        if failure.is_none() {
+                            outbox.push(reply);
+                        }
+                    });
+                }
+                WAKE_READ => waiting = false,
+                PING_TIMER => timing = false,
+                _ => {
+                    writing = false;
+                    if result > 0 {
+                        outbox.advance(result as usize);
+                    } else if failure.is_none() {
+                        failure = Some(if result == 0 {
+                            io::ErrorKind::WriteZero.into()
+                        } else {
+                            io::Error::from_raw_os_error(-result)
+                        });
+                        outbox = Outbox::default();
+                        link = None;
+                        // completes the pending read so the loop can wind down
+                        let _ = stream.shutdown(Shutdown::Both);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn eventfd() -> io::Result<File> {
+    // SAFETY: plain syscall; the returned descriptor is owned by the File
+    let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
@@ -0,0 +1,164 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{Value, json};
+
+use crate::request::{SearchMode, SearchRequest};
+
+/// Distinct hit domains counted before the least served is forgotten.
+pub const DEFAULT_TRACKED_DOMAINS: usize = 1_000;
+
+/// Latest search queries kept for the `top_queries` operation.
+pub const RECENT_QUERIES: usize = 100;
+
+// domains listed by the `stats` operation
+const REPORTED_DOMAINS: usize = 20;
+
+/// How the search API is used since start: searches by mode, the orderings
+/// `sort_fusion` searches fuse, the filters searches set, and the domains
+/// of the hits served. Requests carry no domain filter, so the domains are
+/// those of each hit's `url`, counted once per search. The latest
+/// [`RECENT_QUERIES`] query texts are kept as well.
+#[derive(Debug)]
+pub struct Usage {
+    tracked_domains: usize,
+    tallies: Mutex<Tallies>,
+}
+
+#[derive(Debug, Default)]
+struct Tallies {
+    searches: u64,
+    modes: BTreeMap<&'static str, u64>,
+    sorts: BTreeMap<&'static str, u64>,
+    filters: BTreeMap<&'static str, u64>,
+    domains: HashMap<String, u64>,
+    // newest first, with when they arrived in Unix milliseconds
+    recent: VecDeque<(u64, String)>,
+}
+
+impl Default for Usage {
+    fn default() -> Self {
+        Self::new(DEFAULT_TRACKED_DOMAINS)
+    }
+}
+
+impl Usage {
+    pub fn new(tracked_domains: usize) -> Self {
+        Self {
+            tracked_domains,
+            tallies: Mutex::default(),
+        }
+    }
+
+    /// Counts what `request` asked for.
+    pub fn search(&self, request: &SearchRequest) {
+        let mut tallies = self.tallies();
+        tallies.searches += 1;
+        *tallies.modes.entry(request.mode.as_str()).or_default() += 1;
+        if request.mode == SearchMode::SortFusion {
+            for sort in &request.sorts {
+                *tallies.sorts.entry(sort.as_str()).or_default() += 1;
+            }
+        }
+        let filters = [
+            ("after", request.filters.after.is_some()),
+            ("before", request.filters.before.is_some()),
+            ("dedup", request.dedup),
+            ("language", request.language.is_some()),
+            ("stem", request.stem.is_some()),
+        ];
+        for (filter, set) in filters {
+            if set {
+                *tallies.filters.entry(filter).or_default() += 1;
+            }
+        }
+        if !request.query.is_empty() {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            tallies.recent.push_front((now, request.query.clone()));
+            tallies.recent.truncate(RECENT_QUERIES);
+        }
+    }
+
+    /// The `n` latest search queries, newest first, with when each arrived
+    /// in Unix milliseconds.
+    pub fn recent(&self, n: usize) -> Vec<(u64, String)> {
+        self.tallies().recent.iter().take(n).cloned().collect()
+    }
+
+    /// Counts the domains of the hits one search served.
+    pub fn served(&self, hits: &[Value]) {
+        let domains: HashSet<&str> = hits
+            .iter()
+            .filter_map(|hit| hit.get("url")?.as_str())
+            .filter_map(domain)
+            .collect();
+        if domains.is_empty() {
+            return;
+        }
+        let mut tallies = self.tallies();
+        for domain in domains {
+            if let Some(count) = tallies.domains.get_mut(domain) {
+                *count += 1;
+                continue;
+            }
+            if self.tracked_domains == 0 {
+                continue;
+            }
+            if tallies.domains.len() >= self.tracked_domains {
+                // make room by forgetting the least served domain
+                let least = tallies
+                    .domains
+                    .iter()
+                    .min_by_key(|(_, count)| **count)
+                    .map(|(domain, _)| domain.clone());
+                if let Some(least) = least {
+                    tallies.domains.remove(&least);
+                }
+            }
+            tallies.domains.insert(domain.to_string(), 1);
+        }
+    }
+
+    /// What the `stats` operation reports: `searches`, counts by `modes`,
+    /// `sorts` and `filters`, and the most served `domains`, most first.
+    pub fn report(&self) -> Value {
+        let tallies = self.tallies();
+        let mut domains: Vec<(&String, &u64)> = tallies.domains.iter().collect();
+        domains.sort_by_key(|&(domain, count)| (Reverse(*count), domain));
+        let domains: Vec<Value> = domains
+            .into_iter()
+            .take(REPORTED_DOMAINS)
+            .map(|(domain, count)| json!({"domain": domain, "searches": count}))
+            .collect();
+        json!({
+            "searches": tallies.searches,
+            "modes": tallies.modes,
+            "sorts": tallies.sorts,
+            "filters": tallies.filters,
+            "domains": domains,
+        })
+    }
+
+    fn tallies(&self) -> MutexGuard<'_, Tallies> {
+        self.tallies.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The host of `url`, without scheme, credentials, port or path.
+fn domain(url: &str) -> Option<&str> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next()?,
+        None => host.split(':').next()?,
+    };
+    (!host.is_empty()).then_some(host)
+}
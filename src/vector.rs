@@ -0,0 +1,342 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use serde_json::{Map, Value};
+use tracing::info;
+
+use crate::state::CancelToken;
+
+/// Sidecar file, stored inside the tantivy index directory, holding one JSON
+/// object per line: the hit fields to return plus a `"vector"` array.
+pub const SIDECAR_FILE: &str = "vectors.jsonl";
+
+const M: usize = 16;
+const M0: usize = 2 * M;
+const EF_CONSTRUCTION: usize = 100;
+const DEFAULT_EF_SEARCH: usize = 64;
+/// Graph nodes expanded between cancellation checks.
+const CANCEL_CHECK_INTERVAL: usize = 256;
+
+/// Turns query text into an embedding for vector search.
+///
+/// The adapter ships no models; embedders are plugged in by whoever embeds the
+/// adapter, and text-only vector queries fail without one.
+pub trait Embedder: Send + Sync {
+    fn embed(&self, text: &str) -> io::Result<Vec<f32>>;
+}
+
+/// Approximate nearest-neighbour index over the sidecar vectors (HNSW,
+/// cosine similarity), built in memory when the adapter starts.
+pub struct VectorIndex {
+    dimensions: usize,
+    fields: Vec<Map<String, Value>>,
+    vectors: Vec<Vec<f32>>,
+    graph: Hnsw,
+}
+
+impl VectorIndex {
+    /// Loads the sidecar next to `index_path`; `Ok(None)` when there is none.
+    pub fn load(index_path: &Path) -> io::Result<Option<Self>> {
+        let path = index_path.join(SIDECAR_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let mut entries = Vec::new();
+        for (n, line) in BufReader::new(File::open(&path)?).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut fields: Map<String, Value> = serde_json::from_str(&line)
+                .map_err(|e| invalid(format!("{}:{}: {e}", path.display(), n + 1)))?;
+            let vector = fields
+                .remove("vector")
+                .and_then(|v| serde_json::from_value::<Vec<f32>>(v).ok())
+                .ok_or_else(|| invalid(format!("{}:{}: missing vector", path.display(), n + 1)))?;
+            entries.push((fields, vector));
+        }
+
+        let index = Self::build(entries)?;
+        info!(
+            path = %path.display(),
+            vectors = index.len(),
+            dimensions = index.dimensions,
+            "vector sidecar loaded"
+        );
+        Ok(Some(index))
+    }
+
+    pub fn build(entries: Vec<(Map<String, Value>, Vec<f32>)>) -> io::Result<Self> {
+        let dimensions = entries.first().map_or(0, |(_, v)| v.len());
+        let mut index = Self {
+            dimensions,
+            fields: Vec::with_capacity(entries.len()),
+            vectors: Vec::with_capacity(entries.len()),
+            graph: Hnsw::new(),
+        };
+        for (fields, mut vector) in entries {
+            if vector.len() != dimensions {
+                return Err(invalid(format!(
+                    "vector dimension mismatch: expected {dimensions}, got {}",
+                    vector.len()
+                )));
+            }
+            normalize(&mut vector);
+            index.fields.push(fields);
+            index.vectors.push(vector);
+            index.graph.insert(index.vectors.len() - 1, &index.vectors);
+        }
+        Ok(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.vectors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vectors.is_empty()
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    /// Nearest neighbours of `query`, shaped like lexical hits with the cosine
+    /// similarity as `"score"`. Fails with `Interrupted` once `cancel` trips.
+    pub fn search(
+        &self,
+        query: &[f32],
+        limit: usize,
+        offset: usize,
+        cancel: &CancelToken,
+    ) -> io::Result<Vec<Value>> {
+        if query.len() != self.dimensions {
+            return Err(invalid(format!(
+                "query vector has {} dimensions, index has {}",
+                query.len(),
+                self.dimensions
+            )));
+        }
+        let mut query = query.to_vec();
+        normalize(&mut query);
+
+        let k = offset + limit;
+        let nearest = self
+            .graph
+            .search(&query, &self.vectors, k, DEFAULT_EF_SEARCH.max(k), cancel);
+        cancel.check()?;
+        Ok(nearest
+            .into_iter()
+            .skip(offset)
+            .map(|Scored(distance, id)| {
+                let mut hit = self.fields[id].clone();
+                hit.insert("score".into(), Value::from(1.0 - distance));
+                Value::Object(hit)
+            })
+            .collect())
+    }
+}
+
+fn normalize(v: &mut [f32]) {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+fn distance(a: &[f32], b: &[f32]) -> f32 {
+    1.0 - a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>()
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[derive(Clone, Copy)]
+struct Scored(f32, usize);
+
+impl PartialEq for Scored {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+/// Hierarchical navigable small world graph over externally stored vectors.
+struct Hnsw {
+    /// node -> level -> neighbour ids
+    links: Vec<Vec<Vec<usize>>>,
+    entry: Option<usize>,
+    max_level: usize,
+    rng: u64,
+}
+
+impl Hnsw {
+    fn new() -> Self {
+        Self {
+            links: Vec::new(),
+            entry: None,
+            max_level: 0,
+            rng: 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+
+    fn random_level(&mut self) -> usize {
+        // xorshift; deterministic so identical sidecars build identical graphs
+        let mut level = 0;
+        loop {
+            self.rng ^= self.rng << 13;
+            self.rng ^= self.rng >> 7;
+            self.rng ^= self.rng << 17;
+            if !self.rng.is_multiple_of(M as u64) || level >= 16 {
+                return level;
+            }
+            level += 1;
+        }
+    }
+
+    fn insert(&mut self, id: usize, vectors: &[Vec<f32>]) {
+        let level = self.random_level();
+        self.links.push(vec![Vec::new(); level + 1]);
+
+        let Some(mut entry) = self.entry else {
+            self.entry = Some(id);
+            self.max_level = level;
+            return;
+        };
+
+        let query = &vectors[id];
+        for l in (level + 1..=self.max_level).rev() {
+            entry = self.greedy(query, vectors, entry, l);
+        }
+
+        for l in (0..=level.min(self.max_level)).rev() {
+            let candidates = self.search_layer(query, vectors, entry, EF_CONSTRUCTION, l, None);
+            let cap = if l == 0 { M0 } else { M };
+            let neighbours: Vec<usize> = candidates.iter().take(M).map(|s| s.1).collect();
+            for &n in &neighbours {
+                self.links[n][l].push(id);
+                if self.links[n][l].len() > cap {
+                    let base = &vectors[n];
+                    let mut scored: Vec<Scored> = self.links[n][l]
+                        .iter()
+                        .map(|&c| Scored(distance(base, &vectors[c]), c))
+                        .collect();
+                    scored.sort();
+                    self.links[n][l] = scored.into_iter().take(cap).map(|s| s.1).collect();
+                }
+            }
+            self.links[id][l] = neighbours;
+            entry = candidates[0].1;
+        }
+
+        if level > self.max_level {
+            self.entry = Some(id);
+            self.max_level = level;
+        }
+    }
+
+    fn greedy(
+        &self,
+        query: &[f32],
+        vectors: &[Vec<f32>],
+        mut current: usize,
+        level: usize,
+    ) -> usize {
+        let mut best = distance(query, &vectors[current]);
+        loop {
+            let mut improved = false;
+            for &n in &self.links[current][level] {
+                let d = distance(query, &vectors[n]);
+                if d < best {
+                    best = d;
+                    current = n;
+                    improved = true;
+                }
+            }
+            if !improved {
+                return current;
+            }
+        }
+    }
+
+    /// Best-first search of one layer; results sorted nearest first. Stops
+    /// early, with what it has, once `cancel` trips.
+    fn search_layer(
+        &self,
+        query: &[f32],
+        vectors: &[Vec<f32>],
+        entry: usize,
+        ef: usize,
+        level: usize,
+        cancel: Option<&CancelToken>,
+    ) -> Vec<Scored> {
+        let first = Scored(distance(query, &vectors[entry]), entry);
+        let mut visited = HashSet::from([entry]);
+        let mut candidates = BinaryHeap::from([std::cmp::Reverse(first)]);
+        let mut results = BinaryHeap::from([first]);
+
+        let mut expanded = 0;
+        while let Some(std::cmp::Reverse(current)) = candidates.pop() {
+            expanded += 1;
+            if expanded % CANCEL_CHECK_INTERVAL == 0
+                && cancel.is_some_and(CancelToken::is_cancelled)
+            {
+                break;
+            }
+            if results.len() >= ef && current.0 > results.peek().map_or(f32::MAX, |s| s.0) {
+                break;
+            }
+            for &n in &self.links[current.1][level] {
+                if !visited.insert(n) {
+                    continue;
+                }
+                let scored = Scored(distance(query, &vectors[n]), n);
+                if results.len() < ef || scored.0 < results.peek().map_or(f32::MAX, |s| s.0) {
+                    candidates.push(std::cmp::Reverse(scored));
+                    results.push(scored);
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+        results.into_sorted_vec()
+    }
+
+    fn search(
+        &self,
+        query: &[f32],
+        vectors: &[Vec<f32>],
+        k: usize,
+        ef: usize,
+        cancel: &CancelToken,
+    ) -> Vec<Scored> {
+        let Some(mut entry) = self.entry else {
+            return Vec::new();
+        };
+        for l in (1..=self.max_level).rev() {
+            entry = self.greedy(query, vectors, entry, l);
+        }
+        let mut nearest = self.search_layer(query, vectors, entry, ef, 0, Some(cancel));
+        nearest.truncate(k);
+        nearest
+    }
+}
@@ -0,0 +1,309 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::context::Context;
+use crate::handler;
+use crate::introspect::{open_index, tantivy_error};
+use crate::request::SearchRequest;
+use crate::shards::Shards;
+use crate::state::CancelToken;
+
+/// Distinct searches counted for warming.
+pub const DEFAULT_POPULAR_CAPACITY: usize = 1_000;
+
+/// Searches re-run after the index changes, by default.
+pub const DEFAULT_WARM_QUERIES: usize = 20;
+
+// how often the idle warmer looks for a stop
+const WARM_POLL: Duration = Duration::from_millis(100);
+
+struct Popular {
+    request: SearchRequest,
+    // the query as it arrived, for the warm state file
+    payload: String,
+    count: u64,
+}
+
+/// How often each recent search was asked, so the most popular ones can be
+/// re-run against a freshly reloaded index.
+pub struct PopularQueries {
+    capacity: usize,
+    queries: Mutex<HashMap<String, Popular>>,
+    // set when the index changed and the new readers are still cold
+    stale: Mutex<bool>,
+    changed: Condvar,
+}
+
+impl PopularQueries {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            queries: Mutex::new(HashMap::new()),
+            stale: Mutex::new(false),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Counts one run of `request`, identified by its cache key; `payload`
+    /// is the query as it arrived.
+    pub fn record(&self, key: &str, request: &SearchRequest, payload: &[u8]) {
+        let mut queries = self.queries();
+        if let Some(popular) = queries.get_mut(key) {
+            popular.count += 1;
+            return;
+        }
+        let popular = Popular {
+            request: request.clone(),
+            payload: String::from_utf8_lossy(payload).into_owned(),
+            count: 1,
+        };
+        self.insert(&mut queries, key, popular);
+    }
+
+    fn insert(&self, queries: &mut HashMap<String, Popular>, key: &str, popular: Popular) {
+        if self.capacity == 0 {
+            return;
+        }
+        if queries.len() >= self.capacity {
+            // make room by forgetting the least asked search
+            let least = queries
+                .iter()
+                .min_by_key(|(_, popular)| popular.count)
+                .map(|(key, _)| key.clone());
+            if let Some(least) = least {
+                queries.remove(&least);
+            }
+        }
+        queries.insert(key.to_string(), popular);
+    }
+
+    /// The `n` most asked searches, most asked first.
+    pub fn top(&self, n: usize) -> Vec<SearchRequest> {
+        let queries = self.queries();
+        let mut popular: Vec<&Popular> = queries.values().collect();
+        popular.sort_by_key(|popular| Reverse(popular.count));
+        popular
+            .into_iter()
+            .take(n)
+            .map(|popular| popular.request.clone())
+            .collect()
+    }
+
+    /// The `n` most asked query texts with how often each was asked, most
+    /// asked first. Searches for one text with different options count
+    /// together.
+    pub fn counts(&self, n: usize) -> Vec<(String, u64)> {
+        let mut counts: HashMap<&str, u64> = HashMap::new();
+        let queries = self.queries();
+        for popular in queries.values() {
+            *counts.entry(&popular.request.query).or_default() += popular.count;
+        }
+        let mut counts: Vec<(&str, u64)> = counts.into_iter().collect();
+        counts.sort_by_key(|&(query, count)| (Reverse(count), query));
+        counts
+            .into_iter()
+            .take(n)
+            .map(|(query, count)| (query.to_string(), count))
+            .collect()
+    }
+
+    /// Every counted search as it arrived, most asked first.
+    fn saved(&self) -> Vec<SavedQuery> {
+        let queries = self.queries();
+        let mut saved: Vec<SavedQuery> = queries
+            .values()
+            .map(|popular| SavedQuery {
+                payload: popular.payload.clone(),
+                count: popular.count,
+            })
+            .collect();
+        saved.sort_by_key(|saved| Reverse(saved.count));
+        saved
+    }
+
+    fn restore(&self, key: &str, request: SearchRequest, saved: SavedQuery) {
+        let popular = Popular {
+            request,
+            payload: saved.payload,
+            count: saved.count,
+        };
+        self.insert(&mut self.queries(), key, popular);
+    }
+
+    pub fn len(&self) -> usize {
+        self.queries().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queries().is_empty()
+    }
+
+    /// Notes that the index changed, waking the [`run`] warmer to re-run
+    /// the searches.
+    pub fn mark_stale(&self) {
+        *self.stale.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        self.changed.notify_all();
+    }
+
+    /// Waits up to `timeout` for the index to change; true, and no longer
+    /// stale, if it did.
+    fn wait_stale(&self, timeout: Duration) -> bool {
+        let stale = self.stale.lock().unwrap_or_else(|e| e.into_inner());
+        let (mut stale, _) = self
+            .changed
+            .wait_timeout_while(stale, timeout, |stale| !*stale)
+            .unwrap_or_else(|e| e.into_inner());
+        std::mem::take(&mut *stale)
+    }
+
+    fn queries(&self) -> MutexGuard<'_, HashMap<String, Popular>> {
+        self.queries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for PopularQueries {
+    fn default() -> Self {
+        Self::new(DEFAULT_POPULAR_CAPACITY)
+    }
+}
+
+/// Warms on its own thread until `stop` is set: whenever the index changes
+/// ([`PopularQueries::mark_stale`]), re-runs the most popular searches, so
+/// the engine's new readers and the negative cache are warm before traffic
+/// reaches them, without holding up a worker. Replies go nowhere.
+pub fn run(context: &Context, stop: &AtomicBool) {
+    if context.warm_queries == 0 {
+        return;
+    }
+    while !stop.load(Ordering::Relaxed) {
+        if context.popular.wait_stale(WARM_POLL) {
+            warm(context);
+        }
+    }
+}
+
+fn warm(context: &Context) {
+    let start = Instant::now();
+    let requests = context.popular.top(context.warm_queries);
+    let cancel = CancelToken::default();
+    for request in &requests {
+        match handler::search_local(request, context, &cancel) {
+            Ok(hits) if hits.is_empty() => context.misses().record_miss(&request.cache_key()),
+            Ok(_) => {}
+            Err(e) => debug!(query = %request.query, error = %e, "warming search failed"),
+        }
+    }
+    info!(
+        queries = requests.len(),
+        elapsed_ms = start.elapsed().as_millis() as u64,
+        "caches warmed"
+    );
+}
+
+/// What survives a restart: the popular searches, and the known misses of
+/// the index generation they were seen on.
+#[derive(Serialize, Deserialize)]
+struct WarmState {
+    /// Last commit opstamp of every shard when the state was saved.
+    generation: Vec<u64>,
+    queries: Vec<SavedQuery>,
+    misses: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedQuery {
+    payload: String,
+    count: u64,
+}
+
+/// Saves the popular searches and known misses to the configured warm state
+/// file, for [`restore`] on the next start.
+pub fn save(context: &Context) {
+    let Some(path) = &context.warm_state else {
+        return;
+    };
+    // a crash mid-write must not leave a torn file behind
+    let tmp = path.with_extension("tmp");
+    let saved = generation(&context.shards).and_then(|generation| {
+        let state = WarmState {
+            generation,
+            queries: context.popular.saved(),
+            misses: context.misses().queries().map(str::to_string).collect(),
+        };
+        fs::write(&tmp, serde_json::to_vec(&state)?)?;
+        fs::rename(&tmp, path)
+    });
+    match saved {
+        Ok(()) => info!(path = %path.display(), "warm state saved"),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            warn!(path = %path.display(), error = %e, "warm state not saved");
+        }
+    }
+}
+
+/// Loads the warm state saved by [`save`] and hands its searches to the
+/// [`run`] warmer. Saved misses are dropped if the index was committed to
+/// since; the searches themselves are always re-run. A missing, truncated or
+/// corrupt file just means a cold start; only failing to read it is an error.
+pub fn restore(context: &Context) -> io::Result<()> {
+    let Some(path) = &context.warm_state else {
+        return Ok(());
+    };
+    let state: WarmState = match fs::read(path) {
+        Ok(bytes) => match serde_json::from_slice(&bytes) {
+            Ok(state) => state,
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "warm state unreadable, starting cold");
+                return Ok(());
+            }
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    let current = state.generation == generation(&context.shards)?;
+    if current {
+        let mut misses = context.misses();
+        for query in &state.misses {
+            misses.record_miss(query);
+        }
+    }
+    for saved in state.queries {
+        let Some(mut request) = SearchRequest::parse(saved.payload.as_bytes()) else {
+            continue;
+        };
+        request.limit = request.limit.min(context.max_limit);
+        context
+            .popular
+            .restore(&request.cache_key(), request, saved);
+    }
+    info!(
+        path = %path.display(),
+        queries = context.popular.len(),
+        misses_kept = current,
+        "warm state restored"
+    );
+    context.popular.mark_stale();
+    Ok(())
+}
+
+fn generation(shards: &Shards) -> io::Result<Vec<u64>> {
+    shards
+        .iter()
+        .map(|shard| {
+            let metas = open_index(&shard.path)?
+                .load_metas()
+                .map_err(tantivy_error)?;
+            Ok(metas.opstamp)
+        })
+        .collect()
+}
@@ -0,0 +1,133 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nerve_protocol::frame::OwnedFrame;
+use serde::Deserialize;
+use tracing::error;
+
+use crate::context::Context;
+
+// most in-flight request ids a stall report lists
+const REPORTED_REQUESTS: usize = 32;
+
+/// `[watchdog]` section: noticing a frame loop that stopped making
+/// progress.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    /// A connection's loop busy this long without getting back to waiting
+    /// for the core is reported as stalled; off when unset.
+    pub stall_secs: Option<u64>,
+    /// Abort the process after reporting a stall, for a supervisor to
+    /// restart it.
+    pub abort: bool,
+}
+
+impl WatchdogConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.stall_secs == Some(0) {
+            return Err("watchdog.stall_secs must be at least 1".into());
+        }
+        if self.abort && self.stall_secs.is_none() {
+            return Err("watchdog.abort needs watchdog.stall_secs".into());
+        }
+        Ok(())
+    }
+}
+
+/// Where one connection's frame loop is: waiting for the core, or busy
+/// since when, and the last frame it took.
+#[derive(Debug, Default)]
+pub struct Progress {
+    // milliseconds since the Unix epoch the loop woke up; 0 while waiting
+    busy_since: AtomicU64,
+    // the last frame's request id, and its message type + 1 (0 for none)
+    last_request_id: AtomicU64,
+    last_msg_type: AtomicU64,
+}
+
+impl Progress {
+    /// Notes the loop woken up to work.
+    pub fn busy(&self) {
+        self.busy_since.store(now_ms().max(1), Ordering::Relaxed);
+    }
+
+    /// Notes the loop back to waiting for the core.
+    pub fn idle(&self) {
+        self.busy_since.store(0, Ordering::Relaxed);
+    }
+
+    /// Notes the frames the loop took.
+    pub fn took(&self, frames: &[OwnedFrame]) {
+        if let Some(frame) = frames.last() {
+            self.last_request_id
+                .store(frame.header.request_id, Ordering::Relaxed);
+            self.last_msg_type
+                .store(u64::from(frame.header.msg_type) + 1, Ordering::Relaxed);
+        }
+    }
+
+    /// When the loop woke up (Unix milliseconds), if it hasn't gone back to
+    /// waiting since.
+    pub fn busy_since(&self) -> Option<u64> {
+        Some(self.busy_since.load(Ordering::Relaxed)).filter(|&since| since > 0)
+    }
+
+    /// The request id and message type of the last frame taken.
+    pub fn last_frame(&self) -> Option<(u64, u8)> {
+        let msg_type = self.last_msg_type.load(Ordering::Relaxed);
+        let request_id = self.last_request_id.load(Ordering::Relaxed);
+        (msg_type > 0).then(|| (request_id, (msg_type - 1) as u8))
+    }
+}
+
+/// Checks every connection's [`Progress`] until `stop` is set, reporting
+/// each loop busy past `stall_secs` once per stall: how long, the queries
+/// waiting or running, the connection's unanswered requests and the last
+/// frame it took. With `abort` set, the process aborts after the report.
+pub fn run(config: WatchdogConfig, context: &Context, stop: &AtomicBool) {
+    let Some(stall) = config.stall_secs.map(Duration::from_secs) else {
+        return;
+    };
+    let check = (stall / 4).clamp(Duration::from_millis(100), Duration::from_secs(1));
+    let mut reported = vec![0; context.connections.len()];
+    while !stop.load(Ordering::Relaxed) {
+        thread::sleep(check);
+        let now = now_ms();
+        for (slot, reported) in reported.iter_mut().enumerate() {
+            let health = context.connections.slot(slot);
+            let Some(since) = health.progress().busy_since() else {
+                continue;
+            };
+            let stalled = Duration::from_millis(now.saturating_sub(since));
+            if stalled < stall || *reported == since {
+                continue;
+            }
+            *reported = since;
+            let unanswered = health.unanswered();
+            let last_frame = health.progress().last_frame();
+            error!(
+                connection = slot,
+                stalled_ms = stalled.as_millis() as u64,
+                queue_depth = context.counters.snapshot().queue_depth,
+                unanswered = unanswered.len(),
+                requests = ?unanswered.iter().take(REPORTED_REQUESTS).map(|id| id.0).collect::<Vec<_>>(),
+                last_request_id = last_frame.map(|(id, _)| id),
+                last_msg_type = last_frame.map(|(_, msg_type)| msg_type),
+                "frame loop stalled"
+            );
+            if config.abort {
+                error!("aborting for a restart");
+                std::process::abort();
+            }
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
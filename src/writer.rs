@@ -0,0 +1,300 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tantivy::{Index, IndexWriter, TantivyDocument, Term};
+use tracing::{info, warn};
+
+use crate::directory::{self, IndexAccess};
+use crate::introspect::tantivy_error;
+
+pub const DEFAULT_WRITER_HEAP_BYTES: usize = 50_000_000;
+/// tantivy refuses writers with a smaller heap than this.
+pub const MIN_WRITER_HEAP_BYTES: usize = 15_000_000;
+/// tantivy's per-thread arena is addressed with 32 bits.
+pub const MAX_WRITER_HEAP_BYTES: usize = u32::MAX as usize - 1_000_000;
+pub const DEFAULT_COMMIT_EVERY_DOCS: usize = 1_000;
+pub const DEFAULT_COMMIT_INTERVAL_MS: u64 = 1_000;
+
+/// Indexing threads tantivy splits the writer heap across: one per core, at
+/// most 8.
+pub fn writer_threads() -> usize {
+    thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(8)
+}
+
+/// When buffered writes are committed without an explicit `commit` op.
+///
+/// A write that brings the buffer to `every_docs` operations commits inline;
+/// a background thread commits whatever is pending once `interval_ms` has
+/// passed since the last commit. Either trigger can be disabled with 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct CommitPolicy {
+    pub every_docs: usize,
+    pub interval_ms: u64,
+}
+
+impl Default for CommitPolicy {
+    fn default() -> Self {
+        Self {
+            every_docs: DEFAULT_COMMIT_EVERY_DOCS,
+            interval_ms: DEFAULT_COMMIT_INTERVAL_MS,
+        }
+    }
+}
+
+/// Acknowledgement returned for every write operation.
+#[derive(Debug, Clone, Serialize)]
+pub struct WriteAck {
+    pub op: &'static str,
+    pub opstamp: u64,
+    /// Whether the write is already visible to searches.
+    pub committed: bool,
+    /// Buffered operations not yet committed.
+    pub pending: usize,
+}
+
+struct OpenWriter {
+    index: Index,
+    writer: IndexWriter,
+    pending: usize,
+    last_commit: Instant,
+}
+
+impl OpenWriter {
+    fn commit(&mut self) -> io::Result<u64> {
+        let opstamp = self.writer.commit().map_err(tantivy_error)?;
+        self.pending = 0;
+        self.last_commit = Instant::now();
+        Ok(opstamp)
+    }
+}
+
+/// The adapter's tantivy writer for the primary index.
+///
+/// Opened lazily on the first write, since tantivy allows a single writer per
+/// index and read-only deployments should never take the lock. Writes are
+/// buffered and become searchable on commit (see [`CommitPolicy`]).
+pub struct DocumentWriter {
+    index_path: PathBuf,
+    heap_bytes: usize,
+    access: IndexAccess,
+    policy: CommitPolicy,
+    open: Arc<Mutex<Option<OpenWriter>>>,
+}
+
+impl DocumentWriter {
+    pub fn new(index_path: impl Into<PathBuf>) -> Self {
+        Self::with_policy(index_path, CommitPolicy::default())
+    }
+
+    pub fn with_policy(index_path: impl Into<PathBuf>, policy: CommitPolicy) -> Self {
+        Self {
+            index_path: index_path.into(),
+            heap_bytes: DEFAULT_WRITER_HEAP_BYTES,
+            access: IndexAccess::default(),
+            policy,
+            open: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Sets the memory tantivy's writer buffers documents in before it
+    /// flushes a segment; split across its indexing threads.
+    pub fn with_heap_bytes(mut self, heap_bytes: usize) -> Self {
+        self.heap_bytes = heap_bytes;
+        self
+    }
+
+    /// Sets how the writer reads the segments it merges and deletes from.
+    pub fn with_access(mut self, access: IndexAccess) -> Self {
+        self.access = access;
+        self
+    }
+
+    fn writer(&self) -> io::Result<MutexGuard<'_, Option<OpenWriter>>> {
+        let mut open = self.open.lock().unwrap_or_else(|e| e.into_inner());
+        if open.is_none() {
+            let index = directory::open_index(&self.index_path, self.access)?;
+            let writer = index.writer(self.heap_bytes).map_err(tantivy_error)?;
+            info!(index = %self.index_path.display(), "index writer opened");
+            *open = Some(OpenWriter {
+                index,
+                writer,
+                pending: 0,
+                last_commit: Instant::now(),
+            });
+            if self.policy.interval_ms > 0 {
+                spawn_committer(
+                    Arc::downgrade(&self.open),
+                    Duration::from_millis(self.policy.interval_ms),
+                );
+            }
+        }
+        Ok(open)
+    }
+
+    /// Runs one buffered write, then applies the doc-count commit trigger.
+    fn write(
+        &self,
+        op: &'static str,
+        apply: impl FnOnce(&mut OpenWriter) -> io::Result<u64>,
+    ) -> io::Result<WriteAck> {
+        let mut guard = self.writer()?;
+        let open = guard.as_mut().expect("writer opened above");
+        let mut opstamp = apply(open)?;
+        open.pending += 1;
+
+        let committed = self.policy.every_docs > 0 && open.pending >= self.policy.every_docs;
+        if committed {
+            opstamp = open.commit()?;
+        }
+        Ok(WriteAck {
+            op,
+            opstamp,
+            committed,
+            pending: open.pending,
+        })
+    }
+
+    /// Adds one document, given as a JSON object keyed by schema field name.
+    pub fn index_document(&self, doc: Map<String, Value>) -> io::Result<WriteAck> {
+        self.write("index_document", |open| {
+            let doc = parse_document(&open.index, doc)?;
+            open.writer.add_document(doc).map_err(tantivy_error)
+        })
+    }
+
+    /// Removes every document whose `url` field holds exactly `url`.
+    ///
+    /// Matching is on the raw indexed term, so it relies on `url` being
+    /// indexed untokenized (a `STRING` field), as the crawler schema does.
+    pub fn delete_document(&self, url: &str) -> io::Result<WriteAck> {
+        self.write("delete_document", |open| {
+            let term = url_term(&open.index, url)?;
+            Ok(open.writer.delete_term(term))
+        })
+    }
+
+    /// Replaces the document with the same `url` (or adds it if absent).
+    pub fn update_document(&self, doc: Map<String, Value>) -> io::Result<WriteAck> {
+        let url = match doc.get("url") {
+            Some(Value::String(url)) => url.clone(),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "document has no url",
+                ));
+            }
+        };
+        self.write("update_document", |open| {
+            let term = url_term(&open.index, &url)?;
+            let doc = parse_document(&open.index, doc)?;
+            open.writer.delete_term(term);
+            open.writer.add_document(doc).map_err(tantivy_error)
+        })
+    }
+
+    /// Makes every buffered write searchable.
+    pub fn commit(&self) -> io::Result<WriteAck> {
+        let mut guard = self.writer()?;
+        let open = guard.as_mut().expect("writer opened above");
+        let opstamp = open.commit()?;
+        Ok(WriteAck {
+            op: "commit",
+            opstamp,
+            committed: true,
+            pending: 0,
+        })
+    }
+
+    /// Merges the smallest committed segments until at most `max_segments`
+    /// remain, then removes the files of the merged-away segments.
+    ///
+    /// Only committed segments take part; buffered writes are left alone.
+    pub fn merge_segments(&self, max_segments: usize) -> io::Result<()> {
+        let max_segments = max_segments.max(1);
+        let mut guard = self.writer()?;
+        let open = guard.as_mut().expect("writer opened above");
+
+        let mut segments = open.index.load_metas().map_err(tantivy_error)?.segments;
+        if segments.len() <= max_segments {
+            return Ok(());
+        }
+        segments.sort_by_key(|segment| segment.max_doc());
+        let ids: Vec<_> = segments[..segments.len() - max_segments + 1]
+            .iter()
+            .map(|segment| segment.id())
+            .collect();
+        open.writer.merge(&ids).wait().map_err(tantivy_error)?;
+        open.writer
+            .garbage_collect_files()
+            .wait()
+            .map_err(tantivy_error)?;
+        Ok(())
+    }
+
+    /// Buffered operations not yet committed; 0 if the writer isn't open.
+    pub fn pending(&self) -> usize {
+        let open = self.open.lock().unwrap_or_else(|e| e.into_inner());
+        open.as_ref().map_or(0, |open| open.pending)
+    }
+}
+
+/// Commits pending writes once `interval` has passed since the last commit.
+/// Exits when the owning [`DocumentWriter`] is dropped.
+fn spawn_committer(open: Weak<Mutex<Option<OpenWriter>>>, interval: Duration) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(interval / 4);
+            let Some(open) = open.upgrade() else {
+                return;
+            };
+            let mut guard = open.lock().unwrap_or_else(|e| e.into_inner());
+            let Some(writer) = guard.as_mut() else {
+                continue;
+            };
+            if writer.pending == 0 || writer.last_commit.elapsed() < interval {
+                continue;
+            }
+            let pending = writer.pending;
+            match writer.commit() {
+                Ok(opstamp) => info!(opstamp, docs = pending, "auto-commit"),
+                Err(e) => warn!(error = %e, "auto-commit failed"),
+            }
+        }
+    });
+}
+
+fn url_term(index: &Index, url: &str) -> io::Result<Term> {
+    let field = index
+        .schema()
+        .get_field("url")
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "schema has no url field"))?;
+    Ok(Term::from_field_text(field, url))
+}
+
+/// Converts a JSON document into a tantivy document for `index`'s schema,
+/// refusing fields the schema doesn't know instead of silently dropping them.
+pub fn parse_document(index: &Index, doc: Map<String, Value>) -> io::Result<TantivyDocument> {
+    let schema = index.schema();
+    if let Some(unknown) = doc.keys().find(|name| schema.get_field(name).is_err()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown document field: {unknown}"),
+        ));
+    }
+    if !doc.contains_key("url") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "document has no url",
+        ));
+    }
+    TantivyDocument::from_json_object(&schema, doc)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
+}
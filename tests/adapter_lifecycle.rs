@@ -5,6 +5,9 @@ use std::thread;
 use std::time::Duration;
 
 use nerve_search_adapter::client;
+use nerve_search_adapter::config::{Config, DEFAULT_QUEUE_DEPTH};
+use nerve_search_adapter::heartbeat::HeartbeatConfig;
+use nerve_search_adapter::shards::ReaderReload;
 use crawler::search::SearchSchema;
 use tempfile::tempdir;
 use tantivy::{doc, Index};
@@ -69,25 +72,7 @@ impl CoreHarness {
     }
 }
 
-struct CwdGuard {
-    original: PathBuf,
-}
-
-impl CwdGuard {
-    fn set_new(path: &Path) -> std::io::Result<Self> {
-        let original = std::env::current_dir()?;
-        std::env::set_current_dir(path)?;
-        Ok(Self { original })
-    }
-}
-
-impl Drop for CwdGuard {
-    fn drop(&mut self) {
-        let _ = std::env::set_current_dir(&self.original);
-    }
-}
-
-fn create_search_index(root: &Path) {
+fn create_search_index(root: &Path) -> PathBuf {
     let index_path = root.join("search_index");
     std::fs::create_dir_all(&index_path).expect("create search_index dir");
     let schema = SearchSchema::build();
@@ -105,6 +90,7 @@ fn create_search_index(root: &Path) {
         ))
         .expect("add doc");
     writer.commit().expect("commit");
+    index_path
 }
 
 fn wait_for_socket(path: &Path) {
@@ -120,15 +106,14 @@ fn wait_for_socket(path: &Path) {
 #[test]
 fn adapter_errors_if_core_missing() {
     let tmp = tempdir().expect("tmpdir");
-    create_search_index(tmp.path());
-    let _cwd = CwdGuard::set_new(tmp.path()).expect("set cwd");
+    let index_path = create_search_index(tmp.path());
     let socket_path = tmp.path().join("nerve-missing.sock");
     // ensure no socket exists
     if socket_path.exists() {
         std::fs::remove_file(&socket_path).ok();
     }
 
-    let result = client::run(socket_path.to_str().unwrap());
+    let result = client::run(&Config::new(&socket_path, &index_path));
     assert!(result.is_err(), "adapter should fail when core is absent");
 }
 
@@ -136,16 +121,15 @@ fn adapter_errors_if_core_missing() {
 #[ignore]
 fn adapter_connects_when_core_available() {
     let tmp = tempdir().expect("tmpdir");
-    create_search_index(tmp.path());
-    let _cwd = CwdGuard::set_new(tmp.path()).expect("set cwd");
+    let index_path = create_search_index(tmp.path());
     let socket_path = tmp.path().join("nerve-core.sock");
     let core = CoreHarness::start(socket_path.clone());
 
     wait_for_socket(&socket_path);
 
     let adapter_handle = thread::spawn({
-        let path = socket_path.clone();
-        move || client::run(path.to_str().unwrap())
+        let config = Config::new(&socket_path, &index_path);
+        move || client::run(&config)
     });
 
     assert!(
@@ -163,16 +147,15 @@ fn adapter_connects_when_core_available() {
 #[ignore]
 fn adapter_exits_when_core_shuts_down() {
     let tmp = tempdir().expect("tmpdir");
-    create_search_index(tmp.path());
-    let _cwd = CwdGuard::set_new(tmp.path()).expect("set cwd");
+    let index_path = create_search_index(tmp.path());
     let socket_path = tmp.path().join("nerve-core-shutdown.sock");
     let core = CoreHarness::start(socket_path.clone());
 
     wait_for_socket(&socket_path);
 
     let adapter_handle = thread::spawn({
-        let path = socket_path.clone();
-        move || client::run(path.to_str().unwrap())
+        let config = Config::new(&socket_path, &index_path);
+        move || client::run(&config)
     });
 
     assert!(
@@ -185,3 +168,496 @@ fn adapter_exits_when_core_shuts_down() {
     assert!(adapter_result.is_ok(), "adapter should exit when core shuts down");
     core.join().expect("core join");
 }
+
+#[test]
+fn adapter_drops_core_that_stops_answering_pings() {
+    let tmp = tempdir().expect("tmpdir");
+    let index_path = create_search_index(tmp.path());
+    let socket_path = tmp.path().join("nerve-silent.sock");
+    let listener = UnixListener::bind(&socket_path).expect("bind");
+    // a core that takes the connection and never says anything
+    let core = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        std::io::copy(&mut stream, &mut std::io::sink())
+    });
+
+    let mut config = Config::new(&socket_path, &index_path);
+    config.heartbeat = HeartbeatConfig {
+        interval_ms: Some(20),
+        max_missed: 2,
+        ..HeartbeatConfig::default()
+    };
+    let result = client::run(&config);
+    let error = result.expect_err("silent core is given up on");
+    assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+    core.join().expect("core join").expect("core read");
+}
+
+#[test]
+fn adapter_serves_every_core() {
+    let tmp = tempdir().expect("tmpdir");
+    let index_path = create_search_index(tmp.path());
+    let sockets = [tmp.path().join("tenant-a.sock"), tmp.path().join("tenant-b.sock")];
+    // two cores that each take their connection and hang up
+    let cores: Vec<_> = sockets
+        .iter()
+        .map(|socket| {
+            let listener = UnixListener::bind(socket).expect("bind");
+            thread::spawn(move || drop(listener.accept().expect("accept")))
+        })
+        .collect();
+
+    let mut config = Config::new(&sockets[0], &index_path);
+    config.core_socket_paths.push(sockets[1].clone());
+    let result = client::run(&config);
+    assert!(result.is_ok(), "adapter exits once both cores hung up: {result:?}");
+    for core in cores {
+        core.join().expect("core join");
+    }
+}
+
+#[test]
+fn adapter_serves_a_core_over_tcp() {
+    let tmp = tempdir().expect("tmpdir");
+    let index_path = create_search_index(tmp.path());
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let address = listener.local_addr().expect("address");
+    let core = thread::spawn(move || drop(listener.accept().expect("accept")));
+
+    let config = Config::new(format!("tcp://{address}"), &index_path);
+    let result = client::run(&config);
+    assert!(result.is_ok(), "adapter exits once the TCP core hung up: {result:?}");
+    core.join().expect("core join");
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn adapter_serves_a_core_over_mutual_tls() {
+    use std::sync::Arc;
+
+    use nerve_protocol::codec::encode;
+    use nerve_protocol::constants::HEADER_SIZE;
+    use nerve_protocol::io::FrameReader;
+    use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+    use nerve_search_adapter::transport::TlsConfig;
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use rustls::server::WebPkiClientVerifier;
+    use rustls::{RootCertStore, ServerConfig, ServerConnection, StreamOwned};
+
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls");
+    let certs = |name: &str| -> Vec<CertificateDer<'static>> {
+        CertificateDer::pem_file_iter(fixtures.join(name))
+            .expect("pem")
+            .collect::<Result<_, _>>()
+            .expect("certs")
+    };
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut roots = RootCertStore::empty();
+    roots.add(certs("ca.pem").remove(0)).expect("ca");
+    let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .expect("verifier");
+    let server = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .expect("versions")
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(
+            certs("core.pem"),
+            PrivateKeyDer::from_pem_file(fixtures.join("core.key")).expect("key"),
+        )
+        .expect("server config");
+
+    let tmp = tempdir().expect("tmpdir");
+    let index_path = create_search_index(tmp.path());
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let address = listener.local_addr().expect("address");
+    // a core that pings the adapter over TLS and hangs up on the pong
+    let core = thread::spawn(move || {
+        let (sock, _) = listener.accept().expect("accept");
+        let conn = ServerConnection::new(Arc::new(server)).expect("server connection");
+        let mut stream = StreamOwned::new(conn, sock);
+        let ping = encode(MessageType::Ping, FrameFlags::FINAL, RequestId(9), b"tls").expect("encode");
+        std::io::Write::write_all(&mut stream, &ping).expect("write ping");
+        let mut pong = vec![0u8; HEADER_SIZE + 3];
+        std::io::Read::read_exact(&mut stream, &mut pong).expect("read pong");
+        let frames = FrameReader::new()
+            .read_from(&mut std::io::Cursor::new(pong))
+            .expect("decode pong");
+        stream.conn.send_close_notify();
+        let _ = stream.conn.complete_io(&mut stream.sock);
+        frames
+    });
+
+    let mut config = Config::new(format!("tls://localhost:{}", address.port()), &index_path);
+    config.tls = TlsConfig {
+        ca_path: Some(fixtures.join("ca.pem")),
+        cert_path: Some(fixtures.join("adapter.pem")),
+        key_path: Some(fixtures.join("adapter.key")),
+        server_name: None,
+    };
+    let result = client::run(&config);
+    assert!(result.is_ok(), "adapter exits once the TLS core hung up: {result:?}");
+    let frames = core.join().expect("core join");
+    assert_eq!(frames[0].header.msg_type, MessageType::Pong as u8);
+    assert_eq!(frames[0].payload, b"tls");
+}
+
+#[test]
+fn adapter_opens_every_configured_connection() {
+    let tmp = tempdir().expect("tmpdir");
+    let index_path = create_search_index(tmp.path());
+    let socket_path = tmp.path().join("nerve-multi.sock");
+    let listener = UnixListener::bind(&socket_path).expect("bind");
+    // a core that waits for both connections, then hangs up on them
+    let core = thread::spawn(move || {
+        let first = listener.accept().expect("first accept");
+        let second = listener.accept().expect("second accept");
+        drop((first, second));
+    });
+
+    let mut config = Config::new(&socket_path, &index_path);
+    config.connections.count = 2;
+    let result = client::run(&config);
+    assert!(result.is_ok(), "adapter exits once every connection is gone: {result:?}");
+    core.join().expect("core join");
+}
+
+#[test]
+fn adapter_errors_if_index_missing() {
+    let tmp = tempdir().expect("tmpdir");
+    let socket_path = tmp.path().join("nerve-core-noindex.sock");
+    let index_path = tmp.path().join("does-not-exist");
+
+    let result = client::run(&Config::new(&socket_path, &index_path));
+    let err = result.expect_err("adapter should refuse to start without an index");
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+}
+
+#[test]
+fn config_requires_explicit_index_path() {
+    let result = Config::from_args(vec!["--socket".to_string(), "/tmp/x.sock".to_string()]);
+    assert!(result.is_err(), "missing --index must be a startup error");
+}
+
+#[test]
+fn config_read_only_flag() {
+    let tmp = tempdir().expect("tmpdir");
+    let index = tmp.path().display().to_string();
+
+    let config = Config::from_args(vec!["--index".to_string(), index.clone()]).expect("config");
+    assert!(!config.read_only);
+    let config = Config::from_args(vec![
+        "--index".to_string(),
+        index,
+        "--read-only".to_string(),
+    ])
+    .expect("config");
+    assert!(config.read_only);
+}
+
+#[test]
+fn config_core_flag() {
+    let tmp = tempdir().expect("tmpdir");
+    let index = tmp.path().display().to_string();
+    let args = |cores: &[&str]| {
+        let mut args = vec!["--index".to_string(), index.clone()];
+        for core in cores {
+            args.extend(["--core".to_string(), core.to_string()]);
+        }
+        args
+    };
+
+    let config = Config::from_args(args(&["/run/tenant-a.sock", "/run/tenant-b.sock"]))
+        .expect("config");
+    assert_eq!(config.socket_paths().len(), 3);
+    assert_eq!(config.core_socket_paths[1], PathBuf::from("/run/tenant-b.sock"));
+    let result = Config::from_args(args(&["/run/tenant-a.sock", "/run/tenant-a.sock"]));
+    assert!(result.is_err(), "a core listed twice is a startup error");
+}
+
+#[test]
+fn config_workers_flag() {
+    let tmp = tempdir().expect("tmpdir");
+    let index = tmp.path().display().to_string();
+
+    let config = Config::from_args(vec![
+        "--index".to_string(),
+        index.clone(),
+        "--workers".to_string(),
+        "8".to_string(),
+    ])
+    .expect("config");
+    assert_eq!(config.workers, 8);
+    let zero = Config::from_args(vec![
+        "--index".to_string(),
+        index,
+        "--workers".to_string(),
+        "0".to_string(),
+    ]);
+    assert!(zero.is_err(), "a pool without workers can't serve queries");
+}
+
+#[test]
+fn config_profile_flag() {
+    let tmp = tempdir().expect("tmpdir");
+    let index = tmp.path().display().to_string();
+    let folded = tmp.path().join("adapter.folded");
+
+    let config = Config::from_args(vec![
+        "--index".to_string(),
+        index,
+        "--profile".to_string(),
+        folded.display().to_string(),
+    ])
+    .expect("config");
+    assert_eq!(config.profile.path, Some(folded));
+    assert_eq!(config.profile.sample_every, 1);
+}
+
+#[test]
+fn config_queue_depth_flag() {
+    let tmp = tempdir().expect("tmpdir");
+    let index = tmp.path().display().to_string();
+
+    let config = Config::from_args(vec!["--index".to_string(), index.clone()]).expect("config");
+    assert_eq!(config.queue_depth, DEFAULT_QUEUE_DEPTH);
+    let config = Config::from_args(vec![
+        "--index".to_string(),
+        index.clone(),
+        "--queue-depth".to_string(),
+        "2".to_string(),
+    ])
+    .expect("config");
+    assert_eq!(config.queue_depth, 2);
+    let zero = Config::from_args(vec![
+        "--index".to_string(),
+        index,
+        "--queue-depth".to_string(),
+        "0".to_string(),
+    ]);
+    assert!(zero.is_err());
+}
+
+#[test]
+fn config_max_in_flight_flag() {
+    let tmp = tempdir().expect("tmpdir");
+    let index = tmp.path().display().to_string();
+
+    let config = Config::from_args(vec!["--index".to_string(), index.clone()]).expect("config");
+    assert_eq!(config.max_in_flight_searches, None);
+    let config = Config::from_args(vec![
+        "--index".to_string(),
+        index.clone(),
+        "--max-in-flight".to_string(),
+        "3".to_string(),
+    ])
+    .expect("config");
+    assert_eq!(config.max_in_flight_searches, Some(3));
+    let zero = Config::from_args(vec![
+        "--index".to_string(),
+        index,
+        "--max-in-flight".to_string(),
+        "0".to_string(),
+    ]);
+    assert!(zero.is_err());
+}
+
+#[test]
+fn config_validates_writer_and_reload_settings() {
+    let tmp = tempdir().expect("tmpdir");
+    let file = tmp.path().join("adapter.toml");
+    let write = |extra: &str| {
+        std::fs::write(
+            &file,
+            format!("index_path = {:?}\n{extra}", tmp.path().display().to_string()),
+        )
+        .expect("write config");
+        Config::from_args(vec!["--config".to_string(), file.display().to_string()])
+    };
+
+    let config = write("").expect("defaults");
+    assert_eq!(config.reader_reload, ReaderReload::Engine);
+    let config = write("writer_heap_bytes = 100000000\nreader_reload = \"on_commit\"").expect("config");
+    assert_eq!(config.writer_heap_bytes, 100_000_000);
+    assert_eq!(config.reader_reload, ReaderReload::OnCommit);
+
+    assert!(write("writer_heap_bytes = 1000000").is_err(), "below tantivy's minimum");
+    assert!(write("reader_reload = \"manual\"").is_err(), "the engine's reader can't be stopped");
+}
+
+#[test]
+fn config_validates_auth_settings() {
+    let tmp = tempdir().expect("tmpdir");
+    let file = tmp.path().join("adapter.toml");
+    let write = |extra: &str| {
+        std::fs::write(
+            &file,
+            format!("index_path = {:?}\n{extra}", tmp.path().display().to_string()),
+        )
+        .expect("write config");
+        Config::from_args(vec!["--config".to_string(), file.display().to_string()])
+    };
+
+    let config = write("[auth]\ntoken = \"open sesame\"").expect("inline token");
+    assert_eq!(config.auth.token().expect("token").as_deref(), Some("open sesame"));
+    // SAFETY: no other test reads this variable
+    unsafe { std::env::set_var("NERVE_ADAPTER_TEST_TOKEN", "from env") };
+    let config = write("[auth]\ntoken_env = \"NERVE_ADAPTER_TEST_TOKEN\"").expect("env token");
+    assert_eq!(config.auth.token().expect("token").as_deref(), Some("from env"));
+
+    assert!(write("[auth]\ntoken_env = \"NERVE_ADAPTER_TEST_UNSET\"").is_err(), "unset variable");
+    assert!(write("[auth]\ntoken = \"\"").is_err(), "empty secret");
+    assert!(
+        write("[auth]\ntoken = \"a\"\ntoken_env = \"NERVE_ADAPTER_TEST_TOKEN\"").is_err(),
+        "two sources"
+    );
+    assert!(write("socket_path = \"-\"\n[auth]\ntoken = \"a\"").is_err(), "stdio has no handshake");
+}
+
+#[test]
+fn adapter_signs_and_checks_frames_once_macs_are_agreed() {
+    use nerve_protocol::codec::encode;
+    use nerve_protocol::constants::HEADER_SIZE;
+    use nerve_protocol::io::FrameReader;
+    use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+    use nerve_search_adapter::auth::{self, Secret};
+    use nerve_search_adapter::handshake::{
+        Auth, Capabilities, Capability, HELLO_REQUEST_ID, Hello,
+    };
+    use std::io::{Cursor, Read, Write};
+
+    let tmp = tempdir().expect("tmpdir");
+    let index_path = create_search_index(tmp.path());
+    let socket_path = tmp.path().join("signing-core.sock");
+    let listener = UnixListener::bind(&socket_path).expect("bind");
+    let core = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        // the adapter's HELLO: only the nonce varies
+        let offered = Capabilities::default()
+            .with(Capability::Streaming)
+            .with(Capability::FrameMac)
+            .with(Capability::GoingAway);
+        let mut hello = Hello::adapter(offered);
+        hello.auth = Some(Auth {
+            nonce: Some("0".repeat(2 * auth::NONCE_BYTES)),
+            proof: None,
+        });
+        let length = serde_json::to_vec(&serde_json::json!({ "hello": hello }))
+            .expect("json")
+            .len();
+        let mut frame = vec![0u8; HEADER_SIZE + length];
+        stream.read_exact(&mut frame).expect("read hello");
+        let hello = FrameReader::new()
+            .read_from(&mut Cursor::new(frame))
+            .expect("decode hello");
+        let envelope: serde_json::Value =
+            serde_json::from_slice(&hello[0].payload).expect("hello payload");
+        let nonce = envelope["hello"]["auth"]["nonce"]
+            .as_str()
+            .expect("challenge")
+            .to_string();
+
+        let secret = Secret::new(b"open sesame");
+        let answer = serde_json::json!({ "hello": {
+            "name": "nerve-core",
+            "version": "0.3.0",
+            "protocol_versions": [nerve_protocol::constants::VERSION],
+            "capabilities": ["frame_mac"],
+            "auth": { "proof": secret.prove(&nonce) },
+        }});
+        let answer = encode(
+            MessageType::Pong,
+            FrameFlags::FINAL,
+            HELLO_REQUEST_ID,
+            &serde_json::to_vec(&answer).expect("json"),
+        )
+        .expect("encode");
+        stream.write_all(&answer).expect("write answer");
+
+        let mac = secret.frame_mac(&nonce);
+        let ping = encode(
+            MessageType::Ping,
+            FrameFlags::FINAL,
+            RequestId(9),
+            b"signed",
+        )
+        .expect("encode");
+        stream
+            .write_all(&mac.sign(ping.into()))
+            .expect("write signed ping");
+        let forged = encode(
+            MessageType::Ping,
+            FrameFlags::FINAL,
+            RequestId(10),
+            b"forged",
+        )
+        .expect("encode");
+        stream.write_all(&forged).expect("write unsigned ping");
+        stream.shutdown(std::net::Shutdown::Write).expect("hang up");
+
+        let mut replies = Vec::new();
+        stream.read_to_end(&mut replies).expect("read replies");
+        let mut frames = FrameReader::new()
+            .read_from(&mut Cursor::new(replies))
+            .expect("decode replies");
+        assert!(
+            frames.iter_mut().all(|frame| mac.verify(frame)),
+            "every reply is signed"
+        );
+        frames
+    });
+
+    let mut config = Config::new(&socket_path, &index_path);
+    config.auth.token = Some("open sesame".to_string());
+    config.auth.frame_mac = true;
+    let result = client::run(&config);
+    assert!(
+        result.is_ok(),
+        "adapter exits once the core hung up: {result:?}"
+    );
+    let mut frames = core.join().expect("core join");
+    frames.sort_by_key(|frame| frame.header.request_id);
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].header.msg_type, MessageType::Pong as u8);
+    assert_eq!(frames[0].payload, b"signed");
+    assert_eq!(frames[1].header.msg_type, MessageType::Error as u8);
+    assert!(String::from_utf8_lossy(&frames[1].payload).contains("bad_mac"));
+}
+
+#[test]
+fn adapter_drops_a_core_that_stops_reading_replies() {
+    use nerve_protocol::codec::encode;
+    use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+    use std::io::Write;
+
+    let tmp = tempdir().expect("tmpdir");
+    let index_path = create_search_index(tmp.path());
+    let socket_path = tmp.path().join("stalled-core.sock");
+    let listener = UnixListener::bind(&socket_path).expect("bind");
+    let core = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        // far more PONGs than the socket buffers hold, and none read
+        let payload = vec![0u8; 64 * 1024];
+        for id in 0..64 {
+            let ping = encode(MessageType::Ping, FrameFlags::FINAL, RequestId(id), &payload)
+                .expect("encode");
+            if stream.write_all(&ping).is_err() {
+                break;
+            }
+        }
+        stream
+    });
+
+    let mut config = Config::new(&socket_path, &index_path);
+    config.write_timeout_ms = Some(200);
+    let result = client::run(&config);
+    assert_eq!(
+        result.expect_err("dropped").kind(),
+        std::io::ErrorKind::TimedOut
+    );
+    drop(core.join().expect("core join"));
+
+    config.write_timeout_ms = Some(0);
+    assert!(config.validate().is_err(), "a zero timeout fails every reply");
+}
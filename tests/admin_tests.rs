@@ -0,0 +1,112 @@
+use std::path::Path;
+
+use tantivy::schema::{STORED, STRING, Schema};
+use tantivy::{Index, doc};
+use tempfile::tempdir;
+
+use nerve_search_adapter::admin;
+use nerve_search_adapter::introspect;
+use nerve_search_adapter::request::Request;
+use nerve_search_adapter::writer::DocumentWriter;
+
+fn create_index(path: &Path, commits: usize) {
+    let mut builder = Schema::builder();
+    let url = builder.add_text_field("url", STRING | STORED);
+    let index = Index::create_in_dir(path, builder.build()).expect("create index");
+    let mut writer = index.writer(15_000_000).expect("writer");
+    for i in 0..commits {
+        writer
+            .add_document(doc!(url => format!("https://example.com/{i}")))
+            .expect("add doc");
+        writer.commit().expect("commit");
+    }
+}
+
+#[test]
+fn snapshot_copies_committed_index() {
+    let tmp = tempdir().expect("tmpdir");
+    let index_path = tmp.path().join("index");
+    std::fs::create_dir(&index_path).expect("mkdir");
+    create_index(&index_path, 3);
+    let target = tmp.path().join("backup");
+
+    let mut updates = Vec::new();
+    let report =
+        admin::snapshot(&index_path, &target, |p| updates.push(p.clone())).expect("snapshot");
+
+    assert_eq!(report.segments, 3);
+    assert_eq!(updates.last().map(|p| p.files_done), Some(report.files));
+    let copy = introspect::shard_stats(&target).expect("open snapshot");
+    assert_eq!(copy.num_docs, 3);
+    assert_eq!(copy.opstamp, report.opstamp);
+}
+
+#[test]
+fn snapshot_refuses_non_empty_target() {
+    let tmp = tempdir().expect("tmpdir");
+    let index_path = tmp.path().join("index");
+    std::fs::create_dir(&index_path).expect("mkdir");
+    create_index(&index_path, 1);
+    let target = tmp.path().join("backup");
+    std::fs::create_dir(&target).expect("mkdir");
+    std::fs::write(target.join("junk"), b"x").expect("write");
+
+    assert!(admin::snapshot(&index_path, &target, |_| {}).is_err());
+}
+
+#[test]
+fn merge_reduces_segment_count() {
+    let tmp = tempdir().expect("tmpdir");
+    create_index(tmp.path(), 4);
+    let writer = DocumentWriter::new(tmp.path());
+
+    let report = admin::merge(&writer, tmp.path(), 2).expect("merge");
+
+    assert_eq!(report.before.segments, 4);
+    assert_eq!(report.after.segments, 2);
+    let stats = introspect::shard_stats(tmp.path()).expect("stats");
+    assert_eq!(stats.num_docs, 4);
+}
+
+#[test]
+fn merge_is_a_no_op_below_target() {
+    let tmp = tempdir().expect("tmpdir");
+    create_index(tmp.path(), 2);
+    let writer = DocumentWriter::new(tmp.path());
+
+    let report = admin::merge(&writer, tmp.path(), 5).expect("merge");
+
+    assert_eq!(report.before.segments, 2);
+    assert_eq!(report.after.segments, 2);
+}
+
+#[test]
+fn mutations_are_classified() {
+    let parse = |payload: &str| Request::parse(payload.as_bytes()).expect("parse");
+
+    assert!(parse(r#"{"op": "commit"}"#).is_mutation());
+    assert!(parse(r#"{"op": "merge", "max_segments": 2}"#).is_mutation());
+    assert!(parse(r#"{"op": "delete_document", "url": "u"}"#).is_mutation());
+    assert!(!parse(r#"{"op": "snapshot", "target": "/tmp/x"}"#).is_mutation());
+    assert!(!parse(r#"{"op": "index_stats"}"#).is_mutation());
+    assert!(!parse("plain query").is_mutation());
+}
+
+#[test]
+fn indexing_payloads_route_like_parsed_requests() {
+    for payload in [
+        r#"{"op": "commit"}"#,
+        r#"{"op": "merge", "max_segments": 2}"#,
+        r#"{"op": "snapshot", "target": "/tmp/x"}"#,
+        r#"{"op": "index_stats"}"#,
+        r#"{"op": "search", "query": "q"}"#,
+        "plain query",
+    ] {
+        let parsed = Request::parse(payload.as_bytes()).expect("parse");
+        assert_eq!(
+            Request::is_indexing_payload(payload.as_bytes()),
+            parsed.is_indexing(),
+            "{payload}"
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+
+use nerve_search_adapter::analysis::{
+    AnalysisConfig, AnalyzerConfig, Analyzers, FieldAnalyzer, Language, StemmingConfig,
+    TokenizerKind, detect, validate_fields,
+};
+use nerve_search_adapter::request::SearchRequest;
+
+#[test]
+fn detects_script_and_stopword_languages() {
+    assert_eq!(detect("東京タワー 行き方"), Some(Language::Ja));
+    assert_eq!(detect("北京大学"), Some(Language::Zh));
+    assert_eq!(detect("서울 날씨"), Some(Language::Ko));
+    assert_eq!(detect("погода в москве"), Some(Language::Ru));
+    assert_eq!(detect("wie ist das Wetter"), Some(Language::De));
+    assert_eq!(detect("how to parse the config"), Some(Language::En));
+    assert_eq!(detect("rust tantivy"), None);
+}
+
+#[test]
+fn cjk_is_split_into_bigrams() {
+    let analyzers = Analyzers::default();
+    assert_eq!(
+        analyzers.analyze(Language::Zh, "北京大学"),
+        ["北京", "京大", "大学"]
+    );
+}
+
+#[test]
+fn query_is_untouched_without_a_language() {
+    let analyzers = Analyzers::default();
+    assert_eq!(
+        analyzers.rewrite_query(None, None, "Rust Adapter"),
+        "Rust Adapter"
+    );
+    assert_eq!(
+        analyzers.rewrite_query(Some(Language::En), None, "Rust, Adapter!"),
+        "rust adapter"
+    );
+}
+
+#[test]
+fn configured_analyzer_overrides_default() {
+    let config = AnalysisConfig {
+        default_language: Some(Language::De),
+        detect: false,
+        languages: HashMap::from([(
+            Language::De,
+            AnalyzerConfig {
+                tokenizer: TokenizerKind::Whitespace,
+                lowercase: true,
+                ascii_folding: true,
+            },
+        )]),
+        ..AnalysisConfig::default()
+    };
+    let analyzers = Analyzers::new(config);
+    assert_eq!(
+        analyzers.rewrite_query(None, None, "Müller-Lüdenscheidt"),
+        "muller-ludenscheidt"
+    );
+}
+
+#[test]
+fn language_hint_is_parsed_and_keys_the_cache() {
+    let hinted = SearchRequest::parse(br#"{"query": "bahn", "language": "de"}"#).expect("parse");
+    let plain = SearchRequest::parse(br#"{"query": "bahn"}"#).expect("parse");
+    assert_eq!(hinted.language, Some(Language::De));
+    assert_ne!(hinted.cache_key(), plain.cache_key());
+}
+
+fn stemming(fields: &[&str]) -> Analyzers {
+    Analyzers::new(AnalysisConfig {
+        stemming: StemmingConfig {
+            enabled: true,
+            language: Some(Language::En),
+            fields: fields.iter().map(|f| f.to_string()).collect(),
+        },
+        ..AnalysisConfig::default()
+    })
+}
+
+#[test]
+fn stemming_applies_to_whole_query_without_fields() {
+    let analyzers = stemming(&[]);
+    assert_eq!(
+        analyzers.rewrite_query(None, None, "running crawlers"),
+        "run crawler"
+    );
+}
+
+#[test]
+fn stemming_targets_configured_fields() {
+    let analyzers = stemming(&["content"]);
+    assert_eq!(
+        analyzers.rewrite_query(None, None, "running"),
+        r#"(running content:"run")"#
+    );
+}
+
+#[test]
+fn request_can_disable_stemming() {
+    let analyzers = stemming(&[]);
+    assert_eq!(
+        analyzers.rewrite_query(None, Some(false), "running crawlers"),
+        "running crawlers"
+    );
+    assert_eq!(
+        Analyzers::default().rewrite_query(Some(Language::En), Some(true), "running"),
+        "run"
+    );
+}
+
+fn index_with(tokenizer: &str) -> tantivy::schema::Schema {
+    use tantivy::schema::{Schema, TextFieldIndexing, TextOptions};
+
+    let mut builder = Schema::builder();
+    let indexing = TextFieldIndexing::default().set_tokenizer(tokenizer);
+    builder.add_text_field(
+        "content",
+        TextOptions::default().set_indexing_options(indexing),
+    );
+    builder.add_u64_field("pagerank", tantivy::schema::INDEXED);
+    builder.build()
+}
+
+fn fields(entries: &[(&str, FieldAnalyzer)]) -> AnalysisConfig {
+    AnalysisConfig {
+        fields: entries
+            .iter()
+            .map(|(name, analyzer)| (name.to_string(), *analyzer))
+            .collect(),
+        ..AnalysisConfig::default()
+    }
+}
+
+#[test]
+fn field_settings_must_match_the_index() {
+    let stemmed = FieldAnalyzer {
+        stemmer: Some(Language::En),
+        ..FieldAnalyzer::default()
+    };
+    let config = fields(&[("content", stemmed)]);
+    assert!(validate_fields(&config, &index_with("en_stem")).is_ok());
+
+    let error = validate_fields(&config, &index_with("default")).expect_err("mismatch");
+    assert!(error.to_string().contains("`default`"), "{error}");
+    assert!(validate_fields(&fields(&[("missing", stemmed)]), &index_with("en_stem")).is_err());
+    assert!(validate_fields(&fields(&[("pagerank", stemmed)]), &index_with("en_stem")).is_err());
+    assert!(validate_fields(&config, &index_with("lang_custom")).is_ok());
+}
+
+#[test]
+fn field_analyzers_shape_the_query() {
+    let analyzers = Analyzers::new(fields(&[
+        (
+            "body",
+            FieldAnalyzer {
+                stopwords: true,
+                stemmer: Some(Language::En),
+                ..FieldAnalyzer::default()
+            },
+        ),
+        (
+            "url",
+            FieldAnalyzer {
+                lowercase: false,
+                ..FieldAnalyzer::default()
+            },
+        ),
+    ]));
+    assert_eq!(
+        analyzers.rewrite_query(None, None, "The Crawlers"),
+        r#"(the url:"The") (crawlers body:"crawler" url:"Crawlers")"#
+    );
+    assert_eq!(
+        analyzers.rewrite_query(None, Some(false), "Crawlers"),
+        r#"(crawlers body:"crawlers" url:"Crawlers")"#
+    );
+}
@@ -0,0 +1,106 @@
+use std::fs;
+use std::time::Duration;
+
+use nerve_protocol::types::RequestId;
+use nerve_search_adapter::audit::{AuditConfig, AuditLog, Outcome, normalize};
+use nerve_search_adapter::filters::Filters;
+use nerve_search_adapter::slowlog::Trace;
+use serde_json::Value;
+use tempfile::tempdir;
+
+fn search(query: &str) -> Trace {
+    Trace {
+        op: "search",
+        query: Some(query.to_string()),
+        filters: Filters {
+            after: Some(1_700_000_000),
+            before: None,
+        },
+        hits: Some(3),
+        ..Trace::default()
+    }
+}
+
+#[test]
+fn every_request_is_appended_as_a_json_line() {
+    let tmp = tempdir().expect("tmpdir");
+    let path = tmp.path().join("audit.jsonl");
+    let config = AuditConfig {
+        path: Some(path.clone()),
+        ..AuditConfig::default()
+    };
+    let audit = AuditLog::open(&config).expect("open audit log");
+    audit.record(
+        RequestId(4),
+        &search("  Rust   Borrow Checker "),
+        Duration::from_micros(1500),
+        Outcome::Ok,
+    );
+    let commit = Trace {
+        op: "commit",
+        ..Trace::default()
+    };
+    audit.record(
+        RequestId(5),
+        &commit,
+        Duration::from_millis(2),
+        Outcome::Error,
+    );
+    // a frame that never parsed has nothing to audit
+    audit.record(
+        RequestId(6),
+        &Trace::default(),
+        Duration::ZERO,
+        Outcome::Error,
+    );
+
+    let written = fs::read_to_string(&path).expect("read audit log");
+    let lines: Vec<Value> = written
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("JSON line"))
+        .collect();
+    assert_eq!(lines.len(), 2, "{written}");
+    assert_eq!(lines[0]["request_id"], 4);
+    assert_eq!(lines[0]["op"], "search");
+    assert_eq!(lines[0]["query"], "rust borrow checker");
+    assert_eq!(lines[0]["filters"]["after"], 1_700_000_000);
+    assert_eq!(lines[0]["hits"], 3);
+    assert_eq!(lines[0]["latency_us"], 1500);
+    assert_eq!(lines[0]["outcome"], "ok");
+    assert!(lines[0]["ts_ms"].as_u64().is_some_and(|ts| ts > 0));
+    assert_eq!(lines[1]["op"], "commit");
+    assert_eq!(lines[1]["outcome"], "error");
+    assert!(lines[1].get("query").is_none() && lines[1].get("hits").is_none());
+}
+
+#[test]
+fn hashed_queries_keep_the_text_out_and_depend_on_the_salt() {
+    let config: AuditConfig = toml::from_str(r#"hash_salt = "pepper""#).expect("parse [audit]");
+    assert!(config.validate().is_err(), "a salt without hashing");
+
+    let tmp = tempdir().expect("tmpdir");
+    let hashed = |salt: Option<&str>| {
+        let config = AuditConfig {
+            path: Some(tmp.path().join("audit.jsonl")),
+            hash_queries: true,
+            hash_salt: salt.map(str::to_string),
+        };
+        assert!(config.validate().is_ok());
+        let audit = AuditLog::open(&config).expect("open audit log");
+        let entry = audit.entry(
+            RequestId(1),
+            &search("Rust  borrow checker"),
+            Duration::ZERO,
+            Outcome::Cancelled,
+        );
+        assert!(entry.get("query").is_none(), "{entry}");
+        assert_eq!(entry["outcome"], "cancelled");
+        entry["query_hash"]
+            .as_str()
+            .expect("query hash")
+            .to_string()
+    };
+    assert_eq!(hashed(None).len(), 16);
+    assert_ne!(hashed(None), hashed(Some("pepper")));
+    assert_eq!(normalize(" RUST\tborrow  checker"), "rust borrow checker");
+}
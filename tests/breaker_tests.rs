@@ -0,0 +1,89 @@
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use nerve_search_adapter::breaker::{BreakerConfig, Circuit, CircuitBreaker};
+
+fn failure() -> io::Result<()> {
+    Err(io::Error::other("segment checksum mismatch"))
+}
+
+fn breaker() -> CircuitBreaker {
+    CircuitBreaker::new(&BreakerConfig {
+        failure_threshold: Some(2),
+        cooldown_ms: 50,
+    })
+}
+
+#[test]
+fn breaker_settings_must_be_positive() {
+    assert!(BreakerConfig::default().validate().is_ok());
+    let config: BreakerConfig = toml::from_str("failure_threshold = 0").expect("parse [breaker]");
+    assert!(config.validate().is_err());
+    let config: BreakerConfig = toml::from_str("cooldown_ms = 0").expect("parse [breaker]");
+    assert!(config.validate().is_err());
+
+    // never opens unless asked to
+    let breaker = CircuitBreaker::default();
+    for _ in 0..100 {
+        breaker.admit().expect("closed").record(&failure());
+    }
+    assert_eq!(breaker.circuit(), Circuit::Closed { failures: 0 });
+}
+
+#[test]
+fn failures_in_a_row_open_the_circuit_until_a_probe_succeeds() {
+    let breaker = breaker();
+    breaker.admit().expect("closed").record(&failure());
+    // a success in between starts the count over
+    breaker.admit().expect("closed").record(&Ok(()));
+    breaker.admit().expect("closed").record(&failure());
+    // cancelled searches say nothing about the engine
+    let cancelled: io::Result<()> = Err(io::ErrorKind::Interrupted.into());
+    breaker.admit().expect("closed").record(&cancelled);
+    assert_eq!(breaker.circuit(), Circuit::Closed { failures: 1 });
+
+    let timed_out: io::Result<()> = Err(io::ErrorKind::TimedOut.into());
+    breaker.admit().expect("closed").record(&timed_out);
+    assert_eq!(breaker.circuit().as_str(), "open");
+    let wait = breaker.admit().err().expect("fast-failed");
+    assert!(wait <= Duration::from_millis(50), "{wait:?}");
+
+    thread::sleep(Duration::from_millis(60));
+    let probe = breaker.admit().expect("probe let through");
+    assert_eq!(breaker.circuit(), Circuit::HalfOpen { probing: true });
+    assert!(breaker.admit().is_err(), "one probe at a time");
+    probe.record(&failure());
+    assert_eq!(breaker.circuit().as_str(), "open");
+
+    thread::sleep(Duration::from_millis(60));
+    // a probe that never reports back lets the next search probe
+    drop(breaker.admit().expect("probe let through"));
+    assert_eq!(breaker.circuit(), Circuit::HalfOpen { probing: false });
+    breaker.admit().expect("probe let through").record(&Ok(()));
+    assert_eq!(breaker.circuit(), Circuit::Closed { failures: 0 });
+}
+
+#[test]
+fn bad_requests_leave_the_circuit_closed() {
+    let breaker = breaker();
+    for kind in [
+        io::ErrorKind::InvalidData,
+        io::ErrorKind::InvalidInput,
+        io::ErrorKind::Unsupported,
+    ] {
+        for _ in 0..5 {
+            let rejected: io::Result<()> = Err(kind.into());
+            breaker.admit().expect("closed").record(&rejected);
+        }
+    }
+    assert_eq!(breaker.circuit(), Circuit::Closed { failures: 0 });
+
+    // while a probe runs, a bad request frees it for the next search
+    breaker.admit().expect("closed").record(&failure());
+    breaker.admit().expect("closed").record(&failure());
+    thread::sleep(Duration::from_millis(60));
+    let rejected: io::Result<()> = Err(io::ErrorKind::InvalidData.into());
+    breaker.admit().expect("probe let through").record(&rejected);
+    assert_eq!(breaker.circuit(), Circuit::HalfOpen { probing: false });
+}
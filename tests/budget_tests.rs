@@ -0,0 +1,38 @@
+use std::io::{ErrorKind, Write};
+
+use nerve_search_adapter::budget::{MemoryBudget, value_bytes};
+use serde_json::json;
+
+#[test]
+fn unlimited_budget_accepts_everything() {
+    let hits = vec![json!({"url": "https://example.com", "score": 1.0}); 1000];
+    assert!(MemoryBudget::default().check_hits(&hits).is_ok());
+}
+
+#[test]
+fn hits_over_budget_are_out_of_memory() {
+    let hit = json!({"url": "https://example.com/rust", "title": "Rust", "score": 0.5});
+    let budget = MemoryBudget::new(Some(value_bytes(&hit) * 3));
+    assert!(budget.check_hits(&vec![hit.clone(); 3]).is_ok());
+
+    let err = budget.check_hits(&vec![hit; 4]).expect_err("over budget");
+    assert_eq!(err.kind(), ErrorKind::OutOfMemory);
+}
+
+#[test]
+fn value_bytes_counts_string_contents() {
+    let short = value_bytes(&json!({"title": "a"}));
+    let long = value_bytes(&json!({"title": "a".repeat(1000)}));
+    assert_eq!(long - short, 999);
+}
+
+#[test]
+fn writer_refuses_to_grow_past_the_budget() {
+    let mut buf = Vec::new();
+    let budget = MemoryBudget::new(Some(8));
+    let mut writer = budget.writer(&mut buf);
+    writer.write_all(b"12345678").expect("fits");
+    let err = writer.write_all(b"9").expect_err("over budget");
+    assert_eq!(err.kind(), ErrorKind::OutOfMemory);
+    assert_eq!(buf, b"12345678");
+}
@@ -0,0 +1,85 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use nerve_search_adapter::analysis::Language;
+use nerve_search_adapter::cache::{NegativeCache, QueryCache, QueryCacheStats, QueryKey};
+
+#[test]
+fn negative_cache_remembers_misses_within_ttl() {
+    let mut cache = NegativeCache::new(Duration::from_secs(60), 16);
+    assert!(!cache.is_miss("rust"));
+
+    cache.record_miss("rust");
+    assert!(cache.is_miss("rust"));
+    assert!(!cache.is_miss("tantivy"));
+}
+
+#[test]
+fn negative_cache_entries_expire() {
+    let mut cache = NegativeCache::new(Duration::from_millis(20), 16);
+    cache.record_miss("rust");
+    thread::sleep(Duration::from_millis(40));
+    assert!(!cache.is_miss("rust"));
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn negative_cache_is_cleared_on_invalidate() {
+    let mut cache = NegativeCache::default();
+    cache.record_miss("rust");
+    cache.record_miss("adapter");
+    cache.invalidate();
+    assert!(!cache.is_miss("rust"));
+    assert_eq!(cache.len(), 0);
+}
+
+#[test]
+fn negative_cache_respects_capacity() {
+    let mut cache = NegativeCache::new(Duration::from_secs(60), 2);
+    cache.record_miss("a");
+    cache.record_miss("b");
+    cache.record_miss("c");
+    assert_eq!(cache.len(), 2);
+    assert!(!cache.is_miss("c"));
+}
+
+#[test]
+fn query_cache_counts_hits_and_misses() {
+    let mut cache = QueryCache::new(16);
+    let key = QueryKey::new(Some(Language::De), None, "Häuser bauen");
+    assert!(cache.get(&key).is_none());
+
+    cache.insert(key.clone(), Arc::from("haus bau"));
+    assert_eq!(cache.get(&key).as_deref(), Some("haus bau"));
+    assert_eq!(
+        cache.stats(),
+        QueryCacheStats {
+            hits: 1,
+            misses: 1,
+            entries: 1
+        }
+    );
+}
+
+#[test]
+fn query_keys_ignore_whitespace_but_not_options() {
+    let key = QueryKey::new(None, None, "  rust   search ");
+    assert_eq!(key.text(), "rust search");
+    assert_eq!(key, QueryKey::new(None, None, "rust search"));
+    assert_ne!(key, QueryKey::new(None, Some(false), "rust search"));
+    assert_ne!(key, QueryKey::new(Some(Language::En), None, "rust search"));
+}
+
+#[test]
+fn query_cache_respects_capacity() {
+    let mut cache = QueryCache::new(2);
+    for text in ["a", "b", "c"] {
+        cache.insert(QueryKey::new(None, None, text), Arc::from(text));
+    }
+    assert_eq!(cache.stats().entries, 2);
+
+    let mut disabled = QueryCache::new(0);
+    disabled.insert(QueryKey::new(None, None, "a"), Arc::from("a"));
+    assert_eq!(disabled.stats().entries, 0);
+}
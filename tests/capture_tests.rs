@@ -0,0 +1,86 @@
+use std::fs::File;
+use std::io::Cursor;
+
+use nerve_protocol::codec::encode;
+use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+use tempfile::tempdir;
+
+use nerve_search_adapter::capture::{self, CAPTURE_MAGIC, Direction};
+use nerve_search_adapter::config::Config;
+use nerve_search_adapter::context::Context;
+use nerve_search_adapter::stdio;
+
+mod common;
+
+use common::create_search_index;
+
+fn serve_pings(config: &Config, ids: &[u64]) {
+    let context = Context::from_config(config).expect("context");
+    let mut input = Vec::new();
+    for &id in ids {
+        input.extend(
+            encode(MessageType::Ping, FrameFlags::FINAL, RequestId(id), b"tape").expect("encode"),
+        );
+    }
+    stdio::serve(config, &context, Cursor::new(input), &mut Vec::new()).expect("serve");
+}
+
+#[test]
+fn record_captures_both_directions_and_appends() {
+    let tmp = tempdir().expect("tmpdir");
+    let index = create_search_index(tmp.path());
+    let capture_path = tmp.path().join("frames.cap");
+    let config = Config::from_args(
+        [
+            "--index",
+            &index.to_string_lossy(),
+            "--stdio",
+            "--record",
+            &capture_path.to_string_lossy(),
+        ]
+        .map(String::from),
+    )
+    .expect("config");
+    assert_eq!(config.record_path.as_deref(), Some(capture_path.as_path()));
+
+    serve_pings(&config, &[7]);
+    let captured = capture::read(File::open(&capture_path).expect("open")).expect("read");
+    assert_eq!(captured.len(), 2, "{captured:?}");
+    let (ping, pong) = (&captured[0], &captured[1]);
+    assert_eq!(ping.direction, Direction::Inbound);
+    assert_eq!(ping.frame.header.msg_type, MessageType::Ping as u8);
+    assert_eq!(pong.direction, Direction::Outbound);
+    assert_eq!(pong.frame.header.msg_type, MessageType::Pong as u8);
+    for frame in [ping, pong] {
+        assert_eq!(frame.connection, 0);
+        assert_eq!(frame.frame.header.request_id, 7);
+        assert_eq!(frame.frame.payload, b"tape");
+    }
+    assert!(ping.at <= pong.at);
+
+    // a second run adds to the same capture
+    serve_pings(&config, &[8]);
+    let captured = capture::read(File::open(&capture_path).expect("open")).expect("read");
+    assert_eq!(captured.len(), 4);
+    assert_eq!(captured[3].frame.header.request_id, 8);
+    assert_eq!(captured[3].connection, 1, "runs keep their connections apart");
+}
+
+#[test]
+fn captures_cut_short_end_at_the_last_whole_frame() {
+    let tmp = tempdir().expect("tmpdir");
+    let index = create_search_index(tmp.path());
+    let capture_path = tmp.path().join("frames.cap");
+    let mut config = Config::new("-", &index);
+    config.record_path = Some(capture_path.clone());
+    serve_pings(&config, &[1, 2]);
+
+    let bytes = std::fs::read(&capture_path).expect("capture");
+    assert!(bytes.starts_with(CAPTURE_MAGIC));
+    let whole = capture::read(&bytes[..]).expect("read").len();
+    let cut = capture::read(&bytes[..bytes.len() - 2]).expect("read cut");
+    assert_eq!(cut.len(), whole - 1);
+
+    let not_a_capture = capture::read(&b"NERVE-NOPE"[..]).expect_err("bad magic");
+    assert_eq!(not_a_capture.kind(), std::io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,122 @@
+#![cfg(feature = "chaos")]
+
+use std::io::Cursor;
+
+use bytes::Bytes;
+use nerve_protocol::codec::encode;
+use nerve_protocol::constants::{MAGIC, VERSION};
+use nerve_protocol::frame::{FrameHeader, OwnedFrame};
+use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+use tempfile::tempdir;
+
+use nerve_search_adapter::chaos::{Chaos, ChaosConfig, Faults};
+use nerve_search_adapter::config::{Config, DEFAULT_MAX_PAYLOAD_BYTES};
+use nerve_search_adapter::context::Context;
+use nerve_search_adapter::framing::FrameDecoder;
+use nerve_search_adapter::stdio;
+
+mod common;
+
+use common::create_search_index;
+
+fn frames(count: u64) -> Vec<OwnedFrame> {
+    (1..=count)
+        .map(|id| OwnedFrame {
+            header: FrameHeader {
+                magic: MAGIC,
+                version: VERSION,
+                msg_type: MessageType::SearchQuery as u8,
+                flags: FrameFlags::FINAL.bits(),
+                request_id: id,
+                payload_length: 8,
+            },
+            payload: b"payloads".to_vec(),
+        })
+        .collect()
+}
+
+fn faulted(faults: &Faults) -> Vec<(u64, Vec<u8>)> {
+    let mut read = frames(64);
+    faults.inbound(&mut read);
+    read.into_iter()
+        .map(|frame| (frame.header.request_id, frame.payload))
+        .collect()
+}
+
+#[test]
+fn the_same_seed_meets_the_same_faults() {
+    let config = ChaosConfig {
+        seed: 7,
+        drop_rate: 0.2,
+        duplicate_rate: 0.2,
+        corrupt_rate: 0.2,
+        ..ChaosConfig::default()
+    };
+    let first = faulted(&Faults::new(config, 7));
+    assert_eq!(first, faulted(&Faults::new(config, 7)));
+    assert_ne!(first, faulted(&Faults::new(config, 8)));
+    assert_ne!(first.len(), 64, "some frames dropped or doubled");
+
+    // each connection gets its own, repeatable faults
+    let (one, two) = (Chaos::new(config), Chaos::new(config));
+    let first = faulted(&one.connection().expect("faults"));
+    assert_eq!(first, faulted(&two.connection().expect("faults")));
+    assert_ne!(first, faulted(&one.connection().expect("faults")));
+    assert!(Chaos::new(ChaosConfig::default()).connection().is_none());
+}
+
+#[test]
+fn certain_faults_always_happen() {
+    let every = |config: ChaosConfig| Faults::new(config, 1);
+    let dropped = every(ChaosConfig {
+        drop_rate: 1.0,
+        ..ChaosConfig::default()
+    });
+    assert!(faulted(&dropped).is_empty());
+    assert!(dropped.outbound(Bytes::from_static(b"frame")).is_empty());
+
+    let doubled = every(ChaosConfig {
+        duplicate_rate: 1.0,
+        ..ChaosConfig::default()
+    });
+    assert_eq!(faulted(&doubled).len(), 128);
+    assert_eq!(doubled.outbound(Bytes::from_static(b"frame")).len(), 2);
+
+    let corrupted = every(ChaosConfig {
+        corrupt_rate: 1.0,
+        ..ChaosConfig::default()
+    });
+    for (_, payload) in faulted(&corrupted) {
+        let flipped: u32 = payload
+            .iter()
+            .zip(b"payloads")
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum();
+        assert_eq!(flipped, 1);
+    }
+}
+
+#[test]
+fn chaos_reaches_the_frames_of_a_connection() {
+    let tmp = tempdir().expect("tmpdir");
+    let index_path = create_search_index(tmp.path());
+    let mut config = Config::new("-", &index_path);
+    config.chaos = ChaosConfig {
+        duplicate_rate: 1.0,
+        ..ChaosConfig::default()
+    };
+    config.validate().expect("valid chaos");
+    let context = Context::from_config(&config).expect("context");
+
+    let input = encode(MessageType::Ping, FrameFlags::FINAL, RequestId(5), b"twice").expect("ping");
+    let mut output = Vec::new();
+    stdio::serve(&config, &context, Cursor::new(input), &mut output).expect("serve");
+    let mut pongs = Vec::new();
+    FrameDecoder::new(DEFAULT_MAX_PAYLOAD_BYTES).decode(&output, &mut pongs, |_| {});
+    // the ping is read twice and each pong written twice
+    assert_eq!(pongs.len(), 4);
+    assert!(pongs.iter().all(|pong| pong.header.request_id == 5));
+
+    config.chaos.drop_rate = 1.5;
+    assert!(config.validate().is_err());
+}
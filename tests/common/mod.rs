@@ -0,0 +1,27 @@
+use std::path::{Path, PathBuf};
+
+use crawler::search::SearchSchema;
+use tantivy::{Index, doc};
+
+/// A one-document `search_index` under `root`, for tests that run the
+/// adapter against a real index.
+pub fn create_search_index(root: &Path) -> PathBuf {
+    let index_path = root.join("search_index");
+    std::fs::create_dir_all(&index_path).expect("create search_index dir");
+    let schema = SearchSchema::build();
+    let index = Index::create_in_dir(&index_path, schema.schema.clone()).expect("create index");
+    let mut writer = index.writer(50_000_000).expect("writer");
+    writer
+        .add_document(doc!(
+            schema.url_field => "https://example.com/",
+            schema.title_field => "adapter smoke",
+            schema.content_field => "search adapter test",
+            schema.domain_field => "example.com",
+            schema.quality_field => "0.5",
+            schema.pagerank_field => 0.1f64,
+            schema.tfidf_field => 0.1f64
+        ))
+        .expect("add doc");
+    writer.commit().expect("commit");
+    index_path
+}
@@ -0,0 +1,163 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use nerve_protocol::types::RequestId;
+use nerve_search_adapter::connections::{Balance, Connections, Routes};
+use nerve_search_adapter::state::RequestState;
+
+#[test]
+fn origin_balance_answers_where_the_query_came_in() {
+    let routes = Routes::new(Balance::Origin, 1, 2);
+    let _first = routes.open(0, "first");
+    let _second = routes.open(1, "second");
+    for id in 0..4 {
+        assert_eq!(routes.pick(RequestId(id), 1, &"second"), "second");
+    }
+}
+
+#[test]
+fn round_robin_takes_turns_over_open_connections() {
+    let routes = Routes::new(Balance::RoundRobin, 1, 3);
+    let _first = routes.open(0, "first");
+    let second = routes.open(1, "second");
+    let _third = routes.open(2, "third");
+    let picked: Vec<_> = (0..3)
+        .map(|_| routes.pick(RequestId(7), 0, &"first"))
+        .collect();
+    assert_eq!(picked, ["first", "second", "third"]);
+
+    // a closed connection is skipped
+    drop(second);
+    let picked: Vec<_> = (0..2)
+        .map(|_| routes.pick(RequestId(7), 0, &"first"))
+        .collect();
+    assert!(picked.contains(&"first") && picked.contains(&"third"));
+}
+
+#[test]
+fn request_id_balance_is_stable_per_request() {
+    let routes = Routes::new(Balance::RequestId, 1, 2);
+    let _first = routes.open(0, "first");
+    let _second = routes.open(1, "second");
+    assert_eq!(routes.pick(RequestId(4), 1, &"second"), "first");
+    assert_eq!(routes.pick(RequestId(5), 0, &"first"), "second");
+    assert_eq!(routes.pick(RequestId(5), 0, &"first"), "second");
+}
+
+#[test]
+fn replies_stay_with_the_core_that_sent_the_query() {
+    let routes = Routes::new(Balance::RoundRobin, 2, 2);
+    let _a = routes.open(0, "a0");
+    let _b0 = routes.open(2, "b0");
+    let _b1 = routes.open(3, "b1");
+    for _ in 0..4 {
+        assert_eq!(routes.pick(RequestId(1), 1, &"a1"), "a0");
+        assert_ne!(routes.pick(RequestId(1), 3, &"b1"), "a0");
+    }
+}
+
+#[test]
+fn replies_fall_back_to_their_origin_when_nothing_is_open() {
+    let routes = Routes::new(Balance::RoundRobin, 1, 2);
+    assert_eq!(routes.pick(RequestId(1), 0, &"origin"), "origin");
+}
+
+#[test]
+fn health_tracks_sessions_and_failures() {
+    let connections = Connections::new(2);
+    let health = connections.slot(1);
+    health.up();
+    assert_eq!(connections.connected(), 1);
+    health.down(&Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "pings unanswered",
+    )));
+    health.up();
+    health.down(&Ok(()));
+
+    let snapshot = connections.snapshot();
+    assert_eq!(snapshot.len(), 2);
+    assert_eq!(connections.connected(), 0);
+    assert_eq!(snapshot[0].sessions, 0);
+    assert_eq!(snapshot[1].sessions, 2);
+    assert_eq!(snapshot[1].failures, 1);
+    assert_eq!(snapshot[1].last_error.as_deref(), Some("pings unanswered"));
+}
+
+#[test]
+fn health_tells_why_connections_ended_and_what_they_carried() {
+    let connections = Connections::new(1);
+    let health = connections.slot(0);
+    assert_eq!(connections.snapshot()[0].connected_at_ms, None);
+    for ended in [
+        Err(io::Error::new(io::ErrorKind::TimedOut, "pings unanswered")),
+        Err(io::Error::from(io::ErrorKind::BrokenPipe)),
+        Err(io::Error::from(io::ErrorKind::ConnectionReset)),
+        Ok(()),
+    ] {
+        health.up();
+        // each connection's state counts into the slot's traffic
+        let traffic = RequestState::new().with_traffic(health.traffic());
+        traffic.traffic().read(40);
+        traffic.traffic().received(2);
+        traffic.traffic().wrote(25);
+        traffic.traffic().sent(1);
+        health.down(&ended);
+    }
+
+    let snapshot = &connections.snapshot()[0];
+    assert!(snapshot.connected_at_ms.is_some_and(|at| at > 0));
+    assert_eq!(snapshot.reconnects, 3);
+    assert_eq!(
+        snapshot
+            .disconnects
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .collect::<Vec<_>>(),
+        [("hung_up", 1), ("reset", 2), ("timed_out", 1)]
+    );
+    assert_eq!(
+        (
+            snapshot.bytes_in,
+            snapshot.frames_in,
+            snapshot.bytes_out,
+            snapshot.frames_out
+        ),
+        (160, 8, 100, 4)
+    );
+}
+
+#[test]
+fn each_core_gets_its_own_slots() {
+    let sockets = [PathBuf::from("/run/a.sock"), PathBuf::from("/run/b.sock")];
+    let connections = Connections::for_cores(&sockets, 2);
+    let snapshot = connections.snapshot();
+    let per_slot: Vec<_> = snapshot
+        .iter()
+        .map(|health| health.socket.clone())
+        .collect();
+    assert_eq!(
+        per_slot,
+        [&sockets[0], &sockets[0], &sockets[1], &sockets[1]].map(|s| Some(s.clone()))
+    );
+}
+
+#[test]
+fn requests_a_dropped_connection_left_unanswered_are_handed_on() {
+    let connections = Connections::new(1);
+    let health = connections.slot(0);
+    let state = Arc::new(RequestState::new());
+    let orphans = health.track(Arc::clone(&state));
+    for id in [3, 1, 2, 4] {
+        state.queue(RequestId(id));
+    }
+    state.settle(RequestId(2));
+    // cancelled requests expect no reply either
+    state.cancel(RequestId(4));
+    assert!(health.take_orphans().is_empty(), "connection still up");
+
+    drop(orphans);
+    assert_eq!(health.take_orphans(), vec![RequestId(1), RequestId(3)]);
+    assert!(health.take_orphans().is_empty(), "reported once");
+}
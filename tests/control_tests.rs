@@ -0,0 +1,119 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use nerve_protocol::types::RequestId;
+use serde_json::{Value, json};
+use tempfile::tempdir;
+
+use nerve_search_adapter::cache::QueryKey;
+use nerve_search_adapter::config::Config;
+use nerve_search_adapter::context::Context;
+use nerve_search_adapter::control::{self, ControlConfig, ControlSocket};
+use nerve_search_adapter::request::SearchRequest;
+use nerve_search_adapter::state::RequestState;
+
+mod common;
+
+use common::create_search_index;
+
+fn context(root: &Path) -> Context {
+    let index = create_search_index(root);
+    let config =
+        Config::from_args(["--index", &index.to_string_lossy()].map(String::from)).expect("config");
+    Context::from_config(&config).expect("context")
+}
+
+#[test]
+fn commands_report_and_act_on_the_adapter() {
+    let tmp = tempdir().expect("tmpdir");
+    let context = context(tmp.path());
+
+    let status = control::run(r#"{"command": "status"}"#, &context);
+    assert_eq!(status["ok"], true, "{status}");
+    assert_eq!(status["connected"], 0);
+    assert_eq!(status["shards"], 1);
+    assert!(status["uptime_ms"].is_u64());
+
+    context
+        .queries()
+        .insert(QueryKey::new(None, None, "rust"), Arc::from("rust"));
+    let flushed = control::run(r#"{"command": "flush_cache"}"#, &context);
+    assert_eq!(flushed["ok"], true, "{flushed}");
+    assert_eq!(flushed["query_cache_entries"], 1);
+    assert_eq!(context.queries().stats().entries, 0);
+
+    let reloaded = control::run(r#"{"command": "reload_index"}"#, &context);
+    assert_eq!(reloaded["ok"], true, "{reloaded}");
+
+    let health = context.connections.slot(0);
+    let state = Arc::new(RequestState::new());
+    let _orphans = health.track(Arc::clone(&state));
+    state.queue(RequestId(17));
+    let dump = control::run(r#"{"command": "dump_state"}"#, &context);
+    assert_eq!(dump["connections"][0]["unanswered"][0], 17, "{dump}");
+    assert_eq!(dump["shutting_down"], false);
+    state.settle(RequestId(17));
+
+    for query in ["rust", "tantivy", "rust"] {
+        let request = SearchRequest::text(query);
+        context
+            .popular
+            .record(&request.cache_key(), &request, query.as_bytes());
+        context.usage.search(&request);
+    }
+    let top = control::run(r#"{"command": "top_queries", "limit": 1}"#, &context);
+    assert_eq!(top["ok"], true, "{top}");
+    assert_eq!(top["popular"], json!([{"query": "rust", "count": 2}]));
+    assert_eq!(top["recent"][0]["query"], "rust");
+    assert_eq!(top["recent"].as_array().unwrap().len(), 1);
+
+    let level = control::run(r#"{"command": "set_log_level", "level": "loud"}"#, &context);
+    assert_eq!(level["ok"], false);
+    assert!(level["error"].as_str().unwrap().contains("loud"), "{level}");
+    let unknown = control::run(r#"{"command": "restart"}"#, &context);
+    assert_eq!(unknown["ok"], false);
+    assert!(
+        unknown["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("bad command"),
+        "{unknown}"
+    );
+}
+
+#[test]
+fn the_socket_answers_a_line_per_command_and_goes_away() {
+    let tmp = tempdir().expect("tmpdir");
+    let context = context(tmp.path());
+    let path = tmp.path().join("control.sock");
+    let config = ControlConfig {
+        socket: Some(path.clone()),
+    };
+    let socket = ControlSocket::bind(&config)
+        .expect("bind")
+        .expect("control socket");
+    let stopped = AtomicBool::new(false);
+
+    thread::scope(|s| {
+        s.spawn(|| socket.serve(&context, &stopped));
+
+        let mut stream = UnixStream::connect(&path).expect("connect");
+        stream
+            .write_all(b"{\"command\": \"status\"}\n\n{\"command\": \"flush_cache\"}\n")
+            .expect("send");
+        let mut replies = BufReader::new(stream).lines();
+        for expected in ["uptime_ms", "query_cache_entries"] {
+            let reply = replies.next().expect("a reply").expect("read");
+            let reply: Value = serde_json::from_str(&reply).expect("JSON reply");
+            assert_eq!(reply["ok"], true, "{reply}");
+            assert!(reply.get(expected).is_some(), "{reply}");
+        }
+        stopped.store(true, Ordering::Relaxed);
+    });
+    drop(socket);
+    assert!(!path.exists(), "socket file removed");
+}
@@ -0,0 +1,52 @@
+use serde_json::json;
+
+use nerve_search_adapter::dedup::{dedup, hit_simhash, simhash};
+use nerve_search_adapter::request::SearchRequest;
+
+const ARTICLE: &str = "The adapter forwards search queries from the core to the engine \
+    and streams ranked results back over the unix socket without touching the protocol";
+
+#[test]
+fn similar_texts_hash_close_together() {
+    let original = simhash(ARTICLE);
+    let repost = simhash(&format!("{ARTICLE} (reposted)"));
+    let other = simhash("completely unrelated recipe for sourdough bread with rye flour");
+
+    assert!((original ^ repost).count_ones() <= 8);
+    assert!((original ^ other).count_ones() > 8);
+    assert_eq!(simhash(""), 0);
+}
+
+#[test]
+fn stored_simhash_wins_over_text() {
+    assert_eq!(
+        hit_simhash(&json!({"simhash": 42, "content": ARTICLE})),
+        Some(42)
+    );
+    assert_eq!(hit_simhash(&json!({"simhash": ["ff"]})), Some(255));
+    assert_eq!(hit_simhash(&json!({"url": "no text"})), None);
+}
+
+#[test]
+fn dedup_keeps_best_ranked_copy() {
+    let hits = vec![
+        json!({"url": "a", "simhash": 0b1111}),
+        json!({"url": "mirror", "simhash": 0b1110}),
+        json!({"url": "b", "simhash": u64::MAX}),
+        json!({"url": "no text"}),
+    ];
+    let kept: Vec<_> = dedup(hits, 3)
+        .into_iter()
+        .map(|h| h["url"].clone())
+        .collect();
+    assert_eq!(kept, ["a", "b", "no text"]);
+}
+
+#[test]
+fn dedup_is_requested_per_query() {
+    let request = SearchRequest::parse(br#"{"query": "q", "dedup": true, "dedup_distance": 5}"#)
+        .expect("parse");
+    assert!(request.dedup);
+    assert_eq!(request.dedup_distance, 5);
+    assert!(!SearchRequest::parse(b"q").expect("parse").dedup);
+}
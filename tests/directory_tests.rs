@@ -0,0 +1,79 @@
+use tantivy::collector::Count;
+use tantivy::query::TermQuery;
+use tantivy::schema::{IndexRecordOption, STORED, Schema, TEXT};
+use tantivy::{Index, Term, doc};
+use tempfile::tempdir;
+
+use nerve_search_adapter::directory::{AccessMode, IndexAccess, MmapAdvice, open_index};
+
+#[test]
+fn buffered_and_mapped_reads_see_the_same_index() {
+    let tmp = tempdir().expect("tempdir");
+    let mut builder = Schema::builder();
+    let title = builder.add_text_field("title", TEXT | STORED);
+    let index = Index::create_in_dir(tmp.path(), builder.build()).expect("create index");
+    let mut writer = index.writer(15_000_000).expect("writer");
+    for i in 0..50 {
+        let text = if i % 5 == 0 { "rust adapter" } else { "search" };
+        writer.add_document(doc!(title => text)).expect("add doc");
+    }
+    writer.commit().expect("commit");
+
+    let query = TermQuery::new(
+        Term::from_field_text(title, "rust"),
+        IndexRecordOption::Basic,
+    );
+    let modes = [
+        IndexAccess::default(),
+        IndexAccess {
+            mode: AccessMode::Mmap,
+            advice: Some(MmapAdvice::Random),
+        },
+        IndexAccess {
+            mode: AccessMode::Buffered,
+            advice: None,
+        },
+    ];
+    for access in modes {
+        let index = open_index(tmp.path(), access).expect("open");
+        let searcher = index.reader().expect("reader").searcher();
+        assert_eq!(searcher.num_docs(), 50, "{access:?}");
+        assert_eq!(
+            searcher.search(&query, &Count).expect("search"),
+            10,
+            "{access:?}"
+        );
+    }
+}
+
+#[test]
+fn buffered_writer_can_commit() {
+    let tmp = tempdir().expect("tempdir");
+    let mut builder = Schema::builder();
+    let title = builder.add_text_field("title", TEXT | STORED);
+    Index::create_in_dir(tmp.path(), builder.build()).expect("create index");
+
+    let buffered = IndexAccess {
+        mode: AccessMode::Buffered,
+        advice: None,
+    };
+    let index = open_index(tmp.path(), buffered).expect("open");
+    let mut writer = index
+        .writer::<tantivy::TantivyDocument>(15_000_000)
+        .expect("writer");
+    writer.add_document(doc!(title => "rust")).expect("add doc");
+    writer.commit().expect("commit");
+
+    let reopened = open_index(tmp.path(), IndexAccess::default()).expect("open");
+    assert_eq!(reopened.reader().expect("reader").searcher().num_docs(), 1);
+}
+
+#[test]
+fn advice_needs_mapped_files() {
+    let access = IndexAccess {
+        mode: AccessMode::Buffered,
+        advice: Some(MmapAdvice::Sequential),
+    };
+    assert!(access.validate().is_err());
+    assert!(IndexAccess::default().validate().is_ok());
+}
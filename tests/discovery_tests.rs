@@ -0,0 +1,54 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use tempfile::tempdir;
+
+use nerve_search_adapter::config::{Config, DEFAULT_SOCKET_PATH};
+use nerve_search_adapter::discovery::{SocketSource, discover_with};
+
+fn env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<OsString> + 'a {
+    move |name| {
+        vars.iter()
+            .find(|(var, _)| *var == name)
+            .map(|(_, value)| OsString::from(value))
+    }
+}
+
+#[test]
+fn environment_then_runtime_dir_then_legacy_path() {
+    let anywhere = |_: &Path| true;
+    let vars = [
+        ("NERVE_SOCKET", "/srv/nerve/core.sock"),
+        ("XDG_RUNTIME_DIR", "/run/user/1000"),
+    ];
+    assert_eq!(
+        discover_with(env(&vars), anywhere),
+        (PathBuf::from("/srv/nerve/core.sock"), SocketSource::Env)
+    );
+    assert_eq!(
+        discover_with(env(&vars[1..]), anywhere),
+        (
+            PathBuf::from("/run/user/1000/nerve/core.sock"),
+            SocketSource::RuntimeDir
+        )
+    );
+    // a runtime dir without the core's socket in it isn't where the core is
+    assert_eq!(
+        discover_with(env(&vars[1..]), |_| false),
+        (PathBuf::from(DEFAULT_SOCKET_PATH), SocketSource::Legacy)
+    );
+    assert_eq!(
+        discover_with(env(&[("NERVE_SOCKET", "")]), anywhere),
+        (PathBuf::from(DEFAULT_SOCKET_PATH), SocketSource::Legacy)
+    );
+}
+
+#[test]
+fn socket_flag_wins_over_discovery() {
+    let dir = tempdir().expect("tempdir");
+    let index = dir.path().to_str().expect("utf-8 path");
+    let args = ["--socket", "/tmp/flagged.sock", "--index", index];
+    let config = Config::from_args(args.map(String::from)).expect("config");
+    assert_eq!(config.socket_path, Path::new("/tmp/flagged.sock"));
+    assert_eq!(config.socket_source, SocketSource::Flag);
+}
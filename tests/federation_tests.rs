@@ -0,0 +1,81 @@
+use std::io::Write;
+use std::os::unix::net::UnixListener;
+use std::thread;
+
+use nerve_protocol::codec::encode;
+use nerve_protocol::io::FrameReader;
+use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+use serde_json::json;
+use tempfile::tempdir;
+
+use nerve_search_adapter::federation::{self, Federation, PeerConfig};
+
+#[test]
+fn merge_dedupes_by_url_and_orders_by_score() {
+    let local = vec![
+        json!({"url": "https://a.example/", "score": 1.0}),
+        json!({"url": "https://b.example/", "score": 3.0}),
+    ];
+    let remote = vec![
+        json!({"url": "https://a.example/", "score": 9.0}),
+        json!({"url": "https://c.example/", "score": 2.0}),
+    ];
+
+    let merged = federation::merge(local, remote, 10);
+    let urls: Vec<_> = merged.iter().map(|h| h["url"].as_str().unwrap()).collect();
+    assert_eq!(urls, vec!["https://b.example/", "https://c.example/", "https://a.example/"]);
+}
+
+#[test]
+fn merge_truncates_to_limit() {
+    let local = vec![json!({"url": "a", "score": 1.0}), json!({"url": "b", "score": 2.0})];
+    let merged = federation::merge(local, vec![json!({"url": "c", "score": 3.0})], 2);
+    assert_eq!(merged.len(), 2);
+    assert_eq!(merged[0]["url"], "c");
+}
+
+#[test]
+fn federation_collects_peer_results() {
+    let tmp = tempdir().expect("tmpdir");
+    let socket_path = tmp.path().join("peer.sock");
+    let listener = UnixListener::bind(&socket_path).expect("bind peer");
+
+    let peer = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut reader = FrameReader::new();
+        let frames = reader.read_from(&mut stream).expect("read query");
+        let query = &frames[0];
+        assert_eq!(query.header.msg_type, MessageType::SearchQuery as u8);
+        assert_eq!(query.payload, b"rust");
+
+        let payload = serde_json::to_vec(&json!([{"url": "https://peer.example/", "score": 1.5}])).unwrap();
+        let reply = encode(
+            MessageType::SearchResult,
+            FrameFlags::FINAL,
+            RequestId(query.header.request_id),
+            &payload,
+        )
+        .expect("encode reply");
+        stream.write_all(&reply).expect("write reply");
+    });
+
+    let federation = Federation::new(vec![PeerConfig {
+        socket_path: socket_path.clone(),
+        timeout_ms: 1_000,
+    }]);
+    let hits = federation.search(RequestId(5), b"rust");
+    peer.join().expect("peer thread");
+
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0]["url"], "https://peer.example/");
+}
+
+#[test]
+fn federation_skips_unreachable_peers() {
+    let tmp = tempdir().expect("tmpdir");
+    let federation = Federation::new(vec![PeerConfig {
+        socket_path: tmp.path().join("nobody-home.sock"),
+        timeout_ms: 50,
+    }]);
+    assert!(federation.search(RequestId(1), b"rust").is_empty());
+}
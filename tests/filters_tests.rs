@@ -0,0 +1,59 @@
+use serde_json::json;
+
+use nerve_search_adapter::filters::{Filters, parse_date};
+use nerve_search_adapter::request::SearchRequest;
+
+#[test]
+fn parses_dates_and_timestamps() {
+    assert_eq!(parse_date("1970-01-01"), Some(0));
+    assert_eq!(parse_date("2024-03-01"), Some(1_709_251_200));
+    assert_eq!(parse_date("2024-03-01T12:30:00Z"), Some(1_709_296_200));
+    assert_eq!(parse_date("2024-03-01T12:30:00.250Z"), Some(1_709_296_200));
+    assert_eq!(parse_date("2024-13-01"), None);
+    assert_eq!(parse_date("yesterday"), None);
+}
+
+#[test]
+fn range_is_after_inclusive_before_exclusive() {
+    let filters = Filters {
+        after: Some(100),
+        before: Some(200),
+    };
+    assert!(filters.matches(&json!({"crawled_at": 100}), "crawled_at"));
+    assert!(!filters.matches(&json!({"crawled_at": 200}), "crawled_at"));
+    assert!(!filters.matches(&json!({"crawled_at": 99}), "crawled_at"));
+    assert!(!filters.matches(&json!({"url": "no date"}), "crawled_at"));
+    assert!(Filters::default().matches(&json!({"url": "no date"}), "crawled_at"));
+}
+
+#[test]
+fn stored_date_strings_are_compared() {
+    let filters = Filters {
+        after: parse_date("2024-01-01"),
+        before: None,
+    };
+    let hits = vec![
+        json!({"url": "old", "crawled_at": ["2023-06-01T00:00:00Z"]}),
+        json!({"url": "new", "crawled_at": "2024-06-01T00:00:00Z"}),
+    ];
+    let kept = filters.apply(hits, "crawled_at");
+    assert_eq!(kept.len(), 1);
+    assert_eq!(kept[0]["url"], "new");
+}
+
+#[test]
+fn request_filters_are_parsed() {
+    let request = SearchRequest::parse(
+        br#"{"query": "news", "filters": {"after": "2024-01-01", "before": 1735689600}}"#,
+    )
+    .expect("parse");
+    assert_eq!(request.filters.after, Some(1_704_067_200));
+    assert_eq!(request.filters.before, Some(1_735_689_600));
+    assert!(SearchRequest::parse(br#"{"query": "x", "filters": {"after": "soon"}}"#).is_none());
+    assert_ne!(
+        request.cache_key(),
+        SearchRequest::parse(br#"{"query": "news"}"#)
+            .expect("parse")
+            .cache_key()
+    );
+}
@@ -0,0 +1,133 @@
+use nerve_protocol::codec::encode;
+use nerve_protocol::constants::VERSION;
+use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+use nerve_search_adapter::framing::{Downgrade, FrameDecoder, Rejected, message_type};
+use nerve_search_adapter::handshake::OLDEST_PROTOCOL_VERSION;
+
+fn query(request_id: u64, payload: &[u8]) -> Vec<u8> {
+    encode(
+        MessageType::SearchQuery,
+        FrameFlags::FINAL,
+        RequestId(request_id),
+        payload,
+    )
+    .expect("encode")
+}
+
+#[test]
+fn oversized_frame_is_skipped_and_reported() {
+    let mut stream = query(1, b"rust");
+    stream.extend(query(2, &[b'x'; 1024]));
+    stream.extend(query(3, b"search"));
+
+    let mut decoder = FrameDecoder::new(64);
+    let mut frames = Vec::new();
+    let mut oversized = Vec::new();
+    decoder.decode(&stream, &mut frames, |rejected| oversized.push(rejected));
+
+    assert!(matches!(
+        oversized.as_slice(),
+        [Rejected::Oversized {
+            request_id: RequestId(2),
+            length: 1024,
+            limit: 64
+        }]
+    ));
+    let ids: Vec<_> = frames.iter().map(|f| f.header.request_id).collect();
+    assert_eq!(ids, vec![1, 3]);
+    assert_eq!(frames[1].payload, b"search");
+}
+
+#[test]
+fn frames_split_across_reads_are_reassembled() {
+    let mut stream = query(7, b"split across reads");
+    stream.extend(query(8, &[b'y'; 512]));
+
+    let mut decoder = FrameDecoder::new(256);
+    let mut frames = Vec::new();
+    let mut oversized = Vec::new();
+    // one byte at a time crosses every header and payload boundary
+    for byte in stream.chunks(1) {
+        decoder.decode(byte, &mut frames, |rejected| oversized.push(rejected));
+    }
+
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].payload, b"split across reads");
+    assert!(matches!(
+        oversized.as_slice(),
+        [Rejected::Oversized {
+            request_id: RequestId(8),
+            length: 512,
+            ..
+        }]
+    ));
+}
+
+#[test]
+fn garbage_between_frames_is_skipped_until_the_next_header() {
+    let mut stream = query(1, b"before");
+    stream.extend(b"\0\x01 not a frame \xff");
+    stream.extend(query(2, b"after"));
+    stream.extend(query(3, b"and on"));
+
+    let mut decoder = FrameDecoder::new(256);
+    let mut frames = Vec::new();
+    let mut rejected = Vec::new();
+    for chunk in stream.chunks(5) {
+        decoder.decode(chunk, &mut frames, |r| rejected.push(r));
+    }
+
+    assert!(rejected.is_empty(), "nothing to answer: {rejected:?}");
+    let ids: Vec<_> = frames.iter().map(|f| f.header.request_id).collect();
+    assert_eq!(ids, vec![1, 2, 3]);
+    assert_eq!(frames[1].payload, b"after");
+}
+
+#[test]
+fn frames_of_an_older_protocol_version_decode_as_current() {
+    assert!(Downgrade::to(VERSION).is_none());
+    let older = Downgrade::to(OLDEST_PROTOCOL_VERSION).expect("older version");
+    let current = query(4, b"older core");
+    let marked = older.apply(current.clone().into());
+    assert_ne!(&marked[..], &current[..]);
+
+    let mut frames = Vec::new();
+    let mut rejected = Vec::new();
+    FrameDecoder::new(64).decode(&marked, &mut frames, |r| rejected.push(r));
+    assert!(rejected.is_empty());
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].header.version, VERSION);
+    assert_eq!(frames[0].payload, b"older core");
+}
+
+#[test]
+fn frames_cut_short_by_their_packet_are_dropped() {
+    let whole = query(5, b"whole");
+    let cut = query(6, b"cut short");
+
+    let mut decoder = FrameDecoder::new(64).with_packets(true);
+    let mut frames = Vec::new();
+    let mut rejected = Vec::new();
+    decoder.decode(&cut[..cut.len() - 3], &mut frames, |r| rejected.push(r));
+    // the next packet starts a frame of its own, not the rest of the last
+    decoder.decode(&whole, &mut frames, |r| rejected.push(r));
+
+    assert!(matches!(
+        rejected.as_slice(),
+        [Rejected::Malformed {
+            request_id: Some(RequestId(6)),
+            ..
+        }]
+    ));
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].payload, b"whole");
+}
+
+#[test]
+fn message_type_is_read_off_the_header() {
+    for msg_type in [MessageType::Error, MessageType::SearchResult] {
+        let frame = encode(msg_type, FrameFlags::FINAL, RequestId(3), b"{}").expect("encode");
+        assert_eq!(message_type(&frame), Some(msg_type as u8));
+    }
+    assert_eq!(message_type(b"short"), None);
+}
@@ -1,4 +1,5 @@
-use std::io::Cursor;
+use std::io::{self, Cursor};
+use std::time::{Duration, Instant};
 
 use crawler::search::SearchSchema;
 use nerve_protocol::constants::{MAGIC, VERSION};
@@ -8,8 +9,17 @@ use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
 use tempfile::tempdir;
 use tantivy::{doc, Index};
 
-use nerve_search_adapter::handler::handle_search;
+use nerve_search_adapter::audit::{AuditConfig, AuditLog};
+use nerve_search_adapter::breaker::{BreakerConfig, Circuit, CircuitBreaker};
+use nerve_search_adapter::context::Context;
+use nerve_search_adapter::handshake::Capabilities;
+use nerve_search_adapter::handler::{
+    handle_queued, handle_search, handle_streaming, overloaded, OVERLOAD_RETRY_AFTER_MS,
+};
+use nerve_search_adapter::shards::Shards;
 use nerve_search_adapter::state::RequestState;
+use nerve_search_adapter::vector::{Embedder, VectorIndex};
+use nerve_search_adapter::writer::DocumentWriter;
 
 fn build_search_engine_with_sample() -> SearchEngineTestHarness {
     let dir = tempdir().expect("tempdir");
@@ -31,18 +41,19 @@ fn build_search_engine_with_sample() -> SearchEngineTestHarness {
     writer.commit().expect("commit");
 
     let engine = crawler::SearchEngine::new(dir.path()).expect("search engine");
-    SearchEngineTestHarness { _dir: dir, engine }
+    let context = Context::new(Shards::single(dir.path(), engine));
+    SearchEngineTestHarness { _dir: dir, context }
 }
 
 struct SearchEngineTestHarness {
     _dir: tempfile::TempDir,
-    engine: crawler::SearchEngine,
+    context: Context,
 }
 
 #[test]
 fn handle_search_returns_search_result_frame() {
     let harness = build_search_engine_with_sample();
-    let mut state = RequestState::new();
+    let state = RequestState::new();
 
     let payload = b"rust".to_vec();
     let header = FrameHeader {
@@ -55,7 +66,7 @@ fn handle_search_returns_search_result_frame() {
     };
     let frame = OwnedFrame { header, payload };
 
-    let bytes = handle_search(frame, &mut state, &harness.engine)
+    let bytes = handle_search(frame, &state, &harness.context)
         .expect("expected search reply bytes");
 
     let mut reader = FrameReader::new();
@@ -78,7 +89,7 @@ fn handle_search_returns_search_result_frame() {
 #[test]
 fn handle_search_is_suppressed_when_cancelled() {
     let harness = build_search_engine_with_sample();
-    let mut state = RequestState::new();
+    let state = RequestState::new();
 
     let request_id = RequestId(99);
     state.cancel(request_id);
@@ -94,6 +105,445 @@ fn handle_search_is_suppressed_when_cancelled() {
     };
     let frame = OwnedFrame { header, payload };
 
-    let bytes = handle_search(frame, &mut state, &harness.engine);
+    let bytes = handle_search(frame, &state, &harness.context);
     assert!(bytes.is_none(), "cancelled request must not emit output");
 }
+
+#[test]
+fn handle_search_answers_known_miss_with_empty_results() {
+    let harness = build_search_engine_with_sample();
+    let state = RequestState::new();
+    harness.context.misses().record_miss("nothing-matches-this");
+
+    let payload = b"nothing-matches-this".to_vec();
+    let header = FrameHeader {
+        magic: MAGIC,
+        version: VERSION,
+        msg_type: MessageType::SearchQuery as u8,
+        flags: FrameFlags::empty().bits(),
+        request_id: 7,
+        payload_length: payload.len() as u32,
+    };
+    let frame = OwnedFrame { header, payload };
+
+    let bytes = handle_search(frame, &state, &harness.context)
+        .expect("expected search reply bytes");
+
+    let mut reader = FrameReader::new();
+    let mut cursor = Cursor::new(bytes);
+    let frames = reader.read_from(&mut cursor).expect("decode frame");
+    assert_eq!(frames.len(), 1);
+
+    let json: serde_json::Value = serde_json::from_slice(&frames[0].payload).expect("json payload");
+    assert_eq!(json, serde_json::json!([]));
+}
+
+#[test]
+fn handle_streaming_emits_snapshot_progress_as_separate_frames() {
+    let harness = build_search_engine_with_sample();
+    let state = RequestState::new();
+    let target = tempdir().expect("tempdir");
+
+    let payload = serde_json::to_vec(&serde_json::json!({
+        "op": "snapshot",
+        "target": target.path().join("snap"),
+    }))
+    .unwrap();
+    let header = FrameHeader {
+        magic: MAGIC,
+        version: VERSION,
+        msg_type: MessageType::SearchQuery as u8,
+        flags: FrameFlags::empty().bits(),
+        request_id: 11,
+        payload_length: payload.len() as u32,
+    };
+    let frame = OwnedFrame { header, payload };
+
+    let mut replies = Vec::new();
+    handle_streaming(frame, &state, &harness.context, |reply| replies.push(reply));
+    assert!(replies.len() >= 2, "expected progress before the final reply");
+
+    // every emitted reply is whole frames, so writers can interleave replies
+    let mut reader = FrameReader::new();
+    let last = replies.len() - 1;
+    for (i, reply) in replies.into_iter().enumerate() {
+        let frames = reader.read_from(&mut Cursor::new(reply)).expect("decode frame");
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].header.request_id, 11);
+        let flags = FrameFlags::from_bits_truncate(frames[0].header.flags);
+        assert_eq!(flags.contains(FrameFlags::FINAL), i == last);
+    }
+}
+
+#[test]
+fn overloaded_reply_is_an_error_frame_with_retry_hint() {
+    let bytes = overloaded(RequestId(5)).expect("reply bytes");
+    let mut reader = FrameReader::new();
+    let frames = reader.read_from(&mut Cursor::new(bytes)).expect("decode frame");
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].header.msg_type, MessageType::Error as u8);
+    assert_eq!(frames[0].header.request_id, 5);
+
+    let json: serde_json::Value = serde_json::from_slice(&frames[0].payload).expect("json payload");
+    assert_eq!(json["code"], "overloaded");
+    assert_eq!(json["retry_after_ms"], OVERLOAD_RETRY_AFTER_MS);
+}
+
+#[test]
+fn handle_queued_answers_expired_request_with_timeout() {
+    let harness = build_search_engine_with_sample();
+    let state = RequestState::new();
+
+    let payload = br#"{"query": "rust", "timeout_ms": 10}"#.to_vec();
+    let header = FrameHeader {
+        magic: MAGIC,
+        version: VERSION,
+        msg_type: MessageType::SearchQuery as u8,
+        flags: FrameFlags::empty().bits(),
+        request_id: 13,
+        payload_length: payload.len() as u32,
+    };
+    let frame = OwnedFrame { header, payload };
+
+    // queued for longer than its deadline
+    let received = Instant::now() - Duration::from_millis(50);
+    let mut replies = Vec::new();
+    handle_queued(frame, received, &state, &harness.context, |reply| replies.push(reply));
+    assert_eq!(replies.len(), 1);
+
+    let mut reader = FrameReader::new();
+    let frames = reader.read_from(&mut Cursor::new(replies.remove(0))).expect("decode frame");
+    assert_eq!(frames[0].header.msg_type, MessageType::Error as u8);
+    let json: serde_json::Value = serde_json::from_slice(&frames[0].payload).expect("json payload");
+    assert_eq!(json["code"], "timeout");
+}
+
+#[test]
+fn handle_search_clamps_limit_and_marks_truncation() {
+    let mut harness = build_search_engine_with_sample();
+    harness.context.max_limit = 1;
+    let state = RequestState::new();
+
+    let payload = br#"{"query": "rust", "limit": 100000}"#.to_vec();
+    let header = FrameHeader {
+        magic: MAGIC,
+        version: VERSION,
+        msg_type: MessageType::SearchQuery as u8,
+        flags: FrameFlags::empty().bits(),
+        request_id: 17,
+        payload_length: payload.len() as u32,
+    };
+    let frame = OwnedFrame { header, payload };
+
+    let bytes = handle_search(frame, &state, &harness.context).expect("reply bytes");
+    let mut reader = FrameReader::new();
+    let frames = reader.read_from(&mut Cursor::new(bytes)).expect("decode frame");
+    let json: serde_json::Value = serde_json::from_slice(&frames[0].payload).expect("json payload");
+    assert_eq!(json["truncated"], true);
+    assert_eq!(json["limit"], 1);
+    assert_eq!(json["requested_limit"], 100000);
+    assert_eq!(json["hits"].as_array().map(Vec::len), Some(1));
+}
+
+#[test]
+fn handle_streaming_splits_large_search_replies_into_frames() {
+    let dir = tempdir().expect("tempdir");
+    let schema = SearchSchema::build();
+    let index = Index::create_in_dir(dir.path(), schema.schema.clone()).expect("index create");
+    let mut writer = index.writer(50_000_000).expect("writer");
+    let title = format!("rust export {}", "padding ".repeat(60));
+    for i in 0..500 {
+        writer
+            .add_document(doc!(
+                schema.url_field => format!("https://example.com/{i}"),
+                schema.title_field => title.clone(),
+                schema.content_field => "rust export",
+                schema.domain_field => "example.com",
+                schema.quality_field => "0.5",
+                schema.pagerank_field => 0.1f64,
+                schema.tfidf_field => 0.1f64
+            ))
+            .expect("add doc");
+    }
+    writer.commit().expect("commit");
+    let engine = crawler::SearchEngine::new(dir.path()).expect("search engine");
+    let context = Context::new(Shards::single(dir.path(), engine));
+    let state = RequestState::new();
+
+    let payload = br#"{"query": "rust", "limit": 500, "stream": true}"#.to_vec();
+    let header = FrameHeader {
+        magic: MAGIC,
+        version: VERSION,
+        msg_type: MessageType::SearchQuery as u8,
+        flags: FrameFlags::empty().bits(),
+        request_id: 19,
+        payload_length: payload.len() as u32,
+    };
+    let frame = OwnedFrame { header, payload };
+
+    let mut replies = Vec::new();
+    handle_streaming(frame, &state, &context, |reply| replies.push(reply));
+    assert!(replies.len() >= 2, "expected the hits split over several frames");
+
+    // each frame holds a JSON array of its own; only the last one is final
+    let mut reader = FrameReader::new();
+    let last = replies.len() - 1;
+    let mut hits = 0;
+    for (i, reply) in replies.into_iter().enumerate() {
+        let frames = reader.read_from(&mut Cursor::new(reply)).expect("decode frame");
+        let flags = FrameFlags::from_bits_truncate(frames[0].header.flags);
+        assert_eq!(flags.contains(FrameFlags::FINAL), i == last);
+        let chunk: Vec<serde_json::Value> =
+            serde_json::from_slice(&frames[0].payload).expect("json array");
+        hits += chunk.len();
+    }
+    assert_eq!(hits, 500);
+
+    // a core that didn't agree to streaming gets every hit in one frame
+    let state = RequestState::with_capabilities(Capabilities::default());
+    let payload = br#"{"query": "rust", "limit": 500, "stream": true}"#.to_vec();
+    let header = FrameHeader {
+        magic: MAGIC,
+        version: VERSION,
+        msg_type: MessageType::SearchQuery as u8,
+        flags: FrameFlags::empty().bits(),
+        request_id: 20,
+        payload_length: payload.len() as u32,
+    };
+    let mut replies = Vec::new();
+    handle_streaming(OwnedFrame { header, payload }, &state, &context, |reply| {
+        replies.push(reply)
+    });
+    assert_eq!(replies.len(), 1);
+}
+
+#[test]
+fn a_panicking_request_is_answered_with_internal_error() {
+    let harness = build_search_engine_with_sample();
+    let state = RequestState::new();
+
+    let payload = b"rust".to_vec();
+    let header = FrameHeader {
+        magic: MAGIC,
+        version: VERSION,
+        msg_type: MessageType::SearchQuery as u8,
+        flags: FrameFlags::empty().bits(),
+        request_id: 31,
+        payload_length: payload.len() as u32,
+    };
+    // the first reply blowing up stands in for a bug in the engine
+    let mut replies = Vec::new();
+    handle_streaming(OwnedFrame { header, payload }, &state, &harness.context, |reply| {
+        if replies.is_empty() {
+            replies.push(reply);
+            panic!("engine bug");
+        }
+        replies.push(reply);
+    });
+    assert_eq!(replies.len(), 2);
+
+    let mut reader = FrameReader::new();
+    let frames = reader
+        .read_from(&mut Cursor::new(replies.pop().unwrap()))
+        .expect("decode frame");
+    assert_eq!(frames[0].header.msg_type, MessageType::Error as u8);
+    assert_eq!(frames[0].header.request_id, 31);
+    let error: serde_json::Value = serde_json::from_slice(&frames[0].payload).expect("json");
+    assert_eq!(error["code"], "internal_error");
+
+    let counters = harness.context.counters.snapshot();
+    assert_eq!(counters.panics, 1);
+    assert!(counters.errors >= 1);
+}
+
+fn op_frame(request_id: u64, payload: serde_json::Value) -> OwnedFrame {
+    raw_frame(request_id, serde_json::to_vec(&payload).unwrap())
+}
+
+fn raw_frame(request_id: u64, payload: Vec<u8>) -> OwnedFrame {
+    let header = FrameHeader {
+        magic: MAGIC,
+        version: VERSION,
+        msg_type: MessageType::SearchQuery as u8,
+        flags: FrameFlags::empty().bits(),
+        request_id,
+        payload_length: payload.len() as u32,
+    };
+    OwnedFrame { header, payload }
+}
+
+/// The code of the FINAL ERROR frame `bytes` holds for `request_id`.
+fn error_code(bytes: bytes::Bytes, request_id: u64) -> String {
+    let mut reader = FrameReader::new();
+    let frames = reader.read_from(&mut Cursor::new(bytes)).expect("decode frame");
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].header.msg_type, MessageType::Error as u8);
+    assert_eq!(frames[0].header.request_id, request_id);
+    assert!(FrameFlags::from_bits_truncate(frames[0].header.flags).contains(FrameFlags::FINAL));
+    let error: serde_json::Value = serde_json::from_slice(&frames[0].payload).expect("json");
+    assert!(error["message"].as_str().is_some_and(|m| !m.is_empty()), "{error}");
+    error["code"].as_str().expect("code").to_string()
+}
+
+#[test]
+fn a_document_with_an_unknown_field_is_answered_with_an_error_frame() {
+    let harness = build_search_engine_with_sample();
+    let state = RequestState::new();
+
+    let write = serde_json::json!({"op": "index_document", "doc": {"url": "u", "bogus": 1}});
+    let reply = handle_search(op_frame(40, write), &state, &harness.context)
+        .expect("an error reply");
+    assert_eq!(error_code(reply, 40), "invalid_request");
+}
+
+#[test]
+fn writes_missing_their_document_or_url_are_answered_with_an_error_frame() {
+    let harness = build_search_engine_with_sample();
+    let state = RequestState::new();
+
+    let writes = [
+        serde_json::json!({"op": "index_document"}),
+        serde_json::json!({"op": "index_document", "doc": "not an object"}),
+        serde_json::json!({"op": "update_document"}),
+        serde_json::json!({"op": "delete_document"}),
+        serde_json::json!({"op": "delete_document", "url": 7}),
+    ];
+    for (id, write) in (43..).zip(writes) {
+        let reply = handle_search(op_frame(id, write), &state, &harness.context)
+            .expect("an error reply");
+        assert_eq!(error_code(reply, id), "invalid_request");
+    }
+}
+
+#[test]
+fn failed_updates_and_deletes_are_answered_with_an_error_frame() {
+    let mut harness = build_search_engine_with_sample();
+    let state = RequestState::new();
+
+    let update = serde_json::json!({"op": "update_document", "doc": {"title": "no url"}});
+    let reply = handle_search(op_frame(41, update), &state, &harness.context)
+        .expect("an error reply");
+    assert_eq!(error_code(reply, 41), "invalid_request");
+
+    // a directory holding no index: the writer can't open to delete from it
+    let empty = tempdir().expect("tempdir");
+    harness.context.writer = DocumentWriter::new(empty.path());
+    let delete = serde_json::json!({"op": "delete_document", "url": "u"});
+    let reply = handle_search(op_frame(42, delete), &state, &harness.context)
+        .expect("an error reply");
+    assert!(!error_code(reply, 42).is_empty());
+}
+
+#[test]
+fn a_failed_commit_is_answered_with_an_error_frame() {
+    let mut harness = build_search_engine_with_sample();
+    // a directory holding no index: the writer can't open, so nothing commits
+    let empty = tempdir().expect("tempdir");
+    harness.context.writer = DocumentWriter::new(empty.path());
+    let state = RequestState::new();
+
+    let commit = op_frame(50, serde_json::json!({"op": "commit"}));
+    let reply = handle_search(commit, &state, &harness.context).expect("an error reply");
+    assert!(!error_code(reply, 50).is_empty());
+}
+
+/// Embeds queries mentioning rust along the first axis, others the second.
+struct Axes;
+
+impl Embedder for Axes {
+    fn embed(&self, text: &str) -> io::Result<Vec<f32>> {
+        Ok(if text.contains("rust") { vec![1.0, 0.0] } else { vec![0.0, 1.0] })
+    }
+}
+
+#[test]
+fn text_vector_queries_are_embedded_or_answered_with_an_error_frame() {
+    let mut harness = build_search_engine_with_sample();
+    let entries = [("https://example.com/rust", [1.0, 0.0]), ("https://example.com/go", [0.0, 1.0])]
+        .map(|(url, vector)| {
+            let mut fields = serde_json::Map::new();
+            fields.insert("url".into(), url.into());
+            (fields, vector.to_vec())
+        });
+    harness.context.vectors = Some(VectorIndex::build(entries.to_vec()).expect("build"));
+    let state = RequestState::new();
+    let query = serde_json::json!({"mode": "vector", "query": "rust", "limit": 1});
+
+    let reply = handle_search(op_frame(60, query.clone()), &state, &harness.context)
+        .expect("an error reply");
+    assert_eq!(error_code(reply, 60), "unsupported");
+
+    let context = harness.context.with_embedder(Axes);
+    let bytes = handle_search(op_frame(61, query), &state, &context).expect("a search reply");
+    let frames = FrameReader::new().read_from(&mut Cursor::new(bytes)).expect("decode frame");
+    assert_eq!(frames[0].header.msg_type, MessageType::SearchResult as u8);
+    let hits: serde_json::Value = serde_json::from_slice(&frames[0].payload).expect("json");
+    assert_eq!(hits[0]["url"], "https://example.com/rust");
+}
+
+#[test]
+fn shard_timings_show_up_in_metrics() {
+    let harness = build_search_engine_with_sample();
+    let state = RequestState::new();
+    handle_search(op_frame(70, serde_json::json!({"query": "rust"})), &state, &harness.context);
+
+    let bytes = handle_search(op_frame(71, serde_json::json!({"op": "metrics"})), &state, &harness.context)
+        .expect("a metrics reply");
+    let frames = FrameReader::new().read_from(&mut Cursor::new(bytes)).expect("decode frame");
+    let metrics: serde_json::Value = serde_json::from_slice(&frames[0].payload).expect("json");
+    assert_eq!(metrics["shards"]["0"]["count"], 1, "{metrics}");
+}
+
+#[test]
+fn undecodable_requests_are_answered_with_an_error_frame_and_audited() {
+    let mut harness = build_search_engine_with_sample();
+    let tmp = tempdir().expect("tempdir");
+    let audit = tmp.path().join("audit.jsonl");
+    harness.context.audit = AuditLog::open(&AuditConfig {
+        path: Some(audit.clone()),
+        ..AuditConfig::default()
+    })
+    .expect("open audit log");
+    let state = RequestState::new();
+
+    let payloads: [Vec<u8>; 4] = [
+        br#"{"op": "no_such_op"}"#.to_vec(),
+        br#"{"op": "top_q"#.to_vec(),
+        vec![0xff, 0xfe, 0x00],
+        br#"{"op": "snapshot"}"#.to_vec(),
+    ];
+    for (id, payload) in (80..).zip(payloads) {
+        let reply = handle_search(raw_frame(id, payload), &state, &harness.context)
+            .expect("an error reply");
+        assert_eq!(error_code(reply, id), "invalid_request");
+    }
+
+    let lines = std::fs::read_to_string(&audit).expect("read audit log");
+    let outcomes: Vec<serde_json::Value> = lines
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).expect("json")["outcome"].clone())
+        .collect();
+    assert_eq!(outcomes, vec![serde_json::json!("error"); 4]);
+}
+
+#[test]
+fn a_client_sending_bad_queries_leaves_the_circuit_closed() {
+    let mut harness = build_search_engine_with_sample();
+    let mut fields = serde_json::Map::new();
+    fields.insert("url".into(), "https://example.com/rust".into());
+    harness.context.vectors = Some(VectorIndex::build(vec![(fields, vec![1.0, 0.0])]).expect("build"));
+    harness.context.breaker = CircuitBreaker::new(&BreakerConfig {
+        failure_threshold: Some(2),
+        ..BreakerConfig::default()
+    });
+    let state = RequestState::new();
+
+    // a vector of the wrong length, again and again
+    for id in 90..95 {
+        let query = serde_json::json!({"vector": [1.0, 0.0, 0.0]});
+        let reply = handle_search(op_frame(id, query), &state, &harness.context)
+            .expect("an error reply");
+        assert_eq!(error_code(reply, id), "invalid_request");
+    }
+    assert_eq!(harness.context.breaker.circuit(), Circuit::Closed { failures: 0 });
+}
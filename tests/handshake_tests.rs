@@ -0,0 +1,361 @@
+use std::io::{self, Cursor, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::thread;
+
+use nerve_protocol::codec::encode;
+use nerve_protocol::constants::{HEADER_SIZE, MAGIC, VERSION};
+use nerve_protocol::frame::{FrameHeader, OwnedFrame};
+use nerve_protocol::io::FrameReader;
+use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+use serde_json::json;
+
+use nerve_search_adapter::auth::{self, MAC_BYTES, Secret};
+use nerve_search_adapter::framing::{Downgrade, FrameDecoder};
+use nerve_search_adapter::handshake::{
+    self, Auth, Capabilities, Capability, HELLO_REQUEST_ID, HandshakeConfig, Hello,
+    OLDEST_PROTOCOL_VERSION, check_answer,
+};
+use nerve_search_adapter::transport::Stream;
+
+fn answer(msg_type: MessageType, payload: Vec<u8>) -> OwnedFrame {
+    OwnedFrame {
+        header: FrameHeader {
+            magic: MAGIC,
+            version: VERSION,
+            msg_type: msg_type as u8,
+            flags: FrameFlags::FINAL.bits(),
+            request_id: HELLO_REQUEST_ID.0,
+            payload_length: payload.len() as u32,
+        },
+        payload,
+    }
+}
+
+fn core_hello(protocol_versions: &[u8]) -> Vec<u8> {
+    serde_json::to_vec(&json!({
+        "hello": {
+            "name": "nerve-core",
+            "version": "0.3.0",
+            "protocol_versions": protocol_versions,
+            "capabilities": ["compression", "streaming", "telepathy"],
+        }
+    }))
+    .expect("hello json")
+}
+
+fn offered() -> Capabilities {
+    Capabilities::default()
+        .with(Capability::Streaming)
+        .with(Capability::VectorSearch)
+}
+
+fn enabled(timeout_ms: u64) -> HandshakeConfig {
+    HandshakeConfig {
+        enabled: true,
+        timeout_ms,
+    }
+}
+
+#[test]
+fn core_sharing_the_protocol_version_is_accepted() {
+    let core = check_answer(&answer(MessageType::Pong, core_hello(&[VERSION])))
+        .expect("compatible")
+        .expect("core introduced itself");
+    assert_eq!(core.name, "nerve-core");
+    assert_eq!(core.protocol_versions, vec![VERSION]);
+}
+
+#[test]
+fn echoed_hello_comes_from_a_core_without_the_handshake() {
+    let echo =
+        serde_json::to_vec(&json!({ "hello": Hello::adapter(offered()) })).expect("hello json");
+    assert_eq!(
+        check_answer(&answer(MessageType::Pong, echo)).expect("served as before"),
+        None
+    );
+}
+
+#[test]
+fn incompatible_or_refusing_cores_are_unsupported() {
+    let newer = check_answer(&answer(
+        MessageType::Pong,
+        core_hello(&[VERSION.wrapping_add(1)]),
+    ))
+    .expect_err("no shared version");
+    assert_eq!(newer.kind(), io::ErrorKind::Unsupported);
+
+    let refused = check_answer(&answer(MessageType::Error, b"{\"code\":\"bad\"}".to_vec()))
+        .expect_err("core said no");
+    assert_eq!(refused.kind(), io::ErrorKind::Unsupported);
+}
+
+#[test]
+fn frames_sent_before_the_answer_are_kept() {
+    let (adapter, mut core) = UnixStream::pair().expect("pair");
+    let mut adapter = Stream::from(adapter);
+    let query = encode(
+        MessageType::SearchQuery,
+        FrameFlags::FINAL,
+        RequestId(7),
+        b"rust",
+    )
+    .expect("encode");
+    core.write_all(&query).expect("write query");
+    let pong = encode(
+        MessageType::Pong,
+        FrameFlags::FINAL,
+        HELLO_REQUEST_ID,
+        &core_hello(&[VERSION]),
+    )
+    .expect("encode");
+    core.write_all(&pong).expect("write pong");
+
+    let mut decoder = FrameDecoder::new(1024);
+    let done = handshake::exchange(&mut adapter, enabled(1_000), offered(), None, &mut decoder)
+        .expect("handshake");
+    assert_eq!(done.core.expect("core hello").name, "nerve-core");
+    // only what both sides listed; names the adapter doesn't know are dropped
+    assert_eq!(
+        done.capabilities,
+        Capabilities::default().with(Capability::Streaming)
+    );
+    let early: Vec<_> = done.early.iter().map(|f| f.header.request_id).collect();
+    assert_eq!(early, vec![7]);
+}
+
+#[test]
+fn older_core_is_served_without_what_its_version_cannot_carry() {
+    let (adapter, mut core) = UnixStream::pair().expect("pair");
+    let mut adapter = Stream::from(adapter);
+    let older = Downgrade::to(OLDEST_PROTOCOL_VERSION).expect("older version");
+    let hello = serde_json::to_vec(&json!({
+        "hello": {
+            "name": "nerve-core",
+            "version": "0.2.0",
+            "protocol_versions": [OLDEST_PROTOCOL_VERSION],
+            "capabilities": ["streaming", "vector_search"],
+        }
+    }))
+    .expect("hello json");
+    let pong = encode(
+        MessageType::Pong,
+        FrameFlags::FINAL,
+        HELLO_REQUEST_ID,
+        &hello,
+    )
+    .expect("encode");
+    core.write_all(&older.apply(pong.into()))
+        .expect("write pong");
+
+    let mut decoder = FrameDecoder::new(1024);
+    let done = handshake::exchange(&mut adapter, enabled(1_000), offered(), None, &mut decoder)
+        .expect("handshake");
+    assert_eq!(done.protocol_version, OLDEST_PROTOCOL_VERSION);
+    // streaming replies need the STREAM flag, which the older version lacks
+    assert_eq!(
+        done.capabilities,
+        Capabilities::default().with(Capability::VectorSearch)
+    );
+}
+
+#[test]
+fn silent_core_times_out_and_disabled_handshake_sends_nothing() {
+    let (adapter, core) = UnixStream::pair().expect("pair");
+    let mut adapter = Stream::from(adapter);
+    let mut decoder = FrameDecoder::new(1024);
+    let done = handshake::exchange(
+        &mut adapter,
+        HandshakeConfig::default(),
+        offered(),
+        None,
+        &mut decoder,
+    )
+    .expect("handshake off");
+    assert!(done.core.is_none() && done.early.is_empty());
+    assert_eq!(done.capabilities, offered());
+
+    let silent = handshake::exchange(&mut adapter, enabled(20), offered(), None, &mut decoder)
+        .expect_err("no answer");
+    assert_eq!(silent.kind(), io::ErrorKind::TimedOut);
+    drop(core);
+}
+
+#[test]
+fn capabilities_round_trip_through_their_names() {
+    let set = Capabilities::from_names(&["vector_search", "batch_queries", "unknown"]);
+    assert!(set.contains(Capability::VectorSearch) && set.contains(Capability::BatchQueries));
+    assert!(!set.contains(Capability::Streaming));
+    assert_eq!(set.names(), vec!["batch_queries", "vector_search"]);
+    assert_eq!(
+        Capabilities::from_names(&Capabilities::all().names()),
+        Capabilities::all()
+    );
+}
+
+/// Plays a core that reads the adapter's HELLO and answers its challenge
+/// with `answer`, after a query that must only be served once it's proven.
+fn authenticating_core(
+    mut core: UnixStream,
+    capabilities: &[&str],
+    answer: impl FnOnce(&str) -> Option<String>,
+) {
+    // the HELLO's length is fixed: only the nonce varies
+    let mut hello = Hello::adapter(offered());
+    hello.auth = Some(Auth {
+        nonce: Some("0".repeat(2 * auth::NONCE_BYTES)),
+        proof: None,
+    });
+    let payload = serde_json::to_vec(&json!({ "hello": hello })).expect("hello json");
+    let mut frame = vec![0u8; HEADER_SIZE + payload.len()];
+    core.read_exact(&mut frame).expect("read hello");
+    let frames = FrameReader::new()
+        .read_from(&mut Cursor::new(frame))
+        .expect("decode hello");
+    let envelope: serde_json::Value =
+        serde_json::from_slice(&frames[0].payload).expect("hello payload");
+    let nonce = envelope["hello"]["auth"]["nonce"]
+        .as_str()
+        .expect("challenge");
+
+    let query = encode(
+        MessageType::SearchQuery,
+        FrameFlags::FINAL,
+        RequestId(7),
+        b"rust",
+    )
+    .expect("encode");
+    core.write_all(&query).expect("write query");
+    let mut hello = json!({
+        "name": "nerve-core",
+        "version": "0.3.0",
+        "protocol_versions": [VERSION],
+        "capabilities": capabilities,
+    });
+    if let Some(proof) = answer(nonce) {
+        hello["auth"] = json!({ "proof": proof });
+    }
+    let payload = serde_json::to_vec(&json!({ "hello": hello })).expect("hello json");
+    let pong = encode(
+        MessageType::Pong,
+        FrameFlags::FINAL,
+        HELLO_REQUEST_ID,
+        &payload,
+    )
+    .expect("encode");
+    core.write_all(&pong).expect("write pong");
+}
+
+fn authenticate(answer: fn(&str) -> Option<String>) -> io::Result<handshake::Handshake> {
+    authenticate_with(Secret::new(b"open sesame"), &[], answer)
+}
+
+fn authenticate_with(
+    secret: Secret,
+    capabilities: &'static [&'static str],
+    answer: fn(&str) -> Option<String>,
+) -> io::Result<handshake::Handshake> {
+    let (adapter, core) = UnixStream::pair().expect("pair");
+    let core = thread::spawn(move || authenticating_core(core, capabilities, answer));
+    // the secret turns the handshake on by itself
+    let done = handshake::exchange(
+        &mut Stream::from(adapter),
+        HandshakeConfig::default(),
+        offered(),
+        Some(&secret),
+        &mut FrameDecoder::new(1024),
+    );
+    core.join().expect("core join");
+    done
+}
+
+#[test]
+fn core_proving_the_shared_secret_is_served() {
+    let done = authenticate(|nonce| Some(Secret::new(b"open sesame").prove(nonce)))
+        .expect("authenticated");
+    assert_eq!(done.core.expect("core hello").name, "nerve-core");
+    let early: Vec<_> = done.early.iter().map(|f| f.header.request_id).collect();
+    assert_eq!(early, vec![7]);
+}
+
+#[test]
+fn core_without_the_secret_is_refused_before_its_queries() {
+    let wrong =
+        authenticate(|nonce| Some(Secret::new(b"guess").prove(nonce))).expect_err("wrong secret");
+    assert_eq!(wrong.kind(), io::ErrorKind::PermissionDenied);
+    let missing = authenticate(|_| None).expect_err("no proof");
+    assert_eq!(missing.kind(), io::ErrorKind::PermissionDenied);
+    let replayed = authenticate(|_| Some(Secret::new(b"open sesame").prove("stale")))
+        .expect_err("proof for another challenge");
+    assert_eq!(replayed.kind(), io::ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn proofs_are_bound_to_secret_and_challenge() {
+    let secret = Secret::new(b"open sesame");
+    let nonce = auth::nonce().expect("nonce");
+    assert_eq!(nonce.len(), 2 * auth::NONCE_BYTES);
+    assert_ne!(nonce, auth::nonce().expect("nonce"));
+    let proof = secret.prove(&nonce);
+    assert!(secret.verify(&nonce, &proof));
+    assert!(!secret.verify(&nonce, "not hex"));
+    assert!(!secret.verify(&nonce, &proof[..proof.len() - 2]));
+    assert!(!Secret::new(b"other").verify(&nonce, &proof));
+}
+
+#[test]
+fn frame_macs_are_agreed_or_the_core_is_refused() {
+    let prove = |nonce: &str| Some(Secret::new(b"open sesame").prove(nonce));
+    let signing = || Secret::new(b"open sesame").with_frame_mac();
+    let done = authenticate_with(signing(), &["frame_mac"], prove).expect("agreed");
+    assert!(done.frame_mac.is_some());
+
+    let downgraded = authenticate_with(signing(), &[], prove).expect_err("no frame MACs");
+    assert_eq!(downgraded.kind(), io::ErrorKind::PermissionDenied);
+
+    // not asked for, not used
+    let done = authenticate_with(Secret::new(b"open sesame"), &["frame_mac"], prove)
+        .expect("authenticated");
+    assert!(done.frame_mac.is_none());
+}
+
+#[test]
+fn signed_frames_verify_only_untouched_and_under_their_own_key() {
+    let secret = Secret::new(b"open sesame");
+    let mac = secret.frame_mac("first");
+    let frame = encode(
+        MessageType::SearchQuery,
+        FrameFlags::FINAL,
+        RequestId(7),
+        b"rust",
+    )
+    .expect("encode");
+    let signed = mac.sign(frame.into());
+    let decode = |bytes: &[u8]| {
+        FrameReader::new()
+            .read_from(&mut Cursor::new(bytes.to_vec()))
+            .expect("decode")
+            .remove(0)
+    };
+
+    let mut frame = decode(&signed);
+    assert_eq!(frame.payload.len(), 4 + MAC_BYTES);
+    assert!(mac.verify(&mut frame));
+    assert_eq!(frame.payload, b"rust");
+
+    let mut tampered = decode(&signed);
+    tampered.payload[0] ^= 1;
+    assert!(!mac.verify(&mut tampered));
+    let mut redirected = decode(&signed);
+    redirected.header.request_id = 8;
+    assert!(!mac.verify(&mut redirected));
+    let mut other_connection = decode(&signed);
+    assert!(!secret.frame_mac("second").verify(&mut other_connection));
+    assert_eq!(
+        other_connection.payload.len(),
+        4 + MAC_BYTES,
+        "left as it was"
+    );
+    let mut unsigned =
+        decode(&encode(MessageType::Ping, FrameFlags::FINAL, RequestId(1), b"").expect("encode"));
+    assert!(!mac.verify(&mut unsigned));
+}
@@ -0,0 +1,80 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use tempfile::tempdir;
+
+use nerve_search_adapter::config::Config;
+use nerve_search_adapter::context::Context;
+use nerve_search_adapter::health::{HealthConfig, HealthServer};
+
+mod common;
+
+use common::create_search_index;
+
+/// The status line and body of `method path`.
+fn probe(address: SocketAddr, method: &str, path: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(address).expect("connect");
+    write!(stream, "{method} {path} HTTP/1.1\r\nHost: adapter\r\n\r\n").expect("send");
+    let mut response = String::new();
+    stream.read_to_string(&mut response).expect("read");
+    let (head, body) = response.split_once("\r\n\r\n").expect("headers end");
+    let status = head.lines().next().unwrap_or_default().to_string();
+    (status, body.to_string())
+}
+
+#[test]
+fn health_listen_must_be_an_address() {
+    let config: HealthConfig = toml::from_str(r#"listen = "adapter""#).expect("parse [health]");
+    assert!(config.validate().is_err());
+    let config: HealthConfig =
+        toml::from_str("listen = \"0.0.0.0:9102\"\nmax_queue_depth = 0").expect("parse [health]");
+    assert!(config.validate().is_err());
+    assert!(HealthConfig::default().validate().is_ok());
+}
+
+#[test]
+fn readyz_waits_for_a_core_while_healthz_answers_at_once() {
+    let tmp = tempdir().expect("tmpdir");
+    let index = create_search_index(tmp.path());
+    let config =
+        Config::from_args(["--index", &index.to_string_lossy()].map(String::from)).expect("config");
+    let context = Context::from_config(&config).expect("context");
+    let health = HealthConfig {
+        listen: Some("127.0.0.1:0".into()),
+        ..HealthConfig::default()
+    };
+    let server = HealthServer::bind(&health, 4)
+        .expect("bind")
+        .expect("listening");
+    let address = server.local_addr().expect("address");
+    let stop = AtomicBool::new(false);
+
+    thread::scope(|s| {
+        s.spawn(|| server.serve(&context, &stop));
+
+        let (status, body) = probe(address, "GET", "/healthz");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body, "ok\n");
+
+        let (status, body) = probe(address, "GET", "/readyz");
+        assert_eq!(status, "HTTP/1.1 503 Service Unavailable");
+        let readiness: serde_json::Value = serde_json::from_str(&body).expect("JSON body");
+        assert_eq!(readiness["ready"], false);
+        assert_eq!(readiness["connected"], 0);
+        assert_eq!(readiness["index_open"], true);
+        assert_eq!(readiness["max_queue_depth"], 4);
+
+        context.connections.slot(0).up();
+        let (status, body) = probe(address, "GET", "/readyz");
+        assert_eq!(status, "HTTP/1.1 200 OK", "{body}");
+
+        let (status, _) = probe(address, "POST", "/readyz");
+        assert_eq!(status, "HTTP/1.1 405 Method Not Allowed");
+        let (status, _) = probe(address, "GET", "/metrics");
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+
+        stop.store(true, Ordering::Relaxed);
+    });
+}
@@ -0,0 +1,98 @@
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use nerve_protocol::codec::encode;
+use nerve_protocol::constants::{MAGIC, VERSION};
+use nerve_protocol::frame::{FrameHeader, OwnedFrame};
+use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+use nerve_search_adapter::heartbeat::{self, Heartbeat, HeartbeatConfig};
+
+fn every_ms(interval_ms: u64, max_missed: u32) -> Heartbeat {
+    Heartbeat::new(HeartbeatConfig {
+        interval_ms: Some(interval_ms),
+        max_missed,
+        ..HeartbeatConfig::default()
+    })
+}
+
+#[test]
+fn heartbeat_is_off_by_default() {
+    let mut heartbeat = Heartbeat::new(HeartbeatConfig::default());
+    assert_eq!(heartbeat.until_due(), None);
+    assert!(heartbeat.tick().expect("tick").is_none());
+}
+
+#[test]
+fn pings_wait_for_the_interval() {
+    let mut heartbeat = every_ms(60_000, 3);
+    assert!(heartbeat.tick().expect("tick").is_none());
+    let due = heartbeat.until_due().expect("pings on");
+    assert!(due > Duration::from_secs(50), "due in {due:?}");
+}
+
+#[test]
+fn unanswered_pings_time_out_until_a_pong_arrives() {
+    let mut heartbeat = every_ms(1, 2);
+    let mut tick = || {
+        thread::sleep(Duration::from_millis(2));
+        heartbeat.tick()
+    };
+    let ping = tick().expect("first ping").expect("due");
+    assert_eq!(
+        &ping[..],
+        &encode(MessageType::Ping, FrameFlags::FINAL, RequestId(0), &[]).expect("encode")[..]
+    );
+    assert!(tick().expect("second ping").is_some());
+    let lost = tick().expect_err("two pings unanswered");
+    assert_eq!(lost.kind(), io::ErrorKind::TimedOut);
+
+    heartbeat.pong();
+    thread::sleep(Duration::from_millis(2));
+    assert!(heartbeat.tick().expect("answered").is_some());
+}
+
+#[test]
+fn idle_timeout_pings_only_a_quiet_core() {
+    let mut heartbeat = Heartbeat::new(HeartbeatConfig {
+        idle_timeout_ms: Some(5),
+        max_missed: 1,
+        ..HeartbeatConfig::default()
+    });
+    // a core that keeps talking is never pinged
+    for _ in 0..4 {
+        thread::sleep(Duration::from_millis(2));
+        heartbeat.heard();
+        assert!(heartbeat.tick().expect("tick").is_none());
+    }
+
+    thread::sleep(Duration::from_millis(6));
+    assert!(heartbeat.tick().expect("idle ping").is_some());
+    thread::sleep(Duration::from_millis(6));
+    let lost = heartbeat.tick().expect_err("still quiet");
+    assert_eq!(lost.kind(), io::ErrorKind::TimedOut);
+
+    // any frame answers, not just a pong
+    heartbeat.heard();
+    assert!(heartbeat.tick().expect("heard from").is_none());
+    assert!(heartbeat.until_due().expect("pings on") > Duration::from_millis(1));
+}
+
+#[test]
+fn pong_echoes_the_ping() {
+    let ping = OwnedFrame {
+        header: FrameHeader {
+            magic: MAGIC,
+            version: VERSION,
+            msg_type: MessageType::Ping as u8,
+            flags: FrameFlags::FINAL.bits(),
+            request_id: 42,
+            payload_length: 4,
+        },
+        payload: b"beat".to_vec(),
+    };
+    let pong = heartbeat::pong(&ping).expect("pong");
+    let expected =
+        encode(MessageType::Pong, FrameFlags::FINAL, RequestId(42), b"beat").expect("encode");
+    assert_eq!(&pong[..], &expected[..]);
+}
@@ -0,0 +1,71 @@
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::json;
+
+use nerve_search_adapter::inflight::InFlight;
+use nerve_search_adapter::state::CancelToken;
+
+#[test]
+fn identical_searches_run_the_engine_once() {
+    let in_flight = InFlight::default();
+    let runs = AtomicUsize::new(0);
+    let search = || {
+        runs.fetch_add(1, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(100));
+        Ok(vec![json!({"url": "https://example.com"})])
+    };
+
+    let results = thread::scope(|s| {
+        let leader = s.spawn(|| in_flight.run("rust", &CancelToken::default(), search));
+        thread::sleep(Duration::from_millis(20));
+        let follower = s.spawn(|| in_flight.run("rust", &CancelToken::default(), search));
+        [leader.join().unwrap(), follower.join().unwrap()]
+    });
+
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+    for result in results {
+        assert_eq!(result.expect("hits").len(), 1);
+    }
+}
+
+#[test]
+fn waiter_runs_itself_when_the_running_search_is_cancelled() {
+    let in_flight = InFlight::default();
+    let runs = AtomicUsize::new(0);
+
+    let follower = thread::scope(|s| {
+        s.spawn(|| {
+            in_flight.run("rust", &CancelToken::default(), || {
+                runs.fetch_add(1, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(100));
+                Err(io::ErrorKind::Interrupted.into())
+            })
+        });
+        thread::sleep(Duration::from_millis(20));
+        s.spawn(|| {
+            in_flight.run("rust", &CancelToken::default(), || {
+                runs.fetch_add(1, Ordering::SeqCst);
+                Ok(vec![json!({"url": "https://example.com"})])
+            })
+        })
+        .join()
+        .unwrap()
+    });
+
+    assert_eq!(runs.load(Ordering::SeqCst), 2);
+    assert_eq!(follower.expect("own hits").len(), 1);
+}
+
+#[test]
+fn different_searches_do_not_wait_on_each_other() {
+    let in_flight = InFlight::default();
+    let a = in_flight.run("a", &CancelToken::default(), || Ok(vec![json!(1)]));
+    let b = in_flight.run("b", &CancelToken::default(), || {
+        Ok(vec![json!(2), json!(3)])
+    });
+    assert_eq!(a.expect("a").len(), 1);
+    assert_eq!(b.expect("b").len(), 2);
+}
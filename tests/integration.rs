@@ -1,7 +1,14 @@
 use std::os::unix::net::UnixStream;
 use std::thread;
 use std::time::Duration;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+use nerve_search_adapter::config::Config;
+
+/// The crate's `search_index` directory, wherever the tests run from.
+fn index_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("search_index")
+}
 
 /// Wait for a Unix socket to be available
 fn wait_for_socket(socket_path: &str, timeout_ms: u64) {
@@ -51,7 +58,7 @@ fn test_adapter_connection_and_basic_query() {
     // Start nerve-search-adapter (connects as the only client to core)
     let core_socket_for_adapter = core_socket.to_string();
     let adapter_handle = thread::spawn(move || {
-        nerve_search_adapter::client::run(&core_socket_for_adapter)
+        nerve_search_adapter::client::run(&Config::new(&core_socket_for_adapter, index_path()))
     });
     
     // Give adapter time to connect to core
@@ -95,7 +102,7 @@ fn test_adapter_processes_search_queries_via_core() {
     // Start nerve-search-adapter as the adapter between clients and core
     let core_socket_for_adapter = core_socket.to_string();
     let adapter_handle = thread::spawn(move || {
-        nerve_search_adapter::client::run(&core_socket_for_adapter)
+        nerve_search_adapter::client::run(&Config::new(&core_socket_for_adapter, index_path()))
     });
     
     thread::sleep(Duration::from_millis(500));
@@ -134,7 +141,7 @@ fn test_adapter_cancellation_state_management() {
     
     let core_socket_for_adapter = core_socket.to_string();
     let adapter_handle = thread::spawn(move || {
-        nerve_search_adapter::client::run(&core_socket_for_adapter)
+        nerve_search_adapter::client::run(&Config::new(&core_socket_for_adapter, index_path()))
     });
     
     thread::sleep(Duration::from_millis(500));
@@ -171,7 +178,7 @@ fn test_adapter_protocol_encoding_via_core_dispatcher() {
     
     let core_socket_for_adapter = core_socket.to_string();
     let adapter_handle = thread::spawn(move || {
-        nerve_search_adapter::client::run(&core_socket_for_adapter)
+        nerve_search_adapter::client::run(&Config::new(&core_socket_for_adapter, index_path()))
     });
     
     thread::sleep(Duration::from_millis(500));
@@ -206,7 +213,7 @@ fn test_adapter_maintains_request_state() {
     
     let core_socket_for_adapter = core_socket.to_string();
     let adapter_handle = thread::spawn(move || {
-        nerve_search_adapter::client::run(&core_socket_for_adapter)
+        nerve_search_adapter::client::run(&Config::new(&core_socket_for_adapter, index_path()))
     });
     
     thread::sleep(Duration::from_millis(500));
@@ -0,0 +1,103 @@
+use std::path::Path;
+
+use tantivy::schema::{STORED, Schema, TEXT};
+use tantivy::{Index, doc};
+use tempfile::tempdir;
+
+use nerve_search_adapter::introspect;
+use nerve_search_adapter::request::Request;
+
+fn create_index(path: &Path, docs: usize) {
+    let mut builder = Schema::builder();
+    let url = builder.add_text_field("url", TEXT | STORED);
+    let index = Index::create_in_dir(path, builder.build()).expect("create index");
+    let mut writer = index.writer(15_000_000).expect("writer");
+    for i in 0..docs {
+        writer
+            .add_document(doc!(url => format!("https://example.com/{i}")))
+            .expect("add doc");
+    }
+    writer.commit().expect("commit");
+}
+
+#[test]
+fn shard_stats_reports_counts_and_size() {
+    let tmp = tempdir().expect("tmpdir");
+    create_index(tmp.path(), 3);
+
+    let stats = introspect::shard_stats(tmp.path()).expect("stats");
+    assert_eq!(stats.num_docs, 3);
+    assert_eq!(stats.num_deleted_docs, 0);
+    assert_eq!(stats.segments, 1);
+    assert!(stats.size_bytes > 0);
+    assert!(stats.last_commit.is_some());
+    assert_eq!(stats.schema_version.len(), 16);
+}
+
+#[test]
+fn schema_version_is_stable_across_indexes() {
+    let a = tempdir().expect("tmpdir");
+    let b = tempdir().expect("tmpdir");
+    create_index(a.path(), 1);
+    create_index(b.path(), 5);
+
+    let a = introspect::shard_stats(a.path()).expect("stats");
+    let b = introspect::shard_stats(b.path()).expect("stats");
+    assert_eq!(a.schema_version, b.schema_version);
+}
+
+#[test]
+fn index_stats_op_is_decoded() {
+    let request = Request::parse(br#"{"op": "index_stats"}"#).expect("parse");
+    assert!(matches!(request, Request::IndexStats));
+    assert!(Request::parse(br#"{"op": "no_such_op"}"#).is_err());
+}
+
+#[test]
+fn schema_info_lists_fields() {
+    let tmp = tempdir().expect("tmpdir");
+    create_index(tmp.path(), 1);
+
+    let index = introspect::open_index(tmp.path()).expect("open");
+    let info = introspect::schema_fields(&index.schema());
+    assert_eq!(info.len(), 1);
+    assert_eq!(info[0].name, "url");
+    assert_eq!(info[0].field_type, "str");
+    assert!(info[0].indexed && info[0].stored && !info[0].fast);
+    assert_eq!(info[0].tokenizer.as_deref(), Some("default"));
+}
+
+#[test]
+fn term_stats_counts_documents_and_occurrences() {
+    let tmp = tempdir().expect("tmpdir");
+    let mut builder = Schema::builder();
+    let body = builder.add_text_field("body", TEXT);
+    let index = Index::create_in_dir(tmp.path(), builder.build()).expect("create index");
+    let mut writer = index.writer(15_000_000).expect("writer");
+    writer
+        .add_document(doc!(body => "rust rust adapter"))
+        .expect("add doc");
+    writer
+        .add_document(doc!(body => "rust search"))
+        .expect("add doc");
+    writer.commit().expect("commit");
+
+    let stats = introspect::shard_term_stats(
+        tmp.path(),
+        "body",
+        &["Rust".to_string(), "missing".to_string()],
+    )
+    .expect("term stats");
+    assert_eq!(stats.len(), 2);
+    assert_eq!(stats[0].term, "rust");
+    assert_eq!(stats[0].doc_freq, 2);
+    assert_eq!(stats[0].total_term_freq, 3);
+    assert_eq!(stats[1].doc_freq, 0);
+}
+
+#[test]
+fn term_stats_rejects_unknown_field() {
+    let tmp = tempdir().expect("tmpdir");
+    create_index(tmp.path(), 1);
+    assert!(introspect::shard_term_stats(tmp.path(), "nope", &["x".to_string()]).is_err());
+}
@@ -0,0 +1,115 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use nerve_protocol::types::RequestId;
+use nerve_search_adapter::metrics::{Counters, Histogram, Latencies, ShardLatencies};
+use nerve_search_adapter::shards::ShardTiming;
+use nerve_search_adapter::request::Request;
+use nerve_search_adapter::state::RequestState;
+
+#[test]
+fn histogram_quantiles_are_within_bucket_precision() {
+    let mut histogram = Histogram::default();
+    for micros in 1..=1000 {
+        histogram.record(micros);
+    }
+    assert_eq!(histogram.count(), 1000);
+    assert_eq!(histogram.max(), 1000);
+
+    for (q, expected) in [(0.5, 500.0), (0.9, 900.0), (0.99, 990.0)] {
+        let got = histogram.quantile(q) as f64;
+        assert!((got - expected).abs() / expected < 0.04, "p{q}: {got}");
+    }
+    assert_eq!(histogram.quantile(1.0), 1000);
+}
+
+#[test]
+fn histogram_handles_extremes() {
+    let mut histogram = Histogram::default();
+    assert_eq!(histogram.quantile(0.5), 0);
+    histogram.record(0);
+    histogram.record(u64::MAX);
+    assert_eq!(histogram.quantile(0.5), 0);
+    assert_eq!(histogram.quantile(1.0), u64::MAX);
+}
+
+#[test]
+fn latencies_are_kept_per_operation() {
+    let latencies = Latencies::default();
+    latencies.record("search", Duration::from_millis(5));
+    latencies.record("search", Duration::from_millis(7));
+    latencies.record("commit", Duration::from_millis(40));
+
+    let summary = latencies.summary();
+    assert_eq!(summary["search"].count, 2);
+    assert_eq!(summary["search"].max_us, 7000);
+    assert_eq!(summary["commit"].count, 1);
+    assert!(!summary.contains_key("merge"));
+}
+
+#[test]
+fn latencies_are_kept_per_shard() {
+    let latencies = ShardLatencies::default();
+    let timing = |shard, millis| ShardTiming {
+        shard,
+        hits: 10,
+        elapsed: Duration::from_millis(millis),
+    };
+    latencies.record(&[timing(0, 3), timing(1, 9)]);
+    latencies.record(&[timing(0, 5)]);
+
+    let summary = latencies.summary();
+    assert_eq!(summary[&0].count, 2);
+    assert_eq!(summary[&0].max_us, 5000);
+    assert_eq!(summary[&1].count, 1);
+    assert_eq!(summary.len(), 2);
+}
+
+#[test]
+fn metrics_op_is_parsed() {
+    let request = Request::parse(br#"{"op": "metrics"}"#).expect("request");
+    assert!(matches!(request, Request::Metrics));
+    assert!(!request.is_mutation());
+}
+
+#[test]
+fn stats_op_is_parsed() {
+    let request = Request::parse(br#"{"op": "stats"}"#).expect("request");
+    assert!(matches!(request, Request::Stats));
+    assert_eq!(request.op(), "stats");
+}
+
+#[test]
+fn top_queries_op_is_parsed() {
+    let request = Request::parse(br#"{"op": "top_queries"}"#).expect("request");
+    assert!(matches!(request, Request::TopQueries { limit: 10 }));
+    assert_eq!(request.op(), "top_queries");
+    let request = Request::parse(br#"{"op": "top_queries", "limit": 3}"#).expect("request");
+    assert!(matches!(request, Request::TopQueries { limit: 3 }));
+    assert!(Request::parse(br#"{"op": "top_queries", "limit": -1}"#).is_err());
+}
+
+#[test]
+fn counters_follow_queries_through_the_workers() {
+    let counters = Arc::new(Counters::default());
+    let state = RequestState::new().with_counters(Arc::clone(&counters));
+    for id in 1..=3 {
+        state.queue(RequestId(id));
+    }
+    assert_eq!(counters.snapshot().queue_depth, 3);
+
+    state.cancel(RequestId(2));
+    // cancelling twice, or a request never queued, counts once at most
+    state.cancel(RequestId(2));
+    state.cancel(RequestId(9));
+    for id in 1..=3 {
+        state.settle(RequestId(id));
+    }
+    counters.request();
+    counters.error();
+
+    let snapshot = counters.snapshot();
+    assert_eq!(snapshot.cancellations, 1);
+    assert_eq!(snapshot.queue_depth, 0);
+    assert_eq!((snapshot.requests, snapshot.errors), (1, 1));
+}
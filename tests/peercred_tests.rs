@@ -0,0 +1,91 @@
+use std::io::ErrorKind;
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::thread;
+
+use nerve_search_adapter::client;
+use nerve_search_adapter::config::Config;
+use nerve_search_adapter::peercred::{self, PeerCredentialsConfig};
+use nerve_search_adapter::reconnect::ReconnectConfig;
+use nerve_search_adapter::transport::Stream;
+use tempfile::tempdir;
+
+fn own_ids() -> (u32, u32) {
+    // SAFETY: getuid and getgid always succeed
+    unsafe { (libc::getuid(), libc::getgid()) }
+}
+
+#[test]
+fn unix_peers_report_their_credentials() {
+    let (adapter, _core) = UnixStream::pair().expect("pair");
+    let credentials = peercred::peer_credentials(&adapter).expect("credentials");
+    assert_eq!((credentials.uid, credentials.gid), own_ids());
+    assert_eq!(credentials.pid, std::process::id() as i32);
+}
+
+#[test]
+fn only_listed_users_and_groups_are_served() {
+    let (uid, gid) = own_ids();
+    let (adapter, _core) = UnixStream::pair().expect("pair");
+    let stream = Stream::from(adapter);
+
+    let open = PeerCredentialsConfig::default();
+    assert!(!open.is_enabled());
+    peercred::verify(&stream, &open).expect("no allowlist");
+
+    let listed = PeerCredentialsConfig {
+        uids: vec![uid.wrapping_add(1), uid],
+        gids: vec![gid],
+    };
+    peercred::verify(&stream, &listed).expect("listed");
+
+    let stranger = PeerCredentialsConfig {
+        uids: vec![uid.wrapping_add(1)],
+        gids: Vec::new(),
+    };
+    let refused = peercred::verify(&stream, &stranger).expect_err("unlisted uid");
+    assert_eq!(refused.kind(), ErrorKind::PermissionDenied);
+
+    let wrong_group = PeerCredentialsConfig {
+        uids: vec![uid],
+        gids: vec![gid.wrapping_add(1)],
+    };
+    assert!(peercred::verify(&stream, &wrong_group).is_err());
+}
+
+#[test]
+fn network_cores_are_not_checked() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let stream = TcpStream::connect(listener.local_addr().expect("address")).expect("connect");
+    let stranger = PeerCredentialsConfig {
+        uids: vec![own_ids().0.wrapping_add(1)],
+        gids: Vec::new(),
+    };
+    peercred::verify(&Stream::from(stream), &stranger).expect("tcp");
+}
+
+#[test]
+fn adapter_refuses_an_unexpected_core_without_retrying() {
+    let tmp = tempdir().expect("tmpdir");
+    let socket = tmp.path().join("core.sock");
+    let listener = UnixListener::bind(&socket).expect("bind");
+    let core = thread::spawn(move || {
+        let (stream, _) = listener.accept().expect("accept");
+        (listener, stream)
+    });
+
+    let mut config = Config::new(&socket, tmp.path());
+    config.reconnect = ReconnectConfig {
+        enabled: true,
+        max_attempts: Some(3),
+        initial_backoff_ms: 10,
+        max_backoff_ms: 10,
+    };
+    config.peer_credentials.uids = vec![own_ids().0.wrapping_add(1)];
+    let refused = client::run(&config).expect_err("refused");
+    assert_eq!(refused.kind(), ErrorKind::PermissionDenied);
+    let (listener, _stream) = core.join().expect("core join");
+    listener.set_nonblocking(true).expect("nonblocking");
+    let retried = listener.accept().map(drop).expect_err("connected once");
+    assert_eq!(retried.kind(), ErrorKind::WouldBlock);
+}
@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use nerve_search_adapter::profile::{ProfileConfig, Profiler};
+use nerve_search_adapter::slowlog::Trace;
+
+fn enabled(sample_every: u64) -> Profiler {
+    Profiler::new(&ProfileConfig {
+        path: Some(PathBuf::from("/tmp/adapter.folded")),
+        sample_every,
+    })
+}
+
+#[test]
+fn profiler_is_off_without_a_path() {
+    let profiler = Profiler::new(&ProfileConfig::default());
+    assert!(!profiler.is_enabled());
+    assert!(!profiler.sample());
+}
+
+#[test]
+fn profiler_samples_one_request_in_n() {
+    let profiler = enabled(3);
+    let sampled: Vec<bool> = (0..6).map(|_| profiler.sample()).collect();
+    assert_eq!(sampled, [true, false, false, true, false, false]);
+}
+
+#[test]
+fn profiler_folds_stages_under_their_operation() {
+    let profiler = enabled(1);
+    let trace = Trace {
+        op: "search",
+        stages: vec![
+            ("parse", Duration::from_micros(10)),
+            ("search", Duration::from_micros(300)),
+            ("serialize", Duration::from_micros(40)),
+        ],
+        ..Trace::default()
+    };
+    profiler.record(&trace, Duration::from_micros(400));
+    profiler.record(&trace, Duration::from_micros(400));
+    profiler.record_io("decode", Duration::from_micros(5));
+
+    let totals = profiler.totals();
+    assert_eq!(totals["search;search"], 600);
+    assert_eq!(totals["search;parse"], 20);
+    // time outside any stage is the operation's own
+    assert_eq!(totals["search"], 100);
+    assert_eq!(totals["io;decode"], 5);
+    assert!(
+        profiler
+            .folded()
+            .lines()
+            .any(|line| line == "search;serialize 80")
+    );
+}
@@ -0,0 +1,85 @@
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+use nerve_protocol::types::RequestId;
+use nerve_search_adapter::querylog::{QueryLog, QueryLogConfig, rotated};
+use nerve_search_adapter::slowlog::Trace;
+use serde_json::Value;
+use tempfile::tempdir;
+
+fn search(query: &str) -> Trace {
+    Trace {
+        op: "search",
+        query: Some(query.to_string()),
+        hits: Some(2),
+        ..Trace::default()
+    }
+}
+
+fn lines(path: &std::path::Path) -> Vec<Value> {
+    fs::read_to_string(path)
+        .expect("read query log")
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("JSON line"))
+        .collect()
+}
+
+#[test]
+fn full_files_rotate_and_only_keep_are_kept() {
+    let tmp = tempdir().expect("tmpdir");
+    let path = tmp.path().join("queries.log");
+    let config = QueryLogConfig {
+        path: Some(path.clone()),
+        max_bytes: 150,
+        keep: 2,
+        ..QueryLogConfig::default()
+    };
+    assert!(config.validate().is_ok());
+    let log = QueryLog::open(&config).expect("open query log");
+    for (n, query) in ["one", "two", "three", "four", "five"].iter().enumerate() {
+        log.record(
+            RequestId(n as u64),
+            &search(query),
+            Duration::from_micros(40),
+        );
+    }
+    // not a search: nothing to log
+    log.record(RequestId(9), &Trace::default(), Duration::ZERO);
+
+    // each line is over half the limit, so every file holds one
+    let current = lines(&path);
+    assert_eq!(current.len(), 1);
+    assert_eq!(current[0]["query"], "five");
+    assert_eq!(current[0]["request_id"], 4);
+    assert_eq!(current[0]["hits"], 2);
+    assert_eq!(current[0]["latency_us"], 40);
+    assert_eq!(lines(&rotated(&path, 1))[0]["query"], "four");
+    assert_eq!(lines(&rotated(&path, 2))[0]["query"], "three");
+    assert!(!rotated(&path, 3).exists(), "past keep");
+}
+
+#[test]
+fn files_rotate_once_old_enough() {
+    let tmp = tempdir().expect("tmpdir");
+    let path = tmp.path().join("queries.log");
+    let config: QueryLogConfig = toml::from_str(&format!(
+        "path = {:?}\nrotate_every_secs = 1\n",
+        path.display().to_string()
+    ))
+    .expect("parse [querylog]");
+    let log = QueryLog::open(&config).expect("open query log");
+    log.record(RequestId(1), &search("before"), Duration::ZERO);
+    log.record(RequestId(2), &search("same file"), Duration::ZERO);
+    thread::sleep(Duration::from_millis(1100));
+    log.record(RequestId(3), &search("after"), Duration::ZERO);
+
+    assert_eq!(lines(&rotated(&path, 1)).len(), 2);
+    assert_eq!(lines(&path)[0]["query"], "after");
+
+    let config = QueryLogConfig {
+        rotate_every_secs: Some(0),
+        ..QueryLogConfig::default()
+    };
+    assert!(config.validate().is_err());
+}
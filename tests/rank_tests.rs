@@ -0,0 +1,161 @@
+use serde_json::json;
+
+use nerve_search_adapter::rank::{self, Fusion, ScoringWeights, WeightOverrides};
+use nerve_search_adapter::request::{SearchMode, SearchRequest, SortOrder};
+use nerve_search_adapter::shards::EngineBoosts;
+
+#[test]
+fn rrf_rewards_hits_found_by_both_passes() {
+    let lexical = vec![
+        json!({"url": "a", "score": 12.0}),
+        json!({"url": "b", "score": 8.0}),
+    ];
+    let vector = vec![
+        json!({"url": "c", "score": 0.99}),
+        json!({"url": "b", "score": 0.90}),
+    ];
+
+    let fused = rank::fuse(lexical, vector, Fusion::Rrf { k: 60.0 });
+    let urls: Vec<_> = fused.iter().map(|h| h["url"].as_str().unwrap()).collect();
+    assert_eq!(urls[0], "b");
+    assert_eq!(fused.len(), 3);
+}
+
+#[test]
+fn weighted_blend_follows_the_weight() {
+    let lexical = vec![
+        json!({"url": "a", "score": 10.0}),
+        json!({"url": "b", "score": 1.0}),
+    ];
+    let vector = vec![
+        json!({"url": "b", "score": 0.9}),
+        json!({"url": "a", "score": 0.1}),
+    ];
+
+    let lexical_heavy = rank::fuse(
+        lexical.clone(),
+        vector.clone(),
+        Fusion::Weighted {
+            lexical_weight: 0.9,
+        },
+    );
+    assert_eq!(lexical_heavy[0]["url"], "a");
+
+    let vector_heavy = rank::fuse(
+        lexical,
+        vector,
+        Fusion::Weighted {
+            lexical_weight: 0.1,
+        },
+    );
+    assert_eq!(vector_heavy[0]["url"], "b");
+}
+
+#[test]
+fn hybrid_request_keeps_mode_and_fusion() {
+    let request = SearchRequest::parse(
+        br#"{"mode": "hybrid", "query": "rust", "vector": [1.0], "fusion": {"method": "weighted", "lexical_weight": 0.7}}"#,
+    )
+    .expect("parse");
+    assert_eq!(request.mode, SearchMode::Hybrid);
+    assert_eq!(
+        request.fusion,
+        Fusion::Weighted {
+            lexical_weight: 0.7
+        }
+    );
+}
+
+#[test]
+fn composite_weights_can_promote_pagerank() {
+    let hits = vec![
+        json!({"url": "a", "score": 10.0, "pagerank": 0.0, "quality": "0.1"}),
+        json!({"url": "b", "score": 9.0, "pagerank": 0.9, "quality": "0.9"}),
+    ];
+
+    let bm25_only = ScoringWeights {
+        bm25: 1.0,
+        pagerank: 0.0,
+        tfidf: 0.0,
+        quality: 0.0,
+    };
+    assert_eq!(rank::composite(hits.clone(), &bm25_only)[0]["url"], "a");
+
+    let boosted = ScoringWeights::default().with(&WeightOverrides {
+        pagerank: Some(2.0),
+        ..WeightOverrides::default()
+    });
+    let ranked = rank::composite(hits, &boosted);
+    assert_eq!(ranked[0]["url"], "b");
+    assert_eq!(ranked[0]["bm25"], 9.0);
+}
+
+#[test]
+fn rrf_fuses_any_number_of_rankings() {
+    let by_relevance = vec![
+        json!({"url": "a"}),
+        json!({"url": "b"}),
+        json!({"url": "c"}),
+    ];
+    let by_pagerank = vec![
+        json!({"url": "c"}),
+        json!({"url": "b"}),
+        json!({"url": "a"}),
+    ];
+    let by_quality = vec![
+        json!({"url": "b"}),
+        json!({"url": "c"}),
+        json!({"url": "a"}),
+    ];
+
+    let fused = rank::rrf(
+        vec![by_relevance, by_pagerank, by_quality],
+        rank::DEFAULT_RRF_K,
+    );
+    assert_eq!(fused[0]["url"], "b");
+    assert_eq!(fused.len(), 3);
+}
+
+#[test]
+fn sort_fusion_request_defaults_to_all_sorts() {
+    let request =
+        SearchRequest::parse(br#"{"mode": "sort_fusion", "query": "rust"}"#).expect("parse");
+    assert_eq!(request.mode, SearchMode::SortFusion);
+    assert_eq!(
+        request.sorts,
+        vec![
+            SortOrder::Relevance,
+            SortOrder::Pagerank,
+            SortOrder::Quality
+        ]
+    );
+}
+
+#[test]
+fn sort_fusion_cache_key_covers_the_fusion() {
+    let request = |fusion| SearchRequest {
+        mode: SearchMode::SortFusion,
+        fusion,
+        ..SearchRequest::text("rust")
+    };
+    let k60 = request(Fusion::Rrf { k: 60.0 });
+    let k10 = request(Fusion::Rrf { k: 10.0 });
+    assert_ne!(k60.cache_key(), k10.cache_key());
+    assert_eq!(k60.cache_key(), request(Fusion::Rrf { k: 60.0 }).cache_key());
+}
+
+#[test]
+fn engine_boosts_are_named_request_options() {
+    let request =
+        SearchRequest::parse(br#"{"query": "rust", "use_pagerank": true}"#).expect("parse");
+    assert_eq!(
+        request.boosts(),
+        EngineBoosts {
+            pagerank: true,
+            tfidf: false
+        }
+    );
+    let plain = SearchRequest::parse(b"rust").expect("parse");
+    assert_eq!(plain.boosts(), EngineBoosts::default());
+    assert_ne!(request.cache_key(), plain.cache_key());
+}
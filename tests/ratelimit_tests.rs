@@ -0,0 +1,65 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use nerve_search_adapter::metrics::Counters;
+use nerve_search_adapter::ratelimit::{RateLimitConfig, RateLimiter};
+use nerve_search_adapter::state::RequestState;
+
+#[test]
+fn rates_must_be_positive_and_bursts_need_a_rate() {
+    assert!(RateLimitConfig::default().validate().is_ok());
+    assert!(!RateLimiter::new(&RateLimitConfig::default()).is_enabled());
+
+    let config: RateLimitConfig = toml::from_str("queries_per_sec = 0").expect("parse [ratelimit]");
+    assert!(config.validate().is_err());
+    let config: RateLimitConfig = toml::from_str("burst = 10").expect("parse [ratelimit]");
+    assert!(config.validate().is_err());
+    let config: RateLimitConfig =
+        toml::from_str("per_query_per_sec = 5\nper_query_burst = 10").expect("parse [ratelimit]");
+    assert!(config.validate().is_ok());
+}
+
+#[test]
+fn queries_over_the_rate_are_turned_away_until_tokens_refill() {
+    let limiter = RateLimiter::new(&RateLimitConfig {
+        queries_per_sec: Some(100),
+        burst: Some(3),
+        per_query_per_sec: Some(100),
+        per_query_burst: Some(1),
+        ..RateLimitConfig::default()
+    });
+    assert!(limiter.is_enabled());
+
+    // the same query, however it is spaced or cased, has one allowance
+    assert!(limiter.admit(b"rust search").is_ok());
+    let wait = limiter
+        .admit(b"  Rust   SEARCH ")
+        .expect_err("over the per-query rate");
+    assert!(wait <= Duration::from_millis(10), "{wait:?}");
+
+    // other queries still get in, up to the overall burst
+    assert!(limiter.admit(b"tantivy").is_ok());
+    assert!(limiter.admit(b"adapter").is_ok());
+    assert!(limiter.admit(b"core").is_err(), "over the overall burst");
+
+    thread::sleep(Duration::from_millis(30));
+    assert!(limiter.admit(b"rust search").is_ok());
+}
+
+#[test]
+fn a_connection_counts_the_queries_it_turns_away() {
+    let counters = Arc::new(Counters::default());
+    let limiter = RateLimiter::new(&RateLimitConfig {
+        queries_per_sec: Some(1),
+        ..RateLimitConfig::default()
+    });
+    let state = RequestState::new()
+        .with_counters(Arc::clone(&counters))
+        .with_limiter(Arc::new(limiter));
+
+    assert!(state.admit(b"rust").is_ok());
+    assert!(state.admit(b"rust").is_err());
+    assert!(state.admit(b"tantivy").is_err());
+    assert_eq!(counters.snapshot().rate_limited, 2);
+}
@@ -0,0 +1,104 @@
+use std::io;
+use std::os::unix::net::UnixListener;
+use std::thread;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use nerve_search_adapter::reconnect::{Backoff, ReconnectConfig, run_sessions};
+use nerve_search_adapter::shutdown::Shutdown;
+
+fn enabled(max_attempts: Option<u32>) -> ReconnectConfig {
+    ReconnectConfig {
+        enabled: true,
+        max_attempts,
+        initial_backoff_ms: 100,
+        max_backoff_ms: 400,
+    }
+}
+
+#[test]
+fn backoff_is_off_by_default() {
+    let mut backoff = Backoff::new(ReconnectConfig::default());
+    assert_eq!(backoff.next_delay(), None);
+}
+
+#[test]
+fn backoff_doubles_up_to_the_cap_with_jitter() {
+    let mut backoff = Backoff::new(enabled(None));
+    for ceiling in [100, 200, 400, 400, 400] {
+        let delay = backoff.next_delay().expect("unlimited attempts");
+        let ms = delay.as_millis() as u64;
+        assert!(
+            (ceiling / 2..=ceiling).contains(&ms),
+            "{ms}ms outside {ceiling}ms backoff"
+        );
+    }
+}
+
+#[test]
+fn backoff_gives_up_after_max_attempts_until_reset() {
+    let mut backoff = Backoff::new(enabled(Some(2)));
+    assert!(backoff.next_delay().is_some());
+    assert!(backoff.next_delay().is_some());
+    assert_eq!(backoff.next_delay(), None);
+
+    backoff.reset();
+    let first = backoff.next_delay().expect("attempts start over");
+    assert!(first <= Duration::from_millis(100));
+}
+
+#[test]
+fn sessions_resume_after_the_core_hangs_up() {
+    let tmp = tempdir().expect("tempdir");
+    let path = tmp.path().join("core.sock");
+    let listener = UnixListener::bind(&path).expect("bind");
+    // a core that hangs up twice, going away for good before the second
+    // hang-up, so no reconnect can slip into its backlog
+    let core = thread::spawn(move || {
+        drop(listener.accept().expect("first accept"));
+        let last = listener.accept().expect("second accept");
+        drop(listener);
+        drop(last);
+    });
+
+    let config = ReconnectConfig {
+        enabled: true,
+        max_attempts: Some(3),
+        initial_backoff_ms: 1,
+        max_backoff_ms: 5,
+    };
+    let mut sessions = 0;
+    let result = run_sessions(config, &path, None, &Shutdown::default(), |mut stream| {
+        sessions += 1;
+        io::copy(&mut stream, &mut io::sink()).map(drop)
+    });
+    core.join().expect("core");
+
+    assert_eq!(sessions, 2);
+    assert!(result.is_err(), "gives up once the core stays away");
+}
+
+#[test]
+fn refused_cores_are_not_retried() {
+    let tmp = tempdir().expect("tempdir");
+    let path = tmp.path().join("core.sock");
+    let listener = UnixListener::bind(&path).expect("bind");
+    let core = thread::spawn(move || drop(listener.accept().expect("accept")));
+
+    let mut sessions = 0;
+    let result = run_sessions(enabled(None), &path, None, &Shutdown::default(), |_stream| {
+        sessions += 1;
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "incompatible core",
+        ))
+    });
+    core.join().expect("core");
+
+    assert_eq!(sessions, 1);
+    assert_eq!(
+        result.expect_err("refused").kind(),
+        io::ErrorKind::Unsupported
+    );
+}
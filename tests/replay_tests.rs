@@ -0,0 +1,73 @@
+use std::fs::File;
+use std::io::Cursor;
+use std::path::Path;
+
+use nerve_protocol::codec::encode;
+use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+use tempfile::tempdir;
+
+use nerve_search_adapter::capture::{self, Direction};
+use nerve_search_adapter::config::Config;
+use nerve_search_adapter::context::Context;
+use nerve_search_adapter::{replay, stdio};
+
+mod common;
+
+use common::create_search_index;
+
+#[test]
+fn replay_matches_its_own_capture_and_flags_changed_replies() {
+    let tmp = tempdir().expect("tmpdir");
+    let index = create_search_index(tmp.path());
+    let capture_path = tmp.path().join("frames.cap");
+    let mut config = Config::new("-", &index);
+    config.record_path = Some(capture_path.clone());
+    let recording = Context::from_config(&config).expect("context");
+    let mut input =
+        encode(MessageType::Ping, FrameFlags::FINAL, RequestId(3), b"again").expect("encode ping");
+    input.extend(
+        encode(
+            MessageType::SearchQuery,
+            FrameFlags::FINAL,
+            RequestId(4),
+            b"replay",
+        )
+        .expect("encode query"),
+    );
+    stdio::serve(&config, &recording, Cursor::new(input), &mut Vec::new()).expect("serve");
+    drop(recording);
+
+    let mut captured = capture::read(File::open(&capture_path).expect("open")).expect("read");
+    config.record_path = None;
+    let context = Context::from_config(&config).expect("context");
+    let report = replay::replay(&config, &context, &captured).expect("replay");
+    assert_eq!(report.connections, 1);
+    assert_eq!(report.requests, 2);
+    assert!(report.mismatches.is_empty(), "{:?}", report.mismatches);
+
+    let answer = captured
+        .iter_mut()
+        .find(|e| e.direction == Direction::Outbound && e.frame.header.request_id == 4)
+        .expect("captured answer");
+    answer.frame.payload = b"tampered".to_vec();
+    let report = replay::replay(&config, &context, &captured).expect("replay");
+    assert_eq!(report.mismatches.len(), 1);
+    let mismatch = &report.mismatches[0];
+    assert_eq!(mismatch.request_id, RequestId(4));
+    assert_eq!(mismatch.captured[0].payload, b"tampered");
+    assert_ne!(mismatch.replayed[0].payload, b"tampered");
+}
+
+#[test]
+fn replay_flag_is_command_line_only() {
+    let tmp = tempdir().expect("tmpdir");
+    let index = tmp.path().to_string_lossy().into_owned();
+    let config =
+        Config::from_args(["--index", &index, "--replay", "/tmp/frames.cap"].map(String::from))
+            .expect("config");
+    assert_eq!(
+        config.replay_path.as_deref(),
+        Some(Path::new("/tmp/frames.cap"))
+    );
+    assert_eq!(Config::new("-", &index).replay_path, None);
+}
@@ -0,0 +1,37 @@
+#![cfg(feature = "scripting")]
+
+use std::time::Duration;
+
+use serde_json::json;
+
+use nerve_search_adapter::script::Rescorer;
+
+#[test]
+fn script_rescores_and_reorders_hits() {
+    let rescorer = Rescorer::compile(
+        r#"if hit.domain == "docs.rs" { score + 10.0 } else { score }"#,
+        Duration::from_millis(50),
+    )
+    .expect("compile");
+
+    let hits = vec![
+        json!({"url": "a", "domain": "example.com", "score": 5.0}),
+        json!({"url": "b", "domain": "docs.rs", "score": 1.0}),
+    ];
+    let rescored = rescorer.rescore(hits).expect("rescore");
+    assert_eq!(rescored[0]["url"], "b");
+    assert_eq!(rescored[0]["score"], 11.0);
+}
+
+#[test]
+fn script_is_cut_off_by_time_limit() {
+    let rescorer = Rescorer::compile("loop { }", Duration::from_millis(20)).expect("compile");
+    let result = rescorer.rescore(vec![json!({"url": "a", "score": 1.0})]);
+    assert!(result.is_err(), "runaway script must be aborted");
+}
+
+#[test]
+fn script_must_return_a_number() {
+    let rescorer = Rescorer::compile(r#""nope""#, Duration::from_millis(20)).expect("compile");
+    assert!(rescorer.rescore(vec![json!({"url": "a", "score": 1.0})]).is_err());
+}
@@ -0,0 +1,155 @@
+use std::path::{Path, PathBuf};
+
+use crawler::search::SearchSchema;
+use crawler::search::filters::SortBy;
+use serde_json::json;
+use tempfile::tempdir;
+use tantivy::{doc, Index};
+
+use nerve_search_adapter::shards::{merge, EngineBoosts, Shards};
+use nerve_search_adapter::state::CancelToken;
+
+fn create_shard(root: &Path, name: &str, url: &str) -> PathBuf {
+    create_ranked_shard(root, name, url, 0.1)
+}
+
+fn create_ranked_shard(root: &Path, name: &str, url: &str, pagerank: f64) -> PathBuf {
+    let index_path = root.join(name);
+    std::fs::create_dir_all(&index_path).expect("create shard dir");
+    let schema = SearchSchema::build();
+    let index = Index::create_in_dir(&index_path, schema.schema.clone()).expect("create index");
+    let mut writer = index.writer(50_000_000).expect("writer");
+    writer
+        .add_document(doc!(
+            schema.url_field => url,
+            schema.title_field => "Rust shard",
+            schema.content_field => "rust sharded search",
+            schema.domain_field => "example.com",
+            schema.quality_field => "0.5",
+            schema.pagerank_field => pagerank,
+            schema.tfidf_field => 0.1f64
+        ))
+        .expect("add doc");
+    writer.commit().expect("commit");
+    index_path
+}
+
+#[test]
+fn search_merges_hits_from_every_shard() {
+    let tmp = tempdir().expect("tmpdir");
+    let paths = vec![
+        create_shard(tmp.path(), "shard-0", "https://example.com/a"),
+        create_shard(tmp.path(), "shard-1", "https://example.com/b"),
+    ];
+    let shards = Shards::open(&paths).expect("open shards");
+    assert_eq!(shards.len(), 2);
+
+    let (hits, timings) = shards.search("rust", 10, 0, SortBy::Relevance, EngineBoosts::default(), &CancelToken::default()).expect("search");
+    assert_eq!(hits.len(), 2);
+    assert_eq!(timings.len(), 2);
+    assert!(timings.iter().all(|t| t.hits == 1));
+}
+
+#[test]
+fn search_applies_window_after_merge() {
+    let tmp = tempdir().expect("tmpdir");
+    let paths = vec![
+        create_shard(tmp.path(), "shard-0", "https://example.com/a"),
+        create_shard(tmp.path(), "shard-1", "https://example.com/b"),
+    ];
+    let shards = Shards::open(&paths).expect("open shards");
+
+    let (hits, _) = shards.search("rust", 1, 1, SortBy::Relevance, EngineBoosts::default(), &CancelToken::default()).expect("search");
+    assert_eq!(hits.len(), 1);
+}
+
+#[test]
+fn search_merges_shards_by_the_requested_sort() {
+    let tmp = tempdir().expect("tmpdir");
+    // the same text in both shards: only pagerank tells the hits apart
+    let paths = vec![
+        create_ranked_shard(tmp.path(), "shard-0", "https://example.com/low", 0.1),
+        create_ranked_shard(tmp.path(), "shard-1", "https://example.com/high", 0.9),
+    ];
+    let shards = Shards::open(&paths).expect("open shards");
+
+    let (hits, _) = shards.search("rust", 10, 0, SortBy::PageRank, EngineBoosts::default(), &CancelToken::default()).expect("search");
+    let urls: Vec<&str> = hits.iter().filter_map(|hit| hit["url"].as_str()).collect();
+    assert_eq!(urls, ["https://example.com/high", "https://example.com/low"]);
+}
+
+#[test]
+fn merge_orders_by_the_sort_key_then_score() {
+    let per_shard = vec![
+        vec![
+            json!({"url": "a", "score": 9.0, "pagerank": 0.1, "quality": "0.9"}),
+            json!({"url": "b", "score": 1.0, "pagerank": 0.5, "quality": "0.2"}),
+        ],
+        vec![
+            json!({"url": "c", "score": 5.0, "pagerank": 0.8, "quality": "0.2"}),
+            json!({"url": "d", "score": 3.0, "pagerank": 0.5}),
+        ],
+    ];
+    let urls = |sort, offset, limit| -> Vec<String> {
+        merge(per_shard.clone(), sort, offset, limit)
+            .iter()
+            .map(|hit| hit["url"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(urls(SortBy::Relevance, 0, 10), ["a", "c", "d", "b"]);
+    assert_eq!(urls(SortBy::PageRank, 0, 10), ["c", "d", "b", "a"]);
+    assert_eq!(urls(SortBy::Quality, 0, 10), ["a", "c", "b", "d"]);
+    assert_eq!(urls(SortBy::PageRank, 1, 2), ["d", "b"]);
+}
+
+#[test]
+fn search_with_one_engine_slot_still_covers_every_shard() {
+    let tmp = tempdir().expect("tmpdir");
+    let paths = vec![
+        create_shard(tmp.path(), "shard-0", "https://example.com/a"),
+        create_shard(tmp.path(), "shard-1", "https://example.com/b"),
+    ];
+    let shards = Shards::open(&paths).expect("open shards").with_max_in_flight(Some(1));
+
+    let (hits, _) = shards.search("rust", 10, 0, SortBy::Relevance, EngineBoosts::default(), &CancelToken::default()).expect("search");
+    assert_eq!(hits.len(), 2);
+}
+
+#[test]
+fn open_fails_on_missing_shard() {
+    let tmp = tempdir().expect("tmpdir");
+    let result = Shards::open(&[tmp.path().join("missing")]);
+    assert!(result.is_err());
+}
+
+#[test]
+fn pooled_searchers_see_commits_after_reload() {
+    let tmp = tempdir().expect("tmpdir");
+    let paths = vec![create_shard(tmp.path(), "shard-0", "https://example.com/a")];
+    let shards = Shards::open(&paths).expect("open shards").with_searchers(3).expect("searchers");
+
+    for _ in 0..3 {
+        let (hits, _) = shards.search("rust", 10, 0, SortBy::Relevance, EngineBoosts::default(), &CancelToken::default()).expect("search");
+        assert_eq!(hits.len(), 1);
+    }
+
+    let schema = SearchSchema::build();
+    let index = Index::open_in_dir(&paths[0]).expect("open index");
+    let mut writer = index.writer(50_000_000).expect("writer");
+    writer
+        .add_document(doc!(
+            schema.url_field => "https://example.com/c",
+            schema.title_field => "Rust reload",
+            schema.content_field => "rust reloaded search",
+            schema.domain_field => "example.com",
+            schema.quality_field => "0.5",
+            schema.pagerank_field => 0.1f64,
+            schema.tfidf_field => 0.1f64
+        ))
+        .expect("add doc");
+    writer.commit().expect("commit");
+
+    shards.reload().expect("reload");
+    let (hits, _) = shards.search("rust", 10, 0, SortBy::Relevance, EngineBoosts::default(), &CancelToken::default()).expect("search");
+    assert_eq!(hits.len(), 2);
+}
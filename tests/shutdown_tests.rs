@@ -0,0 +1,202 @@
+use std::io::{self, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nerve_protocol::codec::encode;
+use nerve_protocol::constants::{HEADER_SIZE, VERSION};
+use nerve_protocol::frame::OwnedFrame;
+use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+use serde_json::json;
+use tempfile::tempdir;
+
+use nerve_search_adapter::client;
+use nerve_search_adapter::config::{Config, DEFAULT_MAX_PAYLOAD_BYTES};
+use nerve_search_adapter::framing::FrameDecoder;
+use nerve_search_adapter::handshake::HELLO_REQUEST_ID;
+use nerve_search_adapter::reconnect::{ReconnectConfig, run_sessions};
+use nerve_search_adapter::shutdown::{Shutdown, ShutdownConfig};
+use nerve_search_adapter::state::RequestState;
+use nerve_search_adapter::transport::Stream;
+
+mod common;
+
+use common::create_search_index;
+
+fn draining(drain_timeout_ms: u64) -> Shutdown {
+    Shutdown::new(ShutdownConfig { drain_timeout_ms })
+}
+
+#[test]
+fn drain_waits_for_connections_to_finish() {
+    let shutdown = draining(10_000);
+    let (adapter, mut core) = UnixStream::pair().expect("pair");
+    let mut adapter = Stream::from(adapter);
+    let state = Arc::new(RequestState::new());
+    let token = state.begin(RequestId(1));
+    let watch = shutdown.watch(&adapter, &state).expect("watch");
+
+    let drain = thread::spawn({
+        let shutdown = shutdown.clone();
+        move || shutdown.drain()
+    });
+    // the adapter stops reading, but can still answer
+    let mut rest = Vec::new();
+    adapter.read_to_end(&mut rest).expect("read");
+    assert!(rest.is_empty());
+    assert!(shutdown.is_started());
+    adapter.write_all(b"reply").expect("write");
+    let mut reply = [0u8; 5];
+    core.read_exact(&mut reply).expect("reply");
+    assert_eq!(&reply, b"reply");
+
+    let started = Instant::now();
+    drop(watch);
+    drain.join().expect("drain");
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(!token.is_cancelled(), "finished in time");
+}
+
+#[test]
+fn drain_cancels_what_outlives_the_timeout() {
+    let shutdown = draining(50);
+    let (adapter, _core) = UnixStream::pair().expect("pair");
+    let adapter = Stream::from(adapter);
+    let state = Arc::new(RequestState::new());
+    let running = state.begin(RequestId(1));
+    let _watch = shutdown.watch(&adapter, &state).expect("watch");
+
+    shutdown.drain();
+    assert!(running.is_cancelled());
+    assert!(state.is_cancelled(RequestId(2)));
+    assert!(
+        state.begin(RequestId(2)).is_cancelled(),
+        "queued queries don't run"
+    );
+
+    // connections made from here on are stopped right away
+    let (late, _core) = UnixStream::pair().expect("pair");
+    let mut late = Stream::from(late);
+    let _late = shutdown
+        .watch(&late, &Arc::new(RequestState::new()))
+        .expect("watch");
+    assert_eq!(late.read(&mut [0u8; 8]).expect("read"), 0);
+}
+
+#[test]
+fn reconnecting_stops_once_shutdown_starts() {
+    let tmp = tempdir().expect("tempdir");
+    let path = tmp.path().join("core.sock");
+    let _listener = UnixListener::bind(&path).expect("bind");
+    let shutdown = draining(1_000);
+    let reconnect = ReconnectConfig {
+        enabled: true,
+        initial_backoff_ms: 1,
+        max_backoff_ms: 5,
+        ..ReconnectConfig::default()
+    };
+
+    let mut sessions = 0;
+    let result = run_sessions(reconnect, &path, None, &shutdown, |_stream| {
+        sessions += 1;
+        shutdown.drain();
+        Ok(())
+    });
+    result.expect("shut down cleanly");
+    assert_eq!(sessions, 1);
+}
+
+/// Signals reach every adapter running in this process, so one test at a
+/// time raises them.
+static SIGNALS: Mutex<()> = Mutex::new(());
+
+/// Runs the adapter against a core that answers the handshake with
+/// `capabilities` (no handshake when `None`), signals it once it serves a
+/// PING, and returns what the core read afterwards along with how the
+/// adapter exited.
+fn signalled(signal: i32, capabilities: Option<&[&str]>) -> (Vec<OwnedFrame>, io::Result<()>) {
+    let _one = SIGNALS.lock().unwrap_or_else(|e| e.into_inner());
+    let tmp = tempdir().expect("tmpdir");
+    let index_path = create_search_index(tmp.path());
+    let socket_path = tmp.path().join("core.sock");
+    let listener = UnixListener::bind(&socket_path).expect("bind");
+
+    let mut config = Config::new(&socket_path, &index_path);
+    config.reconnect = ReconnectConfig {
+        enabled: true,
+        initial_backoff_ms: 10,
+        max_backoff_ms: 20,
+        ..ReconnectConfig::default()
+    };
+    config.handshake.enabled = capabilities.is_some();
+    let (done, ended) = mpsc::channel();
+    thread::spawn(move || done.send(client::run(&config)));
+
+    let (mut core, _) = listener.accept().expect("accept");
+    if let Some(capabilities) = capabilities {
+        let mut header = [0u8; HEADER_SIZE];
+        core.read_exact(&mut header).expect("hello header");
+        let length = u32::from_be_bytes(header[HEADER_SIZE - 4..].try_into().unwrap());
+        core.read_exact(&mut vec![0u8; length as usize]).expect("hello");
+        let hello = serde_json::to_vec(&json!({
+            "hello": {
+                "name": "nerve-core",
+                "version": "0.3.0",
+                "protocol_versions": [VERSION],
+                "capabilities": capabilities,
+            }
+        }))
+        .expect("hello json");
+        let answer = encode(
+            MessageType::Pong,
+            FrameFlags::FINAL,
+            HELLO_REQUEST_ID,
+            &hello,
+        )
+        .expect("answer");
+        core.write_all(&answer).expect("answer");
+    }
+    let ping = encode(MessageType::Ping, FrameFlags::FINAL, RequestId(9), b"drain").expect("ping");
+    core.write_all(&ping).expect("ping");
+    // the pong shows the adapter is serving, and listening for signals
+    let mut pong = vec![0u8; ping.len()];
+    core.read_exact(&mut pong).expect("pong");
+    let mut frames = Vec::new();
+    FrameDecoder::new(DEFAULT_MAX_PAYLOAD_BYTES).decode(&pong, &mut frames, |_| {});
+    assert_eq!(frames[0].header.msg_type, MessageType::Pong as u8);
+
+    // SAFETY: the adapter has a handler for the signal installed while it runs
+    assert_eq!(unsafe { libc::raise(signal) }, 0);
+    let mut rest = Vec::new();
+    core.read_to_end(&mut rest)
+        .expect("adapter closes the connection");
+    let served = ended
+        .recv_timeout(Duration::from_secs(10))
+        .expect("adapter exits instead of reconnecting");
+    let mut frames = Vec::new();
+    FrameDecoder::new(DEFAULT_MAX_PAYLOAD_BYTES).decode(&rest, &mut frames, |_| {});
+    (frames, served)
+}
+
+#[test]
+fn sigterm_drains_the_adapter_and_exits() {
+    let (frames, served) = signalled(libc::SIGTERM, None);
+    served.expect("clean shutdown");
+    assert!(frames.is_empty(), "no notice without a handshake");
+}
+
+#[test]
+fn sigint_tells_a_core_that_asked_before_exiting() {
+    let (frames, served) = signalled(libc::SIGINT, Some(&["streaming", "going_away"]));
+    served.expect("clean shutdown");
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].header.msg_type, MessageType::Error as u8);
+    assert_eq!(frames[0].header.request_id, 0);
+    let error: serde_json::Value = serde_json::from_slice(&frames[0].payload).expect("json");
+    assert_eq!(error["code"], "going_away");
+
+    let (frames, served) = signalled(libc::SIGINT, Some(&["streaming"]));
+    served.expect("clean shutdown");
+    assert!(frames.is_empty(), "only cores that agreed are told");
+}
@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use nerve_search_adapter::config::Config;
+use nerve_search_adapter::request::SearchRequest;
+use nerve_search_adapter::slowlog::{SlowLog, SlowLogConfig, Trace};
+
+#[test]
+fn slowlog_is_off_unless_a_threshold_is_set() {
+    let slowlog = SlowLog::new(&SlowLogConfig::default());
+    assert!(!slowlog.is_slow(Duration::from_secs(60)));
+
+    let slowlog = SlowLog::new(&SlowLogConfig {
+        threshold_ms: Some(250),
+        hash_queries: false,
+    });
+    assert!(!slowlog.is_slow(Duration::from_millis(249)));
+    assert!(slowlog.is_slow(Duration::from_millis(250)));
+}
+
+#[test]
+fn slowlog_can_hash_query_text() {
+    let plain = SlowLog::new(&SlowLogConfig::default());
+    assert_eq!(plain.render_query("rust adapter"), "rust adapter");
+
+    let hashed = SlowLog::new(&SlowLogConfig {
+        threshold_ms: Some(1),
+        hash_queries: true,
+    });
+    let rendered = hashed.render_query("rust adapter");
+    assert!(rendered.starts_with("fnv1a:"));
+    assert!(!rendered.contains("rust"));
+    assert_eq!(rendered, hashed.render_query("rust adapter"));
+}
+
+#[test]
+fn trace_records_search_params_and_stages() {
+    let request: SearchRequest = serde_json::from_value(serde_json::json!({
+        "query": "rust",
+        "limit": 5,
+        "dedup": true,
+    }))
+    .unwrap();
+    let mut trace = Trace::default();
+    trace.search(&request);
+    let answer = trace.time("search", || 42);
+
+    assert_eq!(answer, 42);
+    assert_eq!(trace.op, "search");
+    assert_eq!(trace.query.as_deref(), Some("rust"));
+    assert!(trace.params.contains("limit=5"));
+    assert!(trace.params.contains("dedup=3"));
+    assert_eq!(trace.stages.len(), 1);
+    assert_eq!(trace.stages[0].0, "search");
+}
+
+#[test]
+fn slowlog_section_is_read_from_config() {
+    let tmp = tempfile::tempdir().expect("tmpdir");
+    let path = tmp.path().join("adapter.toml");
+    std::fs::write(
+        &path,
+        "index_path = \"/tmp/index\"\n\n[slowlog]\nthreshold_ms = 200\nhash_queries = true\n",
+    )
+    .unwrap();
+
+    let config = Config::load(&path).expect("config");
+    assert_eq!(config.slowlog.threshold_ms, Some(200));
+    assert!(config.slowlog.hash_queries);
+}
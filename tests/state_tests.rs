@@ -0,0 +1,45 @@
+use std::io::ErrorKind;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nerve_protocol::types::RequestId;
+use nerve_search_adapter::state::{CancelToken, RequestState};
+
+#[test]
+fn cancellation_is_visible_across_threads() {
+    let state = RequestState::new();
+    thread::scope(|s| {
+        s.spawn(|| state.cancel(RequestId(7)));
+    });
+    assert!(state.is_cancelled(RequestId(7)));
+    assert!(!state.is_cancelled(RequestId(8)));
+}
+
+#[test]
+fn cancel_trips_the_token_of_a_running_request() {
+    let state = RequestState::new();
+    let token = state.begin(RequestId(3));
+    assert!(!token.is_cancelled());
+    state.cancel(RequestId(3));
+    assert!(token.is_cancelled());
+    assert!(token.check().is_err());
+
+    state.finish(RequestId(3));
+    state.cancel(RequestId(4));
+    assert!(state.begin(RequestId(4)).is_cancelled());
+}
+
+#[test]
+fn token_expires_at_its_deadline() {
+    let token = CancelToken::default();
+    let later = token.with_deadline(Instant::now() + Duration::from_secs(60));
+    assert!(later.check().is_ok());
+
+    let expired = token.with_deadline(Instant::now() - Duration::from_millis(1));
+    assert!(expired.is_cancelled());
+    assert_eq!(expired.check().unwrap_err().kind(), ErrorKind::TimedOut);
+    // the deadline belongs to the copy; a cancel reaches every copy
+    assert!(!token.is_cancelled());
+    token.cancel();
+    assert_eq!(later.check().unwrap_err().kind(), ErrorKind::Interrupted);
+}
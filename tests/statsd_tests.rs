@@ -0,0 +1,81 @@
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use nerve_search_adapter::slowlog::Trace;
+use nerve_search_adapter::statsd::{Statsd, StatsdConfig};
+
+fn search_trace() -> Trace {
+    let mut trace = Trace {
+        op: "search",
+        hits: Some(7),
+        ..Trace::default()
+    };
+    trace.stages.push(("parse", Duration::from_micros(250)));
+    trace.stages.push(("search", Duration::from_millis(3)));
+    trace
+}
+
+/// The lines an agent receives for one search taking 5 ms.
+fn received(config: StatsdConfig) -> Vec<String> {
+    let agent = UdpSocket::bind("127.0.0.1:0").expect("bind agent");
+    agent
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .expect("timeout");
+    let config = StatsdConfig {
+        address: Some(agent.local_addr().expect("address").to_string()),
+        ..config
+    };
+    let statsd = Statsd::open(&config).expect("open");
+    statsd.record(&search_trace(), Duration::from_millis(5));
+
+    let mut buf = [0u8; 1024];
+    let read = agent.recv(&mut buf).expect("datagram");
+    std::str::from_utf8(&buf[..read])
+        .expect("utf-8")
+        .lines()
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn each_request_goes_out_as_one_datagram() {
+    let lines = received(StatsdConfig {
+        prefix: "nsa".into(),
+        ..StatsdConfig::default()
+    });
+    assert_eq!(
+        lines,
+        [
+            "nsa.search.requests:1|c",
+            "nsa.search.request_time:5.000|ms",
+            "nsa.search.parse_time:0.250|ms",
+            "nsa.search.search_time:3.000|ms",
+            "nsa.search.hits:7|h",
+        ]
+    );
+}
+
+#[test]
+fn dogstatsd_tags_the_operation_and_stage() {
+    let config = StatsdConfig {
+        dogstatsd: true,
+        tags: vec!["env:prod".into()],
+        ..StatsdConfig::default()
+    };
+    assert!(config.validate().is_ok());
+    let lines = received(config);
+    assert_eq!(
+        lines[0],
+        "nerve_search_adapter.requests:1|c|#op:search,env:prod"
+    );
+    assert_eq!(
+        lines[3],
+        "nerve_search_adapter.stage_time:3.000|ms|#op:search,stage:search,env:prod"
+    );
+
+    let untagged = StatsdConfig {
+        tags: vec!["env:prod".into()],
+        ..StatsdConfig::default()
+    };
+    assert!(untagged.validate().is_err(), "tags need dogstatsd");
+}
@@ -0,0 +1,65 @@
+use std::io::Cursor;
+
+use nerve_protocol::codec::encode;
+use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+use tempfile::tempdir;
+
+use nerve_search_adapter::config::{Config, DEFAULT_MAX_PAYLOAD_BYTES};
+use nerve_search_adapter::context::Context;
+use nerve_search_adapter::framing::FrameDecoder;
+use nerve_search_adapter::stdio;
+
+mod common;
+
+use common::create_search_index;
+
+#[test]
+fn stdio_answers_every_frame_before_returning() {
+    let tmp = tempdir().expect("tmpdir");
+    let index_path = create_search_index(tmp.path());
+    let config = Config::new("-", &index_path);
+    assert!(config.uses_stdio());
+    let context = Context::from_config(&config).expect("context");
+
+    let mut input = Vec::new();
+    for id in [3, 4] {
+        input.extend(
+            encode(
+                MessageType::Ping,
+                FrameFlags::FINAL,
+                RequestId(id),
+                b"stdio",
+            )
+            .expect("encode"),
+        );
+    }
+    let mut output = Vec::new();
+    stdio::serve(&config, &context, Cursor::new(input), &mut output).expect("serve");
+
+    let mut frames = Vec::new();
+    FrameDecoder::new(DEFAULT_MAX_PAYLOAD_BYTES).decode(&output, &mut frames, |_| {});
+    let mut pongs: Vec<u64> = frames
+        .iter()
+        .inspect(|frame| {
+            assert_eq!(frame.header.msg_type, MessageType::Pong as u8);
+            assert_eq!(frame.payload, b"stdio");
+        })
+        .map(|frame| frame.header.request_id)
+        .collect();
+    pongs.sort();
+    assert_eq!(pongs, [3, 4]);
+}
+
+#[test]
+fn stdio_flag_serves_a_single_peer() {
+    let tmp = tempdir().expect("tmpdir");
+    let index = tmp.path().to_string_lossy().into_owned();
+    let config =
+        Config::from_args(["--index", &index, "--stdio"].map(String::from)).expect("config");
+    assert!(config.uses_stdio());
+
+    let config = Config::from_args(
+        ["--index", &index, "--stdio", "--core", "/tmp/other.sock"].map(String::from),
+    );
+    assert!(config.is_err(), "stdio can't serve several cores");
+}
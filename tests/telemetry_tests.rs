@@ -0,0 +1,108 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use nerve_protocol::codec::encode;
+use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+use nerve_search_adapter::framing::FrameDecoder;
+use nerve_search_adapter::state::RequestState;
+use nerve_search_adapter::telemetry::{self, DEFAULT_SERVICE_NAME, OtlpConfig, is_exporting};
+use tracing::level_filters::LevelFilter;
+
+#[test]
+fn otlp_endpoints_must_be_http_urls() {
+    let config = OtlpConfig::default();
+    assert_eq!(config.service_name, DEFAULT_SERVICE_NAME);
+    assert!(config.validate().is_ok(), "nothing exported by default");
+
+    let config: OtlpConfig =
+        toml::from_str(r#"endpoint = "collector:4317""#).expect("parse [otlp]");
+    let error = config.validate().expect_err("not a URL");
+    if cfg!(feature = "otel") {
+        assert!(error.contains("http(s) URL"), "{error}");
+    } else {
+        assert!(error.contains("`otel` feature"), "{error}");
+    }
+
+    let config: OtlpConfig = toml::from_str(
+        r#"
+        endpoint = "http://collector:4318/v1/traces"
+        service_name = "search-eu"
+        "#,
+    )
+    .expect("parse [otlp]");
+    assert_eq!(config.service_name, "search-eu");
+    assert_eq!(config.validate().is_ok(), cfg!(feature = "otel"));
+}
+
+#[test]
+fn no_spans_are_kept_while_not_exporting() {
+    assert!(!is_exporting());
+    let state = RequestState::new();
+    state.queue(RequestId(7));
+    assert!(state.spans().get(RequestId(7)).is_none());
+}
+
+#[derive(Clone, Default)]
+struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn logs_made_handling_a_request_carry_its_id_type_and_query_hash() {
+    let payload = br#"{"query": "rust"}"#;
+    let frame = encode(
+        MessageType::SearchQuery,
+        FrameFlags::FINAL,
+        RequestId(7),
+        payload,
+    )
+    .expect("encode");
+    let mut frames = Vec::new();
+    FrameDecoder::new(1024).decode(&frame, &mut frames, |_| {});
+    let [frame] = frames.as_slice() else {
+        panic!("one frame decoded, got {}", frames.len());
+    };
+
+    let logs = Logs::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    tracing::subscriber::with_default(subscriber, || {
+        let span = telemetry::request(RequestId(7));
+        telemetry::describe(&span, frame);
+        span.in_scope(|| tracing::warn!("search failed"));
+    });
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).expect("utf-8 logs");
+    assert!(logs.contains("request_id=7"), "{logs}");
+    assert!(logs.contains("msg_type=SearchQuery"), "{logs}");
+    assert!(logs.contains("query_hash="), "{logs}");
+    assert!(!logs.contains("rust"), "query text kept out: {logs}");
+}
+
+#[test]
+fn log_levels_cycle_from_info_through_trace_and_back() {
+    let mut level = LevelFilter::INFO;
+    let mut seen = Vec::new();
+    for _ in 0..3 {
+        level = telemetry::cycled(level);
+        seen.push(level);
+    }
+    assert_eq!(
+        seen,
+        [LevelFilter::DEBUG, LevelFilter::TRACE, LevelFilter::INFO]
+    );
+    assert_eq!(telemetry::cycled(LevelFilter::WARN), LevelFilter::DEBUG);
+    assert_eq!(telemetry::cycled(LevelFilter::OFF), LevelFilter::DEBUG);
+}
@@ -0,0 +1,188 @@
+use std::io::{ErrorKind, IoSlice, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nerve_search_adapter::transport::{Endpoint, Stream, Transport};
+
+#[test]
+fn sockets_parse_by_scheme() {
+    assert_eq!(
+        Endpoint::parse(Path::new("tcp://core.internal:7400")),
+        Endpoint::Tcp("core.internal:7400".to_string())
+    );
+    assert_eq!(
+        Endpoint::parse(Path::new("/tmp/nerve.sock")),
+        Endpoint::Unix(PathBuf::from("/tmp/nerve.sock"))
+    );
+    assert_eq!(
+        Endpoint::parse(Path::new("tcp://127.0.0.1:7400")).to_string(),
+        "tcp://127.0.0.1:7400"
+    );
+}
+
+#[test]
+fn tcp_endpoints_connect() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let address = listener.local_addr().expect("address");
+    let core = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut hello = [0u8; 5];
+        stream.read_exact(&mut hello).expect("read");
+        stream.write_all(&hello).expect("echo");
+    });
+
+    let endpoint = Endpoint::parse(Path::new(&format!("tcp://{address}")));
+    let mut stream = endpoint.connect(None).expect("connect");
+    assert!(matches!(stream, Stream::Tcp(_)));
+    stream.write_all(b"hello").expect("write");
+    let mut echoed = [0u8; 5];
+    stream.read_exact(&mut echoed).expect("read echo");
+    assert_eq!(&echoed, b"hello");
+    core.join().expect("core join");
+}
+
+#[test]
+fn polled_streams_carry_frames_without_blocking() {
+    let (adapter, mut core) = UnixStream::pair().expect("pair");
+    let stream = Stream::from(adapter);
+    stream.set_nonblocking(true).expect("nonblocking");
+    let mut transport = stream.into_mio();
+
+    let mut buf = [0u8; 16];
+    let idle = transport
+        .read_frames(&mut buf)
+        .expect_err("nothing to read");
+    assert_eq!(idle.kind(), ErrorKind::WouldBlock);
+
+    let written = transport
+        .write_frames(&[IoSlice::new(b"two "), IoSlice::new(b"frames")])
+        .expect("write");
+    assert_eq!(written, 10);
+    assert!(!transport.has_pending_writes());
+    let mut frames = [0u8; 10];
+    core.read_exact(&mut frames).expect("core read");
+    assert_eq!(&frames, b"two frames");
+
+    core.write_all(b"reply").expect("core write");
+    core.shutdown(Shutdown::Write).expect("core hang-up");
+    assert_eq!(transport.read_frames(&mut buf).expect("read"), 5);
+    assert_eq!(&buf[..5], b"reply");
+    assert_eq!(transport.read_frames(&mut buf).expect("hang-up"), 0);
+    transport.shutdown(Shutdown::Both).expect("shutdown");
+}
+
+#[test]
+fn socket_buffers_take_the_configured_sizes() {
+    let (adapter, _core) = UnixStream::pair().expect("pair");
+    let stream = Stream::from(adapter);
+    stream
+        .set_buffer_sizes(Some(65_536), Some(65_536))
+        .expect("size buffers");
+    let (send, receive) = stream.buffer_sizes().expect("sizes");
+    // the kernel may round up (Linux doubles), never down below the cap
+    assert!(send >= 65_536, "send buffer {send}");
+    assert!(receive >= 65_536, "receive buffer {receive}");
+
+    stream
+        .set_buffer_sizes(Some(16_384), None)
+        .expect("shrink send buffer");
+    let (smaller, same) = stream.buffer_sizes().expect("sizes");
+    assert!(smaller < send, "send buffer {smaller}");
+    assert_eq!(same, receive);
+}
+
+/// A `SOCK_SEQPACKET` listener at `path`, as a core would open one.
+fn seqpacket_listener(path: &Path) -> OwnedFd {
+    // SAFETY: an all-zero sockaddr_un is a valid, empty one
+    let mut address: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    address.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    for (slot, byte) in address.sun_path.iter_mut().zip(path.as_os_str().as_bytes()) {
+        *slot = *byte as libc::c_char;
+    }
+    // SAFETY: plain socket(2), bind(2) and listen(2) on an fd owned here
+    unsafe {
+        let fd = libc::socket(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0);
+        assert!(fd >= 0, "socket");
+        let listener = OwnedFd::from_raw_fd(fd);
+        let bound = libc::bind(
+            fd,
+            (&address as *const libc::sockaddr_un).cast(),
+            std::mem::size_of::<libc::sockaddr_un>() as libc::socklen_t,
+        );
+        assert_eq!(bound, 0, "bind");
+        assert_eq!(libc::listen(fd, 1), 0, "listen");
+        listener
+    }
+}
+
+#[test]
+fn seqpacket_endpoints_write_one_frame_per_packet() {
+    let tmp = tempfile::tempdir().expect("tempdir");
+    let path = tmp.path().join("core.sock");
+    let listener = seqpacket_listener(&path);
+    let socket = format!("seqpacket://{}", path.display());
+    assert_eq!(
+        Endpoint::parse(Path::new(&socket)),
+        Endpoint::SeqPacket(path)
+    );
+    assert_eq!(Endpoint::parse(Path::new(&socket)).to_string(), socket);
+
+    let mut stream = Endpoint::parse(Path::new(&socket))
+        .connect(None)
+        .expect("connect");
+    assert!(stream.keeps_packets());
+    // SAFETY: accept(2) on the listener; the new fd is owned from here on
+    let core = unsafe {
+        let fd = libc::accept(
+            listener.as_raw_fd(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        );
+        assert!(fd >= 0, "accept");
+        OwnedFd::from_raw_fd(fd)
+    };
+    let mut core = UnixStream::from(core);
+
+    let frames = [IoSlice::new(b"first"), IoSlice::new(b"second")];
+    assert_eq!(stream.write_vectored(&frames).expect("write"), 5);
+    stream.write_all(b"second").expect("write");
+    let mut packet = [0u8; 64];
+    let n = core.read(&mut packet).expect("read");
+    assert_eq!(&packet[..n], b"first");
+    let n = core.read(&mut packet).expect("read");
+    assert_eq!(&packet[..n], b"second");
+}
+
+#[test]
+fn half_close_reads_off_what_the_core_still_sends() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let address = listener.local_addr().expect("address");
+    let adapter = TcpStream::connect(address).expect("connect");
+    let (mut core, _) = listener.accept().expect("accept");
+    let mut adapter = Stream::from(adapter);
+    adapter.write_all(b"last reply").expect("adapter write");
+    // queries the adapter will never read, sent before it closes
+    core.write_all(&[0u8; 8192]).expect("core write");
+
+    let closing = thread::spawn(move || {
+        let started = Instant::now();
+        adapter
+            .half_close(Duration::from_secs(5))
+            .expect("half-close");
+        started.elapsed()
+    });
+    let mut replies = Vec::new();
+    core.read_to_end(&mut replies).expect("read to the end");
+    assert_eq!(replies, b"last reply");
+    drop(core);
+    let took = closing.join().expect("join");
+    assert!(
+        took < Duration::from_secs(5),
+        "returns once the core hangs up"
+    );
+}
@@ -0,0 +1,95 @@
+use serde_json::json;
+
+use nerve_search_adapter::filters::Filters;
+use nerve_search_adapter::request::{SearchMode, SearchRequest, SortOrder};
+use nerve_search_adapter::usage::{RECENT_QUERIES, Usage};
+
+#[test]
+fn searches_are_counted_by_mode_sort_and_filter() {
+    let usage = Usage::default();
+    usage.search(&SearchRequest::text("rust"));
+    usage.search(&SearchRequest {
+        mode: SearchMode::SortFusion,
+        sorts: vec![SortOrder::Pagerank, SortOrder::Quality],
+        filters: Filters {
+            after: Some(1_700_000_000),
+            before: None,
+        },
+        dedup: true,
+        ..SearchRequest::text("rust")
+    });
+    usage.search(&SearchRequest {
+        mode: SearchMode::Hybrid,
+        stem: Some(false),
+        ..SearchRequest::text("tantivy")
+    });
+
+    let report = usage.report();
+    assert_eq!(report["searches"], 3);
+    assert_eq!(
+        report["modes"],
+        json!({"lexical": 1, "sort_fusion": 1, "hybrid": 1})
+    );
+    assert_eq!(report["sorts"], json!({"pagerank": 1, "quality": 1}));
+    assert_eq!(
+        report["filters"],
+        json!({"after": 1, "dedup": 1, "stem": 1})
+    );
+}
+
+#[test]
+fn hit_domains_are_counted_once_per_search_and_bounded() {
+    let usage = Usage::new(2);
+    usage.served(&[
+        json!({"url": "https://docs.rs/tantivy"}),
+        json!({"url": "https://docs.rs/serde"}),
+        json!({"url": "http://user@example.com:8080/a?b#c"}),
+    ]);
+    usage.served(&[
+        json!({"url": "https://docs.rs/"}),
+        json!({"title": "no url"}),
+    ]);
+    assert_eq!(
+        usage.report()["domains"],
+        json!([
+            {"domain": "docs.rs", "searches": 2},
+            {"domain": "example.com", "searches": 1},
+        ])
+    );
+
+    // a new domain pushes out the least served one
+    usage.served(&[json!({"url": "https://[::1]:3000/"})]);
+    assert_eq!(
+        usage.report()["domains"],
+        json!([
+            {"domain": "docs.rs", "searches": 2},
+            {"domain": "::1", "searches": 1},
+        ])
+    );
+}
+
+#[test]
+fn recent_queries_are_listed_newest_first_and_bounded() {
+    let usage = Usage::default();
+    for n in 0..RECENT_QUERIES + 5 {
+        usage.search(&SearchRequest::text(format!("query {n}")));
+    }
+    // a vector search without text has no query to list
+    usage.search(&SearchRequest {
+        mode: SearchMode::Vector,
+        vector: Some(vec![0.5; 4]),
+        ..SearchRequest::default()
+    });
+
+    let recent: Vec<String> = usage
+        .recent(3)
+        .into_iter()
+        .map(|(_, query)| query)
+        .collect();
+    let last = RECENT_QUERIES + 4;
+    assert_eq!(
+        recent,
+        [last, last - 1, last - 2].map(|n| format!("query {n}"))
+    );
+    assert_eq!(usage.recent(usize::MAX).len(), RECENT_QUERIES);
+}
@@ -0,0 +1,73 @@
+use serde_json::{json, Map, Value};
+use tempfile::tempdir;
+
+use nerve_search_adapter::request::{SearchMode, SearchRequest};
+use nerve_search_adapter::state::CancelToken;
+use nerve_search_adapter::vector::{VectorIndex, SIDECAR_FILE};
+
+fn entry(url: &str, vector: Vec<f32>) -> (Map<String, Value>, Vec<f32>) {
+    let mut fields = Map::new();
+    fields.insert("url".into(), Value::from(url));
+    (fields, vector)
+}
+
+#[test]
+fn vector_search_returns_nearest_first() {
+    let mut entries = Vec::new();
+    for i in 0..200 {
+        let angle = i as f32 * 0.01;
+        entries.push(entry(&format!("https://example.com/{i}"), vec![angle.cos(), angle.sin(), 0.0]));
+    }
+    let index = VectorIndex::build(entries).expect("build");
+    assert_eq!(index.len(), 200);
+
+    let hits = index.search(&[1.0, 0.0, 0.0], 3, 0, &CancelToken::default()).expect("search");
+    assert_eq!(hits.len(), 3);
+    assert_eq!(hits[0]["url"], "https://example.com/0");
+    let scores: Vec<f64> = hits.iter().map(|h| h["score"].as_f64().unwrap()).collect();
+    assert!(scores.windows(2).all(|w| w[0] >= w[1]));
+}
+
+#[test]
+fn vector_search_rejects_wrong_dimensions() {
+    let index = VectorIndex::build(vec![entry("a", vec![1.0, 0.0])]).expect("build");
+    assert!(index.search(&[1.0, 0.0, 0.0], 1, 0, &CancelToken::default()).is_err());
+}
+
+#[test]
+fn vector_search_stops_when_cancelled() {
+    let index = VectorIndex::build(vec![entry("a", vec![1.0, 0.0])]).expect("build");
+    let cancel = CancelToken::default();
+    cancel.cancel();
+    let err = index.search(&[1.0, 0.0], 1, 0, &cancel).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+}
+
+#[test]
+fn vector_sidecar_loads_from_index_dir() {
+    let tmp = tempdir().expect("tmpdir");
+    assert!(VectorIndex::load(tmp.path()).expect("load").is_none());
+
+    let lines = [
+        json!({"url": "https://example.com/a", "title": "A", "vector": [1.0, 0.0]}),
+        json!({"url": "https://example.com/b", "title": "B", "vector": [0.0, 1.0]}),
+    ];
+    let body: Vec<String> = lines.iter().map(Value::to_string).collect();
+    std::fs::write(tmp.path().join(SIDECAR_FILE), body.join("\n")).expect("write sidecar");
+
+    let index = VectorIndex::load(tmp.path()).expect("load").expect("sidecar present");
+    let hits = index.search(&[0.1, 0.9], 1, 0, &CancelToken::default()).expect("search");
+    assert_eq!(hits[0]["title"], "B");
+    assert!(hits[0].get("vector").is_none());
+}
+
+#[test]
+fn structured_request_with_vector_selects_vector_mode() {
+    let request = SearchRequest::parse(br#"{"vector": [0.5, 0.5], "limit": 3}"#).expect("parse");
+    assert_eq!(request.mode, SearchMode::Vector);
+    assert_eq!(request.limit, 3);
+
+    let plain = SearchRequest::parse(b"rust adapter").expect("parse");
+    assert_eq!(plain.mode, SearchMode::Lexical);
+    assert_eq!(plain.query, "rust adapter");
+}
@@ -0,0 +1,173 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crawler::search::SearchSchema;
+use tantivy::{Index, doc};
+use tempfile::tempdir;
+
+use nerve_search_adapter::config::Config;
+use nerve_search_adapter::context::Context;
+use nerve_search_adapter::request::SearchRequest;
+use nerve_search_adapter::shards::Shards;
+use nerve_search_adapter::warm::{self, PopularQueries};
+
+mod common;
+
+use common::create_search_index;
+
+fn record(popular: &PopularQueries, query: &str, times: usize) {
+    let request = SearchRequest::text(query);
+    for _ in 0..times {
+        popular.record(&request.cache_key(), &request, query.as_bytes());
+    }
+}
+
+#[test]
+fn top_lists_most_asked_searches_first() {
+    let popular = PopularQueries::new(16);
+    record(&popular, "rust", 3);
+    record(&popular, "tantivy", 5);
+    record(&popular, "adapter", 1);
+
+    let top: Vec<String> = popular.top(2).into_iter().map(|r| r.query).collect();
+    assert_eq!(top, vec!["tantivy", "rust"]);
+    assert_eq!(popular.len(), 3);
+}
+
+#[test]
+fn full_table_forgets_least_asked_search() {
+    let popular = PopularQueries::new(2);
+    record(&popular, "rust", 4);
+    record(&popular, "tantivy", 1);
+    record(&popular, "adapter", 1);
+
+    let top: Vec<String> = popular.top(10).into_iter().map(|r| r.query).collect();
+    assert_eq!(top.len(), 2);
+    assert_eq!(top[0], "rust");
+    assert!(!top.contains(&"tantivy".to_string()));
+}
+
+#[test]
+fn counts_add_up_one_query_asked_with_different_options() {
+    let popular = PopularQueries::new(16);
+    record(&popular, "rust", 2);
+    record(&popular, "tantivy", 2);
+    let deduped = SearchRequest {
+        dedup: true,
+        ..SearchRequest::text("rust")
+    };
+    popular.record(
+        &deduped.cache_key(),
+        &deduped,
+        br#"{"query": "rust", "dedup": true}"#,
+    );
+
+    assert_eq!(
+        popular.counts(10),
+        vec![("rust".to_string(), 3), ("tantivy".to_string(), 2)]
+    );
+    assert_eq!(popular.counts(1).len(), 1);
+}
+
+#[test]
+fn zero_capacity_counts_nothing() {
+    let popular = PopularQueries::new(0);
+    record(&popular, "rust", 2);
+    assert!(popular.is_empty());
+}
+
+fn add_page(index_path: &Path, url: &str) {
+    let schema = SearchSchema::build();
+    let index = Index::open_in_dir(index_path)
+        .or_else(|_| Index::create_in_dir(index_path, schema.schema.clone()))
+        .expect("index");
+    let mut writer = index.writer(50_000_000).expect("writer");
+    writer
+        .add_document(doc!(
+            schema.url_field => url,
+            schema.title_field => "Rust warm",
+            schema.content_field => "rust warm state",
+            schema.domain_field => "example.com",
+            schema.quality_field => "0.5",
+            schema.pagerank_field => 0.1f64,
+            schema.tfidf_field => 0.1f64
+        ))
+        .expect("add doc");
+    writer.commit().expect("commit");
+}
+
+fn context(index_path: &Path, state: &Path) -> Context {
+    let engine = crawler::SearchEngine::new(index_path).expect("engine");
+    let mut context = Context::new(Shards::single(index_path, engine));
+    context.warm_state = Some(state.to_path_buf());
+    context
+}
+
+#[test]
+fn warm_state_survives_restart_until_the_index_changes() {
+    let tmp = tempdir().expect("tmpdir");
+    let index_path = tmp.path().join("index");
+    std::fs::create_dir_all(&index_path).expect("index dir");
+    add_page(&index_path, "https://example.com/a");
+    let state = tmp.path().join("warm.json");
+
+    let before = context(&index_path, &state);
+    record(&before.popular, "rust", 2);
+    before.misses().record_miss("nothing-here");
+    warm::save(&before);
+
+    let after = context(&index_path, &state);
+    warm::restore(&after).expect("restore");
+    assert_eq!(after.popular.top(1)[0].query, "rust");
+    assert!(after.misses().is_miss("nothing-here"));
+
+    // a commit since the save makes the saved misses untrustworthy
+    add_page(&index_path, "https://example.com/b");
+    let committed = context(&index_path, &state);
+    warm::restore(&committed).expect("restore");
+    assert_eq!(committed.popular.len(), 1);
+    assert!(!committed.misses().is_miss("nothing-here"));
+}
+
+#[test]
+fn the_warmer_reruns_popular_searches_once_the_index_changes() {
+    let tmp = tempdir().expect("tmpdir");
+    let index_path = tmp.path().join("index");
+    std::fs::create_dir_all(&index_path).expect("index dir");
+    add_page(&index_path, "https://example.com/a");
+    let context = context(&index_path, &tmp.path().join("warm.json"));
+    record(&context.popular, "nowhere to be found", 3);
+    let key = SearchRequest::text("nowhere to be found").cache_key();
+
+    let stop = AtomicBool::new(false);
+    thread::scope(|s| {
+        s.spawn(|| warm::run(&context, &stop));
+        thread::sleep(Duration::from_millis(50));
+        // nothing runs until the index changes
+        assert!(!context.misses().is_miss(&key));
+
+        context.popular.mark_stale();
+        let start = Instant::now();
+        while !context.misses().is_miss(&key) {
+            assert!(start.elapsed() < Duration::from_secs(5), "never warmed");
+            thread::sleep(Duration::from_millis(10));
+        }
+        stop.store(true, Ordering::Relaxed);
+    });
+}
+
+#[test]
+fn a_corrupt_warm_state_file_means_a_cold_start() {
+    let tmp = tempdir().expect("tmpdir");
+    let mut config = Config::new("-", create_search_index(tmp.path()));
+    let state = tmp.path().join("warm.json");
+    config.warm_state_path = Some(state.clone());
+
+    for garbage in [&b"\x00\xffnot json"[..], br#"{"generation": [1], "queries": [{"pay"#] {
+        std::fs::write(&state, garbage).expect("write warm state");
+        let context = Context::from_config(&config).expect("starts despite the bad file");
+        assert!(context.popular.is_empty());
+    }
+}
@@ -0,0 +1,98 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use nerve_protocol::codec::encode;
+use nerve_protocol::types::{FrameFlags, MessageType, RequestId};
+use tempfile::tempdir;
+
+use nerve_search_adapter::config::Config;
+use nerve_search_adapter::context::Context;
+use nerve_search_adapter::framing::FrameDecoder;
+use nerve_search_adapter::state::RequestState;
+use nerve_search_adapter::watchdog::{self, Progress, WatchdogConfig};
+
+mod common;
+
+use common::create_search_index;
+
+#[derive(Clone, Default)]
+struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn progress_tracks_the_loop_and_its_last_frame() {
+    let progress = Progress::default();
+    assert_eq!(progress.busy_since(), None);
+    assert_eq!(progress.last_frame(), None);
+
+    progress.busy();
+    assert!(progress.busy_since().is_some());
+    let bytes = encode(MessageType::Cancel, FrameFlags::FINAL, RequestId(12), b"").expect("encode");
+    let mut frames = Vec::new();
+    FrameDecoder::new(64).decode(&bytes, &mut frames, |_| {});
+    progress.took(&frames);
+    assert_eq!(progress.last_frame(), Some((12, MessageType::Cancel as u8)));
+    progress.idle();
+    assert_eq!(progress.busy_since(), None);
+
+    let abort_alone = WatchdogConfig {
+        stall_secs: None,
+        abort: true,
+    };
+    assert!(abort_alone.validate().is_err());
+    let config: WatchdogConfig = toml::from_str("stall_secs = 0").expect("parse [watchdog]");
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn a_stalled_loop_is_reported_once_with_its_requests() {
+    let tmp = tempdir().expect("tmpdir");
+    let index = create_search_index(tmp.path());
+    let config =
+        Config::from_args(["--index", &index.to_string_lossy()].map(String::from)).expect("config");
+    let context = Context::from_config(&config).expect("context");
+    let health = context.connections.slot(0);
+    let state = Arc::new(RequestState::new());
+    let _orphans = health.track(Arc::clone(&state));
+    state.queue(RequestId(41));
+    health.progress().busy();
+
+    let logs = Logs::default();
+    let writer = logs.clone();
+    let stopped = AtomicBool::new(false);
+    let config = WatchdogConfig {
+        stall_secs: Some(1),
+        abort: false,
+    };
+    thread::scope(|s| {
+        s.spawn(|| {
+            let subscriber = tracing_subscriber::fmt()
+                .with_ansi(false)
+                .with_writer(move || writer.clone())
+                .finish();
+            tracing::subscriber::with_default(subscriber, || {
+                watchdog::run(config, &context, &stopped)
+            });
+        });
+        thread::sleep(Duration::from_millis(1800));
+        stopped.store(true, Ordering::Relaxed);
+    });
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).expect("utf-8 logs");
+    assert_eq!(logs.matches("frame loop stalled").count(), 1, "{logs}");
+    assert!(logs.contains("requests=[41]"), "{logs}");
+    assert!(logs.contains("connection=0"), "{logs}");
+}
@@ -0,0 +1,137 @@
+use std::path::Path;
+
+use serde_json::{Map, Value, json};
+use tantivy::Index;
+use tantivy::schema::{STORED, STRING, Schema, TEXT};
+use tempfile::tempdir;
+
+use nerve_search_adapter::introspect;
+use nerve_search_adapter::writer::{CommitPolicy, DocumentWriter};
+
+fn create_index(path: &Path) {
+    let mut builder = Schema::builder();
+    builder.add_text_field("url", STRING | STORED);
+    builder.add_text_field("title", TEXT | STORED);
+    Index::create_in_dir(path, builder.build()).expect("create index");
+}
+
+fn manual() -> CommitPolicy {
+    CommitPolicy {
+        every_docs: 0,
+        interval_ms: 0,
+    }
+}
+
+fn doc(value: Value) -> Map<String, Value> {
+    value.as_object().cloned().expect("object")
+}
+
+#[test]
+fn index_document_is_buffered_until_commit() {
+    let tmp = tempdir().expect("tmpdir");
+    create_index(tmp.path());
+    let writer = DocumentWriter::with_policy(tmp.path(), manual());
+
+    let ack = writer
+        .index_document(doc(
+            json!({"url": "https://example.com/", "title": "hello"}),
+        ))
+        .expect("index");
+    assert!(!ack.committed);
+    assert_eq!(ack.pending, 1);
+    assert_eq!(introspect::shard_stats(tmp.path()).expect("stats").num_docs, 0);
+
+    let ack = writer.commit().expect("commit");
+    assert!(ack.committed);
+    assert_eq!(writer.pending(), 0);
+    assert_eq!(introspect::shard_stats(tmp.path()).expect("stats").num_docs, 1);
+}
+
+#[test]
+fn index_document_rejects_unknown_fields_and_missing_url() {
+    let tmp = tempdir().expect("tmpdir");
+    create_index(tmp.path());
+    let writer = DocumentWriter::new(tmp.path());
+
+    assert!(
+        writer
+            .index_document(doc(json!({"url": "u", "bogus": 1})))
+            .is_err()
+    );
+    assert!(
+        writer
+            .index_document(doc(json!({"title": "no url"})))
+            .is_err()
+    );
+}
+
+#[test]
+fn update_replaces_and_delete_removes_by_url() {
+    let tmp = tempdir().expect("tmpdir");
+    create_index(tmp.path());
+    let writer = DocumentWriter::new(tmp.path());
+
+    writer
+        .index_document(doc(json!({"url": "https://example.com/", "title": "v1"})))
+        .expect("index");
+    writer
+        .update_document(doc(json!({"url": "https://example.com/", "title": "v2"})))
+        .expect("update");
+    writer.commit().expect("commit");
+    let stats = introspect::shard_stats(tmp.path()).expect("stats");
+    assert_eq!(stats.num_docs, 1);
+
+    let ack = writer
+        .delete_document("https://example.com/")
+        .expect("delete");
+    assert_eq!(ack.op, "delete_document");
+    writer.commit().expect("commit");
+    let stats = introspect::shard_stats(tmp.path()).expect("stats");
+    assert_eq!(stats.num_docs, 0);
+}
+
+#[test]
+fn doc_count_policy_commits_inline() {
+    let tmp = tempdir().expect("tmpdir");
+    create_index(tmp.path());
+    let policy = CommitPolicy {
+        every_docs: 2,
+        interval_ms: 0,
+    };
+    let writer = DocumentWriter::with_policy(tmp.path(), policy);
+
+    let first = writer
+        .index_document(doc(json!({"url": "a"})))
+        .expect("index");
+    assert!(!first.committed);
+    let second = writer
+        .index_document(doc(json!({"url": "b"})))
+        .expect("index");
+    assert!(second.committed);
+    assert_eq!(second.pending, 0);
+    assert_eq!(
+        introspect::shard_stats(tmp.path()).expect("stats").num_docs,
+        2
+    );
+}
+
+#[test]
+fn interval_policy_commits_in_background() {
+    let tmp = tempdir().expect("tmpdir");
+    create_index(tmp.path());
+    let policy = CommitPolicy {
+        every_docs: 0,
+        interval_ms: 40,
+    };
+    let writer = DocumentWriter::with_policy(tmp.path(), policy);
+    writer
+        .index_document(doc(json!({"url": "a"})))
+        .expect("index");
+
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    assert_eq!(writer.pending(), 0);
+    assert_eq!(
+        introspect::shard_stats(tmp.path()).expect("stats").num_docs,
+        1
+    );
+}